@@ -0,0 +1,96 @@
+/// A description of how the emulator was built, for scripts and bug reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Names and versions of the crates making up the emulator. Hosts can add their own.
+    pub crates: Vec<(&'static str, &'static str)>,
+
+    /// Enabled Cargo features.
+    pub features: Vec<&'static str>,
+
+    /// Libraries used to talk to the host, like the windowing library.
+    pub backends: Vec<&'static str>,
+
+    /// Device pages which `VarvaraDevice` implements, by their first port. This is empty if the
+    /// `varvara` feature is disabled.
+    pub device_pages: Vec<(u8, &'static str)>,
+}
+
+/// Describes this build of the emulator.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        crates: vec![(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))],
+        features: [("varvara", cfg!(feature = "varvara")), ("window", cfg!(feature = "window")), ("midi", cfg!(feature = "midi"))]
+            .into_iter()
+            .filter_map(|(feature, enabled)| enabled.then_some(feature))
+            .collect(),
+        backends: if cfg!(feature = "window") { vec!["minifb"] } else { vec![] },
+        device_pages: if cfg!(feature = "varvara") {
+            vec![
+                (0x00, "system"),
+                (0x10, "console"),
+                (0x20, "screen"),
+                (0x80, "controller"),
+                (0xe0, "environment"),
+            ]
+        } else {
+            vec![]
+        },
+    }
+}
+
+impl BuildInfo {
+    /// Formats this as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let strings = |items: &[&str]| items.iter()
+            .map(|item| json_string(item))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let crates = self.crates.iter()
+            .map(|(name, version)| format!("{}:{}", json_string(name), json_string(version)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let device_pages = self.device_pages.iter()
+            .map(|(page, name)| format!("{}:{}", json_string(&format!("{page:#04x}")), json_string(name)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        format!(
+            r#"{{"crates":{{{crates}}},"features":[{}],"backends":[{}],"device_pages":{{{device_pages}}}}}"#,
+            strings(&self.features), strings(&self.backends),
+        )
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::BuildInfo;
+
+    #[test]
+    fn test_json() {
+        let info = BuildInfo {
+            crates: vec![("uxn", "1.0"), ("quo\"te", "\n")],
+            features: vec![],
+            backends: vec!["minifb"],
+            device_pages: vec![(0x00, "system"), (0x10, "console")],
+        };
+        assert_eq!(
+            info.to_json(),
+            r#"{"crates":{"uxn":"1.0","quo\"te":"\u000a"},"features":[],"backends":["minifb"],"device_pages":{"0x00":"system","0x10":"console"}}"#,
+        );
+    }
+}
@@ -42,8 +42,8 @@ pub trait Memory {
 
     fn write_memory(&mut self, addr: Self::AddressSpace, item: Item) {
         match item {
-            Item::Byte(byte) => self.write_byte(addr, byte as u8),
-            Item::Short(short) => self.write_short(addr, short as u16),
+            Item::Byte(byte) => self.write_byte(addr, byte),
+            Item::Short(short) => self.write_short(addr, short),
         }
     }
 }
@@ -80,7 +80,9 @@ impl Item {
 
     pub fn shift(self, left: u8, right: u8) -> Item {
         all_sizes!(self in n => {
-            n.unbounded_shr(right as u32).unbounded_shl(left as u32)
+            // Shifting by the width or more clears every bit
+            let n = n.checked_shr(right as u32).unwrap_or(0);
+            n.checked_shl(left as u32).unwrap_or(0)
         })
     }
 }
@@ -0,0 +1,232 @@
+//! Runs opcode test ROMs, like the ones from the uxn community, and reads their reports so that
+//! each opcode can be checked by its own assertion.
+//!
+//! These ROMs run every opcode against known answers, and print a line for each one to the
+//! console, like `ADD2k: ok` or `SFT fail`. Only the console and the `.System/state` port to exit
+//! are provided, so ROMs which need more than that won't get far.
+//!
+//! For checking individual programs instead, there's [`uxn_test!`](crate::uxn_test).
+
+use std::sync::{Arc, Mutex};
+
+use crate::{device::{Device, DeviceContext, DeviceEvent}, Core, RunResult, UxnError};
+
+/// Whether one opcode passed, according to an opcode test ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpcodeTestResult {
+    pub name: String,
+    pub passed: bool,
+}
+
+/// What an opcode test ROM reported, from [`run_opcode_test`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpcodeTestReport {
+    /// Each opcode the ROM reported on, in the order it reported them.
+    pub results: Vec<OpcodeTestResult>,
+
+    /// Everything the ROM wrote to the console.
+    pub output: String,
+
+    /// How the ROM stopped.
+    pub result: RunResult,
+}
+
+impl OpcodeTestReport {
+    /// Parses a report from what an opcode test ROM wrote to the console.
+    ///
+    /// Each result is a name followed by a word saying how it went, like `ok`, `pass`, `fail` or
+    /// `error`. Colons and commas around them are ignored, so both `ADD: ok` and `ADD ok, SUB ok`
+    /// work. Anything else is ignored.
+    pub fn parse(output: &str, result: RunResult) -> Self {
+        let mut results = vec![];
+        for line in output.lines() {
+            let words: Vec<_> = line.split_whitespace()
+                .map(|word| word.trim_matches(|c: char| matches!(c, ':' | ',' | '.' | '!')))
+                .filter(|word| !word.is_empty())
+                .collect();
+
+            for pair in words.windows(2) {
+                let passed = match pair[1].to_lowercase().as_str() {
+                    "ok" | "pass" | "passed" => true,
+                    "fail" | "failed" | "err" | "error" => false,
+                    _ => continue,
+                };
+                results.push(OpcodeTestResult { name: pair[0].to_string(), passed });
+            }
+        }
+        Self { results, output: output.to_string(), result }
+    }
+
+    /// The result for an opcode, if the ROM reported on it.
+    pub fn result(&self, name: &str) -> Option<&OpcodeTestResult> {
+        self.results.iter().find(|result| result.name == name)
+    }
+
+    /// Panics, with the ROM's output, unless the ROM reported that an opcode passed.
+    #[track_caller]
+    pub fn assert_passed(&self, name: &str) {
+        match self.result(name) {
+            Some(result) if result.passed => {},
+            Some(_) => panic!("opcode test for {name} failed\n{}", self.output),
+            None => panic!("opcode test ROM didn't report on {name}\n{}", self.output),
+        }
+    }
+
+    /// Panics, listing the failures, unless every result passed and the ROM finished.
+    #[track_caller]
+    pub fn assert_all_passed(&self) {
+        let failures: Vec<_> = self.results.iter().filter(|result| !result.passed).map(|result| result.name.as_str()).collect();
+        assert!(failures.is_empty(), "opcode tests failed: {}\n{}", failures.join(", "), self.output);
+        assert!(!self.results.is_empty(), "opcode test ROM didn't report anything\n{}", self.output);
+        assert_ne!(self.result, RunResult::BudgetExceeded, "opcode test ROM didn't finish\n{}", self.output);
+    }
+}
+
+/// Assembles a uxntal program, runs it until it BRKs, and checks what's left on the stacks, bottom
+/// first. If only the working stack is given, the return stack must be empty.
+///
+/// ```
+/// # use uxn_core_emulator::uxn_test;
+/// uxn_test!("#01 #02 ADD BRK" => [0x03]);
+/// uxn_test!("#12 STH #34 BRK" => [0x34], [0x12]);
+/// ```
+#[macro_export]
+macro_rules! uxn_test {
+    ($code:expr => $working:expr) => {
+        $crate::check_uxntal_stacks($code, &$working, &[])
+    };
+    ($code:expr => $working:expr, $return:expr) => {
+        $crate::check_uxntal_stacks($code, &$working, &$return)
+    };
+}
+
+/// The instructions a program given to [`uxn_test!`](crate::uxn_test) can run before it fails.
+const UXN_TEST_FUEL: u64 = 1_000_000;
+
+/// Used by [`uxn_test!`](crate::uxn_test).
+#[doc(hidden)]
+#[track_caller]
+pub fn check_uxntal_stacks(code: &str, working: &[u8], ret: &[u8]) {
+    let mut core = Core::new_with_uxntal(code).unwrap_or_else(|e| panic!("could not assemble {code:?}: {e}"));
+    core.vector_budget = Some(UXN_TEST_FUEL);
+    match core.execute_until_break() {
+        Ok(RunResult::Break) => {},
+        Ok(result) => panic!("{code:?} stopped without reaching BRK: {result:?}"),
+        Err(e) => panic!("{code:?} faulted: {e}"),
+    }
+
+    let actual = (core.working_stack.bytes(), core.return_stack.bytes());
+    assert!(
+        actual == (working, ret),
+        "stacks after {code:?}\n  working: {:02x?}, expected {working:02x?}\n  return:  {:02x?}, expected {ret:02x?}",
+        actual.0, actual.1,
+    );
+}
+
+/// Just enough of Varvara for test programs: the stack pointers, exiting through `.System/state`,
+/// and console output, which is collected in `output`. This is shared by everything in the crate
+/// which runs whole test ROMs without a window.
+pub(crate) struct TestConsoleDevice {
+    output: Arc<Mutex<Vec<u8>>>,
+    exit_code: Option<u8>,
+}
+
+impl TestConsoleDevice {
+    pub fn new(output: Arc<Mutex<Vec<u8>>>) -> Self {
+        Self { output, exit_code: None }
+    }
+}
+
+impl Device for TestConsoleDevice {
+    fn dei(&mut self, port: u8, context: DeviceContext) -> u8 {
+        match port {
+            0x04 => context.working_stack.pointer,
+            0x05 => context.return_stack.pointer,
+            _ => 0,
+        }
+    }
+
+    fn deo(&mut self, port: u8, value: u8, _: DeviceContext) {
+        match port {
+            // .System/state
+            0x0f if value != 0 => self.exit_code = Some(value & 0x7f),
+
+            // .Console/write and .Console/error
+            0x18 | 0x19 => self.output.lock().unwrap().push(value),
+
+            _ => {},
+        }
+    }
+
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        DeviceEvent::Exit(self.exit_code.unwrap_or(0))
+    }
+
+    fn is_halted(&self) -> bool {
+        self.exit_code.is_some()
+    }
+}
+
+/// Runs an opcode test ROM without a window, for at most `fuel` instructions, and parses what it
+/// reports with [`OpcodeTestReport::parse`].
+///
+/// Returns an error if the ROM faults.
+pub fn run_opcode_test(rom: &[u8], fuel: u64) -> Result<OpcodeTestReport, UxnError> {
+    let output = Arc::new(Mutex::new(vec![]));
+    let mut core = Core::new_with_rom(rom);
+    core.set_device(TestConsoleDevice::new(output.clone()));
+    core.vector_budget = Some(fuel);
+
+    let result = core.execute_until_exit()?;
+    let output = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+    Ok(OpcodeTestReport::parse(&output, result))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use uxn_utils::assemble_uxntal;
+
+    use crate::RunResult;
+
+    use super::{run_opcode_test, OpcodeTestReport};
+
+    /// A tiny opcode test ROM, in the same style as the community ones.
+    const OPCODE_TEST: &str = r#"
+        |100
+            ;add print #01 #02 ADD #03 EQU check
+            ;sft print #01 #10 SFT #02 EQU check
+            ;sub print #03 #01 SUB #03 EQU check
+            #80 #0f DEO BRK
+        @check ( passed -- ) ?&passed ;fail !print &passed ;ok !print
+        @print ( str* -- ) LDAk #18 DEO INC2 LDAk ?print POP2 JMP2r
+        @add "ADD: 00 @sft "SFT: 00 @sub "SUB: 00
+        @ok 20 "ok 0a 00 @fail 20 "fail 0a 00
+    "#;
+
+    #[test]
+    fn test_run_opcode_test() {
+        let report = run_opcode_test(&assemble_uxntal(OPCODE_TEST).unwrap(), 10_000).unwrap();
+        assert_eq!(report.output, "ADD: ok\nSFT: ok\nSUB: fail\n");
+        assert_eq!(report.result, RunResult::Exit(0));
+        report.assert_passed("ADD");
+        report.assert_passed("SFT");
+        assert!(!report.result("SUB").unwrap().passed);
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let report = OpcodeTestReport::parse("Opcode tests\nADD ok, SUB2k: FAIL\nINC passed.\n", RunResult::Break);
+        let results: Vec<_> = report.results.iter().map(|result| (result.name.as_str(), result.passed)).collect();
+        assert_eq!(results, [("ADD", true), ("SUB2k", false), ("INC", true)]);
+    }
+
+    /// Runs a community opcode test ROM, like `opctest.rom`, if `UXN_OPCODE_TEST_ROM` points to one.
+    #[test]
+    fn test_community_opcode_test() {
+        let Ok(path) = env::var("UXN_OPCODE_TEST_ROM") else { return };
+        let rom = fs::read(&path).expect("could not read opcode test ROM");
+        run_opcode_test(&rom, 10_000_000).unwrap().assert_all_passed();
+    }
+}
@@ -0,0 +1,136 @@
+use uxn_utils::assemble_uxntal;
+
+use crate::{device::{Device, DeviceBus}, UxnError};
+
+use super::{Core, Symbols};
+
+/// Sets up a [`Core`] step by step, in whichever order is convenient, like giving it a device
+/// before the uxntal to run. Create one with [`Core::builder`].
+///
+/// Anything which isn't set is the same as with [`Core::new`].
+#[derive(Default)]
+pub struct CoreBuilder {
+    program: Option<Program>,
+    device: Option<DeviceBus>,
+    program_counter: Option<u16>,
+    strict_stacks: bool,
+    vector_budget: Option<u64>,
+    symbols: Option<Symbols>,
+}
+
+enum Program {
+    Rom(Vec<u8>),
+    Uxntal(String),
+}
+
+impl CoreBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a ROM. This replaces any ROM or uxntal given before.
+    pub fn rom(mut self, rom: &[u8]) -> Self {
+        self.program = Some(Program::Rom(rom.to_vec()));
+        self
+    }
+
+    /// Assembles uxntal source and loads the result, when the core is built. This replaces any
+    /// ROM or uxntal given before.
+    pub fn uxntal(mut self, code: &str) -> Self {
+        self.program = Some(Program::Uxntal(code.to_string()));
+        self
+    }
+
+    /// Uses one device for every page, like [`Core::set_device`].
+    pub fn device(mut self, device: impl Device + 'static) -> Self {
+        self.device.get_or_insert_with(DeviceBus::new).set_device(device);
+        self
+    }
+
+    /// Uses a device for a single page, like [`Core::register_device`]. Pages without a device
+    /// read as 0.
+    pub fn register_device(mut self, page: u8, device: impl Device + 'static) -> Self {
+        self.device.get_or_insert_with(DeviceBus::new).register_device(page, device);
+        self
+    }
+
+    /// Where execution starts, instead of the reset vector at 0x0100.
+    pub fn pc(mut self, address: u16) -> Self {
+        self.program_counter = Some(address);
+        self
+    }
+
+    /// Whether both stacks are in strict mode, like [`Core::set_strict_stacks`].
+    pub fn strict_stacks(mut self, strict: bool) -> Self {
+        self.strict_stacks = strict;
+        self
+    }
+
+    /// Sets [`Core::vector_budget`].
+    pub fn vector_budget(mut self, budget: u64) -> Self {
+        self.vector_budget = Some(budget);
+        self
+    }
+
+    /// Sets the labels used to describe addresses, like [`Core::set_symbols`].
+    pub fn symbols(mut self, symbols: Symbols) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Creates the core, or returns an error if the uxntal couldn't be assembled.
+    pub fn build(self) -> Result<Core, UxnError> {
+        let mut core = Core::new();
+        match self.program {
+            Some(Program::Rom(rom)) => core.load_rom(&rom),
+            Some(Program::Uxntal(code)) => core.load_rom(&assemble_uxntal(&code).map_err(UxnError::Assembly)?),
+            None => {},
+        }
+
+        if let Some(device) = self.device {
+            core.device = device;
+        }
+        if let Some(address) = self.program_counter {
+            core.program_counter = address;
+        }
+        core.set_strict_stacks(self.strict_stacks);
+        core.vector_budget = self.vector_budget;
+        if let Some(symbols) = self.symbols {
+            core.set_symbols(symbols);
+        }
+        Ok(core)
+    }
+}
+
+impl Core {
+    /// Starts setting up a core with a [`CoreBuilder`].
+    pub fn builder() -> CoreBuilder {
+        CoreBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{device::EmptyDevice, Core, RunResult, UxnError};
+
+    #[test]
+    fn test_builder() {
+        let mut core = Core::builder()
+            .device(EmptyDevice::new())
+            .uxntal("#2a #12 DEO BRK #12 DEI BRK")
+            .pc(0x0106)
+            .vector_budget(100)
+            .build()
+            .unwrap();
+        assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+
+        // The device was kept, but nothing was written to it since execution started after the DEO
+        assert_eq!(core.working_stack.bytes(), [0x00]);
+        assert_eq!(core.vector_budget, Some(100));
+
+        let mut core = Core::builder().rom(&[0x02]).strict_stacks(true).build().unwrap();
+        assert!(matches!(core.execute_until_break(), Err(UxnError::Stack { .. })));
+
+        assert!(matches!(Core::builder().uxntal("#12 FOO").build(), Err(UxnError::Assembly(_))));
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeSet;
+
+use crate::StackMode;
+
+use super::Core;
+
+/// Why the core stopped running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunResult {
+    /// The vector being run reached a BRK.
+    Break,
+
+    /// The device asked to exit, with this exit code.
+    Exit(u8),
+
+    /// The program counter reached a breakpoint at this address. The instruction there hasn't run
+    /// yet, and will run when execution is resumed.
+    Stopped(u16),
+
+    /// The vector ran as many instructions as it was allowed to without finishing.
+    BudgetExceeded,
+}
+
+/// The result of [`Core::execute_vector`] or [`Core::execute_instructions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VectorOutcome {
+    /// Why the vector stopped running.
+    pub result: RunResult,
+
+    /// How many instructions ran, including the final BRK if there was one.
+    pub instructions: u64,
+}
+
+/// Tracks breakpoints, and which one execution last stopped at so it can be resumed past.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    stopped_at: Option<u16>,
+}
+
+impl Debugger {
+    /// Called before each instruction. Returns whether execution should stop before running the
+    /// instruction at `program_counter`.
+    pub fn should_stop(&mut self, program_counter: u16) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+
+        // Don't stop again straight away when resuming from a breakpoint
+        let resuming = self.stopped_at.take() == Some(program_counter);
+        if !resuming && self.breakpoints.contains(&program_counter) {
+            self.stopped_at = Some(program_counter);
+            return true;
+        }
+        false
+    }
+
+    /// Forgets where execution last stopped, for when the program counter has been moved.
+    pub fn forget_stop(&mut self) {
+        self.stopped_at = None;
+    }
+}
+
+impl Core {
+    /// Makes execution stop with [`RunResult::Stopped`] whenever the program counter reaches the
+    /// given address, before running the instruction there.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.debugger.breakpoints.insert(addr);
+    }
+
+    /// Removes a breakpoint. Returns whether there was one at that address.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.debugger.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.breakpoints.clear();
+    }
+
+    /// The addresses of all breakpoints, in order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.debugger.breakpoints.iter().copied()
+    }
+
+    // Editing the machine while it's stopped, like to patch a constant or skip over faulty code.
+    // Rewinding works by re-running from a checkpoint, which wouldn't reproduce these edits, so
+    // they forget the rewind history.
+
+    /// Moves the program counter, so that execution resumes from somewhere else. If there's a
+    /// breakpoint at the new address, execution stops there straight away.
+    pub fn set_program_counter(&mut self, addr: u16) {
+        self.program_counter = addr;
+        self.debugger.forget_stop();
+        self.forget_rewind_history();
+    }
+
+    /// Writes bytes into bank 0, starting at an address and wrapping around at the end.
+    pub fn poke_memory(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.memory[addr.wrapping_add(i as u16) as usize] = *byte;
+        }
+        self.forget_rewind_history();
+    }
+
+    /// Replaces a byte on a stack, counting from 0 at the top. Returns false if the stack isn't
+    /// that deep.
+    pub fn poke_stack(&mut self, stack: StackMode, depth: u8, byte: u8) -> bool {
+        let stack = match stack {
+            StackMode::Working => &mut self.working_stack,
+            StackMode::Return => &mut self.return_stack,
+        };
+        if depth as usize >= stack.depth() {
+            return false;
+        }
+        stack.data[(stack.pointer - 1 - depth) as usize] = byte;
+        self.forget_rewind_history();
+        true
+    }
+
+    fn forget_rewind_history(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+}
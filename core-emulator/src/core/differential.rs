@@ -0,0 +1,146 @@
+// Runs small programs both here and in a reference emulator, `uxncli` from the original 100 Rabbits
+// implementation, and checks they do the same thing. This is skipped if `uxncli` isn't on the PATH.
+//
+// Each program runs from the reset vector, then falls through into an epilogue which prints both
+// stacks to the console, so comparing console output compares the final stacks too. If they
+// disagree, the program is cut down to the fewest instructions which still disagree.
+
+use std::{env, fs, process::Command, sync::{Arc, Mutex}};
+
+use uxn_utils::assemble_uxntal;
+
+use crate::{conformance::TestConsoleDevice, Core};
+
+/// The programs to compare. These shouldn't BRK, so that they reach the epilogue.
+const CORPUS: &[(&str, &str)] = &[
+    ("arithmetic", "#12 #34 ADD #50 #20 SUB #07 #06 MUL #64 #07 DIV"),
+    ("arithmetic-short", "#1234 #4321 ADD2 #0100 #00ff SUB2 #0102 #0003 MUL2 #ffff #0010 DIV2"),
+    ("divide-by-zero", "#12 #00 DIV #1234 #0000 DIV2"),
+    ("overflow", "#ff #01 ADD #00 #01 SUB #ffff INC2"),
+    ("logic", "#f0 #3c AND #f0 #0f ORA #ff #0f EOR #12 #12 EQU #12 #34 NEQ #01 #02 GTH #01 #02 LTH"),
+    ("shifts", "#01 #30 SFT #80 #07 SFT #0001 #f0 SFT2 #8000 #0f SFT2"),
+    ("stack", "#01 #02 #03 ROT SWP OVR NIP DUP POP #0102 #0304 #0506 ROT2 SWP2 OVR2"),
+    ("keep", "#01 #02 ADDk #0304 INC2k #05 DUPk POPk"),
+    ("return-stack", "#01 STH #0203 STH2 #04 STHk #05 #06 STHr"),
+    ("memory", "#2a ;&cell STA ;&cell LDA #abcd ;&cell STA2 ;&cell LDA2 #11 #00 STZ #00 LDZ ,&skip JMP &cell $2 &skip"),
+    ("relative", "#07 ,&cell STR ,&cell LDR ,&skip JMP &cell $1 &skip"),
+    ("loop", "#00 &loop INC DUP #05 LTH ?&loop"),
+    ("subroutine", "#02 double double !&done @double DUP ADD JMP2r &done"),
+    ("console", "LIT \"h #18 DEO LIT \"i #18 DEO #0a #18 DEO"),
+];
+
+/// Prints both stacks, top first, as hex on their own lines. Macros are used rather than
+/// subroutines so that the return stack is left alone while it's printed. As in the reference,
+/// `.System/wst` counts the port byte being read, so the working stack is empty once it reads 1.
+const EPILOGUE: &str = "
+    LIT \"r #18 DEO
+    &rst #05 DEI #00 EQU ?&rst-done #20 #18 DEO STHr emit-hex !&rst &rst-done
+    #0a #18 DEO
+    LIT \"w #18 DEO
+    &wst #04 DEI #01 EQU ?&wst-done #20 #18 DEO emit-hex !&wst &wst-done
+    #0a #18 DEO
+    BRK
+";
+
+const MACROS: &str = "
+    %emit-nibble { DUP #09 GTH #27 MUL ADD #30 ADD #18 DEO }
+    %emit-hex { DUP #04 SFT emit-nibble #0f AND emit-nibble }
+";
+
+/// How many instructions a program can run here, so that one which loops forever still finishes.
+const FUEL: u64 = 100_000;
+
+fn assemble(program: &str) -> Option<Vec<u8>> {
+    assemble_uxntal(&format!("{MACROS} |100 {program} @epilogue {EPILOGUE}")).ok()
+}
+
+fn run_here(rom: &[u8]) -> String {
+    let output = Arc::new(Mutex::new(vec![]));
+    let mut core = Core::new_with_rom(rom);
+    core.set_device(TestConsoleDevice::new(output.clone()));
+    if let Err(e) = core.execute_vector(0x0100, Some(FUEL)) {
+        output.lock().unwrap().extend(format!("error: {e}").bytes());
+    }
+    String::from_utf8_lossy(&output.lock().unwrap()).into_owned()
+}
+
+/// Runs a ROM with `uxncli`, or returns `None` if it can't be run.
+fn run_reference(rom: &[u8]) -> Option<String> {
+    let path = env::temp_dir().join(format!("uxn-differential-{}.rom", std::process::id()));
+    fs::write(&path, rom).ok()?;
+    let output = Command::new("uxncli").arg(&path).output();
+    let _ = fs::remove_file(&path);
+    Some(String::from_utf8_lossy(&output.ok()?.stdout).into_owned())
+}
+
+/// Whether a program behaves differently here than with `reference`, giving both outputs if so.
+fn diverges(program: &str, reference: &impl Fn(&[u8]) -> Option<String>) -> Option<(String, String)> {
+    let rom = assemble(program)?;
+    let (ours, theirs) = (run_here(&rom), reference(&rom)?);
+    (ours != theirs).then_some((ours, theirs))
+}
+
+/// Removes words from a diverging program one at a time, for as long as it still diverges.
+fn minimize(program: &str, reference: &impl Fn(&[u8]) -> Option<String>) -> String {
+    let mut words: Vec<&str> = program.split_whitespace().collect();
+
+    // Removing one word can make another removable, so keep going until nothing more can go
+    let mut removed_any = true;
+    while removed_any {
+        removed_any = false;
+        let mut i = 0;
+        while i < words.len() {
+            let mut shorter = words.clone();
+            shorter.remove(i);
+            if diverges(&shorter.join(" "), reference).is_some() {
+                words = shorter;
+                removed_any = true;
+            } else {
+                i += 1;
+            }
+        }
+    }
+    words.join(" ")
+}
+
+/// Runs the corpus against `reference`, describing each program which diverges.
+fn compare_corpus(reference: &impl Fn(&[u8]) -> Option<String>) -> Vec<String> {
+    CORPUS.iter()
+        .filter_map(|(name, program)| {
+            let (ours, theirs) = diverges(program, reference)?;
+            Some(format!(
+                "{name} diverges\n  ours:   {ours:?}\n  theirs: {theirs:?}\n  repro:  {}",
+                minimize(program, reference),
+            ))
+        })
+        .collect()
+}
+
+#[test]
+fn test_against_uxncli() {
+    if run_reference(&assemble("").unwrap()).is_none() {
+        eprintln!("Skipping differential tests, since uxncli isn't on the PATH");
+        return;
+    }
+
+    let divergences = compare_corpus(&run_reference);
+    assert!(divergences.is_empty(), "{}", divergences.join("\n"));
+}
+
+#[test]
+fn test_differential_harness() {
+    assert_eq!(run_here(&assemble("#01 #0203 #04 STH").unwrap()), "r 04\nw 03 02 01\n");
+    assert_eq!(run_here(&assemble("LIT \"a #18 DEO").unwrap()), "ar\nw\n");
+    assert_eq!(run_here(&assemble("#04 DEI").unwrap()), "r\nw 01\n");
+
+    // A reference which runs EOR as ORA only disagrees on the logic, which cuts down to an EOR of
+    // two equal bytes
+    let broken = |rom: &[u8]| {
+        let rom: Vec<u8> = rom.iter().map(|byte| if *byte == 0x1e { 0x1d } else { *byte }).collect();
+        Some(run_here(&rom))
+    };
+    let divergences = compare_corpus(&broken);
+    assert_eq!(divergences.len(), 1, "{divergences:?}");
+    assert!(divergences[0].starts_with("logic diverges"));
+    assert!(divergences[0].ends_with("repro:  #0f #0f EOR"), "{}", divergences[0]);
+}
@@ -1,8 +1,10 @@
-use std::process::exit;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 
-use crate::{common::{Item, ItemSize, StackMode}, device::DeviceEvent, stack::{AccessMode, Stack}, Memory};
+use crate::{common::{Item, ItemSize, StackMode}, device::{Device, DeviceContext, DeviceEvent}, stack::{AccessMode, Stack}, Instruction, Memory, Opcode, UxnError};
 
-use super::Core;
+use super::{Core, HistoryEntry, RunResult, VectorOutcome};
 
 pub enum ExecutionResult {
     Continue,
@@ -10,130 +12,222 @@ pub enum ExecutionResult {
 }
 
 impl Core {
-    pub fn execute_until_exit(&mut self) {
-        loop {
-            self.execute_until_break();
+    /// Runs the ROM, invoking vectors as the device requests them, until the device asks to exit,
+    /// a breakpoint is reached, or a vector exceeds [`Core::vector_budget`].
+    ///
+    /// After stopping early, calling this again resumes where execution left off.
+    ///
+    /// Returns an error if the ROM faults, like by using a device port which isn't implemented.
+    pub fn execute_until_exit(&mut self) -> Result<RunResult, UxnError> {
+        match self.execute_until_break()? {
+            RunResult::Break => self.handle_events_until_exit(),
+            stopped => Ok(stopped),
+        }
+    }
 
+    /// Like [`Core::execute_until_exit`], but without running from the program counter first. This
+    /// is for when the reset vector has already run, like after restoring a snapshot.
+    pub fn handle_events_until_exit(&mut self) -> Result<RunResult, UxnError> {
+        loop {
             match self.device.wait_for_event() {
-                DeviceEvent::Vector(vector) => self.program_counter = vector,
-                DeviceEvent::Exit => return,
+                DeviceEvent::Vector(vector) => match self.run_vector(vector)? {
+                    RunResult::Break => {},
+                    stopped => return Ok(stopped),
+                },
+                DeviceEvent::Exit(code) => return Ok(RunResult::Exit(code)),
             }
         }
     }
 
-    pub fn execute_until_break(&mut self) {
+    /// Handles at most one event without blocking, for hosts which run their own main loop. If the
+    /// device asks for a vector, it's run until it breaks or reaches a breakpoint. Returns `None`
+    /// if there was nothing to do.
+    ///
+    /// The reset vector should be run with [`Core::execute_until_break`] first. If a vector stopped
+    /// at a breakpoint, that should also be used to finish running it.
+    pub fn execute_pending(&mut self) -> Result<Option<RunResult>, UxnError> {
+        let Some(event) = self.device.poll_event() else { return Ok(None) };
+        match event {
+            DeviceEvent::Vector(vector) => self.run_vector(vector).map(Some),
+            DeviceEvent::Exit(code) => Ok(Some(RunResult::Exit(code))),
+        }
+    }
+
+    /// Runs a vector until its first BRK, even if that's inside a subroutine.
+    ///
+    /// Vectors should leave the return stack as they found it. Any which don't are remembered in
+    /// [`Core::unbalanced_vectors`], since it usually means a subroutine has a BRK where it should
+    /// have a JMP2r.
+    fn run_vector(&mut self, vector: u16) -> Result<RunResult, UxnError> {
+        let return_pointer = self.return_stack.pointer;
+
+        let result = self.execute_vector(vector, self.vector_budget)?.result;
+
+        // A vector which stopped part-way through can't be judged yet
+        let difference = self.return_stack.pointer.wrapping_sub(return_pointer) as i8;
+        if result == RunResult::Break && difference != 0
+            && self.unbalanced_vectors.insert(vector) && self.warn_unbalanced_vectors
+        {
+            crate::warning!("Vector {vector:#06x} changed the return stack by {difference:+} bytes");
+        }
+
+        Ok(result)
+    }
+
+    /// Runs a vector until it reaches a BRK or a breakpoint, or until it has run `fuel`
+    /// instructions, in which case the result is [`RunResult::BudgetExceeded`]. This lets a host
+    /// share its time between many cores.
+    ///
+    /// To carry on after running out of fuel, call this again with the program counter as the
+    /// vector.
+    pub fn execute_vector(&mut self, vector: u16, fuel: Option<u64>) -> Result<VectorOutcome, UxnError> {
+        self.stats.vectors += 1;
+        self.program_counter = vector;
+        self.execute_with_fuel(fuel)
+    }
+
+    /// Runs at most `count` instructions from the program counter, stopping early at a BRK or a
+    /// breakpoint. The outcome's result is [`RunResult::BudgetExceeded`] if all of them ran without
+    /// finishing, and calling this again carries on from there.
+    ///
+    /// This is for hosts which run a slice of emulation per frame, and for stepping in debuggers.
+    pub fn execute_instructions(&mut self, count: u64) -> Result<VectorOutcome, UxnError> {
+        self.execute_with_fuel(Some(count))
+    }
+
+    /// Runs instructions from the program counter until a BRK, until a breakpoint is reached, or
+    /// until [`Core::vector_budget`] is used up.
+    pub fn execute_until_break(&mut self) -> Result<RunResult, UxnError> {
+        Ok(self.execute_with_fuel(self.vector_budget)?.result)
+    }
+
+    fn execute_with_fuel(&mut self, fuel: Option<u64>) -> Result<VectorOutcome, UxnError> {
+        // There's no clock on wasm32-unknown-unknown, so busy time isn't measured there
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = Instant::now();
+        let mut instructions = 0;
+        let mut paused_time = Duration::ZERO;
+        let result = self.execute_with_fuel_untimed(fuel, &mut instructions, &mut paused_time);
+
+        self.stats.instructions += instructions;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.stats.busy_time += start.elapsed().saturating_sub(paused_time);
+        }
+        Ok(VectorOutcome { result: result?, instructions })
+    }
+
+    fn execute_with_fuel_untimed(&mut self, fuel: Option<u64>, instructions: &mut u64, paused_time: &mut Duration) -> Result<RunResult, UxnError> {
+        self.rewind_start_run();
+
         loop {
-            let ins = self.memory[self.program_counter as usize];
-            self.program_counter = self.program_counter.overflowing_add(1).0;
+            *paused_time += self.pause.wait_while_paused();
 
-            match self.execute_one_instruction(ins) {
+            if self.debugger.should_stop(self.program_counter) {
+                return Ok(RunResult::Stopped(self.program_counter));
+            }
+            if fuel.is_some_and(|fuel| *instructions >= fuel) {
+                return Ok(RunResult::BudgetExceeded);
+            }
+            *instructions += 1;
+
+            match self.step_instruction()? {
                 ExecutionResult::Continue => {},
-                ExecutionResult::Break => return,
+                ExecutionResult::Break => return Ok(RunResult::Break),
             }
         }
     }
 
-    pub fn execute_one_instruction(&mut self, ins: u8) -> ExecutionResult {
-        //
-        //   .- Don't pop any operands
-        //   |.- Operate on the return stack
-        //   ||.- Operate on shorts instead of bytes
-        //   |||.---. Opcode
-        // 0b11111111
-        //
-        let keep = ins & 0x80;
-        let use_return_stack = ins & 0x40;
-        let use_short = ins & 0x20;
-        let opcode = ins & 0x1F;
-
-        let stack = if use_return_stack > 0 { StackMode::Return } else { StackMode::Working };
-        let item_size = if use_short > 0 { ItemSize::Short } else { ItemSize::Byte };
-        let mode = if keep > 0 { AccessMode::Keep } else { AccessMode::Pop };
+    /// Runs the instruction at the program counter, keeping all of the records about it.
+    pub(super) fn step_instruction(&mut self) -> Result<ExecutionResult, UxnError> {
+        self.rewind_before_instruction();
+
+        let program_counter = self.program_counter;
+        let ins = self.memory[program_counter as usize];
+        self.history.record(HistoryEntry {
+            program_counter,
+            opcode: ins,
+            working_stack_pointer: self.working_stack.pointer,
+            return_stack_pointer: self.return_stack.pointer,
+        });
+        self.run_before_instruction_hooks(program_counter, Instruction::decode(ins));
+        self.program_counter = self.program_counter.overflowing_add(1).0;
+
+        let result = self.execute_one_instruction(ins)
+            .and_then(|result| self.check_stacks(program_counter).map(|()| result));
+        let result = match result {
+            Ok(result) => result,
+            Err(fault) => self.handle_fault(fault, program_counter, ins)?,
+        };
+        self.record_trace(program_counter, ins);
+        self.rewind_after_instruction();
+        self.run_after_instruction_hooks(program_counter, Instruction::decode(ins));
+        Ok(result)
+    }
+
+    /// Runs a single instruction, which has already been read from the program counter.
+    ///
+    /// Returns an error if the instruction faults, like by using a device port which isn't
+    /// implemented.
+    pub fn execute_one_instruction(&mut self, ins: u8) -> Result<ExecutionResult, UxnError> {
+        let instruction = Instruction::decode(ins);
+        let stack = if instruction.ret { StackMode::Return } else { StackMode::Working };
+        let item_size = if instruction.short { ItemSize::Short } else { ItemSize::Byte };
+        let mode = if instruction.keep { AccessMode::Keep } else { AccessMode::Pop };
 
         // Create an operand accessor, ready for instructions which need one
         let op = self.target_stack(stack).take_operands(mode, item_size);
 
-        match opcode {
-            // BRK
-            0x00 => {
-                use StackMode::*;
-                use ItemSize::*;
-                use AccessMode::*;
-
-                // This instruction is drastically different depending on the modes.
-                match (stack, item_size, mode) {
-                    // BRK
-                    (Working, Byte, Pop)  => return ExecutionResult::Break,
-
-                    // JCI
-                    (Working, Short, Pop) => {
-                        let (cond,) = op.byte().done();
-
-                        let rel = self.read_short(self.program_counter);
-                        self.program_counter = self.program_counter.overflowing_add(2).0;
-
-                        if cond != 0 {
-                            self.program_counter = self.program_counter.overflowing_add(rel).0;
-                        }
-                    }
-
-                    // JMI
-                    (Return, Byte, Pop) => {
-                        let rel = self.read_short(self.program_counter);
-                        self.program_counter = self.program_counter.overflowing_add(2).0;
-                        self.program_counter = self.program_counter.overflowing_add(rel).0;
-                    }
-
-                    // JSI
-                    (Return, Short, Pop) => {
-                        self.return_stack.push_short(self.program_counter.overflowing_add(2).0);
-                        
-                        let rel = self.read_short(self.program_counter);
-                        self.program_counter = self.program_counter.overflowing_add(2).0;
-                        self.program_counter = self.program_counter.overflowing_add(rel).0;
-                    }
-
-                    // LIT
-                    (_, Byte, Keep) => {
-                        let byte = self.memory[self.program_counter as usize];
-                        self.program_counter = self.program_counter.overflowing_add(1).0;
-
-                        self.target_stack(stack).push_byte(byte);
-                    }
-
-                    // LIT2
-                    (_, Short, Keep) => {
-                        let bytes = [
-                            self.memory[self.program_counter as usize],
-                            self.memory[self.program_counter.overflowing_add(1).0 as usize],
-                        ];
-                        self.program_counter = self.program_counter.overflowing_add(2).0;
-
-                        let short = u16::from_be_bytes(bytes);
-                        self.target_stack(stack).push_short(short);
-                    }
+        match instruction.opcode {
+            Opcode::Brk => return Ok(ExecutionResult::Break),
+
+            Opcode::Jci => {
+                let (cond,) = op.byte().done();
+
+                let rel = self.read_short(self.program_counter);
+                self.program_counter = self.program_counter.overflowing_add(2).0;
+
+                if cond != 0 {
+                    self.program_counter = self.program_counter.overflowing_add(rel).0;
                 }
-            }
+            },
+
+            Opcode::Jmi => {
+                let rel = self.read_short(self.program_counter);
+                self.program_counter = self.program_counter.overflowing_add(2).0;
+                self.program_counter = self.program_counter.overflowing_add(rel).0;
+            },
+
+            Opcode::Jsi => {
+                self.return_stack.push_short(self.program_counter.overflowing_add(2).0);
 
-            // INC
-            0x01 => {
+                let rel = self.read_short(self.program_counter);
+                self.program_counter = self.program_counter.overflowing_add(2).0;
+                self.program_counter = self.program_counter.overflowing_add(rel).0;
+            },
+
+            Opcode::Lit => {
+                let item = self.read_memory(self.program_counter, item_size);
+                self.program_counter = self.program_counter.overflowing_add(if instruction.short { 2 } else { 1 }).0;
+
+                self.target_stack(stack).push_item(item);
+            },
+
+            Opcode::Inc => {
                 let (item,) = op.item().done();
                 self.target_stack(stack).push_item(item.increment());
             },
 
-            // POP
-            0x02 => {
+            Opcode::Pop => {
                 op.item().done();
             },
 
-            // NIP
-            0x03 => {
-                let (_, item) = op.item().then_item().done();
+            Opcode::Nip => {
+                let (item, _) = op.item().then_item().done();
                 self.target_stack(stack).push_item(item);
             },
 
-            // SWP
-            0x04 => {
+            Opcode::Swp => {
                 let (first, second) = op.item().then_item().done();
 
                 let stack = self.target_stack(stack);
@@ -141,8 +235,7 @@ impl Core {
                 stack.push_item(second);
             },
 
-            // ROT
-            0x05 => {
+            Opcode::Rot => {
                 let (c, b, a) = op.item().then_item().then_item().done();
 
                 let stack = self.target_stack(stack);
@@ -151,8 +244,7 @@ impl Core {
                 stack.push_item(a);
             },
 
-            // DUP
-            0x06 => {
+            Opcode::Dup => {
                 let (item,) = op.item().done();
 
                 let stack = self.target_stack(stack);
@@ -160,8 +252,7 @@ impl Core {
                 stack.push_item(item);
             },
 
-            // OVR
-            0x07 => {
+            Opcode::Ovr => {
                 let (b, a) = op.item().then_item().done();
 
                 let stack = self.target_stack(stack);
@@ -170,184 +261,219 @@ impl Core {
                 stack.push_item(a);
             },
 
-            // EQU
-            0x08 => {
+            Opcode::Equ => {
                 let (a, b) = op.item().then_item().done();
                 self.target_stack(stack).push_byte(if a == b { 1 } else { 0 });
             },
 
-            // NEQ
-            0x09 => {
+            Opcode::Neq => {
                 let (a, b) = op.item().then_item().done();
                 self.target_stack(stack).push_byte(if a != b { 1 } else { 0 });
             },
 
-            // GTH
-            0x0A => {
+            Opcode::Gth => {
                 let (a, b) = op.item().then_item().done();
                 self.target_stack(stack).push_byte(if b > a { 1 } else { 0 });
             },
 
-            // LTH
-            0x0B => {
+            Opcode::Lth => {
                 let (a, b) = op.item().then_item().done();
                 self.target_stack(stack).push_byte(if b < a { 1 } else { 0 });
             },
 
-            // JMP
-            0x0C => {
+            Opcode::Jmp => {
                 let (dest,) = op.item().done();
                 self.jump_to_dynamic_address(dest);
             },
 
-            // JCN
-            0x0D => {
+            Opcode::Jcn => {
                 let (dest, cond) = op.item().then_byte().done();
                 if cond != 0 {
                     self.jump_to_dynamic_address(dest);
                 }
             },
 
-            // JSR
-            0x0E => {
+            Opcode::Jsr => {
                 let (dest,) = op.item().done();
                 self.return_stack.push_short(self.program_counter);
                 self.jump_to_dynamic_address(dest);
             },
 
-            // STH
-            0x0F => {
+            Opcode::Sth => {
                 let (item,) = op.item().done();
                 self.other_stack(stack).push_item(item);
             },
 
-            // LDZ
-            0x10 => {
+            Opcode::Ldz => {
                 let (addr,) = op.byte().done(); 
                 let item = self.read_memory(addr as u16, item_size);
                 self.target_stack(stack).push_item(item);
             },
 
-            // STZ
-            0x11 => {
+            Opcode::Stz => {
                 let (addr, item) = op.byte().then_item().done();
                 self.write_memory(addr as u16, item);
             },
 
-            // LDR
-            0x12 => {
+            Opcode::Ldr => {
                 let (addr,) = op.byte().done();
                 let abs_addr = self.program_counter.overflowing_add(((addr as i8) as i16) as u16).0;
-                let item = self.read_memory(abs_addr as u16, item_size);
+                let item = self.read_memory(abs_addr, item_size);
                 self.target_stack(stack).push_item(item);
             },
 
-            // STR
-            0x13 => {
+            Opcode::Str => {
                 let (addr, item) = op.byte().then_item().done();
                 let abs_addr = self.program_counter.overflowing_add(((addr as i8) as i16) as u16).0;
-                self.write_memory(abs_addr as u16, item);
+                self.write_memory(abs_addr, item);
             },
 
-            // LDA
-            0x14 => {
+            Opcode::Lda => {
                 let (addr,) = op.short().done();
-                let item = self.read_memory(addr as u16, item_size);
+                let item = self.read_memory(addr, item_size);
                 self.target_stack(stack).push_item(item);
             },
 
-            // STA
-            0x15 => {
+            Opcode::Sta => {
                 let (addr, item) = op.short().then_item().done();
-                self.write_memory(addr as u16, item);
+                self.write_memory(addr, item);
             },
 
-            // DEI
-            0x16 => {
+            Opcode::Dei => {
                 let (addr,) = op.byte().done();
-                let item = self.device.read_memory(addr as u8, item_size);
+                self.stats.device_inputs += 1;
+
+                // As in the reference implementation, the port is still on the stack while the
+                // device reads, so that `.System/wst` and `.System/rst` count it
+                let popped = if instruction.keep { 0 } else { 1 };
+                self.target_stack(stack).pointer = self.target_stack(stack).pointer.wrapping_add(popped);
+                let item = self.rewind_input(|core| match item_size {
+                    ItemSize::Byte => Item::Byte(core.with_device_context(|device, context| device.dei(addr, context))),
+                    ItemSize::Short => Item::Short(core.with_device_context(|device, context| device.dei2(addr, context))),
+                });
+                self.target_stack(stack).pointer = self.target_stack(stack).pointer.wrapping_sub(popped);
+                if let Some(fault) = self.device.take_fault() {
+                    return Err(fault);
+                }
+                self.run_dei_hooks(addr, item);
                 self.target_stack(stack).push_item(item);
             }
 
-            // DEO
-            0x17 => {
+            Opcode::Deo => {
                 let (addr, value) = op.byte().then_item().done();
-                self.device.write_memory(addr as u8, value);
+                self.stats.device_outputs += 1;
+                self.run_deo_hooks(addr, value);
+                match value {
+                    Item::Byte(byte) => self.with_device_context(|device, context| device.deo(addr, byte, context)),
+                    Item::Short(short) => self.with_device_context(|device, context| device.deo2(addr, short, context)),
+                }
+
+                if let Some(fault) = self.device.take_fault() {
+                    return Err(fault);
+                }
+                if self.device.is_halted() {
+                    return Ok(ExecutionResult::Break);
+                }
             },
 
-            // ADD
-            0x18 => {
+            Opcode::Add => {
                 let (b, a) = op.item().then_item().done();
                 self.target_stack(stack).push_item(a + b);
             },
 
-            // SUB
-            0x19 => {
+            Opcode::Sub => {
                 let (b, a) = op.item().then_item().done();
                 self.target_stack(stack).push_item(a - b);
             },
 
-            // MUL
-            0x1A => {
+            Opcode::Mul => {
                 let (b, a) = op.item().then_item().done();
                 self.target_stack(stack).push_item(a * b);
             },
 
-            // DIV
-            0x1B => {
+            Opcode::Div => {
                 let (b, a) = op.item().then_item().done();
                 self.target_stack(stack).push_item(a / b);
             },
 
-            // AND
-            0x1C => {
+            Opcode::And => {
                 let (b, a) = op.item().then_item().done();
                 self.target_stack(stack).push_item(a & b);
             },
 
-            // ORA
-            0x1D => {
+            Opcode::Ora => {
                 let (b, a) = op.item().then_item().done();
                 self.target_stack(stack).push_item(a | b);
             },
 
-            // EOR
-            0x1E => {
+            Opcode::Eor => {
                 let (b, a) = op.item().then_item().done();
                 self.target_stack(stack).push_item(a ^ b);
             },
 
-            // SFT
-            0x1F => {
+            Opcode::Sft => {
                 let (shift, a) = op.byte().then_item().done();
 
-                let shift_left = (0xF0 & (shift as u8)) >> 4;
-                let shift_right = 0x0F & (shift as u8);
+                let shift_left = (0xF0 & shift) >> 4;
+                let shift_right = 0x0F & shift;
 
                 let item = a.shift(shift_left, shift_right);
                 self.target_stack(stack).push_item(item);
             },
+        }
 
-            _ => unreachable!(),
+        Ok(ExecutionResult::Continue)
+    }
+
+    /// Reports a fault from either stack, for an instruction at the given address.
+    fn check_stacks(&mut self, program_counter: u16) -> Result<(), UxnError> {
+        for stack in [StackMode::Working, StackMode::Return] {
+            if let Some(fault) = self.target_stack(stack).take_fault() {
+                return Err(UxnError::Stack { stack, fault, program_counter });
+            }
         }
+        Ok(())
+    }
 
-        ExecutionResult::Continue
+    /// Runs the device's [fault vector](Device::fault_vector) in place of the current one, if it
+    /// has one and the ROM can handle this fault. Like uxn, the working stack is replaced with the
+    /// address and opcode of the faulting instruction, followed by the fault's error code.
+    fn handle_fault(&mut self, fault: UxnError, program_counter: u16, ins: u8) -> Result<ExecutionResult, UxnError> {
+        let (Some(code), Some(vector)) = (fault.code(), self.device.fault_vector()) else {
+            return Err(fault);
+        };
+
+        self.working_stack.clear();
+        self.working_stack.push_short(program_counter);
+        self.working_stack.push_byte(ins);
+        self.working_stack.push_byte(code);
+        self.program_counter = vector;
+        Ok(ExecutionResult::Continue)
     }
 
     fn jump_to_dynamic_address(&mut self, dest: Item) {
         match dest {
             Item::Byte(rel) => {
-                // Relative
+                // Relative, and the only place a value is signed, so that it can jump backwards
                 self.program_counter = self.program_counter
-                    .overflowing_add(rel as u16).0;
+                    .overflowing_add(rel as i8 as u16).0;
             },
             Item::Short(abs) => {
                 // Absolute
-                self.program_counter = abs as u16;
+                self.program_counter = abs;
             },
         }
     }
 
+    fn with_device_context<T>(&mut self, func: impl FnOnce(&mut dyn Device, DeviceContext) -> T) -> T {
+        let context = DeviceContext {
+            memory: &mut self.memory,
+            working_stack: &mut self.working_stack,
+            return_stack: &mut self.return_stack,
+        };
+        func(&mut self.device, context)
+    }
+
     fn target_stack(&mut self, stack: StackMode) -> &mut Stack {
         match stack {
             StackMode::Working => &mut self.working_stack,
@@ -0,0 +1,120 @@
+use crate::{mnemonic, Core, Symbols};
+
+/// How many instructions [`InstructionHistory`] remembers.
+pub const HISTORY_LENGTH: usize = 64;
+
+/// One executed instruction, with the stack pointers from just before it ran.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub program_counter: u16,
+    pub opcode: u8,
+    pub working_stack_pointer: u8,
+    pub return_stack_pointer: u8,
+}
+
+/// A ring buffer of the last [`HISTORY_LENGTH`] instructions executed, which is always recorded so
+/// that faults can be reported with some context.
+#[derive(Clone)]
+pub struct InstructionHistory {
+    entries: [HistoryEntry; HISTORY_LENGTH],
+    next: usize,
+    len: usize,
+}
+
+impl Default for InstructionHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstructionHistory {
+    pub fn new() -> Self {
+        Self {
+            entries: [HistoryEntry::default(); HISTORY_LENGTH],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn record(&mut self, entry: HistoryEntry) {
+        self.entries[self.next] = entry;
+        self.next = (self.next + 1) % HISTORY_LENGTH;
+        self.len = (self.len + 1).min(HISTORY_LENGTH);
+    }
+
+    /// The remembered instructions, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        let start = (self.next + HISTORY_LENGTH - self.len) % HISTORY_LENGTH;
+        (0..self.len).map(move |i| &self.entries[(start + i) % HISTORY_LENGTH])
+    }
+
+    /// Formats the history, one instruction per line, showing how each instruction changed the
+    /// stack pointers. The last instruction's change is measured against the given current stack
+    /// pointers. If symbols are given, each instruction's nearest label is shown too.
+    pub fn dump(&self, working_stack_pointer: u8, return_stack_pointer: u8, symbols: Option<&Symbols>) -> String {
+        let entries: Vec<_> = self.entries().collect();
+        let current = HistoryEntry { working_stack_pointer, return_stack_pointer, ..Default::default() };
+
+        let mut dump = String::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let after = entries.get(i + 1).copied().unwrap_or(&current);
+            let delta = |before: u8, after: u8| after.wrapping_sub(before) as i8;
+
+            dump.push_str(&format!(
+                "{:04x}  {:<7} wst {:+} rst {:+}",
+                entry.program_counter, mnemonic(entry.opcode),
+                delta(entry.working_stack_pointer, after.working_stack_pointer),
+                delta(entry.return_stack_pointer, after.return_stack_pointer),
+            ));
+            if let Some(label) = symbols.and_then(|symbols| symbols.locate(entry.program_counter)) {
+                dump.push_str(&format!("  {label}"));
+            }
+            dump.push('\n');
+        }
+        dump
+    }
+}
+
+impl Core {
+    /// Formats [`Core::history`] with the current stack pointers and symbols, as printed when
+    /// emulation faults.
+    pub fn dump_history(&self) -> String {
+        self.history.dump(self.working_stack.pointer, self.return_stack.pointer, self.symbols())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Symbols;
+
+    use super::{HistoryEntry, InstructionHistory, HISTORY_LENGTH};
+
+    fn entry(program_counter: u16, opcode: u8, working_stack_pointer: u8) -> HistoryEntry {
+        HistoryEntry { program_counter, opcode, working_stack_pointer, return_stack_pointer: 0 }
+    }
+
+    #[test]
+    fn test_history_wraps() {
+        let mut history = InstructionHistory::new();
+        for i in 0..HISTORY_LENGTH as u16 + 3 {
+            history.record(entry(i, 0x01, 0));
+        }
+
+        let counters: Vec<_> = history.entries().map(|e| e.program_counter).collect();
+        assert_eq!(counters.len(), HISTORY_LENGTH);
+        assert_eq!(counters[0], 3);
+        assert_eq!(*counters.last().unwrap(), HISTORY_LENGTH as u16 + 2);
+    }
+
+    #[test]
+    fn test_history_dump() {
+        let mut history = InstructionHistory::new();
+        history.record(entry(0x100, 0x80, 0));
+        history.record(entry(0x102, 0x02, 1));
+        assert_eq!(history.dump(0, 0, None), "0100  LIT     wst +1 rst +0\n0102  POP     wst -1 rst +0\n");
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0x102, "drop");
+        assert_eq!(history.dump(0, 0, Some(&symbols)), "0100  LIT     wst +1 rst +0\n0102  POP     wst -1 rst +0  drop\n");
+    }
+}
@@ -0,0 +1,129 @@
+use std::mem;
+
+use crate::{Instruction, Item};
+
+use super::Core;
+
+type InstructionHook = Box<dyn FnMut(&Core, u16, Instruction) + Send>;
+type PortHook = Box<dyn FnMut(&Core, u8, Item) + Send>;
+
+/// Callbacks which tools like tracers, profilers and coverage can register on a [`Core`]. When
+/// none are registered, the only cost is checking that the lists are empty.
+#[derive(Default)]
+pub(super) struct Hooks {
+    before_instruction: Vec<InstructionHook>,
+    after_instruction: Vec<InstructionHook>,
+    dei: Vec<PortHook>,
+    deo: Vec<PortHook>,
+}
+
+impl Core {
+    /// Calls `hook` before each instruction runs, with its address and the instruction.
+    pub fn on_before_instruction(&mut self, hook: impl FnMut(&Core, u16, Instruction) + Send + 'static) {
+        self.hooks.before_instruction.push(Box::new(hook));
+    }
+
+    /// Calls `hook` after each instruction runs without faulting, with its address and the
+    /// instruction. The program counter and stacks have already been updated.
+    pub fn on_after_instruction(&mut self, hook: impl FnMut(&Core, u16, Instruction) + Send + 'static) {
+        self.hooks.after_instruction.push(Box::new(hook));
+    }
+
+    /// Calls `hook` after each DEI, with the port and the value the device returned.
+    pub fn on_dei(&mut self, hook: impl FnMut(&Core, u8, Item) + Send + 'static) {
+        self.hooks.dei.push(Box::new(hook));
+    }
+
+    /// Calls `hook` before each DEO, with the port and the value being written.
+    pub fn on_deo(&mut self, hook: impl FnMut(&Core, u8, Item) + Send + 'static) {
+        self.hooks.deo.push(Box::new(hook));
+    }
+
+    /// Removes all hooks.
+    pub fn clear_hooks(&mut self) {
+        self.hooks = Hooks::default();
+    }
+
+    // The hooks are taken out while they run, so that they can be given the rest of the core
+
+    pub(super) fn run_before_instruction_hooks(&mut self, program_counter: u16, instruction: Instruction) {
+        if self.hooks.before_instruction.is_empty() {
+            return;
+        }
+        let mut hooks = mem::take(&mut self.hooks.before_instruction);
+        hooks.iter_mut().for_each(|hook| hook(self, program_counter, instruction));
+        self.hooks.before_instruction = hooks;
+    }
+
+    pub(super) fn run_after_instruction_hooks(&mut self, program_counter: u16, instruction: Instruction) {
+        if self.hooks.after_instruction.is_empty() {
+            return;
+        }
+        let mut hooks = mem::take(&mut self.hooks.after_instruction);
+        hooks.iter_mut().for_each(|hook| hook(self, program_counter, instruction));
+        self.hooks.after_instruction = hooks;
+    }
+
+    pub(super) fn run_dei_hooks(&mut self, port: u8, value: Item) {
+        if self.hooks.dei.is_empty() {
+            return;
+        }
+        let mut hooks = mem::take(&mut self.hooks.dei);
+        hooks.iter_mut().for_each(|hook| hook(self, port, value));
+        self.hooks.dei = hooks;
+    }
+
+    pub(super) fn run_deo_hooks(&mut self, port: u8, value: Item) {
+        if self.hooks.deo.is_empty() {
+            return;
+        }
+        let mut hooks = mem::take(&mut self.hooks.deo);
+        hooks.iter_mut().for_each(|hook| hook(self, port, value));
+        self.hooks.deo = hooks;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{Core, Item, Opcode};
+
+    #[test]
+    fn test_hooks() {
+        let mut core = Core::new_with_uxntal("#2a #12 DEO #1234 #12 DEI2 BRK").unwrap();
+        let log = Arc::new(Mutex::new(vec![]));
+
+        let before = log.clone();
+        core.on_before_instruction(move |_, pc, instruction| before.lock().unwrap().push(format!("before {pc:04x} {instruction}")));
+        let after = log.clone();
+        core.on_after_instruction(move |core, _, instruction| {
+            if instruction.opcode == Opcode::Dei {
+                after.lock().unwrap().push(format!("after DEI, wst {:02x?}", core.working_stack.bytes()));
+            }
+        });
+        let ports = log.clone();
+        core.on_deo(move |_, port, value| ports.lock().unwrap().push(format!("DEO {port:02x} {value:?}")));
+        let ports = log.clone();
+        core.on_dei(move |_, port, value| ports.lock().unwrap().push(format!("DEI {port:02x} {value:?}")));
+
+        core.execute_until_break().unwrap();
+        assert_eq!(*log.lock().unwrap(), [
+            "before 0100 LIT",
+            "before 0102 LIT",
+            "before 0104 DEO",
+            "DEO 12 Byte(42)",
+            "before 0105 LIT2",
+            "before 0108 LIT",
+            "before 010a DEI2",
+            "DEI 12 Short(10752)",
+            "after DEI, wst [12, 34, 2a, 00]",
+            "before 010b BRK",
+        ]);
+
+        core.clear_hooks();
+        core.on_deo(|_, _, value| assert_eq!(value, Item::Byte(0)));
+        core.clear_hooks();
+        core.execute_vector(0x0100, None).unwrap();
+    }
+}
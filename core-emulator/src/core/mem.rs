@@ -1,22 +1,214 @@
-use crate::Memory;
+use std::ops::{Bound, Index, IndexMut, RangeBounds};
 
-use super::{Core, ROM_BASE};
+use crate::{device::Device, Memory};
+
+use super::{Core, InstructionHistory, ROM_BASE};
+
+/// The size of each memory bank, which is everything addressable by a short.
+pub const BANK_SIZE: usize = 2usize.pow(16);
+
+/// The total number of memory banks, including the main one.
+pub const BANK_COUNT: usize = 16;
+
+/// Main memory, made up of bank 0 which instructions address directly, and further banks which can
+/// only be reached through `.System/expansion` commands.
+///
+/// Indexing a `MainMemory` (or using it as [`Memory`]) accesses bank 0.
+///
+/// Every bank lives on the heap, so that moving a `MainMemory`, or a [`Core`] holding one, is cheap.
+#[derive(Clone)]
+pub struct MainMemory {
+    main: Box<[u8; BANK_SIZE]>,
+
+    // Most ROMs never touch these, so they're only allocated when first used
+    extra_banks: Vec<Box<[u8; BANK_SIZE]>>,
+}
+
+impl Default for MainMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MainMemory {
+    pub fn new() -> Self {
+        Self {
+            main: empty_bank(),
+            extra_banks: vec![],
+        }
+    }
+
+    /// Gets the contents of a bank. Bank numbers wrap around at [`BANK_COUNT`].
+    pub fn bank(&self, bank: u16) -> &[u8; BANK_SIZE] {
+        static EMPTY: [u8; BANK_SIZE] = [0; BANK_SIZE];
+
+        match bank as usize % BANK_COUNT {
+            0 => &self.main,
+            n => self.extra_banks.get(n - 1).map(|b| &**b).unwrap_or(&EMPTY),
+        }
+    }
+
+    /// Gets the contents of a bank mutably. Bank numbers wrap around at [`BANK_COUNT`].
+    pub fn bank_mut(&mut self, bank: u16) -> &mut [u8; BANK_SIZE] {
+        match bank as usize % BANK_COUNT {
+            0 => &mut self.main,
+            n => {
+                while self.extra_banks.len() < n {
+                    self.extra_banks.push(empty_bank());
+                }
+                &mut self.extra_banks[n - 1]
+            }
+        }
+    }
+
+    /// The number of banks which have been used, counting from bank 0. Banks past this are all
+    /// zero.
+    pub fn allocated_banks(&self) -> usize {
+        1 + self.extra_banks.len()
+    }
+
+    /// Zeroes every bank.
+    pub fn clear(&mut self) {
+        self.main.fill(0);
+        self.extra_banks.clear();
+    }
+}
+
+/// Allocates a zeroed bank directly on the heap. `Box::new([0; BANK_SIZE])` would build it on the
+/// stack first, at least in debug builds.
+fn empty_bank() -> Box<[u8; BANK_SIZE]> {
+    vec![0; BANK_SIZE].into_boxed_slice().try_into().unwrap()
+}
+
+/// Banks which haven't been allocated count as zeroed.
+impl PartialEq for MainMemory {
+    fn eq(&self, other: &Self) -> bool {
+        let banks = self.allocated_banks().max(other.allocated_banks());
+        (0..banks as u16).all(|bank| self.bank(bank) == other.bank(bank))
+    }
+}
+
+impl Eq for MainMemory {}
+
+impl Index<usize> for MainMemory {
+    type Output = u8;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.main[index]
+    }
+}
+
+impl IndexMut<usize> for MainMemory {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.main[index]
+    }
+}
+
+impl Memory for MainMemory {
+    type AddressSpace = u16;
+
+    fn read_byte(&self, addr: Self::AddressSpace) -> u8 {
+        self[addr as usize]
+    }
+
+    fn write_byte(&mut self, addr: Self::AddressSpace, byte: u8) {
+        self[addr as usize] = byte;
+    }
+}
 
 impl Core {
+    /// Loads a ROM at the reset vector. ROMs too large for bank 0 spill over into the following
+    /// banks.
     pub fn load_rom(&mut self, rom: &[u8]) {
         self.clear_memory();
+        self.rom = rom.to_vec();
 
-        for (i, byte) in rom.iter().enumerate() {
-            self.memory[ROM_BASE as usize + i] = *byte;
+        let limit = BANK_SIZE * BANK_COUNT - ROM_BASE as usize;
+        for (i, byte) in rom.iter().take(limit).enumerate() {
+            let addr = ROM_BASE as usize + i;
+            self.memory.bank_mut((addr / BANK_SIZE) as u16)[addr % BANK_SIZE] = *byte;
         }
     }
 
     pub fn clear_memory(&mut self) {
-        // NOTE: there is an uxn convention to keep <0x0100 on a "soft reboot"
-        //       https://wiki.xxiivv.com/site/uxntal_memory.html
-        for item in &mut self.memory {
-            *item = 0;
+        self.memory.clear();
+    }
+
+    /// Formats part of bank 0 as a classic hex and ASCII dump, 16 bytes to a line. Like `hexdump`,
+    /// runs of identical lines are collapsed into a single `*`.
+    pub fn dump_memory(&self, range: impl RangeBounds<u16>) -> String {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start as usize,
+            Bound::Excluded(start) => *start as usize + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => *end as usize + 1,
+            Bound::Excluded(end) => *end as usize,
+            Bound::Unbounded => BANK_SIZE,
+        };
+
+        let mut dump = String::new();
+        let mut previous: Option<&[u8]> = None;
+        let mut collapsing = false;
+        for (i, line) in self.memory.bank(0)[start..end.max(start)].chunks(16).enumerate() {
+            if previous == Some(line) {
+                if !collapsing {
+                    dump.push_str("*\n");
+                    collapsing = true;
+                }
+                continue;
+            }
+            previous = Some(line);
+            collapsing = false;
+
+            let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+            let (left, right) = hex.split_at(hex.len().min(8));
+            let ascii: String = line.iter()
+                .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+                .collect();
+            dump.push_str(&format!("{:04x}  {:<23}  {:<23}  |{ascii}|\n", start + i * 16, left.join(" "), right.join(" ")));
+        }
+        dump
+    }
+
+    /// Reboots the machine, reloading the last ROM and running it again from the reset vector.
+    ///
+    /// By uxn convention, a soft reset keeps the zero page and the devices' state, so that a ROM
+    /// can pass information to its next run. A hard reset clears everything.
+    ///
+    /// See: https://wiki.xxiivv.com/site/uxntal_memory.html
+    pub fn reset(&mut self, hard: bool) {
+        let zero_page: [u8; 0x100] = self.memory.bank(0)[..0x100].try_into().unwrap();
+
+        let rom = std::mem::take(&mut self.rom);
+        self.load_rom(&rom);
+
+        if hard {
+            self.device.reset();
+        } else {
+            self.memory.bank_mut(0)[..0x100].copy_from_slice(&zero_page);
         }
+
+        self.program_counter = ROM_BASE;
+        self.working_stack.clear();
+        self.return_stack.clear();
+        self.history = InstructionHistory::new();
+        self.unbalanced_vectors.clear();
+        self.debugger.forget_stop();
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    /// Reboots into a different ROM like uxn's soft reboot, which keeps the zero page but resets
+    /// the devices, so that none of the old ROM's vectors are left to run in the new one. The old
+    /// ROM's symbols are dropped too. Later resets reload this ROM.
+    pub fn reboot_with_rom(&mut self, rom: &[u8]) {
+        self.rom = rom.to_vec();
+        self.reset(false);
+        self.device.reset();
+        self.symbols = None;
     }
 }
 
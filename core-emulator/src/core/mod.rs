@@ -1,25 +1,135 @@
+use std::{collections::HashSet, fmt::Debug, thread};
+
 use uxn_utils::assemble_uxntal;
 
-use crate::{device::{Device, EmptyDevice}, stack::Stack, Memory};
+use crate::{device::{Device, DeviceBus, EmptyDevice, EventSender}, stack::Stack, UxnError};
 
 pub struct Core {
     pub program_counter: u16,
-    pub memory: [u8; 2usize.pow(16)],
+    pub memory: MainMemory,
     pub working_stack: Stack,
     pub return_stack: Stack,
-    pub device: Box<dyn Device>,
+    pub device: DeviceBus,
+
+    /// The last few instructions executed, which are printed if emulation panics.
+    pub history: InstructionHistory,
+
+    /// Vectors which have been seen to leave the return stack unbalanced.
+    pub unbalanced_vectors: HashSet<u16>,
+
+    /// Whether to print a warning the first time each vector leaves the return stack unbalanced.
+    pub warn_unbalanced_vectors: bool,
+
+    /// The most instructions a vector may run before execution stops with
+    /// [`RunResult::BudgetExceeded`], so that a runaway vector can't hang the emulator. Unlimited
+    /// by default.
+    pub vector_budget: Option<u64>,
+
+    // The last ROM loaded, for resets
+    rom: Vec<u8>,
+
+    debugger: Debugger,
+
+    /// Where every instruction is being traced to, if anywhere.
+    trace: Option<Trace>,
+
+    stats: Stats,
+
+    rewind: Option<Rewind>,
+
+    symbols: Option<Symbols>,
+
+    pause: PauseHandle,
+
+    hooks: Hooks,
 }
 
 const ROM_BASE: u16 = 0x0100;
 
+impl Default for Core {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Core {
+    /// Forks the machine, so that two paths can be run from the same state.
+    ///
+    /// Devices are copied with [`Device::try_clone`], and if any can't be, like one attached to a
+    /// window, this returns `None` rather than a copy which isn't the same machine. Hooks, trace
+    /// writers and pause handles belong to the original, so the copy starts without them.
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(Self {
+            program_counter: self.program_counter,
+            memory: self.memory.clone(),
+            working_stack: self.working_stack.clone(),
+            return_stack: self.return_stack.clone(),
+            device: self.device.try_clone_devices()?,
+            history: self.history.clone(),
+            unbalanced_vectors: self.unbalanced_vectors.clone(),
+            warn_unbalanced_vectors: self.warn_unbalanced_vectors,
+            vector_budget: self.vector_budget,
+            rom: self.rom.clone(),
+            debugger: self.debugger.clone(),
+            trace: self.trace.as_ref().and_then(Trace::try_clone),
+            stats: self.stats,
+            rewind: self.rewind.clone(),
+            symbols: self.symbols.clone(),
+            pause: PauseHandle::default(),
+            hooks: Hooks::default(),
+        })
+    }
+}
+
+/// Shows the registers and stacks, rather than all of memory.
+impl Debug for Core {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Core")
+            .field("program_counter", &format_args!("{:04x}", self.program_counter))
+            .field("working_stack", &format_args!("{}", self.working_stack))
+            .field("return_stack", &format_args!("{}", self.return_stack))
+            .field("allocated_banks", &self.memory.allocated_banks())
+            .field("vector_budget", &self.vector_budget)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Cores are equal if the machines are in the same state: the program counter, stacks, memory,
+/// and devices, as compared by their [`Device::snapshot`]. Debugging state, like breakpoints and
+/// history, isn't compared.
+impl PartialEq for Core {
+    fn eq(&self, other: &Self) -> bool {
+        self.program_counter == other.program_counter
+            && self.working_stack == other.working_stack
+            && self.return_stack == other.return_stack
+            && self.memory == other.memory
+            && self.device.snapshot() == other.device.snapshot()
+    }
+}
+
 impl Core {
     pub fn new() -> Self {
+        let mut device = DeviceBus::new();
+        device.set_device(EmptyDevice::new());
+
         Self {
             program_counter: ROM_BASE,
-            memory: [0; 2usize.pow(16)],
+            memory: MainMemory::new(),
             working_stack: Stack::new(),
             return_stack: Stack::new(),
-            device: Box::new(EmptyDevice::new()),
+            device,
+            history: InstructionHistory::new(),
+            unbalanced_vectors: HashSet::new(),
+            warn_unbalanced_vectors: true,
+            vector_budget: None,
+            rom: vec![],
+            debugger: Debugger::default(),
+            trace: None,
+            stats: Stats::default(),
+            rewind: None,
+            symbols: None,
+            pause: PauseHandle::default(),
+            hooks: Hooks::default(),
         }
     }
 
@@ -29,13 +139,50 @@ impl Core {
         this
     }
 
-    pub fn new_with_uxntal(code: &str) -> Self {
-        let rom = assemble_uxntal(code).unwrap();
-        Self::new_with_rom(&rom)
+    /// Assembles uxntal source, and loads the result.
+    pub fn new_with_uxntal(code: &str) -> Result<Self, UxnError> {
+        let rom = assemble_uxntal(code).map_err(UxnError::Assembly)?;
+        Ok(Self::new_with_rom(&rom))
+    }
+
+    /// Sets whether both stacks are in strict mode, where wrapping around stops execution with
+    /// [`UxnError::Stack`]. See [`Stack::strict`].
+    pub fn set_strict_stacks(&mut self, strict: bool) {
+        self.working_stack.strict = strict;
+        self.return_stack.strict = strict;
     }
 
+    /// Replaces all devices with one which handles every page.
     pub fn set_device(&mut self, device: impl Device + 'static) {
-        self.device = Box::new(device);
+        self.device.set_device(device);
+    }
+
+    /// Gets a handle which can queue events, like vectors to run, from other threads.
+    pub fn event_sender(&self) -> EventSender {
+        self.device.event_sender()
+    }
+
+    /// Replaces the device on a single 16-byte page, like 0x20 for the Screen, leaving the others
+    /// alone.
+    pub fn register_device(&mut self, page: u8, device: impl Device + 'static) {
+        self.device.register_device(page, device);
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        // The core is dropped while unwinding from a fault, which is a good time to give context
+        if thread::panicking() {
+            eprintln!("Last instructions before the fault, oldest first:");
+            eprint!("{}", self.dump_history());
+
+            if self.trace_entries().next().is_some() {
+                eprintln!("Trace, with stacks after each instruction:");
+                for entry in self.trace_entries() {
+                    eprintln!("{entry}");
+                }
+            }
+        }
     }
 }
 
@@ -45,5 +192,41 @@ pub use exec::*;
 mod mem;
 pub use mem::*;
 
+mod history;
+pub use history::*;
+
+mod snapshot;
+pub use snapshot::*;
+
+mod debugger;
+pub use debugger::*;
+
+mod trace;
+pub use trace::*;
+
+mod stats;
+pub use stats::*;
+
+mod symbols;
+pub use symbols::*;
+
+mod rewind;
+use rewind::Rewind;
+
+mod builder;
+pub use builder::*;
+
+mod pause;
+pub use pause::*;
+
+mod run_iter;
+pub use run_iter::*;
+
+mod hooks;
+use hooks::Hooks;
+
 #[cfg(test)]
 mod tests;
+
+#[cfg(test)]
+mod differential;
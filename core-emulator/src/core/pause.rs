@@ -0,0 +1,90 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc, Condvar, Mutex}, time::{Duration, Instant}};
+
+use super::Core;
+
+/// A handle for freezing a [`Core`] from another thread, like a UI's pause button. Get one with
+/// [`Core::pause_handle`].
+///
+/// While paused, the thread running the core blocks before its next instruction, even in the
+/// middle of a vector, and carries on exactly where it was once resumed. Waiting for events between
+/// vectors isn't affected.
+#[derive(Clone, Default)]
+pub struct PauseHandle(Arc<PauseState>);
+
+#[derive(Default)]
+struct PauseState {
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    resumed: Condvar,
+}
+
+impl PauseHandle {
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        let _guard = self.0.lock.lock().unwrap();
+        self.0.paused.store(false, Ordering::SeqCst);
+        self.0.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    /// Blocks for as long as the core is paused, returning how long that was.
+    pub(super) fn wait_while_paused(&self) -> Duration {
+        // Checked without the lock first, since this happens before every instruction
+        if !self.0.paused.load(Ordering::Relaxed) {
+            return Duration::ZERO;
+        }
+
+        let start = Instant::now();
+        let mut guard = self.0.lock.lock().unwrap();
+        while self.is_paused() {
+            guard = self.0.resumed.wait(guard).unwrap();
+        }
+        start.elapsed()
+    }
+}
+
+impl Core {
+    /// Gets a handle which can pause and resume execution from other threads.
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::{Duration, Instant}};
+
+    use crate::{Core, RunResult};
+
+    #[test]
+    fn test_pause() {
+        let mut core = Core::new_with_uxntal("#01 #02 ADD BRK").unwrap();
+        let handle = core.pause_handle();
+        assert!(!handle.is_paused());
+        handle.pause();
+
+        let resumer = thread::spawn({
+            let handle = handle.clone();
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                handle.resume();
+            }
+        });
+
+        // Blocks until the other thread resumes, then finishes the vector as normal
+        let start = Instant::now();
+        assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(core.working_stack.bytes(), [0x03]);
+
+        // Time spent paused isn't counted as busy
+        assert!(core.stats().busy_time < Duration::from_millis(50));
+        resumer.join().unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::VecDeque, error::Error};
+
+use crate::common::Item;
+
+use super::Core;
+
+/// Checkpoints and logged inputs which let execution be stepped backwards.
+///
+/// Snapshots of the whole core are taken every so often. To step back, the nearest checkpoint is
+/// restored, and execution re-runs forward to just before the current instruction. Anything which
+/// could make this re-run differ from the original is logged, and played back instead.
+#[derive(Clone)]
+pub(crate) struct Rewind {
+    interval: u64,
+    max_checkpoints: usize,
+
+    /// How many instructions have run since rewinding was enabled.
+    position: u64,
+
+    checkpoints: VecDeque<(u64, Vec<u8>)>,
+
+    /// Where the program counter was when each run started, like when a vector was invoked.
+    jumps: VecDeque<(u64, u16)>,
+
+    /// Values read by DEI instructions.
+    inputs: VecDeque<(u64, Item)>,
+
+    /// Whether instructions are being re-run, in which case inputs come from the log.
+    replaying: bool,
+}
+
+impl Rewind {
+    fn new(interval: u64, max_checkpoints: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            max_checkpoints: max_checkpoints.max(1),
+            position: 0,
+            checkpoints: VecDeque::new(),
+            jumps: VecDeque::new(),
+            inputs: VecDeque::new(),
+            replaying: false,
+        }
+    }
+
+    /// Forgets all history, for when the core has been changed in a way which can't be replayed.
+    pub fn clear(&mut self) {
+        *self = Self::new(self.interval, self.max_checkpoints);
+    }
+
+    fn logged<T: Copy>(log: &VecDeque<(u64, T)>, position: u64) -> Option<T> {
+        let index = log.partition_point(|(p, _)| *p < position);
+        log.get(index).filter(|(p, _)| *p == position).map(|(_, value)| *value)
+    }
+
+    /// Forgets everything logged at or after a position.
+    fn truncate<T>(log: &mut VecDeque<(u64, T)>, position: u64) {
+        let index = log.partition_point(|(p, _)| *p < position);
+        log.truncate(index);
+    }
+
+    /// Forgets everything logged before a position.
+    fn trim<T>(log: &mut VecDeque<(u64, T)>, position: u64) {
+        let index = log.partition_point(|(p, _)| *p < position);
+        log.drain(..index);
+    }
+}
+
+impl Core {
+    /// Starts keeping enough history to step backwards with [`Core::step_back`], taking a
+    /// checkpoint every `interval` instructions and keeping at most `max_checkpoints` of them.
+    ///
+    /// Each checkpoint is a full [`Core::snapshot`], so these should be chosen with memory use in
+    /// mind. Changes made to the core other than by running it, like writing to memory directly,
+    /// aren't tracked, so rewinding should be enabled again afterwards to start afresh.
+    pub fn enable_rewind(&mut self, interval: u64, max_checkpoints: usize) {
+        self.rewind = Some(Rewind::new(interval, max_checkpoints));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// How many instructions have run since rewinding was enabled, or `None` if it isn't.
+    pub fn rewind_position(&self) -> Option<u64> {
+        self.rewind.as_ref().map(|rewind| rewind.position)
+    }
+
+    /// Returns the core to how it was just before the last instruction ran.
+    ///
+    /// Devices see the instructions between the nearest checkpoint and here run again, so any
+    /// output they make, like printing to the console, will be repeated.
+    ///
+    /// Returns an error if rewinding isn't enabled, or there isn't a checkpoint far enough back.
+    pub fn step_back(&mut self) -> Result<(), Box<dyn Error>> {
+        let rewind = self.rewind.as_mut().ok_or("rewinding isn't enabled")?;
+        let target = rewind.position.checked_sub(1).ok_or("there's nothing to step back to")?;
+        let index = rewind.checkpoints.partition_point(|(p, _)| *p <= target)
+            .checked_sub(1)
+            .ok_or("can't step back past the oldest checkpoint")?;
+
+        // Everything after the checkpoint is about to be re-run
+        rewind.checkpoints.truncate(index + 1);
+        let (position, snapshot) = &mut rewind.checkpoints[index];
+        rewind.position = *position;
+        rewind.replaying = true;
+        let snapshot = std::mem::take(snapshot);
+        let restored = self.restore(&snapshot);
+
+        let rewind = self.rewind.as_mut().unwrap();
+        rewind.checkpoints[index].1 = snapshot;
+        if let Err(e) = restored {
+            rewind.replaying = false;
+            return Err(e);
+        }
+
+        while self.rewind.as_ref().unwrap().position < target {
+            self.rewind_jump();
+            if let Err(e) = self.step_instruction() {
+                self.rewind.as_mut().unwrap().replaying = false;
+                return Err(e.into());
+            }
+        }
+        self.rewind_jump();
+
+        // The instruction at the target will be run afresh, so forget what it did before
+        let rewind = self.rewind.as_mut().unwrap();
+        rewind.replaying = false;
+        Rewind::truncate(&mut rewind.jumps, target);
+        Rewind::truncate(&mut rewind.inputs, target);
+        self.debugger.forget_stop();
+
+        Ok(())
+    }
+
+    /// While re-running, moves the program counter wherever it was moved to at this point
+    /// originally.
+    fn rewind_jump(&mut self) {
+        let Some(rewind) = &self.rewind else { return };
+        if let Some(program_counter) = Rewind::logged(&rewind.jumps, rewind.position) {
+            self.program_counter = program_counter;
+        }
+    }
+
+    /// Called when a run starts, wherever the program counter is.
+    pub(super) fn rewind_start_run(&mut self) {
+        if let Some(rewind) = &mut self.rewind && !rewind.replaying {
+            Rewind::truncate(&mut rewind.jumps, rewind.position);
+            rewind.jumps.push_back((rewind.position, self.program_counter));
+        }
+    }
+
+    /// Called before each instruction, to take checkpoints when they're due.
+    pub(super) fn rewind_before_instruction(&mut self) {
+        let Some(rewind) = &self.rewind else { return };
+        let due = !rewind.replaying
+            && rewind.position.is_multiple_of(rewind.interval)
+            && rewind.checkpoints.back().is_none_or(|(p, _)| *p != rewind.position);
+        if !due {
+            return;
+        }
+
+        let snapshot = self.snapshot();
+        let rewind = self.rewind.as_mut().unwrap();
+        rewind.checkpoints.push_back((rewind.position, snapshot));
+
+        if rewind.checkpoints.len() > rewind.max_checkpoints {
+            rewind.checkpoints.pop_front();
+            let oldest = rewind.checkpoints[0].0;
+            Rewind::trim(&mut rewind.jumps, oldest);
+            Rewind::trim(&mut rewind.inputs, oldest);
+        }
+    }
+
+    pub(super) fn rewind_after_instruction(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.position += 1;
+        }
+    }
+
+    /// Reads from a device with `read`, or while re-running, gives whatever was read originally.
+    pub(super) fn rewind_input(&mut self, read: impl FnOnce(&mut Self) -> Item) -> Item {
+        if let Some(rewind) = &self.rewind
+            && rewind.replaying
+            && let Some(item) = Rewind::logged(&rewind.inputs, rewind.position)
+        {
+            return item;
+        }
+
+        let item = read(self);
+        if let Some(rewind) = &mut self.rewind && !rewind.replaying {
+            rewind.inputs.push_back((rewind.position, item));
+        }
+        item
+    }
+}
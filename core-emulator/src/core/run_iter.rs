@@ -0,0 +1,124 @@
+use crate::{Instruction, UxnError};
+
+use super::{Core, ExecutionResult, RunResult};
+
+/// One instruction run by [`Core::run_iter`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExecutedInstruction {
+    /// Where the instruction was.
+    pub program_counter: u16,
+
+    pub instruction: Instruction,
+
+    /// The working stack and return stack after the instruction ran, if they were asked for with
+    /// [`RunIter::with_stacks`].
+    pub stacks: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Runs a [`Core`] an instruction at a time, yielding each one as it runs. Create one with
+/// [`Core::run_iter`].
+///
+/// This stops after a BRK, which is yielded, or before a breakpoint, and [`RunIter::result`] then
+/// says which. If the ROM faults, the error is yielded and the iterator ends. Otherwise it carries
+/// on for as long as it's iterated, so limit it with [`Iterator::take`] if the ROM might not BRK.
+pub struct RunIter<'a> {
+    core: &'a mut Core,
+    with_stacks: bool,
+    started: bool,
+    finished: bool,
+    result: Option<RunResult>,
+}
+
+impl RunIter<'_> {
+    /// Includes copies of both stacks with each instruction. This is slower, so it's off by default.
+    pub fn with_stacks(mut self) -> Self {
+        self.with_stacks = true;
+        self
+    }
+
+    /// Why the iterator finished, once it has, or `None` if it's still going or the ROM faulted.
+    pub fn result(&self) -> Option<RunResult> {
+        self.result
+    }
+}
+
+impl Iterator for RunIter<'_> {
+    type Item = Result<ExecutedInstruction, UxnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        if !self.started {
+            self.core.rewind_start_run();
+            self.started = true;
+        }
+        self.core.pause.wait_while_paused();
+
+        let program_counter = self.core.program_counter;
+        if self.core.debugger.should_stop(program_counter) {
+            self.finished = true;
+            self.result = Some(RunResult::Stopped(program_counter));
+            return None;
+        }
+
+        let instruction = Instruction::decode(self.core.memory[program_counter as usize]);
+        self.core.stats.instructions += 1;
+        match self.core.step_instruction() {
+            Ok(ExecutionResult::Continue) => {},
+            Ok(ExecutionResult::Break) => {
+                self.finished = true;
+                self.result = Some(RunResult::Break);
+            },
+            Err(e) => {
+                self.finished = true;
+                return Some(Err(e));
+            },
+        }
+
+        let stacks = self.with_stacks.then(|| (
+            self.core.working_stack.bytes().to_vec(),
+            self.core.return_stack.bytes().to_vec(),
+        ));
+        Some(Ok(ExecutedInstruction { program_counter, instruction, stacks }))
+    }
+}
+
+impl Core {
+    /// Runs from the program counter as an iterator of the instructions executed, for tools like
+    /// profilers and visualisers. See [`RunIter`].
+    pub fn run_iter(&mut self) -> RunIter<'_> {
+        RunIter { core: self, with_stacks: false, started: false, finished: false, result: None }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Core, Opcode, RunResult, UxnError};
+
+    #[test]
+    fn test_run_iter() {
+        let mut core = Core::new_with_uxntal("#01 #02 ADD BRK").unwrap();
+        let mut iter = core.run_iter().with_stacks();
+        let executed: Vec<_> = iter.by_ref().map(Result::unwrap).collect();
+        assert_eq!(iter.result(), Some(RunResult::Break));
+
+        let summary: Vec<_> = executed.iter().map(|e| (e.program_counter, e.instruction.opcode)).collect();
+        assert_eq!(summary, [(0x0100, Opcode::Lit), (0x0102, Opcode::Lit), (0x0104, Opcode::Add), (0x0105, Opcode::Brk)]);
+        assert_eq!(executed[2].stacks, Some((vec![0x03], vec![])));
+        assert_eq!(core.stats().instructions, 4);
+
+        // Stops before breakpoints
+        let mut core = Core::new_with_uxntal("#01 #02 ADD BRK").unwrap();
+        core.add_breakpoint(0x0104);
+        let mut iter = core.run_iter();
+        assert_eq!(iter.by_ref().count(), 2);
+        assert_eq!(iter.result(), Some(RunResult::Stopped(0x0104)));
+
+        // Faults end the iterator
+        let mut core = Core::new_with_uxntal("POP BRK").unwrap();
+        core.set_strict_stacks(true);
+        let executed: Vec<_> = core.run_iter().collect();
+        assert!(matches!(executed[..], [Err(UxnError::Stack { .. })]));
+    }
+}
@@ -0,0 +1,168 @@
+//! Saving and restoring the whole machine's state.
+//!
+//! Snapshots are a custom binary format, with all multi-byte values big-endian like uxn itself.
+//! Fields are packed one after another with no padding or alignment, so a snapshot taken on one
+//! platform can be restored on any other:
+//!
+//! | Field          | Size                                              |
+//! |----------------|---------------------------------------------------|
+//! | Magic          | `uxnsnap` followed by a version byte, currently 4 |
+//! | Program counter| 2                                                 |
+//! | Working stack  | 1 (pointer) + 256 (data)                          |
+//! | Return stack   | 1 (pointer) + 256 (data)                          |
+//! | Memory         | 1 (bank count, n) + n * 65536                     |
+//! | Device state   | 4 (length, l) + l, in a format chosen by the device |
+//!
+//! Devices should use [`SnapshotWriter`] and [`SnapshotReader`] for their own state, so that it's
+//! portable in the same way.
+
+use std::error::Error;
+
+use crate::{device::Device, Stack};
+
+use super::{Core, BANK_SIZE};
+
+const MAGIC: &[u8] = b"uxnsnap\x04";
+
+impl Core {
+    /// Captures the state of the machine, including its devices, so that it can be returned to
+    /// later with [`Core::restore`].
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        writer.bytes(MAGIC);
+        writer.u16(self.program_counter);
+
+        for stack in [&self.working_stack, &self.return_stack] {
+            writer.u8(stack.pointer);
+            writer.bytes(&stack.data);
+        }
+
+        let banks = self.memory.allocated_banks();
+        writer.u8(banks as u8);
+        for bank in 0..banks {
+            writer.bytes(self.memory.bank(bank as u16));
+        }
+
+        let device = self.device.snapshot();
+        writer.u32(device.len() as u32);
+        writer.bytes(&device);
+
+        writer.finish()
+    }
+
+    /// Returns the machine to a state captured with [`Core::snapshot`]. The same devices must be
+    /// attached as when the snapshot was taken.
+    ///
+    /// Returns an error if the snapshot is invalid, or doesn't match the attached devices.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut reader = SnapshotReader::new(snapshot);
+        let magic = reader.bytes(MAGIC.len())?;
+        if magic != MAGIC {
+            return Err(match magic.strip_prefix(b"uxnsnap") {
+                Some([version]) => format!("can't restore version {version} snapshots").into(),
+                _ => "not a uxn snapshot".into(),
+            });
+        }
+
+        // Read everything before changing anything
+        let program_counter = reader.u16()?;
+        let mut stacks = [Stack::new(), Stack::new()];
+        for stack in &mut stacks {
+            stack.pointer = reader.u8()?;
+            stack.data.copy_from_slice(reader.bytes(256)?);
+        }
+        let banks = (0..reader.u8()?)
+            .map(|_| reader.bytes(BANK_SIZE))
+            .collect::<Result<Vec<_>, _>>()?;
+        let device_length = reader.u32()? as usize;
+        let device = reader.bytes(device_length)?;
+        reader.finish()?;
+
+        self.device.restore(device)?;
+        self.program_counter = program_counter;
+        for (stack, mut restored) in [&mut self.working_stack, &mut self.return_stack].into_iter().zip(stacks) {
+            restored.strict = stack.strict;
+            *stack = restored;
+        }
+        self.memory.clear();
+        for (i, bank) in banks.into_iter().enumerate() {
+            self.memory.bank_mut(i as u16).copy_from_slice(bank);
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds up a snapshot, for devices saving their own state.
+pub struct SnapshotWriter(Vec<u8>);
+
+impl Default for SnapshotWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.0.extend(value.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Reads a snapshot back, in the same order as it was written with a [`SnapshotWriter`].
+pub struct SnapshotReader<'a>(&'a [u8]);
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.0.len() < length {
+            return Err("snapshot is truncated".into());
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    /// Checks that everything has been read.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err("snapshot has trailing data".into())
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use super::Core;
+
+/// Counters describing how much work the core has done, for benchmarking.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Instructions executed, including BRKs.
+    pub instructions: u64,
+
+    /// Vectors invoked, not counting the reset vector.
+    pub vectors: u64,
+
+    /// DEI instructions executed, in either byte or short mode.
+    pub device_inputs: u64,
+
+    /// DEO instructions executed, in either byte or short mode.
+    pub device_outputs: u64,
+
+    /// Time spent executing instructions, excluding time spent waiting for events.
+    pub busy_time: Duration,
+}
+
+impl Stats {
+    /// Millions of instructions executed per second of busy time, or `None` if nothing has run.
+    pub fn mips(&self) -> Option<f64> {
+        if self.busy_time.is_zero() {
+            return None;
+        }
+        Some(self.instructions as f64 / self.busy_time.as_secs_f64() / 1_000_000.0)
+    }
+}
+
+impl Core {
+    /// The counters accumulated since the core was created, or since [`Core::reset_stats`].
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+}
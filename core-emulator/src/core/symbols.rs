@@ -0,0 +1,34 @@
+use std::error::Error;
+
+pub use uxn_utils::Symbols;
+
+use super::Core;
+
+impl Core {
+    /// Sets the labels used to describe addresses in traces and instruction histories, and to find
+    /// breakpoints by name.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    pub fn symbols(&self) -> Option<&Symbols> {
+        self.symbols.as_ref()
+    }
+
+    /// Finds an address given either as hex, like `0123`, or as a label, like `on-screen/loop`.
+    pub fn resolve_address(&self, location: &str) -> Option<u16> {
+        self.symbols.as_ref().and_then(|symbols| symbols.address(location))
+            .or_else(|| {
+                let hex = location.strip_prefix("0x").or_else(|| location.strip_prefix('|')).unwrap_or(location);
+                u16::from_str_radix(hex, 16).ok()
+            })
+    }
+
+    /// Adds a breakpoint at an address or label, as accepted by [`Core::resolve_address`]. Returns
+    /// the address, or an error if it couldn't be found.
+    pub fn add_breakpoint_at(&mut self, location: &str) -> Result<u16, Box<dyn Error>> {
+        let address = self.resolve_address(location).ok_or_else(|| format!("no label or address {location}"))?;
+        self.add_breakpoint(address);
+        Ok(address)
+    }
+}
@@ -1,55 +1,578 @@
 // A number of these test cases are taken from the examples on the uxntal reference:
 //   https://wiki.xxiivv.com/site/uxntal_reference.html
 
-use std::str;
+use std::sync::{Arc, Mutex};
 
-use crate::Core;
+#[cfg(feature = "window")]
+use crate::device::VarvaraDevice;
+use crate::{conformance::TestConsoleDevice, device::{Device, DeviceContext, DeviceEvent, EmptyDevice, MidiDevice}, Core, RunResult, StackFault, StackMode, Symbols, uxn_test, UxnError, VectorOutcome};
+use uxn_utils::assemble_uxntal;
 
 #[test]
 fn test_inc() {
-    assert_eq!(execute("#01 INC BRK"), [2]); // Byte mode
-    assert_eq!(execute("#00ff INC2 BRK"), [01, 00]); // Short mode
-    assert_eq!(execute("#00ff INC2k BRK"), [00, 0xff, 01, 00]); // Keep mode
+    uxn_test!("#01 INC BRK" => [2]); // Byte mode
+    uxn_test!("#00ff INC2 BRK" => [0x01, 0x00]); // Short mode
+    uxn_test!("#00ff INC2k BRK" => [0x00, 0xff, 0x01, 0x00]); // Keep mode
 }
 
 #[test]
 fn test_jmp() {
-    assert_eq!(execute("#01 #02 ,&skip-rel JMP BRK BRK BRK &skip-rel #03"), [1, 2, 3]); // Relative mode
-    assert_eq!(execute("#01 #02 ;&skip-abs JMP2 BRK BRK BRK &skip-abs #03"), [1, 2, 3]); // Absolute mode
+    uxn_test!("#01 #02 ,&skip-rel JMP BRK BRK BRK &skip-rel #03" => [1, 2, 3]); // Relative mode
+    uxn_test!("#01 #02 ;&skip-abs JMP2 BRK BRK BRK &skip-abs #03" => [1, 2, 3]); // Absolute mode
+    uxn_test!("#00 &loop INC DUP #03 NEQ ,&loop JCN BRK" => [3]); // Relative mode, backwards
+}
+
+#[test]
+fn test_unsigned() {
+    // All values are unsigned, so the top bit is never a sign
+    uxn_test!("#80 #7f GTH #7f #80 GTH #ff #00 LTH BRK" => [1, 0, 0]);
+    uxn_test!("#8000 #7fff GTH2 #7fff #8000 GTH2 #ffff #0000 LTH2 BRK" => [1, 0, 0]);
+    uxn_test!("#ff #02 DIV #8000 #0002 DIV2 BRK" => [0x7f, 0x40, 0x00]);
+    uxn_test!("#80 #01 SFT #8000 #01 SFT2 BRK" => [0x40, 0x40, 0x00]);
+    uxn_test!("#ff #01 ADD #fe #ff SUB BRK" => [0x00, 0xff]);
 }
 
 #[test]
 fn test_jcn() {
-    assert_eq!(execute("#01 ,&true JCN ,&false JMP  &true #42 BRK  &false #ff BRK"), [0x42]); // True
-    assert_eq!(execute("#00 ,&true JCN ,&false JMP  &true #42 BRK  &false #ff BRK"), [0xff]); // False
+    uxn_test!("#01 ,&true JCN ,&false JMP  &true #42 BRK  &false #ff BRK" => [0x42]); // True
+    uxn_test!("#00 ,&true JCN ,&false JMP  &true #42 BRK  &false #ff BRK" => [0xff]); // False
 }
 
 #[test]
 fn test_ldr() {
-    assert_eq!(execute(",cell LDR BRK @cell 12"), [0x12]); // Byte
-    assert_eq!(execute(",cell LDR2 BRK @cell abcd"), [0xab, 0xcd]); // Short
+    uxn_test!(",cell LDR BRK @cell 12" => [0x12]); // Byte
+    uxn_test!(",cell LDR2 BRK @cell abcd" => [0xab, 0xcd]); // Short
 }
 
 #[test]
 fn test_sft() {
-    assert_eq!(execute("#34 #10 SFT BRK"), [0x68]);
-    assert_eq!(execute("#34 #01 SFT BRK"), [0x1a]);
-    assert_eq!(execute("#1248 #34 SFTk2 BRK"), [0x12, 0x48, 0x34, 0x09, 0x20]);
+    uxn_test!("#34 #10 SFT BRK" => [0x68]);
+    uxn_test!("#34 #01 SFT BRK" => [0x1a]);
+    uxn_test!("#1248 #34 SFTk2 BRK" => [0x12, 0x48, 0x34, 0x09, 0x20]);
 }
 
 #[test]
 fn test_ovr() {
-    assert_eq!(execute("#34 #10 OVR BRK"), [0x34, 0x10, 0x34]);
-    assert_eq!(execute("#1234 #5678 OVR2 BRK"), [0x12, 0x34, 0x56, 0x78, 0x12, 0x34]);
+    uxn_test!("#34 #10 OVR BRK" => [0x34, 0x10, 0x34]);
+    uxn_test!("#1234 #5678 OVR2 BRK" => [0x12, 0x34, 0x56, 0x78, 0x12, 0x34]);
+}
+
+#[test]
+fn test_nip() {
+    // NIP keeps the top item, not the one under it
+    uxn_test!("#12 #34 NIP BRK" => [0x34]);
+    uxn_test!("#12 #34 NIPk BRK" => [0x12, 0x34, 0x34]);
+    uxn_test!("LIT2r 1234 LIT2r 5678 NIP2r BRK" => [], [0x56, 0x78]);
 }
 
 #[test]
 fn test_rot() {
-    assert_eq!(execute("#12 #34 #56 ROT BRK"), [0x34, 0x56, 0x12]);
+    uxn_test!("#12 #34 #56 ROT BRK" => [0x34, 0x56, 0x12]);
+}
+
+#[test]
+fn test_stack_opcodes() {
+    uxn_test!("#1234 POP BRK" => [0x12]);
+    uxn_test!("#1234 NIP BRK" => [0x34]);
+    uxn_test!("#1234 #5678 NIP2 BRK" => [0x56, 0x78]);
+    uxn_test!("#1234 SWP BRK" => [0x34, 0x12]);
+    uxn_test!("#1234 SWPk BRK" => [0x12, 0x34, 0x34, 0x12]);
+    uxn_test!("#1234 #5678 SWP2 BRK" => [0x56, 0x78, 0x12, 0x34]);
+    uxn_test!("#12 #34 #56 ROTk BRK" => [0x12, 0x34, 0x56, 0x34, 0x56, 0x12]);
+    uxn_test!("#1234 DUP BRK" => [0x12, 0x34, 0x34]);
+    uxn_test!("#12 DUPk BRK" => [0x12, 0x12, 0x12]);
+    uxn_test!("#1234 DUP2 BRK" => [0x12, 0x34, 0x12, 0x34]);
+}
+
+#[test]
+fn test_comparison_opcodes() {
+    uxn_test!("#12 #12 EQU BRK" => [0x01]);
+    uxn_test!("#1234 DUP2k EQU2 BRK" => [0x12, 0x34, 0x01]);
+    uxn_test!("#12 #34 NEQ BRK" => [0x01]);
+    uxn_test!("#12 #34 GTH BRK" => [0x00]);
+    uxn_test!("#34 #12 GTHk BRK" => [0x34, 0x12, 0x01]);
+    uxn_test!("#01 #12 LTH BRK" => [0x01]);
+    uxn_test!("#0001 #0000 LTH2 BRK" => [0x00]);
+}
+
+#[test]
+fn test_arithmetic_opcodes() {
+    uxn_test!("#1a #2e ADD BRK" => [0x48]);
+    uxn_test!("#02 #5d ADDk BRK" => [0x02, 0x5d, 0x5f]);
+    uxn_test!("#0001 #0002 ADD2 BRK" => [0x00, 0x03]);
+    uxn_test!("#08 #03 SUB BRK" => [0x05]);
+    uxn_test!("#12 #26 MUL BRK" => [0xac]);
+    uxn_test!("#0012 #0026 MUL2 BRK" => [0x02, 0xac]);
+    uxn_test!("#10 #02 DIV BRK" => [0x08]);
+    uxn_test!("#10 #03 DIVk BRK" => [0x10, 0x03, 0x05]);
+    uxn_test!("#0010 #0000 DIV2 BRK" => [0x00, 0x00]); // Division by zero gives zero
+}
+
+#[test]
+fn test_bitwise_opcodes() {
+    uxn_test!("#fc #3f AND BRK" => [0x3c]);
+    uxn_test!("#fc #3f ORA BRK" => [0xff]);
+    uxn_test!("#fc #3f EOR BRK" => [0xc3]);
+}
+
+#[test]
+fn test_memory_opcodes() {
+    uxn_test!("#1234 #80 STZ2 #80 LDZ2 BRK" => [0x12, 0x34]);
+    uxn_test!("#ab ;cell STA ;cell LDA BRK @cell $1" => [0xab]);
+    uxn_test!("#cd ,cell STR ,cell LDR BRK @cell $1" => [0xcd]);
+}
+
+#[test]
+fn test_return_stack_opcodes() {
+    uxn_test!("#12 STH BRK" => [], [0x12]);
+    uxn_test!("LITr 34 STHr BRK" => [0x34]);
+    uxn_test!("#12 STH #34 STH ADDr BRK" => [], [0x46]);
+    uxn_test!(",routine JSR #02 BRK @routine #01 JMP2r" => [0x01, 0x02]);
+}
+
+#[test]
+fn test_dump_memory() {
+    let core = Core::new_with_uxntal("|100 \"Hello 2c 20 \"World 21 0a |200 ff").unwrap();
+    assert_eq!(core.dump_memory(0x100..0x10e), "\
+0100  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0a        |Hello, World!.|
+");
+
+    // Repeated lines are collapsed
+    assert_eq!(core.dump_memory(0x0f0..=0x200), "\
+00f0  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|
+0100  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0a 00 00  |Hello, World!...|
+0110  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|
+*
+0200  ff                                                |.|
+");
+}
+
+#[test]
+#[cfg(feature = "window")]
+fn test_core_is_send() {
+    let mut core = Core::new_with_uxntal("#01 #02 ADD BRK").unwrap();
+    core.set_device(VarvaraDevice::new());
+    let core = std::thread::spawn(move || {
+        core.execute_until_break().unwrap();
+        core
+    }).join().unwrap();
+    assert_eq!(core.working_stack.bytes(), [0x03]);
+}
+
+#[test]
+fn test_fork_core() {
+    // Stops at the JCN, so each fork can take a different branch
+    let mut core = Core::new_with_uxntal("#2a #12 DEO #00 ?&skip #01 &skip #02 BRK").unwrap();
+    core.add_breakpoint(0x0107);
+    assert_eq!(core.execute_until_break().unwrap(), RunResult::Stopped(0x0107));
+
+    let mut fork = core.try_clone().unwrap();
+    assert_eq!(fork, core);
+    assert_eq!(fork.breakpoints().collect::<Vec<_>>(), [0x0107]);
+
+    fork.poke_stack(StackMode::Working, 0, 0x01);
+    assert_ne!(fork, core);
+    fork.execute_until_break().unwrap();
+    core.execute_until_break().unwrap();
+    assert_eq!(fork.working_stack.bytes(), [0x02]);
+    assert_eq!(core.working_stack.bytes(), [0x01, 0x02]);
+
+    // The device was copied too, rather than shared
+    fork.poke_memory(0x0101, &[0x2b]);
+    fork.execute_vector(0x0100, None).unwrap();
+    assert_ne!(fork.device.snapshot(), core.device.snapshot());
+
+    assert_eq!(
+        format!("{core:?}"),
+        "Core { program_counter: 010f, working_stack: 01 02|, return_stack: |, allocated_banks: 1, vector_budget: None, .. }",
+    );
+
+    // Devices which can't be copied can't be forked at all
+    core.set_device(Arc::new(Mutex::new(EmptyDevice::new())));
+    assert!(core.try_clone().is_none());
+}
+
+#[test]
+fn test_memory_is_on_heap() {
+    // Cores are cheap to move, and creating one doesn't need 64KiB of stack
+    assert!(std::mem::size_of::<Core>() < 4096, "{}", std::mem::size_of::<Core>());
+}
+
+#[test]
+fn test_large_rom_spills_into_banks() {
+    let mut rom = vec![0x11; 0xff00];
+    rom.extend([0x22, 0x33]);
+
+    let core = Core::new_with_rom(&rom);
+    assert_eq!(core.memory[0xffff], 0x11);
+    assert_eq!(core.memory.bank(1)[0x0000], 0x22);
+    assert_eq!(core.memory.bank(1)[0x0001], 0x33);
+}
+
+#[test]
+fn test_device_context() {
+    // Reports and sets the working stack pointer through port 0x04, like `.System/wst`. The port
+    // is still on the stack while it's read.
+    struct StackPointerDevice(u8);
+
+    impl Device for StackPointerDevice {
+        fn wait_for_event(&mut self) -> DeviceEvent { DeviceEvent::Exit(0) }
+
+        fn dei(&mut self, _: u8, context: DeviceContext) -> u8 {
+            self.0 = context.working_stack.pointer;
+            self.0
+        }
+
+        fn deo(&mut self, _: u8, value: u8, context: DeviceContext) {
+            self.0 = value;
+            context.working_stack.pointer = self.0;
+        }
+    }
+
+    let mut core = Core::new_with_uxntal("#aa #bb #04 DEI BRK").unwrap();
+    core.set_device(StackPointerDevice(0));
+    core.execute_until_break().unwrap();
+    assert_eq!(core.working_stack.bytes(), [0xaa, 0xbb, 0x03]);
+
+    let mut core = Core::new_with_uxntal("#aa #bb #01 #04 DEO BRK").unwrap();
+    core.set_device(StackPointerDevice(0));
+    core.execute_until_break().unwrap();
+    assert_eq!(core.working_stack.bytes(), [0xaa]);
+}
+
+#[test]
+fn test_device_exit() {
+    // Execution stops straight after the DEO, without reaching the following instructions
+    let mut core = Core::new_with_uxntal("#2a #0f DEO #01 BRK").unwrap();
+    core.set_device(TestConsoleDevice::new(Default::default()));
+    assert_eq!(core.execute_until_exit().unwrap(), RunResult::Exit(0x2a));
+    assert_eq!(core.working_stack.bytes(), []);
+}
+
+#[test]
+#[cfg(feature = "window")]
+fn test_unsupported_port() {
+    // .Audio0 isn't implemented yet, so execution stops with an error rather than panicking
+    let mut core = Core::new_with_uxntal("#01 #3f DEO #02 BRK").unwrap();
+    core.set_device(VarvaraDevice::new());
+    assert_eq!(core.execute_until_break().unwrap_err(), UxnError::UnsupportedPort(0x3f));
+    assert_eq!(core.working_stack.bytes(), []);
+
+    assert!(matches!(Core::new_with_uxntal("#01 ;missing JMP2"), Err(UxnError::Assembly(_))));
+}
+
+#[test]
+fn test_strict_stacks() {
+    let mut core = Core::new_with_uxntal("#01 POP2 BRK").unwrap();
+    core.execute_until_break().unwrap();
+    assert_eq!(core.working_stack.pointer, 0xff);
+
+    // The JMP2r at 0x0103 returns without anything to return to
+    let mut core = Core::new_with_uxntal("#01 POP JMP2r BRK").unwrap();
+    core.set_strict_stacks(true);
+    let error = core.execute_until_break().unwrap_err();
+    assert_eq!(error, UxnError::Stack { stack: StackMode::Return, fault: StackFault::Underflow, program_counter: 0x0103 });
+    assert_eq!(error.to_string(), "return stack underflow at 0x0103");
+
+    core.reset(true);
+    assert!(core.working_stack.strict && core.return_stack.strict);
+}
+
+#[test]
+#[cfg(feature = "window")]
+fn test_fault_vector() {
+    // The second POP, at 0x0109, underflows and is handled by the vector at 0x010b
+    let mut core = Core::new_with_uxntal(";on-error #00 DEO2 #01 POP POP BRK @on-error #2a BRK").unwrap();
+    core.set_device(VarvaraDevice::new());
+    core.set_strict_stacks(true);
+    assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+    assert_eq!(core.working_stack.bytes(), [0x01, 0x09, 0x02, 0x01, 0x2a]);
+}
+
+#[test]
+fn test_execute_pending() {
+    // The vector at 0x0103 pushes a byte, then the EmptyDevice asks to exit
+    let mut core = Core::new_with_uxntal("BRK BRK BRK #2a BRK").unwrap();
+    core.execute_until_break().unwrap();
+    core.event_sender().send(DeviceEvent::Vector(0x0103));
+
+    assert_eq!(core.execute_pending().unwrap(), Some(RunResult::Break));
+    assert_eq!(core.working_stack.bytes(), [0x2a]);
+    assert_eq!(core.execute_pending().unwrap(), Some(RunResult::Exit(0)));
+}
+
+#[test]
+fn test_unbalanced_vector() {
+    // The vector at 0x0101 calls a subroutine which ends with BRK rather than returning
+    let mut core = Core::new_with_uxntal("BRK ;sub JSR2 #01 BRK @sub #02 BRK").unwrap();
+    core.warn_unbalanced_vectors = false;
+    core.execute_until_break().unwrap();
+
+    core.event_sender().send(DeviceEvent::Vector(0x0101));
+    core.execute_pending().unwrap();
+    assert_eq!(core.working_stack.bytes(), [0x02]);
+    assert_eq!(core.return_stack.bytes().len(), 2);
+    assert!(core.unbalanced_vectors.contains(&0x0101));
+
+    // Balanced vectors aren't reported
+    core.event_sender().send(DeviceEvent::Vector(0x0105));
+    core.execute_pending().unwrap();
+    assert_eq!(core.unbalanced_vectors.len(), 1);
+}
+
+#[test]
+fn test_snapshot() {
+    let mut core = Core::new_with_uxntal("#12 #34 STH #56 #80 DEO BRK").unwrap();
+    core.memory.bank_mut(3)[0x1234] = 0xab;
+    core.execute_until_break().unwrap();
+    let snapshot = core.snapshot();
+
+    // Restoring undoes everything since the snapshot
+    let mut restored = Core::new_with_uxntal("#01 BRK").unwrap();
+    restored.execute_until_break().unwrap();
+    restored.restore(&snapshot).unwrap();
+    assert_eq!(restored.program_counter, core.program_counter);
+    assert_eq!(restored.working_stack.bytes(), [0x12]);
+    assert_eq!(restored.return_stack.bytes(), [0x34]);
+    assert_eq!(restored.memory.bank(0)[..], core.memory.bank(0)[..]);
+    assert_eq!(restored.memory.bank(3)[0x1234], 0xab);
+    assert_eq!(restored.snapshot(), snapshot);
+
+    // Bad snapshots don't restore
+    assert!(restored.restore(&snapshot[..100]).is_err());
+    assert!(restored.restore(b"something else").is_err());
+}
+
+#[test]
+fn test_reset() {
+    let mut core = Core::new_with_uxntal("#12 #00 STZ #34 #10 DEO #56 #0300 STA #78 #0100 STA #9a BRK").unwrap();
+    core.execute_until_break().unwrap();
+    let devices = core.device.snapshot();
+
+    // A soft reset keeps the zero page and devices, but reloads the ROM over everything else
+    core.reset(false);
+    assert_eq!(core.program_counter, 0x0100);
+    assert!(core.working_stack.bytes().is_empty());
+    assert_eq!(core.memory.bank(0)[0x00], 0x12);
+    assert_eq!(core.memory.bank(0)[0x0100], 0x80);
+    assert_eq!(core.memory.bank(0)[0x0300], 0x00);
+    assert_eq!(core.device.snapshot(), devices);
+
+    // A hard reset clears everything
+    core.execute_until_break().unwrap();
+    core.reset(true);
+    assert_eq!(core.memory.bank(0)[0x00], 0x00);
+    assert_eq!(core.memory.bank(0)[0x0100], 0x80);
+    assert_ne!(core.device.snapshot(), devices);
 }
 
-fn execute(code: &str) -> Vec<u8> {
-    let mut core = Core::new_with_uxntal(code);
-    core.execute_until_break();
-    core.working_stack.bytes().to_vec()
+#[test]
+fn test_reboot_with_rom() {
+    // Sets the MIDI device's vector
+    let mut core = Core::new_with_uxntal("#12 #00 STZ #1234 #f0 DEO2 BRK").unwrap();
+    core.register_device(0xf0, MidiDevice::new(core.event_sender()));
+    core.execute_until_break().unwrap();
+
+    // The new ROM runs with the old zero page, but not the old vector, and is what later resets
+    // reload
+    core.reboot_with_rom(&assemble_uxntal("#00 LDZ INC #01 STZ #f0 DEI2 #02 STZ2 BRK").unwrap());
+    core.execute_until_break().unwrap();
+    assert_eq!(core.memory.bank(0)[0x01..0x04], [0x13, 0x00, 0x00]);
+    core.reset(true);
+    core.execute_until_break().unwrap();
+    assert_eq!(core.memory.bank(0)[0x01], 0x01);
+}
+
+#[test]
+fn test_snapshot_format() {
+    // Built by hand from the documented format, rather than by `Core::snapshot`, so that this
+    // catches any dependence on the host's byte order
+    let mut fixture = b"uxnsnap\x04".to_vec();
+    fixture.extend([0x01, 0x23]); // Program counter
+    for (pointer, top) in [(1, 0xab), (2, 0xcd)] {
+        let mut stack = [0; 256];
+        stack[pointer as usize - 1] = top;
+        fixture.push(pointer);
+        fixture.extend(stack);
+    }
+    fixture.push(1); // Banks
+    let mut bank = vec![0; 0x10000];
+    bank[0x0100] = 0x42;
+    fixture.extend(bank);
+    fixture.extend([0x00, 0x00, 0x01, 0x05]); // Device state length
+    fixture.push(1); // The bus has one device...
+    fixture.extend([0x00, 0x00, 0x01, 0x00]); // ...with 256 bytes of state
+    let mut device = [0; 256];
+    device[0x10] = 0x99;
+    fixture.extend(device);
+
+    let mut core = Core::new();
+    core.restore(&fixture).unwrap();
+    assert_eq!(core.program_counter, 0x0123);
+    assert_eq!(core.working_stack.bytes(), [0xab]);
+    assert_eq!(core.return_stack.bytes(), [0x00, 0xcd]);
+    assert_eq!(core.memory.bank(0)[0x0100], 0x42);
+    assert_eq!(core.snapshot(), fixture);
+
+    // Snapshots in other versions of the format are turned away before anything changes
+    fixture[7] = 1;
+    assert_eq!(core.restore(&fixture).unwrap_err().to_string(), "can't restore version 1 snapshots");
+}
+
+#[test]
+fn test_vector_budget() {
+    // The reset vector loops forever, but can be stopped and resumed
+    let mut core = Core::new_with_uxntal("@loop #01 POP !loop").unwrap();
+    core.vector_budget = Some(1000);
+    assert_eq!(core.execute_until_break().unwrap(), RunResult::BudgetExceeded);
+    assert_eq!(core.execute_until_exit().unwrap(), RunResult::BudgetExceeded);
+    assert_eq!(core.stats().instructions, 2000);
+}
+
+#[test]
+fn test_step_back() {
+    // The vector at 0x0101 reads from the device, which gives a different answer each time
+    struct CountingDevice(u8);
+
+    impl Device for CountingDevice {
+        fn wait_for_event(&mut self) -> DeviceEvent { DeviceEvent::Exit(0) }
+        fn dei(&mut self, _: u8, _: DeviceContext) -> u8 { self.0 += 1; self.0 }
+        fn deo(&mut self, _: u8, _: u8, _: DeviceContext) {}
+        fn snapshot(&self) -> Vec<u8> { vec![self.0] }
+        fn restore(&mut self, snapshot: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+            self.0 = snapshot[0];
+            Ok(())
+        }
+    }
+
+    let mut core = Core::new_with_uxntal("BRK #00 DEI #01 ADD #00 STZ BRK").unwrap();
+    core.set_device(CountingDevice(0));
+    core.enable_rewind(3, 10);
+    core.execute_until_break().unwrap();
+    for _ in 0..3 {
+        core.execute_vector(0x0101, None).unwrap();
+    }
+    assert_eq!(core.memory.bank(0)[0x00], 4);
+    assert_eq!(core.rewind_position(), Some(1 + 7 * 3));
+
+    // Back to before the last STZ, with the device's answer from the time
+    for _ in 0..2 {
+        core.step_back().unwrap();
+    }
+    assert_eq!(core.rewind_position(), Some(1 + 7 * 3 - 2));
+    assert_eq!(core.program_counter, 0x0109);
+    assert_eq!(core.working_stack.bytes(), [4, 0]);
+    assert_eq!(core.memory.bank(0)[0x00], 3);
+
+    // Stepping back across a vector boundary lands on the previous BRK
+    for _ in 0..6 {
+        core.step_back().unwrap();
+    }
+    assert_eq!(core.program_counter, 0x010a);
+    assert!(core.working_stack.bytes().is_empty());
+
+    // The device went back in time too
+    core.execute_vector(0x0101, None).unwrap();
+    assert_eq!(core.memory.bank(0)[0x00], 4);
+
+    // There's a limit to how far back it goes
+    while core.step_back().is_ok() {}
+    assert_eq!(core.rewind_position(), Some(0));
+}
+
+#[test]
+fn test_stats() {
+    let mut core = Core::new_with_uxntal("#12 #00 DEO #00 DEI BRK").unwrap();
+    core.execute_until_break().unwrap();
+    core.execute_vector(0x0100, None).unwrap();
+
+    let stats = core.stats();
+    assert_eq!((stats.instructions, stats.vectors), (12, 1));
+    assert_eq!((stats.device_inputs, stats.device_outputs), (2, 2));
+
+    core.reset_stats();
+    assert_eq!(core.stats().instructions, 0);
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut core = Core::new_with_uxntal("#01 #02 #03 BRK").unwrap();
+    core.add_breakpoint(0x0102);
+
+    // Stops before the instruction at the breakpoint, then resumes past it
+    assert_eq!(core.execute_until_break().unwrap(), RunResult::Stopped(0x0102));
+    assert_eq!(core.working_stack.bytes(), [0x01]);
+    assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+    assert_eq!(core.working_stack.bytes(), [0x01, 0x02, 0x03]);
+
+    // Breakpoints in a loop are hit every time around
+    let mut core = Core::new_with_uxntal("#03 @loop #01 SUB DUP ?loop BRK").unwrap();
+    core.add_breakpoint(0x0102);
+    for _ in 0..3 {
+        assert_eq!(core.execute_until_exit().unwrap(), RunResult::Stopped(0x0102));
+    }
+    assert_eq!(core.execute_until_exit().unwrap(), RunResult::Exit(0));
+    assert_eq!(core.working_stack.bytes(), [0x00]);
+
+    assert!(core.remove_breakpoint(0x0102));
+    assert_eq!(core.breakpoints().count(), 0);
+
+    // Breakpoints can be given by label, once symbols are loaded
+    assert!(core.add_breakpoint_at("on-frame").is_err());
+    assert_eq!(core.add_breakpoint_at("0x0102").unwrap(), 0x0102);
+    core.set_symbols(Symbols::parse(b"\x01\x01on-frame\0").unwrap());
+    assert_eq!(core.add_breakpoint_at("on-frame").unwrap(), 0x0101);
+    assert_eq!(core.breakpoints().collect::<Vec<_>>(), [0x0101, 0x0102]);
+    core.clear_breakpoints();
+    assert_eq!(core.breakpoints().count(), 0);
+}
+
+#[test]
+fn test_live_editing() {
+    let mut core = Core::new_with_uxntal("#01 #02 ADDk #0f DEO BRK").unwrap();
+    core.add_breakpoint(0x0105);
+    assert_eq!(core.execute_until_break().unwrap(), RunResult::Stopped(0x0105));
+    assert_eq!(core.working_stack.bytes(), [0x01, 0x02, 0x03]);
+
+    // Patch the sum and the first constant, then skip the DEO to some new code after the BRK
+    assert!(core.poke_stack(StackMode::Working, 0, 0x2a));
+    assert!(core.poke_stack(StackMode::Working, 2, 0x10));
+    assert!(!core.poke_stack(StackMode::Working, 3, 0x00));
+    assert!(!core.poke_stack(StackMode::Return, 0, 0x00));
+    core.poke_memory(0x0109, &[0x80, 0x07, 0x00]);
+    core.set_program_counter(0x0109);
+
+    assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+    assert_eq!(core.working_stack.bytes(), [0x10, 0x02, 0x2a, 0x07]);
+}
+
+#[test]
+fn test_execute_vector_fuel() {
+    // The vector at 0x0101 loops forever
+    let mut core = Core::new_with_uxntal("BRK @loop #01 POP !loop").unwrap();
+
+    let outcome = core.execute_vector(0x0101, Some(10)).unwrap();
+    assert_eq!(outcome, VectorOutcome { result: RunResult::BudgetExceeded, instructions: 10 });
+    let outcome = core.execute_vector(core.program_counter, Some(5)).unwrap();
+    assert_eq!(outcome.instructions, 5);
+    assert_eq!(core.history.entries().count(), 15);
+
+    // With enough fuel, vectors finish
+    let outcome = core.execute_vector(0x0100, Some(10)).unwrap();
+    assert_eq!(outcome, VectorOutcome { result: RunResult::Break, instructions: 1 });
+    assert_eq!(core.execute_vector(0x0100, None).unwrap().result, RunResult::Break);
+}
+
+#[test]
+fn test_execute_instructions() {
+    let mut core = Core::new_with_uxntal("#01 #02 ADD #03 BRK").unwrap();
+    core.add_breakpoint(0x0105);
+
+    let outcome = core.execute_instructions(2).unwrap();
+    assert_eq!(outcome, VectorOutcome { result: RunResult::BudgetExceeded, instructions: 2 });
+    assert_eq!(core.working_stack.bytes(), [0x01, 0x02]);
+
+    assert_eq!(core.execute_instructions(5).unwrap(), VectorOutcome { result: RunResult::Stopped(0x0105), instructions: 1 });
+    assert_eq!(core.execute_instructions(5).unwrap(), VectorOutcome { result: RunResult::Break, instructions: 2 });
+    assert_eq!(core.working_stack.bytes(), [0x03, 0x03]);
 }
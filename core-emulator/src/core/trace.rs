@@ -0,0 +1,148 @@
+use std::{collections::VecDeque, fmt::Display, io::Write};
+
+use crate::{mnemonic, Stack};
+
+use super::Core;
+
+/// One executed instruction, with the contents of both stacks just after it ran.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub program_counter: u16,
+    pub opcode: u8,
+    pub working_stack: Vec<u8>,
+    pub return_stack: Vec<u8>,
+
+    /// Where the instruction is relative to the nearest label, if symbols have been loaded.
+    pub label: Option<String>,
+}
+
+impl TraceEntry {
+    fn new(program_counter: u16, opcode: u8, working_stack: &Stack, return_stack: &Stack) -> Self {
+        Self {
+            program_counter,
+            opcode,
+            working_stack: working_stack.bytes().to_vec(),
+            return_stack: return_stack.bytes().to_vec(),
+            label: None,
+        }
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stack = |bytes: &[u8]| bytes.iter().map(|byte| format!(" {byte:02x}")).collect::<String>();
+        write!(
+            f, "{:04x}  {:<7} wst{}| rst{}|",
+            self.program_counter, mnemonic(self.opcode),
+            stack(&self.working_stack), stack(&self.return_stack),
+        )?;
+        if let Some(label) = &self.label {
+            write!(f, "  {label}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where instructions are traced to, once tracing has been enabled.
+pub enum Trace {
+    /// Each instruction is written to this as a line of text.
+    Writer(Box<dyn Write + Send>),
+
+    /// The last few instructions are kept, up to a capacity, to be looked at later.
+    Buffer {
+        entries: VecDeque<TraceEntry>,
+        capacity: usize,
+    },
+}
+
+impl Trace {
+    /// Copies a trace buffer. Writers can't be copied, so give `None`.
+    pub(super) fn try_clone(&self) -> Option<Trace> {
+        match self {
+            Trace::Writer(_) => None,
+            Trace::Buffer { entries, capacity } => Some(Trace::Buffer { entries: entries.clone(), capacity: *capacity }),
+        }
+    }
+
+    fn record(&mut self, entry: TraceEntry) -> std::io::Result<()> {
+        match self {
+            Trace::Writer(writer) => writeln!(writer, "{entry}"),
+            Trace::Buffer { entries, capacity } => {
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Core {
+    /// Starts writing every instruction executed, with both stacks, to a writer. This is slow, so
+    /// is meant for debugging.
+    pub fn trace_to_writer(&mut self, writer: impl Write + Send + 'static) {
+        self.trace = Some(Trace::Writer(Box::new(writer)));
+    }
+
+    /// Starts keeping the last `capacity` instructions executed, with both stacks, which can be
+    /// retrieved with [`Core::trace_entries`]. If the emulator panics, these are printed.
+    pub fn trace_to_buffer(&mut self, capacity: usize) {
+        self.trace = Some(Trace::Buffer { entries: VecDeque::with_capacity(capacity), capacity });
+    }
+
+    /// Stops tracing, returning the trace so that anything buffered can be looked at.
+    pub fn disable_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// The instructions kept by [`Core::trace_to_buffer`], oldest first. This is empty if the trace
+    /// isn't being buffered.
+    pub fn trace_entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        let entries = match &self.trace {
+            Some(Trace::Buffer { entries, .. }) => Some(entries.iter()),
+            _ => None,
+        };
+        entries.into_iter().flatten()
+    }
+
+    /// Called after each instruction runs.
+    pub(super) fn record_trace(&mut self, program_counter: u16, opcode: u8) {
+        let Some(trace) = &mut self.trace else { return };
+
+        let mut entry = TraceEntry::new(program_counter, opcode, &self.working_stack, &self.return_stack);
+        entry.label = self.symbols.as_ref().and_then(|symbols| symbols.locate(program_counter));
+        if let Err(e) = trace.record(entry) {
+            crate::warning!("Stopping trace, since it couldn't be written: {e}");
+            self.trace = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Core, Symbols};
+
+    #[test]
+    fn test_trace_buffer() {
+        let mut core = Core::new_with_uxntal("#01 #02 #03 STH ADDk BRK").unwrap();
+        core.trace_to_buffer(2);
+        core.execute_until_break().unwrap();
+
+        let lines: Vec<_> = core.trace_entries().map(|entry| entry.to_string()).collect();
+        assert_eq!(lines, [
+            "0107  ADDk    wst 01 02 03| rst 03|",
+            "0108  BRK     wst 01 02 03| rst 03|",
+        ]);
+
+        let mut core = Core::new_with_uxntal("#01 POP BRK").unwrap();
+        core.set_symbols(Symbols::parse(b"\x01\x00on-reset\0").unwrap());
+        core.trace_to_buffer(1);
+        core.execute_until_break().unwrap();
+        assert_eq!(core.trace_entries().next().unwrap().to_string(), "0103  BRK     wst| rst|  on-reset+3");
+    }
+}
@@ -0,0 +1,341 @@
+use std::{error::Error, sync::{mpsc::{channel, Receiver, Sender}, Arc}, time::Duration};
+
+use crate::{SnapshotReader, SnapshotWriter, UxnError};
+
+use super::{Device, DeviceContext, DeviceEvent};
+
+/// How long a wait goes between checking the device and the queued events, while an
+/// [`EventSender`] could still queue one.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A handle for pushing events into a [`DeviceBus`] from the host, which can be sent to other
+/// threads.
+///
+/// Events are only handled between vectors, so they can't interrupt one which is running. While
+/// the core is waiting, they're noticed within a millisecond or so, except when the device on page
+/// 0x00 blocks in [`Device::poll_event`], as devices which only implement
+/// [`Device::wait_for_event`] do.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<DeviceEvent>,
+
+    /// Counts the live senders, so the bus knows whether to watch for events while waiting.
+    _token: Arc<()>,
+}
+
+impl EventSender {
+    /// Queues an event, to be handled before any from the devices. Returns false if the bus has
+    /// been dropped.
+    pub fn send(&self, event: DeviceEvent) -> bool {
+        self.sender.send(event).is_ok()
+    }
+}
+
+/// Routes each 16-byte page of the device address space to a separately registered [`Device`].
+///
+/// Devices always receive full port addresses, so one device can be registered on several pages
+/// (like `VarvaraDevice`, which implements them all) while others take
+/// over individual pages.
+///
+/// Events come from the device on page 0x00, since the System device owns the machine's lifecycle,
+/// after any which the host has queued through an [`EventSender`]. While any sender is alive, the
+/// bus polls the device instead of letting it block, so that queued events can wake the wait, and
+/// keeps waiting even once the device is [idle](Device::is_idle).
+/// Reads from unmapped pages return 0, and writes to them are ignored.
+pub struct DeviceBus {
+    devices: Vec<Box<dyn Device>>,
+    pages: [Option<usize>; 16],
+    sender: Sender<DeviceEvent>,
+    receiver: Receiver<DeviceEvent>,
+    senders: Arc<()>,
+}
+
+impl Default for DeviceBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceBus {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            devices: vec![],
+            pages: [None; 16],
+            sender,
+            receiver,
+            senders: Arc::new(()),
+        }
+    }
+
+    /// Gets a handle which can queue events from the host.
+    pub fn event_sender(&self) -> EventSender {
+        EventSender { sender: self.sender.clone(), _token: self.senders.clone() }
+    }
+
+    /// Whether any [`EventSender`] could still queue an event.
+    fn has_senders(&self) -> bool {
+        Arc::strong_count(&self.senders) > 1
+    }
+
+    /// Whether the System device, if there is one, has nothing left to wait for.
+    fn is_system_idle(&self) -> bool {
+        self.pages[0].is_none_or(|i| self.devices[i].is_idle())
+    }
+
+    /// Removes all devices, and maps every page to this one.
+    pub fn set_device(&mut self, device: impl Device + 'static) {
+        self.devices = vec![Box::new(device)];
+        self.pages = [Some(0); 16];
+    }
+
+    /// Maps a single page to a device, replacing whatever was there before. The page is the high
+    /// nibble of its ports, like 0x20 for the Screen.
+    pub fn register_device(&mut self, page: u8, device: impl Device + 'static) {
+        self.devices.push(Box::new(device));
+        self.pages[(page >> 4) as usize] = Some(self.devices.len() - 1);
+
+        // Drop any device which is no longer mapped anywhere
+        let unused = (0..self.devices.len())
+            .rev()
+            .filter(|i| !self.pages.contains(&Some(*i)))
+            .collect::<Vec<_>>();
+        for index in unused {
+            self.devices.remove(index);
+            for page in self.pages.iter_mut().flatten() {
+                if *page > index {
+                    *page -= 1;
+                }
+            }
+        }
+    }
+
+    /// Copies the bus for [`Core::try_clone`](crate::Core::try_clone), or returns `None` if any
+    /// device can't be copied with [`Device::try_clone`]. Queued events aren't copied.
+    pub fn try_clone_devices(&self) -> Option<Self> {
+        let (sender, receiver) = channel();
+        Some(Self {
+            devices: self.devices.iter().map(|device| device.try_clone()).collect::<Option<_>>()?,
+            pages: self.pages,
+            sender,
+            receiver,
+            senders: Arc::new(()),
+        })
+    }
+
+    fn device_for(&mut self, port: u8) -> Option<&mut (dyn Device + 'static)> {
+        self.pages[(port >> 4) as usize].map(|i| self.devices[i].as_mut())
+    }
+}
+
+impl Device for DeviceBus {
+    fn dei(&mut self, port: u8, context: DeviceContext) -> u8 {
+        self.device_for(port).map_or(0, |device| device.dei(port, context))
+    }
+
+    fn deo(&mut self, port: u8, value: u8, context: DeviceContext) {
+        if let Some(device) = self.device_for(port) {
+            device.deo(port, value, context);
+        }
+    }
+
+    // Shorts are routed as a whole, so that devices can handle them specially, unless they
+    // straddle two pages
+    fn dei2(&mut self, port: u8, mut context: DeviceContext) -> u16 {
+        if port & 0x0f == 0x0f {
+            let hi = self.dei(port, context.reborrow());
+            let lo = self.dei(port.wrapping_add(1), context);
+            return u16::from_be_bytes([hi, lo]);
+        }
+        self.device_for(port).map_or(0, |device| device.dei2(port, context))
+    }
+
+    fn deo2(&mut self, port: u8, value: u16, mut context: DeviceContext) {
+        if port & 0x0f == 0x0f {
+            let [hi, lo] = value.to_be_bytes();
+            self.deo(port, hi, context.reborrow());
+            self.deo(port.wrapping_add(1), lo, context);
+            return;
+        }
+        if let Some(device) = self.device_for(port) {
+            device.deo2(port, value, context);
+        }
+    }
+
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        loop {
+            if let Ok(event) = self.receiver.try_recv() {
+                return event;
+            }
+
+            // Nothing can be queued while the device blocks unless there's a sender
+            if !self.has_senders() {
+                return self.device_for(0x00).map_or(DeviceEvent::Exit(0), |device| device.wait_for_event());
+            }
+
+            // An idle device would only ask to exit, but a sender could still start a vector
+            if !self.is_system_idle() && let Some(event) = self.device_for(0x00).and_then(|device| device.poll_event()) {
+                return event;
+            }
+            if let Ok(event) = self.receiver.recv_timeout(POLL_INTERVAL) {
+                return event;
+            }
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<DeviceEvent> {
+        if let Ok(event) = self.receiver.try_recv() {
+            return Some(event);
+        }
+        if self.has_senders() && self.is_system_idle() {
+            return None;
+        }
+        self.device_for(0x00).map_or(Some(DeviceEvent::Exit(0)), |device| device.poll_event())
+    }
+
+    fn is_halted(&self) -> bool {
+        self.devices.iter().any(|device| device.is_halted())
+    }
+
+    fn is_idle(&self) -> bool {
+        !self.has_senders() && self.is_system_idle()
+    }
+
+    fn take_fault(&mut self) -> Option<UxnError> {
+        self.devices.iter_mut().find_map(|device| device.take_fault())
+    }
+
+    fn fault_vector(&self) -> Option<u16> {
+        self.pages[0].and_then(|i| self.devices[i].fault_vector())
+    }
+
+    fn reset(&mut self) {
+        for device in &mut self.devices {
+            device.reset();
+        }
+    }
+
+    // The device count, then each device's length-prefixed snapshot in the order they were added
+    fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        writer.u8(self.devices.len() as u8);
+        for device in &self.devices {
+            let snapshot = device.snapshot();
+            writer.u32(snapshot.len() as u32);
+            writer.bytes(&snapshot);
+        }
+        writer.finish()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut reader = SnapshotReader::new(snapshot);
+        if reader.u8()? as usize != self.devices.len() {
+            return Err("snapshot has a different number of devices".into());
+        }
+        for device in &mut self.devices {
+            let length = reader.u32()? as usize;
+            device.restore(reader.bytes(length)?)?;
+        }
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{Arc, Mutex}, thread, time::Duration};
+
+    use crate::{device::{Device, DeviceContext, DeviceEvent, EmptyDevice}, MainMemory, Stack};
+
+    use super::DeviceBus;
+
+    /// Has an event once `ready` is set, and never blocks for it.
+    struct PendingDevice {
+        ready: bool,
+    }
+
+    impl Device for PendingDevice {
+        fn dei(&mut self, _port: u8, _context: DeviceContext) -> u8 { 0 }
+        fn deo(&mut self, _port: u8, _value: u8, _context: DeviceContext) {}
+
+        fn wait_for_event(&mut self) -> DeviceEvent {
+            panic!("should have been polled");
+        }
+
+        fn poll_event(&mut self) -> Option<DeviceEvent> {
+            self.ready.then_some(DeviceEvent::Exit(0))
+        }
+    }
+
+    #[test]
+    fn test_routing() {
+        let (mut memory, mut wst, mut rst) = (MainMemory::new(), Stack::new(), Stack::new());
+        let mut context = DeviceContext { memory: &mut memory, working_stack: &mut wst, return_stack: &mut rst };
+
+        let mut bus = DeviceBus::new();
+        assert_eq!(bus.dei(0x12, context.reborrow()), 0);
+        bus.deo(0x12, 0xff, context.reborrow());
+
+        bus.set_device(EmptyDevice::new());
+        bus.register_device(0x10, EmptyDevice::new());
+        bus.deo(0x02, 0xaa, context.reborrow());
+        bus.deo(0x12, 0xbb, context.reborrow());
+        bus.deo(0x22, 0xcc, context.reborrow());
+
+        // Pages 0x00 and 0x20 share a device, but 0x10 has its own
+        assert_eq!(bus.dei(0x02, context.reborrow()), 0xaa);
+        assert_eq!(bus.dei(0x12, context.reborrow()), 0xbb);
+        assert_eq!(bus.dei(0x22, context.reborrow()), 0xcc);
+        assert_eq!(bus.devices.len(), 2);
+
+        // Shorts can straddle pages
+        bus.deo2(0x0f, 0x1234, context.reborrow());
+        assert_eq!(bus.dei(0x0f, context.reborrow()), 0x12);
+        assert_eq!(bus.dei(0x10, context.reborrow()), 0x34);
+        assert_eq!(bus.dei2(0x0f, context.reborrow()), 0x1234);
+
+        // Replacing the only mapping for a device drops it
+        bus.register_device(0x10, EmptyDevice::new());
+        assert_eq!(bus.dei(0x12, context), 0x00);
+        assert_eq!(bus.devices.len(), 2);
+    }
+
+    #[test]
+    fn test_injection_wakes_wait() {
+        let mut bus = DeviceBus::new();
+        bus.set_device(PendingDevice { ready: false });
+
+        let sender = bus.event_sender();
+        let injector = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send(DeviceEvent::Vector(0x1234));
+        });
+        assert_eq!(bus.wait_for_event(), DeviceEvent::Vector(0x1234));
+        injector.join().unwrap();
+    }
+
+    #[test]
+    fn test_shared_device_wait() {
+        // The host can lock the device while the core is waiting for it
+        let device = Arc::new(Mutex::new(PendingDevice { ready: false }));
+        let mut shared = device.clone();
+        let waiter = thread::spawn(move || shared.wait_for_event());
+
+        thread::sleep(Duration::from_millis(20));
+        device.lock().unwrap().ready = true;
+        assert_eq!(waiter.join().unwrap(), DeviceEvent::Exit(0));
+    }
+
+    #[test]
+    fn test_injected_events() {
+        let mut bus = DeviceBus::new();
+        bus.set_device(EmptyDevice::new());
+
+        let sender = bus.event_sender();
+        assert!(sender.send(DeviceEvent::Vector(0x1234)));
+        assert_eq!(bus.poll_event(), Some(DeviceEvent::Vector(0x1234)));
+        assert_eq!(bus.poll_event(), Some(DeviceEvent::Exit(0)));
+
+        drop(bus);
+        assert!(!sender.send(DeviceEvent::Vector(0x1234)));
+    }
+}
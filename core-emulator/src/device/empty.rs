@@ -1,12 +1,19 @@
-use crate::Memory;
+use std::error::Error;
 
-use super::{Device, DeviceEvent};
+use super::{Device, DeviceContext, DeviceEvent};
 
 /// A stub device which simply acts as a normal memory page.
+#[derive(Clone)]
 pub struct EmptyDevice {
     memory: [u8; 256]
 }
 
+impl Default for EmptyDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EmptyDevice {
     pub fn new() -> Self {
         Self {
@@ -15,20 +22,33 @@ impl EmptyDevice {
     }
 }
 
-impl Memory for EmptyDevice {
-    type AddressSpace = u8;
+impl Device for EmptyDevice {
+    fn dei(&mut self, port: u8, _context: DeviceContext) -> u8 {
+        self.memory[port as usize]
+    }
 
-    fn read_byte(&self, addr: Self::AddressSpace) -> u8 {
-        self.memory[addr as usize]
+    fn deo(&mut self, port: u8, value: u8, _context: DeviceContext) {
+        self.memory[port as usize] = value;
     }
-    
-    fn write_byte(&mut self, addr: Self::AddressSpace, byte: u8) {
-        self.memory[addr as usize] = byte;
+
+    fn reset(&mut self) {
+        self.memory = [0; 256];
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.memory.to_vec()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.memory = snapshot.try_into()?;
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
     }
-}
 
-impl Device for EmptyDevice {
     fn wait_for_event(&mut self) -> DeviceEvent {
-        DeviceEvent::Exit
+        DeviceEvent::Exit(0)
     }
 }
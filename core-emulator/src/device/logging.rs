@@ -0,0 +1,155 @@
+use std::{error::Error, fmt::Display};
+
+use crate::UxnError;
+
+use super::{Device, DeviceContext, DeviceEvent};
+
+/// One access to a device's ports, as logged by a [`LoggingDevice`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceAccess {
+    /// A DEI, with the port and the value the device returned.
+    Read(u8, u8),
+
+    /// A DEO, with the port and the value written.
+    Write(u8, u8),
+
+    /// A DEI2, with the port and the value the device returned.
+    ReadShort(u8, u16),
+
+    /// A DEO2, with the port and the value written.
+    WriteShort(u8, u16),
+}
+
+impl Display for DeviceAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceAccess::Read(port, value) => write!(f, "DEI  {port:02x} -> {value:02x}"),
+            DeviceAccess::Write(port, value) => write!(f, "DEO  {port:02x} <- {value:02x}"),
+            DeviceAccess::ReadShort(port, value) => write!(f, "DEI2 {port:02x} -> {value:04x}"),
+            DeviceAccess::WriteShort(port, value) => write!(f, "DEO2 {port:02x} <- {value:04x}"),
+        }
+    }
+}
+
+/// Wraps another device, passing everything through to it, but calling a callback with each read
+/// and write of its ports. This is handy when writing a new device, to see what a ROM does with it.
+///
+/// Shorts are logged once, as whichever of DEI2 or DEO2 the ROM used, even if the inner device
+/// handles them as two bytes.
+pub struct LoggingDevice<D: Device> {
+    inner: D,
+    callback: Box<dyn FnMut(DeviceAccess) + Send>,
+}
+
+impl<D: Device> LoggingDevice<D> {
+    pub fn new(inner: D, callback: impl FnMut(DeviceAccess) + Send + 'static) -> Self {
+        Self { inner, callback: Box::new(callback) }
+    }
+
+    /// Logs each access to stderr.
+    pub fn to_stderr(inner: D) -> Self {
+        Self::new(inner, |access| eprintln!("{access}"))
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Device> Device for LoggingDevice<D> {
+    fn dei(&mut self, port: u8, context: DeviceContext) -> u8 {
+        let value = self.inner.dei(port, context);
+        (self.callback)(DeviceAccess::Read(port, value));
+        value
+    }
+
+    fn deo(&mut self, port: u8, value: u8, context: DeviceContext) {
+        (self.callback)(DeviceAccess::Write(port, value));
+        self.inner.deo(port, value, context);
+    }
+
+    fn dei2(&mut self, port: u8, context: DeviceContext) -> u16 {
+        let value = self.inner.dei2(port, context);
+        (self.callback)(DeviceAccess::ReadShort(port, value));
+        value
+    }
+
+    fn deo2(&mut self, port: u8, value: u16, context: DeviceContext) {
+        (self.callback)(DeviceAccess::WriteShort(port, value));
+        self.inner.deo2(port, value, context);
+    }
+
+    fn take_fault(&mut self) -> Option<UxnError> {
+        self.inner.take_fault()
+    }
+
+    fn fault_vector(&self) -> Option<u16> {
+        self.inner.fault_vector()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.inner.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.inner.restore(snapshot)
+    }
+
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        self.inner.wait_for_event()
+    }
+
+    fn poll_event(&mut self) -> Option<DeviceEvent> {
+        self.inner.poll_event()
+    }
+
+    fn is_halted(&self) -> bool {
+        self.inner.is_halted()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{device::EmptyDevice, Core};
+
+    use super::{DeviceAccess, LoggingDevice};
+
+    #[test]
+    fn test_logging_device() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let device = LoggingDevice::new(EmptyDevice::new(), {
+            let log = log.clone();
+            move |access| log.lock().unwrap().push(access)
+        });
+
+        let mut core = Core::new_with_uxntal("#2a #12 DEO #12 DEI #1234 #20 DEO2 #20 DEI2 BRK").unwrap();
+        core.set_device(device);
+        core.execute_until_break().unwrap();
+
+        assert_eq!(*log.lock().unwrap(), [
+            DeviceAccess::Write(0x12, 0x2a),
+            DeviceAccess::Read(0x12, 0x2a),
+            DeviceAccess::WriteShort(0x20, 0x1234),
+            DeviceAccess::ReadShort(0x20, 0x1234),
+        ]);
+        assert_eq!(log.lock().unwrap()[3].to_string(), "DEI2 20 -> 1234");
+    }
+}
@@ -0,0 +1,276 @@
+//! A MIDI device, which isn't part of Varvara, for talking to instruments and other music software.
+//! Like the network device, it's meant to be registered on a spare page, like 0xf0:
+//!
+//! ```text
+//! |f0 @Midi &vector $2 &read $1 &pending $1 $4 &write $1
+//! ```
+//!
+//! - `vector` runs whenever MIDI bytes arrive.
+//! - Reading `read` takes the next byte which arrived, or gives 0 if there aren't any.
+//! - `pending` is how many bytes are waiting to be read, up to 255.
+//! - Writing to `write` sends a byte. Bytes are gathered into whole messages before being sent, so
+//!   a ROM can write messages a byte at a time, and can use running status.
+//!
+//! Without the `midi` feature, bytes only come from [`MidiInput`] and only go to
+//! [`MidiDevice::set_output`], which is enough for tests and for bridging to something else. With
+//! it, [`MidiDevice::connect`] uses the system's MIDI ports through midir.
+
+use std::{collections::VecDeque, sync::{atomic::{AtomicU16, Ordering}, Arc, Mutex}};
+
+use super::{Device, DeviceContext, DeviceEvent, EventSender};
+
+/// Where to send complete MIDI messages which the ROM writes.
+type MidiOutput = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Passes bytes from a MIDI source to a [`MidiDevice`], and runs its vector. This can be cloned and
+/// used from any thread.
+#[derive(Clone)]
+pub struct MidiInput {
+    received: Arc<Mutex<VecDeque<u8>>>,
+    vector: Arc<AtomicU16>,
+    events: EventSender,
+}
+
+impl MidiInput {
+    /// Queues bytes for the ROM to read, and runs the vector if one is set.
+    pub fn send(&self, bytes: &[u8]) {
+        self.received.lock().unwrap().extend(bytes);
+        let vector = self.vector.load(Ordering::Relaxed);
+        if vector != 0 {
+            self.events.send(DeviceEvent::Vector(vector));
+        }
+    }
+}
+
+/// Sends and receives MIDI bytes. See the [module documentation](self) for its ports.
+pub struct MidiDevice {
+    ports: [u8; 16],
+    input: MidiInput,
+    output: Option<MidiOutput>,
+
+    /// The message which the ROM is part-way through writing.
+    message: Vec<u8>,
+
+    /// The last status byte written, for data bytes which follow on from it.
+    running_status: Option<u8>,
+
+    /// Keeps the system's ports open.
+    #[cfg(feature = "midi")]
+    connection: Option<midir::MidiInputConnection<()>>,
+}
+
+impl MidiDevice {
+    /// Creates the device, which will run its vector through `events`, usually from
+    /// [`Core::event_sender`](crate::Core::event_sender). It isn't connected to anything yet.
+    pub fn new(events: EventSender) -> Self {
+        Self {
+            ports: [0; 16],
+            input: MidiInput {
+                received: Arc::new(Mutex::new(VecDeque::new())),
+                vector: Arc::new(AtomicU16::new(0)),
+                events,
+            },
+            output: None,
+            message: vec![],
+            running_status: None,
+            #[cfg(feature = "midi")]
+            connection: None,
+        }
+    }
+
+    /// Gets a handle for passing incoming MIDI bytes to the ROM.
+    pub fn input(&self) -> MidiInput {
+        self.input.clone()
+    }
+
+    /// Sends each complete message which the ROM writes to `output`, instead of wherever they went
+    /// before.
+    pub fn set_output(&mut self, output: impl FnMut(&[u8]) + Send + 'static) {
+        self.output = Some(Box::new(output));
+    }
+
+    /// Connects to the system's first MIDI input and output ports whose names contain `name`, or
+    /// just the first ones if it's `None`. Either can be missing, as long as there's one of them.
+    #[cfg(feature = "midi")]
+    pub fn connect(&mut self, name: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        use midir::MidiIO;
+
+        fn find_port<M: MidiIO>(io: &M, name: Option<&str>) -> Option<M::Port> {
+            io.ports().into_iter().find(|port| {
+                name.is_none_or(|name| io.port_name(port).is_ok_and(|port_name| port_name.contains(name)))
+            })
+        }
+
+        let midi_input = midir::MidiInput::new("uxn")?;
+        let midi_output = midir::MidiOutput::new("uxn")?;
+        let (input_port, output_port) = (find_port(&midi_input, name), find_port(&midi_output, name));
+        if input_port.is_none() && output_port.is_none() {
+            return Err(format!("no MIDI ports matching {:?}", name.unwrap_or("anything")).into());
+        }
+
+        if let Some(port) = input_port {
+            let input = self.input();
+            let connection = midi_input.connect(&port, "uxn-in", move |_, bytes, _| input.send(bytes), ())
+                .map_err(|e| e.to_string())?;
+            self.connection = Some(connection);
+        }
+        if let Some(port) = output_port {
+            let mut connection = midi_output.connect(&port, "uxn-out").map_err(|e| e.to_string())?;
+            self.set_output(move |message| {
+                if let Err(e) = connection.send(message) {
+                    crate::warning!("Could not send MIDI message: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Adds a byte to the message being written, sending it if it's complete.
+    fn write(&mut self, byte: u8) {
+        match byte {
+            // Real-time messages can go between the bytes of another message
+            0xf8..=0xff => return self.send(&[byte]),
+
+            0xf7 if self.message.first() == Some(&0xf0) => {
+                self.message.push(byte);
+                let message = std::mem::take(&mut self.message);
+                return self.send(&message);
+            },
+
+            0x80..=0xff => {
+                self.message = vec![byte];
+                self.running_status = (byte < 0xf0).then_some(byte);
+            },
+
+            _ if self.message.is_empty() => match self.running_status {
+                Some(status) => self.message = vec![status, byte],
+                None => return, // Nothing to go with
+            },
+
+            _ => self.message.push(byte),
+        }
+
+        if self.message.first() != Some(&0xf0) && self.message.len() == message_length(self.message[0]) {
+            let message = std::mem::take(&mut self.message);
+            self.send(&message);
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) {
+        if let Some(output) = &mut self.output {
+            output(message);
+        }
+    }
+}
+
+/// How many bytes a message with this status byte has, including the status byte. System exclusive
+/// messages are ended by 0xf7 instead.
+fn message_length(status: u8) -> usize {
+    match status {
+        0xc0..=0xdf | 0xf1 | 0xf3 => 2,
+        0x80..=0xef | 0xf2 => 3,
+        _ => 1,
+    }
+}
+
+impl Device for MidiDevice {
+    fn dei(&mut self, port: u8, _context: DeviceContext) -> u8 {
+        let mut received = self.input.received.lock().unwrap();
+        match port & 0x0f {
+            0x2 => received.pop_front().unwrap_or(0),
+            0x3 => received.len().min(u8::MAX as usize) as u8,
+            offset => self.ports[offset as usize],
+        }
+    }
+
+    fn deo(&mut self, port: u8, value: u8, _context: DeviceContext) {
+        let offset = port & 0x0f;
+        self.ports[offset as usize] = value;
+        match offset {
+            0x1 => self.input.vector.store(u16::from_be_bytes([self.ports[0], self.ports[1]]), Ordering::Relaxed),
+            0x8 => self.write(value),
+            _ => {},
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ports = [0; 16];
+        self.input.vector.store(0, Ordering::Relaxed);
+        self.input.received.lock().unwrap().clear();
+        self.message.clear();
+        self.running_status = None;
+    }
+
+    // Vectors come through the event sender instead
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        DeviceEvent::Exit(0)
+    }
+
+    fn poll_event(&mut self) -> Option<DeviceEvent> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{Core, RunResult};
+
+    use super::MidiDevice;
+
+    #[test]
+    fn test_midi_output() {
+        // A note on, another using running status, a clock tick in the middle of a note off, and
+        // some system exclusive
+        let mut core = Core::new_with_uxntal("
+            |f0 @Midi &vector $2 &read $1 &pending $1 $4 &write $1
+            |100
+                #90 .Midi/write DEO #3c .Midi/write DEO #7f .Midi/write DEO
+                #40 .Midi/write DEO #7f .Midi/write DEO
+                #80 .Midi/write DEO #3c .Midi/write DEO #f8 .Midi/write DEO #00 .Midi/write DEO
+                #f0 .Midi/write DEO #7d .Midi/write DEO #f7 .Midi/write DEO
+                BRK
+        ").unwrap();
+        let mut device = MidiDevice::new(core.event_sender());
+        let sent = Arc::new(Mutex::new(vec![]));
+        let output = sent.clone();
+        device.set_output(move |message| output.lock().unwrap().push(message.to_vec()));
+        core.set_device(device);
+
+        assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+        assert_eq!(*sent.lock().unwrap(), [
+            vec![0x90, 0x3c, 0x7f],
+            vec![0x90, 0x40, 0x7f],
+            vec![0xf8],
+            vec![0x80, 0x3c, 0x00],
+            vec![0xf0, 0x7d, 0xf7],
+        ]);
+    }
+
+    #[test]
+    fn test_midi_input() {
+        // Stores how many bytes were waiting, then the bytes themselves
+        let mut core = Core::new_with_uxntal("
+            |f0 @Midi &vector $2 &read $1 &pending $1 $4 &write $1
+            |100
+                ;on-midi .Midi/vector DEO2
+                BRK
+            @on-midi
+                .Midi/pending DEI #00 STZ
+                .Midi/read DEI #01 STZ
+                .Midi/read DEI #02 STZ
+                .Midi/read DEI #03 STZ
+                .Midi/read DEI #04 STZ
+                BRK
+        ").unwrap();
+        let device = MidiDevice::new(core.event_sender());
+        let input = device.input();
+        core.set_device(device);
+        assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+
+        input.send(&[0x90, 0x3c, 0x7f]);
+        assert!(core.execute_pending().unwrap().is_some());
+        assert_eq!([0, 1, 2, 3, 4].map(|i| core.memory[i]), [3, 0x90, 0x3c, 0x7f, 0]);
+    }
+}
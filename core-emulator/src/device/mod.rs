@@ -1,19 +1,214 @@
+mod bus;
+pub use bus::*;
+
 mod empty;
 pub use empty::*;
 
+mod scripted;
+pub use scripted::*;
+
+mod logging;
+pub use logging::*;
+
+mod network;
+pub use network::*;
+
+mod midi;
+pub use midi::*;
+
+#[cfg(feature = "varvara")]
 mod varvara;
+#[cfg(feature = "varvara")]
 pub use varvara::*;
 
-use crate::Memory;
+use std::{error::Error, sync::{Arc, Mutex}, thread};
+
+use crate::{MainMemory, Stack, UxnError};
+
+/// A device attached to the core, which the ROM talks to through DEI and DEO.
+///
+/// Devices never hold onto the rest of the machine. Access to main memory and the stacks is only
+/// lent to them through a [`DeviceContext`] for the duration of a single DEI or DEO, while the
+/// core is paused, so a device can't observe memory while another device is changing it. Devices
+/// also can't run vectors while handling a port - instead, they're returned from
+/// [`Device::wait_for_event`] once the current vector has finished.
+///
+/// Devices must be [`Send`], so that a [`Core`](crate::Core) can be moved to a worker thread.
+pub trait Device: Send {
+    /// Handles a DEI instruction reading a byte from a port.
+    fn dei(&mut self, port: u8, context: DeviceContext) -> u8;
+
+    /// Handles a DEO instruction writing a byte to a port, including any side effects.
+    fn deo(&mut self, port: u8, value: u8, context: DeviceContext);
+
+    /// Handles a DEI2 instruction. By default, this reads the high byte, then the low byte from the
+    /// following port.
+    fn dei2(&mut self, port: u8, mut context: DeviceContext) -> u16 {
+        let hi = self.dei(port, context.reborrow());
+        let lo = self.dei(port.wrapping_add(1), context);
+        u16::from_be_bytes([hi, lo])
+    }
+
+    /// Handles a DEO2 instruction. By default, this writes the high byte, then the low byte to the
+    /// following port, so side effects for a short port should happen when its low byte is written.
+    fn deo2(&mut self, port: u8, value: u16, mut context: DeviceContext) {
+        let [hi, lo] = value.to_be_bytes();
+        self.deo(port, hi, context.reborrow());
+        self.deo(port.wrapping_add(1), lo, context);
+    }
 
-pub trait Device: Memory<AddressSpace = u8> {
+    /// Takes the fault caused by the last access, if there was one, like a ROM using a port which
+    /// isn't implemented. The core checks this after every DEI and DEO, and stops the ROM with the
+    /// fault as its error.
+    fn take_fault(&mut self) -> Option<UxnError> {
+        None
+    }
+
+    /// Where the ROM wants to handle faults, like a stack underflow, itself, as with
+    /// `.System/vector` in Varvara. Only the device on page 0x00 is asked. If this is `None`, the
+    /// fault stops execution instead.
+    fn fault_vector(&self) -> Option<u16> {
+        None
+    }
+
+    /// Returns the device to how it was when it was created, for a hard reset. Anything the host
+    /// has set up, like where input comes from, is kept.
+    fn reset(&mut self) {}
+
+    /// Saves whatever state the device needs to carry on from where it is, for
+    /// [`Core::snapshot`](crate::Core::snapshot). Devices without any state can save nothing.
+    fn snapshot(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Returns the device to a state saved with [`Device::snapshot`].
+    fn restore(&mut self, _snapshot: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Makes an independent copy of the device, for [`Core::try_clone`](crate::Core::try_clone).
+    /// Devices which can't be copied, like ones attached to a window, return `None`.
+    fn try_clone(&self) -> Option<Box<dyn Device>> {
+        None
+    }
+
+    /// Blocks until there's something for the core to do, and returns it.
     fn wait_for_event(&mut self) -> DeviceEvent;
+
+    /// Like [`Device::wait_for_event`], but returns `None` instead of blocking when nothing is
+    /// ready yet, for hosts which run their own main loop.
+    ///
+    /// By default this calls [`Device::wait_for_event`], which is only correct for devices which
+    /// never block.
+    fn poll_event(&mut self) -> Option<DeviceEvent> {
+        Some(self.wait_for_event())
+    }
+
+    /// Whether the device wants execution to stop, such as when a ROM asks to exit.
+    ///
+    /// This is checked after each DEO. If it's set, the running vector is abandoned and
+    /// [`Device::wait_for_event`] is called, which should return [`DeviceEvent::Exit`].
+    fn is_halted(&self) -> bool {
+        false
+    }
+
+    /// Whether the device has nothing left which could raise an event, so waiting for one would
+    /// only return [`DeviceEvent::Exit`]. While an [`EventSender`] is alive, the bus waits for that
+    /// instead, so that a ROM which only listens to a device like [`NetworkDevice`] keeps running.
+    fn is_idle(&self) -> bool {
+        false
+    }
+}
+
+/// Lets the host keep hold of a device after giving it to a [`Core`](crate::Core), to look at it
+/// between vectors, like reading the Screen's framebuffer.
+///
+/// Waiting for an event polls the device every millisecond or so, so the lock isn't held while the
+/// core waits. That doesn't help with devices which only implement [`Device::wait_for_event`],
+/// since polling them blocks too.
+impl<D: Device> Device for Arc<Mutex<D>> {
+    fn dei(&mut self, port: u8, context: DeviceContext) -> u8 {
+        self.lock().unwrap().dei(port, context)
+    }
+
+    fn deo(&mut self, port: u8, value: u8, context: DeviceContext) {
+        self.lock().unwrap().deo(port, value, context)
+    }
+
+    fn dei2(&mut self, port: u8, context: DeviceContext) -> u16 {
+        self.lock().unwrap().dei2(port, context)
+    }
+
+    fn deo2(&mut self, port: u8, value: u16, context: DeviceContext) {
+        self.lock().unwrap().deo2(port, value, context)
+    }
+
+    fn take_fault(&mut self) -> Option<UxnError> {
+        self.lock().unwrap().take_fault()
+    }
+
+    fn fault_vector(&self) -> Option<u16> {
+        self.lock().unwrap().fault_vector()
+    }
+
+    fn reset(&mut self) {
+        self.lock().unwrap().reset()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.lock().unwrap().snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.lock().unwrap().restore(snapshot)
+    }
+
+    // The lock is only held while polling, so that the host can use the device during the wait
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        loop {
+            if let Some(event) = self.lock().unwrap().poll_event() {
+                return event;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<DeviceEvent> {
+        self.lock().unwrap().poll_event()
+    }
+
+    fn is_halted(&self) -> bool {
+        self.lock().unwrap().is_halted()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.lock().unwrap().is_idle()
+    }
+}
+
+/// The parts of the machine which a device can access while handling DEI and DEO.
+pub struct DeviceContext<'a> {
+    pub memory: &'a mut MainMemory,
+    pub working_stack: &'a mut Stack,
+    pub return_stack: &'a mut Stack,
+}
+
+impl DeviceContext<'_> {
+    /// Borrows the context again for a shorter time, so that it can be passed on more than once.
+    pub fn reborrow(&mut self) -> DeviceContext<'_> {
+        DeviceContext {
+            memory: self.memory,
+            working_stack: self.working_stack,
+            return_stack: self.return_stack,
+        }
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DeviceEvent {
     /// Invoke a vector at the given address.
     Vector(u16),
 
-    /// Exit emulation.
-    Exit,
+    /// Exit emulation, with an exit code for the host process.
+    Exit(u8),
 }
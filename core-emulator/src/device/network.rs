@@ -0,0 +1,252 @@
+//! A UDP device, which isn't part of Varvara, for networked experiments. Like similar devices in
+//! other uxn forks, it's meant to be registered on a spare page, like 0xd0:
+//!
+//! ```text
+//! |d0 @Network &vector $2 &length $2 &host $4 &port $2 &bind $2 &send $2 &receive $2
+//! ```
+//!
+//! - `vector` runs whenever a datagram arrives.
+//! - `length` is how many bytes to send, or the most to receive. After sending or receiving, it's
+//!   set to how many bytes actually were, which is 0 if that failed.
+//! - `host` and `port` are the IPv4 address and port to send to. After receiving, they're the
+//!   sender's.
+//! - Writing a local port to `bind` starts listening there, or on any free port if it's 0. Reading
+//!   it gives the port which was bound, or 0 if that failed.
+//! - Writing an address to `send` sends `length` bytes from there.
+//! - Writing an address to `receive` copies the oldest waiting datagram there.
+//!
+//! As with other short ports, the action happens once the low byte is written.
+
+use std::{net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket}, sync::{atomic::{AtomicBool, AtomicU16, Ordering}, mpsc::{channel, Receiver}, Arc}, thread::{self, JoinHandle}, time::Duration};
+
+use super::{Device, DeviceContext, DeviceEvent, EventSender};
+
+/// How often the receiving thread checks whether it's still needed, which is also the longest that
+/// dropping a [`Binding`] waits for it.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// The socket a [`NetworkDevice`] has bound, and the datagrams which have arrived on it.
+struct Binding {
+    socket: UdpSocket,
+    received: Receiver<(Vec<u8>, SocketAddr)>,
+
+    /// Cleared to stop the receiving thread.
+    alive: Arc<AtomicBool>,
+
+    /// The receiving thread, which has its own handle to the socket. It's waited for when the
+    /// binding is dropped, so that the port is free to bind again straight away.
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Binding {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sends and receives UDP datagrams. See the [module documentation](self) for its ports.
+///
+/// Arriving datagrams run the vector through an [`EventSender`], so they're handled between the
+/// other devices' vectors.
+pub struct NetworkDevice {
+    ports: [u8; 16],
+    vector: Arc<AtomicU16>,
+    events: EventSender,
+    binding: Option<Binding>,
+}
+
+impl NetworkDevice {
+    /// Creates the device, which will run its vector through `events`, usually from
+    /// [`Core::event_sender`](crate::Core::event_sender).
+    pub fn new(events: EventSender) -> Self {
+        Self {
+            ports: [0; 16],
+            vector: Arc::new(AtomicU16::new(0)),
+            events,
+            binding: None,
+        }
+    }
+
+    fn short(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.ports[offset], self.ports[offset + 1]])
+    }
+
+    fn set_short(&mut self, offset: usize, value: u16) {
+        self.ports[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    fn bind(&mut self) {
+        self.binding = None;
+        let bound = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, self.short(0xa)))
+            .and_then(|socket| Ok((socket.try_clone()?, socket)));
+        let (receiver, socket) = match bound {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                crate::warning!("Could not bind UDP port {}: {e}", self.short(0xa));
+                self.set_short(0xa, 0);
+                return;
+            },
+        };
+        self.set_short(0xa, socket.local_addr().map_or(0, |address| address.port()));
+
+        let (sender, received) = channel();
+        let alive = Arc::new(AtomicBool::new(true));
+        let (vector, events, still_alive) = (self.vector.clone(), self.events.clone(), alive.clone());
+        let _ = receiver.set_read_timeout(Some(RECEIVE_TIMEOUT));
+        let thread = thread::spawn(move || {
+            let mut buffer = vec![0; u16::MAX as usize];
+            while still_alive.load(Ordering::Relaxed) {
+                let Ok((length, from)) = receiver.recv_from(&mut buffer) else { continue };
+                if sender.send((buffer[..length].to_vec(), from)).is_err() {
+                    return;
+                }
+                let vector = vector.load(Ordering::Relaxed);
+                if vector != 0 {
+                    events.send(DeviceEvent::Vector(vector));
+                }
+            }
+        });
+
+        self.binding = Some(Binding { socket, received, alive, thread: Some(thread) });
+    }
+
+    fn send(&mut self, context: DeviceContext) {
+        let (address, length) = (self.short(0xc), self.short(0x2));
+        let data: Vec<u8> = (0..length).map(|i| context.memory[address.wrapping_add(i) as usize]).collect();
+        let host = Ipv4Addr::new(self.ports[4], self.ports[5], self.ports[6], self.ports[7]);
+        let target = SocketAddrV4::new(host, self.short(0x8));
+
+        let sent = match &self.binding {
+            Some(binding) => binding.socket.send_to(&data, target).unwrap_or_else(|e| {
+                crate::warning!("Could not send to {target}: {e}");
+                0
+            }),
+            None => 0,
+        };
+        self.set_short(0x2, sent as u16);
+    }
+
+    fn receive(&mut self, context: DeviceContext) {
+        let Some((data, from)) = self.binding.as_ref().and_then(|binding| binding.received.try_recv().ok()) else {
+            self.set_short(0x2, 0);
+            return;
+        };
+
+        let address = self.short(0xe);
+        let length = data.len().min(self.short(0x2) as usize);
+        for (i, byte) in data[..length].iter().enumerate() {
+            context.memory[address.wrapping_add(i as u16) as usize] = *byte;
+        }
+        self.set_short(0x2, length as u16);
+        if let SocketAddr::V4(from) = from {
+            self.ports[4..8].copy_from_slice(&from.ip().octets());
+            self.set_short(0x8, from.port());
+        }
+    }
+}
+
+impl Device for NetworkDevice {
+    fn dei(&mut self, port: u8, _context: DeviceContext) -> u8 {
+        self.ports[(port & 0x0f) as usize]
+    }
+
+    fn deo(&mut self, port: u8, value: u8, context: DeviceContext) {
+        let offset = port & 0x0f;
+        self.ports[offset as usize] = value;
+        match offset {
+            0x1 => self.vector.store(self.short(0x0), Ordering::Relaxed),
+            0xb => self.bind(),
+            0xd => self.send(context),
+            0xf => self.receive(context),
+            _ => {},
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ports = [0; 16];
+        self.vector.store(0, Ordering::Relaxed);
+        self.binding = None;
+    }
+
+    // Vectors come through the event sender instead
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        DeviceEvent::Exit(0)
+    }
+
+    fn poll_event(&mut self) -> Option<DeviceEvent> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::UdpSocket, thread, time::{Duration, Instant}};
+
+    use crate::{Core, RunResult};
+
+    use super::NetworkDevice;
+
+    #[test]
+    fn test_network_device() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let [high, low] = peer.local_addr().unwrap().port().to_be_bytes();
+
+        // Sends "hi" to the peer, then echoes back whatever arrives with its first byte incremented
+        let mut core = Core::new_with_uxntal(&format!("
+            |d0 @Network &vector $2 &length $2 &host $4 &port $2 &bind $2 &send $2 &receive $2
+            |100
+                ;on-receive .Network/vector DEO2
+                #0000 .Network/bind DEO2
+                #7f00 .Network/host DEO2 #0001 .Network/host INC INC DEO2
+                #{high:02x}{low:02x} .Network/port DEO2
+                #0002 .Network/length DEO2 ;hi .Network/send DEO2
+                BRK
+            @on-receive
+                #0010 .Network/length DEO2 ;buffer .Network/receive DEO2
+                ;buffer LDAk INC ROT ROT STA
+                ;buffer .Network/send DEO2
+                BRK
+            @hi \"hi
+            @buffer $10
+        ")).unwrap();
+        core.set_device(NetworkDevice::new(core.event_sender()));
+        assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+
+        let mut buffer = [0; 16];
+        let (length, from) = peer.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"hi");
+
+        peer.send_to(b"abc", from).unwrap();
+        let start = Instant::now();
+        while core.execute_pending().unwrap().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5), "datagram never arrived");
+            thread::sleep(Duration::from_millis(1));
+        }
+        let (length, _) = peer.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"bbc");
+    }
+    #[test]
+    fn test_rebind() {
+        // Binds any free port, then that port again, which is only free once the first socket
+        // has been closed completely
+        let mut core = Core::new_with_uxntal("
+            |d0 @Network &vector $2 &length $2 &host $4 &port $2 &bind $2 &send $2 &receive $2
+            |100
+                #0000 .Network/bind DEO2
+                .Network/bind DEI2 DUP2 #00 STZ2
+                .Network/bind DEO2
+                .Network/bind DEI2 #02 STZ2
+                BRK
+        ").unwrap();
+        core.set_device(NetworkDevice::new(core.event_sender()));
+        assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+
+        let ports = [0, 2].map(|i| u16::from_be_bytes([core.memory[i], core.memory[i + 1]]));
+        assert_ne!(ports[0], 0);
+        assert_eq!(ports[0], ports[1]);
+    }
+}
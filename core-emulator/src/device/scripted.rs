@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+
+use super::{Device, DeviceContext, DeviceEvent};
+
+/// A test double which gives DEI a scripted sequence of values for each port, and records every
+/// DEO, so that ROM code which drives a device can be tested without any real I/O.
+///
+/// To look at what was written once the device has been given to a [`Core`](crate::Core), wrap it
+/// in an `Arc<Mutex<_>>` and keep a clone.
+#[derive(Clone)]
+pub struct ScriptedDevice {
+    reads: Vec<VecDeque<u8>>,
+    writes: Vec<(u8, u8)>,
+}
+
+impl Default for ScriptedDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedDevice {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![VecDeque::new(); 256],
+            writes: vec![],
+        }
+    }
+
+    /// Queues values to be returned by successive DEIs from a port, after any already queued.
+    /// Once a port's values run out, it reads as 0. A DEI2 takes one value from the port and one
+    /// from the port after it.
+    pub fn script(&mut self, port: u8, values: impl IntoIterator<Item = u8>) {
+        self.reads[port as usize].extend(values);
+    }
+
+    /// How many scripted values haven't been read from a port yet.
+    pub fn remaining(&self, port: u8) -> usize {
+        self.reads[port as usize].len()
+    }
+
+    /// Every DEO so far, as port and value, in the order they happened. Shorts are recorded as
+    /// their high byte, then their low byte on the following port.
+    pub fn writes(&self) -> &[(u8, u8)] {
+        &self.writes
+    }
+
+    /// The values written to one port, in order.
+    pub fn writes_to(&self, port: u8) -> Vec<u8> {
+        self.writes.iter().filter(|(p, _)| *p == port).map(|(_, value)| *value).collect()
+    }
+}
+
+impl Device for ScriptedDevice {
+    fn dei(&mut self, port: u8, _context: DeviceContext) -> u8 {
+        self.reads[port as usize].pop_front().unwrap_or(0)
+    }
+
+    fn deo(&mut self, port: u8, value: u8, _context: DeviceContext) {
+        self.writes.push((port, value));
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        DeviceEvent::Exit(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::Core;
+
+    use super::ScriptedDevice;
+
+    #[test]
+    fn test_scripted_device() {
+        let mut device = ScriptedDevice::new();
+        device.script(0x80, [0x01, 0x02]);
+        device.script(0x80, [0x03]);
+        device.script(0x82, [0x12]);
+        device.script(0x83, [0x34]);
+        let device = Arc::new(Mutex::new(device));
+
+        // Adds up three reads from one port, then writes the total and a short read from another
+        let mut core = Core::new_with_uxntal("#80 DEI #80 DEI ADD #80 DEI ADD #81 DEO #82 DEI2 #84 DEO2 #80 DEI #86 DEO BRK").unwrap();
+        core.set_device(device.clone());
+        core.execute_until_break().unwrap();
+
+        let device = device.lock().unwrap();
+        assert_eq!(device.writes(), [(0x81, 0x06), (0x84, 0x12), (0x85, 0x34), (0x86, 0x00)]);
+        assert_eq!(device.writes_to(0x84), [0x12]);
+        assert_eq!(device.remaining(0x80), 0);
+    }
+}
@@ -0,0 +1,139 @@
+//! Where the Screen's frames go once they're drawn.
+
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "window")]
+use super::display::DisplayOutput;
+
+/// Something which shows the Screen's frames, chosen when creating a
+/// [`VarvaraDevice`](super::VarvaraDevice).
+///
+/// The default is a window, through a `Display`, if the `window` feature is enabled.
+/// [`OffscreenBackend`] keeps frames in memory instead, for tests and hosts without a display.
+pub trait ScreenBackend: Send {
+    /// Shows a new frame of 0RGB pixels, row by row.
+    fn present(&mut self, width: u16, height: u16, pixels: Vec<u32>);
+
+    /// Shows the ROM's name, if the backend has somewhere to put it.
+    fn set_title(&mut self, _title: &str) {}
+
+    /// Whether the frames can no longer be seen, like when the user closes the window. The ROM
+    /// exits once this happens.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "window")]
+impl ScreenBackend for DisplayOutput {
+    fn present(&mut self, width: u16, height: u16, pixels: Vec<u32>) {
+        DisplayOutput::present(self, width, height, pixels)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        DisplayOutput::set_title(self, title)
+    }
+
+    fn is_closed(&self) -> bool {
+        DisplayOutput::is_closed(self)
+    }
+}
+
+/// The latest frame given to an [`OffscreenBackend`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OffscreenFrame {
+    pub width: u16,
+    pub height: u16,
+
+    /// 0RGB pixels, row by row.
+    pub pixels: Vec<u32>,
+
+    pub title: String,
+
+    /// How many frames have been presented.
+    pub count: u64,
+}
+
+/// A screen backend without a window, which keeps the latest frame in memory.
+///
+/// This is a handle, so a clone can be kept by the host to look at frames once the device has been
+/// given to a [`Core`](crate::Core).
+#[derive(Clone, Default)]
+pub struct OffscreenBackend(Arc<Mutex<OffscreenFrame>>);
+
+impl OffscreenBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of the latest frame.
+    pub fn frame(&self) -> OffscreenFrame {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl ScreenBackend for OffscreenBackend {
+    fn present(&mut self, width: u16, height: u16, pixels: Vec<u32>) {
+        let mut frame = self.0.lock().unwrap();
+        frame.width = width;
+        frame.height = height;
+        frame.pixels = pixels;
+        frame.count += 1;
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.0.lock().unwrap().title = title.to_string();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{device::VarvaraDevice, Core, RunResult};
+
+    use super::OffscreenBackend;
+
+    #[test]
+    fn test_offscreen_backend() {
+        let backend = OffscreenBackend::new();
+        let mut device = VarvaraDevice::with_screen_backend(backend.clone());
+        device.disable_stdin();
+        #[cfg(feature = "window")]
+        assert!(device.take_display().is_none());
+
+        // Sets the screen to 16x8, then fills it with colour 1 each frame
+        let mut core = Core::new_with_uxntal("#0010 #22 DEO2 #0008 #24 DEO2 ;on-frame #20 DEO2 BRK @on-frame #81 #2e DEO BRK").unwrap();
+        core.set_device(device);
+        core.execute_until_break().unwrap();
+        assert_eq!(core.execute_pending().unwrap(), Some(RunResult::Break));
+
+        // Frames are presented before the vector draws them, so the fill isn't there yet
+        let frame = backend.frame();
+        assert_eq!((frame.width, frame.height, frame.pixels.len()), (16, 8, 128));
+        assert!(frame.pixels.iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn test_offscreen_backend_pixel() {
+        let backend = OffscreenBackend::new();
+        let mut device = VarvaraDevice::with_screen_backend(backend.clone());
+        device.disable_stdin();
+
+        // Makes colour 1 white, then draws it at (3, 2) on a 16x8 screen straight away
+        let mut core = Core::new_with_uxntal("
+            #0f00 #08 DEO2 #0f00 #0a DEO2 #0f00 #0c DEO2
+            #0010 #22 DEO2 #0008 #24 DEO2
+            #0003 #28 DEO2 #0002 #2a DEO2 #01 #2e DEO
+            ;on-frame #20 DEO2 BRK
+            @on-frame BRK
+        ").unwrap();
+        core.set_device(device);
+        core.execute_until_break().unwrap();
+        assert_eq!(core.execute_pending().unwrap(), Some(RunResult::Break));
+
+        let frame = backend.frame();
+        let lit: Vec<_> = frame.pixels.iter().enumerate()
+            .filter(|(_, pixel)| **pixel != 0)
+            .collect();
+        assert_eq!(lit, [(2 * 16 + 3, &0xffffff)]);
+    }
+}
@@ -0,0 +1,386 @@
+use std::{io::{self, stdin, BufReader, Read, Write}, net::{TcpListener, TcpStream, ToSocketAddrs}, sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex}, thread, time::Duration};
+
+/// The kind of data in `.Console/read` when the Console vector is invoked, reported through
+/// `.Console/type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConsoleType {
+    /// Nothing has been delivered yet.
+    NoQueue = 0x00,
+
+    /// A byte read from standard input.
+    Stdin = 0x01,
+
+    /// A byte of a command-line argument.
+    Argument = 0x02,
+
+    /// The gap between two command-line arguments.
+    ArgumentSpacer = 0x03,
+
+    /// The end of the command-line arguments, or of standard input.
+    End = 0x04,
+}
+
+impl ConsoleType {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 => Some(ConsoleType::NoQueue),
+            0x01 => Some(ConsoleType::Stdin),
+            0x02 => Some(ConsoleType::Argument),
+            0x03 => Some(ConsoleType::ArgumentSpacer),
+            0x04 => Some(ConsoleType::End),
+            _ => None,
+        }
+    }
+}
+
+/// A handle for queueing console input from elsewhere, such as another thread.
+#[derive(Clone)]
+pub struct ConsoleInput(Sender<(u8, ConsoleType)>);
+
+impl ConsoleInput {
+    /// Queues a byte to be delivered to the Console vector. Returns `false` if the console no
+    /// longer exists.
+    pub fn send(&self, byte: u8, input_type: ConsoleType) -> bool {
+        self.0.send((byte, input_type)).is_ok()
+    }
+}
+
+/// Collects the bytes a ROM writes to `.Console/write` and `.Console/error` instead of printing
+/// them, so that tests can check a ROM's output. Clones share the same buffers, so one can be kept
+/// to read from after giving another to [`VarvaraDevice::capture_console`](super::VarvaraDevice::capture_console).
+#[derive(Clone, Default)]
+pub struct CapturingConsole(Arc<Mutex<CapturedOutput>>);
+
+#[derive(Default)]
+struct CapturedOutput {
+    output: Vec<u8>,
+    error: Vec<u8>,
+}
+
+impl CapturingConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written to `.Console/write` so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.0.lock().unwrap().output.clone()
+    }
+
+    /// Everything written to `.Console/error` so far.
+    pub fn error(&self) -> Vec<u8> {
+        self.0.lock().unwrap().error.clone()
+    }
+
+    /// Like [`CapturingConsole::output`], but as a string, replacing any invalid UTF-8.
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.output()).into_owned()
+    }
+
+    /// Like [`CapturingConsole::error`], but as a string, replacing any invalid UTF-8.
+    pub fn error_string(&self) -> String {
+        String::from_utf8_lossy(&self.error()).into_owned()
+    }
+
+    /// Empties both buffers, to check output from just what runs next.
+    pub fn clear(&self) {
+        let mut captured = self.0.lock().unwrap();
+        captured.output.clear();
+        captured.error.clear();
+    }
+}
+
+/// A TCP connection which stands in for stdin and stdout, so that a ROM running headlessly can be
+/// talked to remotely. Give it to [`VarvaraDevice::bridge_console`](super::VarvaraDevice::bridge_console).
+pub struct TcpConsole(TcpStream);
+
+impl TcpConsole {
+    /// Listens on an address, like `127.0.0.1:7777`, and waits for one connection.
+    pub fn accept(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        Ok(Self(stream))
+    }
+
+    /// Uses a connection which has already been made.
+    pub fn from_stream(stream: TcpStream) -> Self {
+        Self(stream)
+    }
+}
+
+/// Where `.Console/write` and `.Console/error` go.
+enum ConsoleOutput {
+    Standard,
+    Capture(CapturingConsole),
+
+    /// Only `.Console/write` goes to the connection, so errors still reach whoever is running
+    /// the emulator.
+    Tcp(TcpStream),
+}
+
+pub struct Console {
+    pub vector: Option<u16>,
+    pub read: u8,
+    pub input_type: ConsoleType,
+
+    sender: Sender<(u8, ConsoleType)>,
+    receiver: Receiver<(u8, ConsoleType)>,
+    stdin_enabled: bool,
+    reading_stdin: bool,
+    ended: bool,
+    output: ConsoleOutput,
+
+    /// How many of the queued ends are the ends of arguments, rather than of all input.
+    argument_ends: usize,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Console {
+            vector: None,
+            read: 0,
+            input_type: ConsoleType::NoQueue,
+
+            sender,
+            receiver,
+            stdin_enabled: true,
+            reading_stdin: false,
+            ended: false,
+            output: ConsoleOutput::Standard,
+            argument_ends: 0,
+        }
+    }
+
+    pub fn map_vector(&mut self, func: impl FnOnce(u16) -> u16) {
+        self.vector = Some(func(self.vector.unwrap_or(0)));
+
+        // Only start consuming stdin once a ROM shows interest in it
+        if self.stdin_enabled && !self.reading_stdin {
+            self.reading_stdin = true;
+            self.spawn_reader(stdin());
+        }
+    }
+
+    /// Queues a byte to be delivered to the Console vector.
+    pub fn queue_input(&self, byte: u8, input_type: ConsoleType) {
+        // The receiver lives as long as we do, so this can't fail
+        self.sender.send((byte, input_type)).unwrap();
+    }
+
+    /// Queues command-line arguments, as Varvara does: each byte of each argument, a spacer
+    /// between arguments, and an end once they're done. Input from stdin carries on afterwards.
+    ///
+    /// Until the first one is delivered, `.Console/type` is 1, so that the reset vector can tell
+    /// there are arguments coming.
+    pub fn queue_arguments(&mut self, arguments: &[String]) {
+        if arguments.is_empty() {
+            return;
+        }
+
+        for (i, argument) in arguments.iter().enumerate() {
+            if i > 0 {
+                self.queue_input(b'\n', ConsoleType::ArgumentSpacer);
+            }
+            for byte in argument.bytes() {
+                self.queue_input(byte, ConsoleType::Argument);
+            }
+        }
+        self.queue_input(b'\n', ConsoleType::End);
+        self.argument_ends += 1;
+        self.input_type = ConsoleType::Stdin;
+    }
+
+    pub fn input_handle(&self) -> ConsoleInput {
+        ConsoleInput(self.sender.clone())
+    }
+
+    /// Takes the next queued input, if there is one, and makes it visible through the `read` and
+    /// `type` ports ready for the Console vector to be invoked.
+    ///
+    /// If `block` is set, this waits for input to arrive, unless all input has already ended.
+    /// Returns whether any input was taken.
+    pub fn take_input(&mut self, block: bool) -> bool {
+        let next = if block && !self.ended {
+            self.receiver.recv().ok()
+        } else {
+            self.receiver.try_recv().ok()
+        };
+
+        let Some((byte, input_type)) = next else { return false };
+        self.set_input(byte, input_type);
+        true
+    }
+
+    /// Like [`Console::take_input`] when blocking, but gives up after a timeout.
+    pub fn take_input_timeout(&mut self, timeout: Duration) -> bool {
+        if self.ended {
+            return self.take_input(false);
+        }
+
+        let Ok((byte, input_type)) = self.receiver.recv_timeout(timeout) else { return false };
+        self.set_input(byte, input_type);
+        true
+    }
+
+    /// Makes a byte visible through the `read` and `type` ports, bypassing the queue.
+    pub fn set_input(&mut self, byte: u8, input_type: ConsoleType) {
+        self.read = byte;
+        self.input_type = input_type;
+        if input_type == ConsoleType::End {
+            if self.argument_ends > 0 {
+                self.argument_ends -= 1;
+            } else {
+                self.ended = true;
+            }
+        }
+    }
+
+    /// Whether the end of input has been delivered, after which no more will arrive.
+    pub fn has_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Stops stdin from being read, for when input is being provided some other way.
+    pub fn disable_stdin(&mut self) {
+        self.stdin_enabled = false;
+    }
+
+    /// Sends output to a [`CapturingConsole`] rather than stdout and stderr.
+    pub fn capture(&mut self, capture: CapturingConsole) {
+        self.output = ConsoleOutput::Capture(capture);
+    }
+
+    /// Takes input from a TCP connection instead of stdin, and sends `.Console/write` back over it.
+    /// Input ends when the other side stops sending.
+    pub fn bridge(&mut self, console: TcpConsole) -> io::Result<()> {
+        let reader = console.0.try_clone()?;
+        self.disable_stdin();
+        self.spawn_reader(reader);
+        self.output = ConsoleOutput::Tcp(console.0);
+        Ok(())
+    }
+
+    /// Writes a byte from `.Console/write`.
+    pub fn write(&self, byte: u8) {
+        match &self.output {
+            ConsoleOutput::Capture(capture) => capture.0.lock().unwrap().output.push(byte),
+
+            // If the other side has gone, there's nobody left to tell
+            ConsoleOutput::Tcp(stream) => { let _ = (&*stream).write_all(&[byte]); },
+
+            // Written raw, so that ROMs can output any bytes, not just ASCII
+            ConsoleOutput::Standard => { let _ = io::stdout().write_all(&[byte]); },
+        }
+    }
+
+    /// Writes a byte from `.Console/error`.
+    pub fn write_error(&self, byte: u8) {
+        match &self.output {
+            ConsoleOutput::Capture(capture) => capture.0.lock().unwrap().error.push(byte),
+            ConsoleOutput::Tcp(_) | ConsoleOutput::Standard => { let _ = io::stderr().write_all(&[byte]); },
+        }
+    }
+
+    /// Queues each byte from a source as input on another thread, then the end of input.
+    fn spawn_reader(&self, source: impl Read + Send + 'static) {
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for byte in BufReader::new(source).bytes() {
+                let Ok(byte) = byte else { break };
+                if sender.send((byte, ConsoleType::Stdin)).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send((0, ConsoleType::End));
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::{Read, Write}, net::{Shutdown, TcpListener, TcpStream}};
+
+    use super::{CapturingConsole, Console, ConsoleType, TcpConsole};
+
+    #[test]
+    fn test_console_input() {
+        let mut console = Console::new();
+        assert!(!console.take_input(false));
+        assert_eq!(console.input_type, ConsoleType::NoQueue);
+
+        console.queue_input(b'a', ConsoleType::Argument);
+        console.queue_input(0, ConsoleType::End);
+
+        assert!(console.take_input(false));
+        assert_eq!((console.read, console.input_type), (b'a', ConsoleType::Argument));
+        assert!(console.take_input(true));
+        assert_eq!((console.read, console.input_type), (0, ConsoleType::End));
+
+        // Once input has ended, blocking doesn't wait forever
+        assert!(!console.take_input(true));
+    }
+
+    #[test]
+    fn test_console_arguments() {
+        let mut console = Console::new();
+        console.queue_arguments(&["ab".to_string(), "c".to_string()]);
+        assert_eq!(console.input_type, ConsoleType::Stdin);
+        console.queue_input(b'x', ConsoleType::Stdin);
+        console.queue_input(0, ConsoleType::End);
+
+        let mut received = vec![];
+        while console.take_input(true) {
+            received.push((console.read, console.input_type));
+        }
+        assert_eq!(received, [
+            (b'a', ConsoleType::Argument), (b'b', ConsoleType::Argument),
+            (b'\n', ConsoleType::ArgumentSpacer),
+            (b'c', ConsoleType::Argument),
+            (b'\n', ConsoleType::End),
+            (b'x', ConsoleType::Stdin),
+            (0, ConsoleType::End),
+        ]);
+    }
+
+    #[test]
+    fn test_capturing_console() {
+        let capture = CapturingConsole::new();
+        let mut console = Console::new();
+        console.capture(capture.clone());
+
+        for byte in b"hi\n" {
+            console.write(*byte);
+        }
+        console.write_error(b'!');
+        assert_eq!(capture.output(), b"hi\n");
+        assert_eq!(capture.output_string(), "hi\n");
+        assert_eq!(capture.error_string(), "!");
+
+        capture.clear();
+        assert!(capture.output().is_empty() && capture.error().is_empty());
+    }
+
+    #[test]
+    fn test_tcp_console() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut console = Console::new();
+        console.bridge(TcpConsole::from_stream(listener.accept().unwrap().0)).unwrap();
+
+        client.write_all(b"hi").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut received = vec![];
+        while console.take_input(true) {
+            received.push((console.read, console.input_type));
+        }
+        assert_eq!(received, [(b'h', ConsoleType::Stdin), (b'i', ConsoleType::Stdin), (0, ConsoleType::End)]);
+
+        console.write(b'o');
+        console.write(b'k');
+        drop(console);
+        let mut sent = String::new();
+        client.read_to_string(&mut sent).unwrap();
+        assert_eq!(sent, "ok");
+    }
+}
@@ -0,0 +1,257 @@
+//! The Controller, which passes key presses from the window to the ROM through a [`Keymap`].
+
+use std::{collections::HashMap, error::Error, sync::mpsc::{channel, Receiver, Sender}};
+
+/// The Controller's buttons, as named in keymaps, and their bits in `.Controller/button`.
+const BUTTONS: [(&str, u8); 8] = [
+    ("a", 0x01), ("b", 0x02), ("select", 0x04), ("start", 0x08),
+    ("up", 0x10), ("down", 0x20), ("left", 0x40), ("right", 0x80),
+];
+
+/// Something happening to a key on the host. Keys are named like minifb's `Key`, such as `Up`,
+/// `LeftCtrl` or `Enter`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(String),
+    Released(String),
+
+    /// Text was typed. This is separate from the key presses which typed it.
+    Typed(char),
+}
+
+/// What a host key does on the Controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyBinding {
+    /// Holds down the buttons with these bits in `.Controller/button`.
+    Button(u8),
+
+    /// Sets `.Controller/key` to this byte when pressed.
+    Key(u8),
+}
+
+/// Which host keys do what on the Controller.
+///
+/// By default, the arrow keys are the directions, Ctrl is A, Alt is B, Shift is Select and Home is
+/// Start, like other Varvara emulators. Enter, Escape, Backspace, Tab and Delete set the key byte,
+/// since they don't type any text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap(HashMap<String, KeyBinding>);
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = [
+            ("Up", KeyBinding::Button(0x10)), ("Down", KeyBinding::Button(0x20)),
+            ("Left", KeyBinding::Button(0x40)), ("Right", KeyBinding::Button(0x80)),
+            ("LeftCtrl", KeyBinding::Button(0x01)), ("RightCtrl", KeyBinding::Button(0x01)),
+            ("LeftAlt", KeyBinding::Button(0x02)), ("RightAlt", KeyBinding::Button(0x02)),
+            ("LeftShift", KeyBinding::Button(0x04)), ("RightShift", KeyBinding::Button(0x04)),
+            ("Home", KeyBinding::Button(0x08)),
+            ("Enter", KeyBinding::Key(0x0d)), ("Escape", KeyBinding::Key(0x1b)),
+            ("Backspace", KeyBinding::Key(0x08)), ("Tab", KeyBinding::Key(0x09)),
+            ("Delete", KeyBinding::Key(0x7f)),
+        ];
+        Keymap(bindings.into_iter().map(|(key, binding)| (key.to_string(), binding)).collect())
+    }
+}
+
+impl Keymap {
+    /// Changes the default keymap with a config file. Each line binds a key to a button, to a key
+    /// byte in hex, or to nothing, and `#` starts a comment:
+    ///
+    /// ```text
+    /// # WASD for the directions
+    /// W = up
+    /// A = left
+    /// S = down
+    /// D = right
+    /// Space = a
+    /// CapsLock = key 1b
+    /// Home = none
+    /// ```
+    pub fn parse(text: &str) -> Result<Keymap, Box<dyn Error>> {
+        let mut keymap = Keymap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, binding) = line.split_once('=')
+                .ok_or_else(|| format!("line {}: expected `key = binding`", number + 1))?;
+            let binding = binding.trim();
+            let binding = if binding == "none" {
+                None
+            } else if let Some(byte) = binding.strip_prefix("key ") {
+                let byte = u8::from_str_radix(byte.trim(), 16)
+                    .map_err(|_| format!("line {}: invalid key byte {byte:?}", number + 1))?;
+                Some(KeyBinding::Key(byte))
+            } else {
+                let (_, bit) = BUTTONS.iter().find(|(name, _)| *name == binding)
+                    .ok_or_else(|| format!("line {}: unknown button {binding:?}", number + 1))?;
+                Some(KeyBinding::Button(*bit))
+            };
+            keymap.bind(key.trim(), binding);
+        }
+        Ok(keymap)
+    }
+
+    /// Binds a key, or unbinds it if `binding` is `None`.
+    pub fn bind(&mut self, key: &str, binding: Option<KeyBinding>) {
+        match binding {
+            Some(binding) => self.0.insert(key.to_string(), binding),
+            None => self.0.remove(key),
+        };
+    }
+
+    pub fn get(&self, key: &str) -> Option<KeyBinding> {
+        self.0.get(key).copied()
+    }
+}
+
+/// A handle for passing key events to the Controller from elsewhere, such as the window's thread.
+#[derive(Clone)]
+pub struct ControllerInput(Sender<KeyEvent>);
+
+impl ControllerInput {
+    /// Queues a key event. Returns `false` if the Controller no longer exists.
+    pub fn send(&self, event: KeyEvent) -> bool {
+        self.0.send(event).is_ok()
+    }
+}
+
+pub struct Controller {
+    pub vector: Option<u16>,
+    pub button: u8,
+    pub key: u8,
+    pub keymap: Keymap,
+
+    sender: Sender<KeyEvent>,
+    receiver: Receiver<KeyEvent>,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Controller { vector: None, button: 0, key: 0, keymap: Keymap::default(), sender, receiver }
+    }
+
+    pub fn input_handle(&self) -> ControllerInput {
+        ControllerInput(self.sender.clone())
+    }
+
+    pub fn map_vector(&mut self, f: impl FnOnce(u16) -> u16) {
+        self.vector = Some(f(self.vector.unwrap_or(0)));
+    }
+
+    /// Applies queued key events until one changes what the ROM can see, and returns whether one
+    /// did. The key byte only lasts for the vector run for it.
+    pub fn take_input(&mut self) -> bool {
+        self.key = 0;
+        while let Ok(event) = self.receiver.try_recv() {
+            let (button, key) = (self.button, self.key);
+            match event {
+                KeyEvent::Pressed(name) => match self.keymap.get(&name) {
+                    Some(KeyBinding::Button(bits)) => self.button |= bits,
+                    Some(KeyBinding::Key(byte)) => self.key = byte,
+                    None => {},
+                },
+                KeyEvent::Released(name) => if let Some(KeyBinding::Button(bits)) = self.keymap.get(&name) {
+                    self.button &= !bits;
+                },
+                KeyEvent::Typed(c) => if c.is_ascii() && !c.is_ascii_control() {
+                    self.key = c as u8;
+                },
+            }
+
+            if (self.button, self.key) != (button, key) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn reset(&mut self) {
+        self.vector = None;
+        self.button = 0;
+        self.key = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{device::{OffscreenBackend, Session, SessionEvent, VarvaraDevice}, Core, RunResult};
+
+    use super::{Controller, KeyBinding, KeyEvent, Keymap};
+
+    #[test]
+    fn test_keymap_parse() {
+        let keymap = Keymap::parse("
+            # WASD
+            W = up
+            Space = a   # Jump
+            CapsLock = key 1b
+            Home = none
+        ").unwrap();
+        assert_eq!(keymap.get("W"), Some(KeyBinding::Button(0x10)));
+        assert_eq!(keymap.get("Space"), Some(KeyBinding::Button(0x01)));
+        assert_eq!(keymap.get("CapsLock"), Some(KeyBinding::Key(0x1b)));
+        assert_eq!(keymap.get("Home"), None);
+        assert_eq!(keymap.get("Up"), Some(KeyBinding::Button(0x10)));
+
+        assert_eq!(Keymap::parse("W = jump").unwrap_err().to_string(), "line 1: unknown button \"jump\"");
+        assert!(Keymap::parse("\nW").is_err());
+        assert!(Keymap::parse("W = key zz").is_err());
+    }
+
+    #[test]
+    fn test_controller_input() {
+        let mut controller = Controller::new();
+        let input = controller.input_handle();
+        assert!(!controller.take_input());
+
+        input.send(KeyEvent::Pressed("Up".to_string()));
+        input.send(KeyEvent::Pressed("LeftCtrl".to_string()));
+        assert!(controller.take_input());
+        assert_eq!(controller.button, 0x10);
+        assert!(controller.take_input());
+        assert_eq!(controller.button, 0x11);
+
+        // Keys which aren't bound, and releasing keys which weren't held, do nothing
+        input.send(KeyEvent::Pressed("F1".to_string()));
+        input.send(KeyEvent::Released("Down".to_string()));
+        input.send(KeyEvent::Released("Up".to_string()));
+        assert!(controller.take_input());
+        assert_eq!(controller.button, 0x01);
+
+        input.send(KeyEvent::Typed('x'));
+        input.send(KeyEvent::Pressed("Enter".to_string()));
+        assert!(controller.take_input());
+        assert_eq!(controller.key, b'x');
+        assert!(controller.take_input());
+        assert_eq!(controller.key, 0x0d);
+        assert!(!controller.take_input());
+        assert_eq!(controller.key, 0);
+    }
+
+    #[test]
+    fn test_controller_replay() {
+        // Keeps the button byte, and counts how many times the vector runs
+        let mut core = Core::new_with_uxntal("
+            |80 @Controller &vector $2 &button $1 &key $1
+            |100 ;on-controller .Controller/vector DEO2 BRK
+            @on-controller .Controller/button DEI #00 STZ #01 LDZ INC #01 STZ BRK
+        ").unwrap();
+        let mut device = VarvaraDevice::with_screen_backend(OffscreenBackend::new());
+        device.disable_stdin();
+        device.replay_session(Session {
+            rom_hash: 0,
+            flags: vec![],
+            build: None,
+            events: vec![SessionEvent::Controller(0x10, 0), SessionEvent::Controller(0x11, 0)],
+        });
+        core.set_device(device);
+
+        assert_eq!(core.execute_until_exit().unwrap(), RunResult::Exit(0));
+        assert_eq!([core.memory[0x00], core.memory[0x01]], [0x11, 2]);
+    }
+}
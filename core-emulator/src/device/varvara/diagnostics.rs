@@ -0,0 +1,86 @@
+use std::collections::{HashMap, HashSet};
+
+use super::event_loop::FRAME_RATE;
+
+/// Ports which are slow to handle, because writing them resizes the framebuffer and window. These
+/// are all shorts, so writes to either byte count towards the first.
+const EXPENSIVE_PORTS: &[u8] = &[
+    0x22, // .Screen/width
+    0x24, // .Screen/height
+];
+
+/// How many frames in a row an expensive port must be written before it's reported. A second's
+/// worth rules out ROMs which are just animating a resize.
+const REPORT_AFTER_FRAMES: u32 = FRAME_RATE;
+
+/// Watches for ROMs writing to expensive ports every frame, which makes them crawl without any
+/// obvious reason why.
+#[derive(Default)]
+pub struct PortDiagnostics {
+    written: HashSet<u8>,
+    streaks: HashMap<u8, u32>,
+    reported: HashSet<u8>,
+}
+
+impl PortDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_write(&mut self, port: u8) {
+        let port = port & 0xfe;
+        if EXPENSIVE_PORTS.contains(&port) {
+            self.written.insert(port);
+        }
+    }
+
+    /// Called once per frame. Returns ports which have now been written every frame for long
+    /// enough to be worth reporting. Each port is only returned once.
+    pub fn end_frame(&mut self) -> Vec<u8> {
+        let mut newly_reported = vec![];
+        for &port in EXPENSIVE_PORTS {
+            let streak = self.streaks.entry(port).or_default();
+            if self.written.contains(&port) {
+                *streak += 1;
+            } else {
+                *streak = 0;
+            }
+
+            if *streak >= REPORT_AFTER_FRAMES && self.reported.insert(port) {
+                newly_reported.push(port);
+            }
+        }
+
+        self.written.clear();
+        newly_reported
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PortDiagnostics, REPORT_AFTER_FRAMES};
+
+    #[test]
+    fn test_port_diagnostics() {
+        let mut diagnostics = PortDiagnostics::new();
+
+        // Cheap ports, and expensive ones which aren't written every frame, are fine
+        for frame in 0..REPORT_AFTER_FRAMES * 2 {
+            diagnostics.record_write(0x28);
+            if frame % 2 == 0 {
+                diagnostics.record_write(0x22);
+            }
+            assert_eq!(diagnostics.end_frame(), []);
+        }
+
+        // Writing one every frame is reported once
+        for _ in 1..REPORT_AFTER_FRAMES {
+            diagnostics.record_write(0x24);
+            assert_eq!(diagnostics.end_frame(), []);
+        }
+        diagnostics.record_write(0x25);
+        assert_eq!(diagnostics.end_frame(), [0x24]);
+        diagnostics.record_write(0x24);
+        assert_eq!(diagnostics.end_frame(), []);
+    }
+}
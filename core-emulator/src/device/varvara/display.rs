@@ -0,0 +1,323 @@
+//! The window which presents the Screen.
+//!
+//! Windows generally have to live on the host's main thread, so the Screen doesn't own one.
+//! Instead, it publishes frames to a [`Display`], which the host runs on its main thread while the
+//! core runs elsewhere. This keeps the window responsive even while a vector takes a long time.
+//!
+//! Several displays can share one window as tabs with [`Display::run_tabs`]. Every core keeps
+//! publishing its frames, but only the focused tab's are shown.
+
+use std::{sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}, thread, time::Duration};
+
+use minifb::{InputCallback, Key, Scale, Window, WindowOptions};
+
+use super::{ControllerInput, KeyEvent};
+
+// The latest frame, waiting to be shown
+struct Frame {
+    width: u16,
+    height: u16,
+    pixels: Vec<u32>,
+    title: String,
+    fresh: bool,
+}
+
+/// The Screen's half of the display, which publishes frames.
+pub struct DisplayOutput {
+    frame: Arc<Mutex<Frame>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl DisplayOutput {
+    /// Creates both halves of a display.
+    pub fn new(width: u16, height: u16, title: &str) -> (Self, Display) {
+        let frame = Arc::new(Mutex::new(Frame {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize],
+            title: title.to_string(),
+            fresh: true,
+        }));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let output = DisplayOutput { frame: frame.clone(), closed: closed.clone() };
+        (output, Display { frame, closed, controller: None, scale: Scale::X1 })
+    }
+
+    /// Replaces the frame being shown. Frames which the display doesn't get around to showing are
+    /// dropped.
+    pub fn present(&self, width: u16, height: u16, pixels: Vec<u32>) {
+        let mut frame = self.frame.lock().unwrap();
+        frame.width = width;
+        frame.height = height;
+        frame.pixels = pixels;
+        frame.fresh = true;
+    }
+
+    pub fn set_title(&self, title: &str) {
+        let mut frame = self.frame.lock().unwrap();
+        frame.title = title.to_string();
+        frame.fresh = true;
+    }
+
+    /// Whether the user has closed the window.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+/// The host's half of the display, which owns the window.
+pub struct Display {
+    frame: Arc<Mutex<Frame>>,
+    closed: Arc<AtomicBool>,
+    controller: Option<ControllerInput>,
+    scale: Scale,
+}
+
+impl Display {
+    /// Makes the window bigger than the Screen, by a scale of 1, 2, 4, 8, 16 or 32. Other scales
+    /// are rounded down to one of those.
+    pub fn set_scale(&mut self, scale: u8) {
+        self.scale = match scale {
+            0..=1 => Scale::X1,
+            2..=3 => Scale::X2,
+            4..=7 => Scale::X4,
+            8..=15 => Scale::X8,
+            16..=31 => Scale::X16,
+            _ => Scale::X32,
+        };
+    }
+
+    /// Sends the window's key presses and typed text to the Controller.
+    pub fn forward_keys(&mut self, controller: ControllerInput) {
+        self.controller = Some(controller);
+    }
+
+    /// Opens the window and shows frames as they're published, until either the window is closed
+    /// or the device which publishes frames is dropped.
+    ///
+    /// Returns whether the window was closed by the user.
+    pub fn run(self) -> bool {
+        Display::run_tabs(vec![self])
+    }
+
+    /// Like [`Display::run`], but shows several displays in one window as tabs, each with its
+    /// title and tab number in the window's title. F1 to F9 switch to the first nine tabs, and keys
+    /// only go to the focused tab's Controller. The window uses the first tab's scale.
+    ///
+    /// Tabs whose devices are dropped are skipped over, and this returns once they all have. Closing
+    /// the window closes every tab.
+    pub fn run_tabs(tabs: Vec<Display>) -> bool {
+        let scale = tabs.first().map_or(Scale::X1, |tab| tab.scale);
+        let input = TabInput {
+            controllers: tabs.iter().map(|tab| tab.controller.clone()).collect(),
+            focus: Arc::new(AtomicUsize::new(0)),
+            held: Arc::new(Mutex::new(vec![])),
+        };
+        let mut window: Option<(Window, u16, u16)> = None;
+        let mut title = String::new();
+        let mut shown = None;
+
+        loop {
+            // Only the device and this hold a tab's frame once its device has gone, so move off it
+            let alive = |tab: &Display| Arc::strong_count(&tab.frame) > 1;
+            let Some(focused) = next_focus(input.focus.load(Ordering::Relaxed), tabs.len(), |i| alive(&tabs[i])) else {
+                return false;
+            };
+            input.focus.store(focused, Ordering::Relaxed);
+
+            // A tab which has just been switched to needs showing, even if it hasn't drawn anything
+            let frame = {
+                let mut frame = tabs[focused].frame.lock().unwrap();
+                let fresh = frame.fresh || shown != Some(focused);
+                frame.fresh = false;
+                fresh.then(|| (frame.width, frame.height, frame.pixels.clone(), frame.title.clone()))
+            };
+            shown = Some(focused);
+
+            match frame {
+                Some((width, height, pixels, new_title)) => {
+                    let new_title = if tabs.len() > 1 {
+                        format!("{new_title} [{}/{}]", focused + 1, tabs.len())
+                    } else {
+                        new_title
+                    };
+
+                    // You can't resize the window in minifb - just create a new one instead
+                    if window.as_ref().is_none_or(|(_, w, h)| (*w, *h) != (width, height)) {
+                        window = Some((create_window(width, height, &new_title, scale, &input), width, height));
+                        title = new_title.clone();
+                    }
+                    let (window, ..) = window.as_mut().unwrap();
+                    if title != new_title {
+                        window.set_title(&new_title);
+                        title = new_title;
+                    }
+
+                    window
+                        .update_with_buffer(&pixels, width as usize, height as usize)
+                        .expect("could not update framebuffer");
+                },
+
+                // Keep handling window events while there's nothing new to show
+                None => match &mut window {
+                    Some((window, ..)) => window.update(),
+                    None => thread::sleep(Duration::from_millis(1)),
+                },
+            }
+
+            if window.as_ref().is_some_and(|(window, ..)| !window.is_open()) {
+                for tab in &tabs {
+                    tab.closed.store(true, Ordering::Relaxed);
+                }
+                return true;
+            }
+        }
+    }
+}
+
+fn create_window(width: u16, height: u16, title: &str, scale: Scale, input: &TabInput) -> Window {
+    let mut window = Window::new(
+        title,
+        width as usize, height as usize, // Correct-feeling default size
+        WindowOptions { resize: false, scale, ..WindowOptions::default() },
+    ).expect("could not create window");
+
+    // Frames are paced by the core's event loop, so this only limits how often we spin
+    // waiting for new ones
+    window.set_target_fps(120);
+    window.set_input_callback(Box::new(input.clone()));
+    window
+}
+
+/// The first tab from `focus` onwards which is still alive, wrapping around.
+fn next_focus(focus: usize, tabs: usize, alive: impl Fn(usize) -> bool) -> Option<usize> {
+    (0..tabs).map(|i| (focus + i) % tabs).find(|i| alive(*i))
+}
+
+/// Passes the window's keys to the focused tab's Controller, and switches tabs with the F keys.
+#[derive(Clone)]
+struct TabInput {
+    controllers: Vec<Option<ControllerInput>>,
+    focus: Arc<AtomicUsize>,
+
+    // Keys which the focused tab has been told are down, so that it can be told they're up when
+    // the focus moves away
+    held: Arc<Mutex<Vec<String>>>,
+}
+
+impl TabInput {
+    fn focused(&self) -> Option<ControllerInput> {
+        self.controllers.get(self.focus.load(Ordering::Relaxed))?.clone()
+    }
+
+    /// The tab an F key switches to. The F keys are left alone when there's only one tab.
+    fn tab_for_key(&self, key: Key) -> Option<usize> {
+        const KEYS: [Key; 9] = [Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9];
+        if self.controllers.len() < 2 {
+            return None;
+        }
+        KEYS.iter().position(|k| *k == key).filter(|tab| *tab < self.controllers.len())
+    }
+}
+
+impl InputCallback for TabInput {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(mut controller) = self.focused() {
+            controller.add_char(uni_char);
+        }
+    }
+
+    fn set_key_state(&mut self, key: Key, pressed: bool) {
+        if let Some(tab) = self.tab_for_key(key) {
+            if pressed {
+                let released = std::mem::take(&mut *self.held.lock().unwrap());
+                if let Some(controller) = self.focused() {
+                    for name in released {
+                        controller.send(KeyEvent::Released(name));
+                    }
+                }
+                self.focus.store(tab, Ordering::Relaxed);
+            }
+            return;
+        }
+
+        let name = format!("{key:?}");
+        let mut held = self.held.lock().unwrap();
+        held.retain(|held| *held != name);
+        if pressed {
+            held.push(name);
+        }
+        drop(held);
+        if let Some(mut controller) = self.focused() {
+            controller.set_key_state(key, pressed);
+        }
+    }
+}
+
+impl InputCallback for ControllerInput {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(c) = char::from_u32(uni_char) {
+            self.send(KeyEvent::Typed(c));
+        }
+    }
+
+    fn set_key_state(&mut self, key: Key, pressed: bool) {
+        let name = format!("{key:?}");
+        self.send(if pressed { KeyEvent::Pressed(name) } else { KeyEvent::Released(name) });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{atomic::AtomicUsize, Arc, Mutex};
+
+    use minifb::{InputCallback, Key};
+
+    use crate::device::varvara::Controller;
+
+    use super::{next_focus, DisplayOutput, TabInput};
+
+    #[test]
+    fn test_run_ends_with_device() {
+        // Nothing is presented, so the display never opens a window
+        let (output, display) = DisplayOutput::new(1, 1, "uxn");
+        display.frame.lock().unwrap().fresh = false;
+        drop(output);
+        assert!(!display.run());
+    }
+
+    #[test]
+    fn test_tab_input() {
+        let mut controllers = [Controller::new(), Controller::new()];
+        let mut input = TabInput {
+            controllers: controllers.iter().map(|controller| Some(controller.input_handle())).collect(),
+            focus: Arc::new(AtomicUsize::new(0)),
+            held: Arc::new(Mutex::new(vec![])),
+        };
+
+        // Keys go to the focused tab, and are let go of when switching away from it
+        input.set_key_state(Key::Up, true);
+        input.set_key_state(Key::F2, true);
+        input.set_key_state(Key::F2, false);
+        input.set_key_state(Key::Down, true);
+        assert!(controllers[0].take_input());
+        assert_eq!(controllers[0].button, 0x10);
+        assert!(controllers[0].take_input());
+        assert_eq!(controllers[0].button, 0x00);
+        assert!(controllers[1].take_input());
+        assert_eq!(controllers[1].button, 0x20);
+        assert!(!controllers[1].take_input());
+
+        // F keys without a tab go to the ROM as usual
+        input.set_key_state(Key::F3, true);
+        assert_eq!(input.held.lock().unwrap().as_slice(), ["Down", "F3"]);
+    }
+
+    #[test]
+    fn test_next_focus() {
+        assert_eq!(next_focus(1, 3, |_| true), Some(1));
+        assert_eq!(next_focus(1, 3, |i| i != 1 && i != 2), Some(0));
+        assert_eq!(next_focus(0, 3, |_| false), None);
+    }
+}
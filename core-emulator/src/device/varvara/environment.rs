@@ -0,0 +1,138 @@
+//! A non-standard device page which lets ROMs find out about the host they're running on, so they
+//! can fit in without hardcoding anything. It must be explicitly enabled by the host.
+//!
+//! | Port        | Name    | Description                                                        |
+//! |-------------|---------|--------------------------------------------------------------------|
+//! | 0xe0        | field   | Write to select a string to read, and rewind to its first byte     |
+//! | 0xe1        | read    | Read the next byte of the selected string, or 0 past its end       |
+//! | 0xe2        | present | Always reads 1 when enabled, so ROMs can detect support            |
+//! | 0xe8 - 0xed | r, g, b | Preferred theme colours in `.System` format, or 0 if there's none  |
+//!
+//! The selectable strings are:
+//!   - 0x00: the locale, like `en_GB.UTF-8`
+//!   - 0x01: a directory the ROM can safely store data in, as a path the File device understands.
+//!     Like the File device's other paths, it's relative to the working directory, which is the
+//!     root of everything the ROM can reach, so it never reveals where that is on the host.
+
+use std::{env, fs};
+
+pub struct Environment {
+    pub locale: String,
+    pub data_path: String,
+
+    /// Red, green and blue shorts, as they'd be written to the System device.
+    pub theme: Option<[u16; 3]>,
+
+    field: u8,
+    cursor: usize,
+}
+
+impl Environment {
+    pub fn new(locale: String, data_path: String, theme: Option<[u16; 3]>) -> Self {
+        Environment { locale, data_path, theme, field: 0, cursor: 0 }
+    }
+
+    /// Builds an environment from the host's configuration, for a ROM with the given name.
+    ///
+    /// The data path is `uxn/<rom name>/`, relative to the File device's root. The theme is read
+    /// from a `.theme` file in the working directory, following the convention used by other uxn
+    /// applications.
+    pub fn from_host(rom_name: &str) -> Self {
+        let locale = env::var("LC_ALL")
+            .or_else(|_| env::var("LANG"))
+            .unwrap_or_default();
+        let data_path = format!("uxn/{rom_name}/");
+
+        let theme = fs::read(".theme").ok()
+            .filter(|bytes| bytes.len() >= 6)
+            .map(|bytes| [
+                u16::from_be_bytes([bytes[0], bytes[1]]),
+                u16::from_be_bytes([bytes[2], bytes[3]]),
+                u16::from_be_bytes([bytes[4], bytes[5]]),
+            ]);
+
+        Self::new(locale, data_path, theme)
+    }
+
+    pub fn read_byte(&mut self, port: u8) -> u8 {
+        match port {
+            // read
+            0x1 => {
+                let byte = self.selected().as_bytes().get(self.cursor).copied().unwrap_or(0);
+                self.cursor += 1;
+                byte
+            },
+
+            // present
+            0x2 => 1,
+
+            // r, g, b
+            0x8..=0xd => {
+                let Some(theme) = self.theme else { return 0 };
+                let short = theme[(port as usize - 0x8) / 2];
+                short.to_be_bytes()[port as usize % 2]
+            },
+
+            _ => 0,
+        }
+    }
+
+    pub fn write_byte(&mut self, port: u8, byte: u8) {
+        // field
+        if port == 0x0 {
+            self.field = byte;
+            self.cursor = 0;
+        }
+    }
+
+    /// Which string is selected, and how far through it reading has got, for snapshots.
+    pub fn position(&self) -> (u8, usize) {
+        (self.field, self.cursor)
+    }
+
+    pub fn set_position(&mut self, field: u8, cursor: usize) {
+        self.field = field;
+        self.cursor = cursor;
+    }
+
+    fn selected(&self) -> &str {
+        match self.field {
+            0x00 => &self.locale,
+            0x01 => &self.data_path,
+            _ => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Environment;
+
+    #[test]
+    fn test_environment_ports() {
+        let mut env = Environment::new(
+            "en_GB".to_string(), "data".to_string(), Some([0x0123, 0x4567, 0x89ab]),
+        );
+        assert_eq!(env.read_byte(0x2), 1);
+
+        // Read a string, including past its end
+        env.write_byte(0x0, 0x01);
+        let bytes: Vec<u8> = (0..5).map(|_| env.read_byte(0x1)).collect();
+        assert_eq!(bytes, b"data\0");
+
+        // Selecting a field rewinds it
+        env.write_byte(0x0, 0x00);
+        assert_eq!(env.read_byte(0x1), b'e');
+        env.write_byte(0x0, 0x00);
+        assert_eq!(env.read_byte(0x1), b'e');
+
+        assert_eq!(env.read_byte(0x8), 0x01);
+        assert_eq!(env.read_byte(0xd), 0xab);
+    }
+
+    #[test]
+    fn test_host_data_path() {
+        // The host's own directories aren't given away
+        assert_eq!(Environment::from_host("clock").data_path, "uxn/clock/");
+    }
+}
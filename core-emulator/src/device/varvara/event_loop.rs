@@ -0,0 +1,119 @@
+//! Decides which of the Varvara devices' vectors to run next.
+
+use std::{thread, time::{Duration, Instant}};
+
+use super::console::Console;
+
+/// The rate at which the Screen vector is invoked.
+pub const FRAME_RATE: u32 = 60;
+
+/// A device with a vector which is ready to run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventSource {
+    Screen,
+    Console,
+}
+
+/// Merges the devices' event sources into a single stream of vectors.
+///
+/// When several sources are ready at once, a due frame wins over pending input, so that a flood of
+/// input can't stall the display. In between frames, input is delivered as soon as it arrives.
+pub struct EventLoop {
+    frame_interval: Duration,
+
+    /// Not set until a source is first picked, so that creating the loop doesn't read the clock.
+    /// There's no clock on wasm32-unknown-unknown, where hosts run frames themselves instead.
+    next_frame: Option<Instant>,
+}
+
+impl Default for EventLoop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLoop {
+    pub fn new() -> Self {
+        Self::with_frame_interval(Duration::from_secs(1) / FRAME_RATE)
+    }
+
+    pub fn with_frame_interval(frame_interval: Duration) -> Self {
+        EventLoop { frame_interval, next_frame: None }
+    }
+
+    pub fn frame_interval(&self) -> Duration {
+        self.frame_interval
+    }
+
+    /// Picks the next source to invoke. The Screen is only considered if `screen` is set, and the
+    /// Console only if it's given.
+    ///
+    /// If `block` is set, this waits until a source is ready. Otherwise, or if no source could ever
+    /// become ready, it returns `None`.
+    pub fn next_source(&mut self, screen: bool, mut console: Option<&mut Console>, block: bool) -> Option<EventSource> {
+        loop {
+            let now = Instant::now();
+            let next_frame = *self.next_frame.get_or_insert(now);
+            if screen && now >= next_frame {
+                // If we've fallen far behind, don't try to catch up with a burst of frames
+                let next_frame = next_frame + self.frame_interval;
+                self.next_frame = Some(if next_frame < now { now + self.frame_interval } else { next_frame });
+                return Some(EventSource::Screen);
+            }
+
+            if let Some(console) = console.as_deref_mut() && console.take_input(false) {
+                return Some(EventSource::Console);
+            }
+
+            if !block {
+                return None;
+            }
+
+            // Wait for whichever comes first, input or the next frame
+            let until_frame = next_frame.saturating_duration_since(now);
+            match (screen, console.as_deref_mut()) {
+                (true, Some(console)) => if console.take_input_timeout(until_frame) {
+                    return Some(EventSource::Console);
+                },
+                (true, None) => thread::sleep(until_frame),
+                (false, Some(console)) => return console.take_input(true).then_some(EventSource::Console),
+                (false, None) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::device::ConsoleType;
+
+    use super::{Console, EventLoop, EventSource};
+
+    #[test]
+    fn test_priority() {
+        let mut event_loop = EventLoop::with_frame_interval(Duration::from_secs(3600));
+        let mut console = Console::new();
+        console.queue_input(b'a', ConsoleType::Stdin);
+
+        // The first frame is due straight away, and beats input
+        assert_eq!(event_loop.next_source(true, Some(&mut console), false), Some(EventSource::Screen));
+        assert_eq!(event_loop.next_source(true, Some(&mut console), false), Some(EventSource::Console));
+        assert_eq!(console.read, b'a');
+
+        // The next frame isn't due for a while
+        assert_eq!(event_loop.next_source(true, Some(&mut console), false), None);
+
+        // With nothing to wait for, blocking gives up
+        assert_eq!(event_loop.next_source(false, None, true), None);
+    }
+
+    #[test]
+    fn test_frame_pacing() {
+        let mut event_loop = EventLoop::with_frame_interval(Duration::from_millis(5));
+        assert_eq!(event_loop.next_source(true, None, false), Some(EventSource::Screen));
+        assert_eq!(event_loop.next_source(true, None, false), None);
+        assert_eq!(event_loop.next_source(true, None, true), Some(EventSource::Screen));
+    }
+}
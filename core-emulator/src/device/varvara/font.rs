@@ -0,0 +1,90 @@
+//! A tiny built-in font, so that the emulator can draw its own text onto the screen without a font
+//! rendering dependency.
+//!
+//! Glyphs are 3x5 pixels, 1bpp. Only printable ASCII is included, with lowercase letters drawn as
+//! uppercase, and anything else drawn as `?`.
+
+use super::{Framebuffer, Layer};
+
+pub const GLYPH_WIDTH: u16 = 3;
+pub const GLYPH_HEIGHT: u16 = 5;
+
+/// The distance between the starts of two characters, or two lines.
+pub const ADVANCE: (u16, u16) = (GLYPH_WIDTH + 1, GLYPH_HEIGHT + 1);
+
+// One glyph per entry, from ' ' to '_' and then '{' to '~'. Each has 15 bits, a row of 3 at a time
+// from the top, most significant first.
+const GLYPHS: [u16; 68] = [
+    0x0000, 0x2482, 0x5a00, 0x5f7d, 0x3c9e, 0x42a1, 0x2aab, 0x2400,
+    0x1491, 0x4494, 0x0aa8, 0x05d0, 0x0014, 0x01c0, 0x0002, 0x12a4,
+    0x7b6f, 0x2c97, 0x62a7, 0x628e, 0x5bc9, 0x798e, 0x39ef, 0x7292,
+    0x7bef, 0x7bce, 0x0410, 0x0414, 0x1511, 0x0e38, 0x4454, 0x6282,
+    0x2b63, 0x2bed, 0x6bae, 0x3923, 0x6b6e, 0x79a7, 0x79a4, 0x396b,
+    0x5bed, 0x7497, 0x126a, 0x5bad, 0x4927, 0x5fed, 0x6b6d, 0x2b6a,
+    0x6ba4, 0x2b73, 0x6bad, 0x388e, 0x7492, 0x5b6f, 0x5b6a, 0x5bfd,
+    0x5aad, 0x5a92, 0x72a7, 0x6926, 0x4889, 0x324b, 0x2a00, 0x0007,
+    0x1591, 0x2492, 0x44d4, 0x0780,
+];
+
+/// Gets a glyph's rows, from the top, with the leftmost pixel in bit 2.
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    let index = match c.to_ascii_uppercase() {
+        c @ ' '..='_' => c as usize - ' ' as usize,
+        c @ '{'..='~' => c as usize - '{' as usize + 64,
+        _ => '?' as usize - ' ' as usize,
+    };
+
+    let bits = GLYPHS[index];
+    std::array::from_fn(|row| ((bits >> (3 * (4 - row))) & 0b111) as u8)
+}
+
+impl Framebuffer {
+    /// Draws text in the built-in font, with its top-left corner at the given position. Newlines
+    /// start a new line, back at the original x position.
+    ///
+    /// Only the glyphs' pixels are drawn, so whatever is behind the text shows through.
+    pub fn draw_text(&mut self, x: u16, y: u16, text: &str, colour_index: u8, layer: Layer) {
+        let (mut cx, mut cy) = (x, y);
+        for c in text.chars() {
+            if c == '\n' {
+                cx = x;
+                cy = cy.wrapping_add(ADVANCE.1);
+                continue;
+            }
+
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> column) != 0 {
+                        self.draw_pixel(cx.wrapping_add(column), cy.wrapping_add(row as u16), colour_index, layer);
+                    }
+                }
+            }
+            cx = cx.wrapping_add(ADVANCE.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glyph, Framebuffer, Layer};
+
+    #[test]
+    fn test_glyph() {
+        assert_eq!(glyph('A'), [0b010, 0b101, 0b111, 0b101, 0b101]);
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), [0b000, 0b011, 0b110, 0b000, 0b000]);
+        assert_eq!(glyph('é'), glyph('?'));
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let mut fb = Framebuffer::new(8, 12);
+        fb.draw_text(0, 0, "T\n_", 2, Layer::Foreground);
+
+        let row = |y| (0..4).map(|x| fb.get_pixel(x, y, Layer::Foreground).unwrap()).collect::<Vec<_>>();
+        assert_eq!(row(0), [2, 2, 2, 0]);
+        assert_eq!(row(1), [0, 2, 0, 0]);
+        assert_eq!(row(6), [0, 0, 0, 0]);
+        assert_eq!(row(10), [2, 2, 2, 0]);
+    }
+}
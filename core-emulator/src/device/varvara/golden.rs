@@ -0,0 +1,137 @@
+// Golden-image tests for the Screen. Each ROM is run for a number of frames without a window, and
+// what's on the screen is compared against a PNG in `goldens/`.
+//
+// After a deliberate change to drawing, regenerate the images with:
+//   UXN_UPDATE_GOLDENS=1 cargo test golden
+// and check the new ones by eye before committing them.
+
+use std::{env, fs::{self, File}, io::BufWriter, path::PathBuf, sync::{Arc, Mutex}};
+
+use crate::{device::{OffscreenBackend, VarvaraDevice}, Core};
+
+/// How many instructions each vector can run, so that a broken ROM fails rather than hanging.
+const FUEL: u64 = 1_000_000;
+
+/// Runs a uxntal program's reset vector, then its Screen vector `frames` times, and gives the
+/// screen's size and composited 0RGB pixels.
+fn render(code: &str, frames: usize) -> (u16, u16, Vec<u32>) {
+    let device = Arc::new(Mutex::new(VarvaraDevice::with_screen_backend(OffscreenBackend::new())));
+    device.lock().unwrap().disable_stdin();
+
+    let mut core = Core::new_with_uxntal(code).unwrap();
+    core.set_device(device.clone());
+    core.execute_vector(0x0100, Some(FUEL)).unwrap();
+    for _ in 0..frames {
+        let vector = device.lock().unwrap().screen_vector().expect("ROM has no Screen vector");
+        core.execute_vector(vector, Some(FUEL)).unwrap();
+    }
+
+    let device = device.lock().unwrap();
+    let (width, height) = device.framebuffer().get_size();
+    (width, height, device.framebuffer().composite())
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("goldens").join(format!("{name}.png"))
+}
+
+fn write_png(path: &PathBuf, width: u16, height: u16, pixels: &[u32]) {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), width.into(), height.into());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.to_be_bytes()[1..].to_vec()).collect();
+    encoder.write_header().unwrap().write_image_data(&data).unwrap();
+}
+
+fn read_png(path: &PathBuf) -> (u16, u16, Vec<u32>) {
+    let mut reader = png::Decoder::new(File::open(path).unwrap()).read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Rgb, png::BitDepth::Eight), "{} isn't 8-bit RGB", path.display());
+
+    let pixels = data[..info.buffer_size()].chunks(3).map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]])).collect();
+    (info.width as u16, info.height as u16, pixels)
+}
+
+/// Checks that a program draws the same as its golden image, or replaces the image if
+/// `UXN_UPDATE_GOLDENS` is set.
+fn check_golden(name: &str, code: &str, frames: usize) {
+    let (width, height, pixels) = render(code, frames);
+    let path = golden_path(name);
+    if env::var_os("UXN_UPDATE_GOLDENS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_png(&path, width, height, &pixels);
+        return;
+    }
+
+    assert!(path.exists(), "no golden image for {name} - run with UXN_UPDATE_GOLDENS=1 to create one");
+    let (golden_width, golden_height, golden) = read_png(&path);
+    if (width, height, &pixels) != (golden_width, golden_height, &golden) {
+        // Keep what was drawn, so that it can be compared with the golden image
+        let actual = env::temp_dir().join(format!("{name}.actual.png"));
+        write_png(&actual, width, height, &pixels);
+        let different = pixels.iter().zip(&golden).filter(|(a, b)| a != b).count();
+        panic!(
+            "{name} drew {width}x{height} with {different} pixels differing from its {golden_width}x{golden_height} golden image, see {}",
+            actual.display(),
+        );
+    }
+}
+
+/// Sets up a small screen, with a palette of black, white, red and blue.
+const SETUP: &str = "
+    |00 @System &vector $2 &expansion $2 &wst $1 &rst $1 &metadata $2 &r $2 &g $2 &b $2
+    |20 @Screen &vector $2 &width $2 &height $2 &auto $1 &pad $1 &x $2 &y $2 &addr $2 &pixel $1 &sprite $1
+    |100
+        #0f80 .System/r DEO2 #0f00 .System/g DEO2 #0f08 .System/b DEO2
+        #0030 .Screen/width DEO2 #0020 .Screen/height DEO2
+";
+
+#[test]
+fn test_golden_pixels() {
+    check_golden("pixels", &format!("{SETUP}
+        ( A background fill from the middle towards the bottom right, and pixels along a diagonal )
+        #0018 .Screen/x DEO2 #0010 .Screen/y DEO2 #82 .Screen/pixel DEO
+        #00 &loop
+            DUP #00 SWP DUP2 .Screen/x DEO2 .Screen/y DEO2 #43 .Screen/pixel DEO
+            INC DUP #20 LTH ?&loop
+        POP BRK
+    "), 0);
+}
+
+#[test]
+fn test_golden_sprites() {
+    check_golden("sprites", &format!("{SETUP}
+        ( The same 1bpp and 2bpp sprites in each of the flips, on both layers )
+        ;arrow .Screen/addr DEO2
+        #0004 .Screen/y DEO2
+        #0004 .Screen/x DEO2 #01 .Screen/sprite DEO
+        #000e .Screen/x DEO2 #11 .Screen/sprite DEO
+        #0018 .Screen/x DEO2 #21 .Screen/sprite DEO
+        #0022 .Screen/x DEO2 #42 .Screen/sprite DEO
+        ;shaded .Screen/addr DEO2
+        #0014 .Screen/y DEO2
+        #0004 .Screen/x DEO2 #81 .Screen/sprite DEO
+        #000e .Screen/x DEO2 #b1 .Screen/sprite DEO
+        #0018 .Screen/x DEO2 #c5 .Screen/sprite DEO
+        BRK
+        @arrow 1030 70f0 7030 1000
+        @shaded 3c42 8181 8181 423c 003c 7e7e 7e7e 3c00
+    "), 0);
+}
+
+#[test]
+fn test_golden_animation() {
+    check_golden("animation", &format!("{SETUP}
+        ( A block which moves right a pixel each frame, clearing where it was )
+        ;on-frame .Screen/vector DEO2
+        ;block .Screen/addr DEO2
+        BRK
+        @on-frame
+            #0008 .Screen/y DEO2
+            .Screen/x DEI2 #40 .Screen/sprite DEO
+            INC2 .Screen/x DEO2 #41 .Screen/sprite DEO
+            BRK
+        @block ffff ffff ffff ffff
+    "), 10);
+}
@@ -0,0 +1,974 @@
+use std::{error::Error, io, time::Duration};
+
+use crate::{SnapshotReader, SnapshotWriter, UxnError};
+
+use super::{Device, DeviceContext, DeviceEvent};
+
+mod screen;
+use screen::*;
+pub use screen::{Colour, FillDirection, Framebuffer, Layer};
+
+mod console;
+use console::*;
+pub use console::{CapturingConsole, ConsoleInput, ConsoleType, TcpConsole};
+
+pub mod font;
+
+#[cfg(feature = "window")]
+mod display;
+#[cfg(feature = "window")]
+pub use display::Display;
+
+mod backend;
+pub use backend::*;
+
+mod event_loop;
+use event_loop::*;
+
+mod environment;
+pub use environment::Environment;
+
+mod controller;
+use controller::*;
+pub use controller::{ControllerInput, KeyBinding, KeyEvent, Keymap};
+
+mod system;
+pub use system::Metadata;
+
+mod session;
+pub use session::*;
+
+mod ports;
+pub use ports::*;
+
+mod diagnostics;
+use diagnostics::*;
+
+#[cfg(test)]
+mod golden;
+
+/// The Varvara machine's devices.
+///
+/// Each device's state is held in its own field, so that a handler can borrow exactly the state it
+/// needs alongside whichever parts of the [`DeviceContext`] it uses. Handlers which only read main
+/// memory, like sprite drawing, are given a shared reference to it.
+pub struct VarvaraDevice {
+    system_vector: u16,
+    expansion: u16,
+    working_stack_pointer: u8,
+    return_stack_pointer: u8,
+    debug: u8,
+    exit_code: Option<u8>,
+    metadata_addr: u16,
+    metadata: Option<Metadata>,
+    screen: Screen,
+    console: Console,
+    controller: Controller,
+    environment: Option<Environment>,
+    event_loop: EventLoop,
+    port_diagnostics: Option<PortDiagnostics>,
+    fault: Option<UxnError>,
+
+    recorder: Option<SessionRecorder>,
+    replay: Option<SessionReplay>,
+}
+
+impl VarvaraDevice {
+    /// Creates the devices, with the Screen shown in a window once the host runs the
+    /// [`Display`] from [`VarvaraDevice::take_display`].
+    #[cfg(feature = "window")]
+    pub fn new() -> Self {
+        Self::with_screen(Screen::new())
+    }
+
+    /// Creates the devices, with the Screen's frames going to a backend instead of a window, like
+    /// an [`OffscreenBackend`] for tests or servers without a display.
+    pub fn with_screen_backend(backend: impl ScreenBackend + 'static) -> Self {
+        Self::with_screen(Screen::with_backend(backend))
+    }
+
+    fn with_screen(screen: Screen) -> Self {
+        Self {
+            system_vector: 0,
+            expansion: 0,
+            working_stack_pointer: 0,
+            return_stack_pointer: 0,
+            debug: 0,
+            exit_code: None,
+            metadata_addr: 0,
+            metadata: None,
+            screen,
+            console: Console::new(),
+            controller: Controller::new(),
+            environment: None,
+            event_loop: EventLoop::new(),
+            port_diagnostics: None,
+            fault: None,
+
+            recorder: None,
+            replay: None,
+        }
+    }
+
+    /// Records console traffic, frames and Controller input into a session, for [`VarvaraDevice::replay_session`].
+    pub fn record_session(&mut self, recorder: SessionRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Replays the inputs of a recorded session instead of reading stdin, reporting whether the
+    /// ROM's console output matches the recording once the session is over.
+    pub fn replay_session(&mut self, session: Session) {
+        self.console.disable_stdin();
+        self.replay = Some(SessionReplay::new(session));
+    }
+
+    /// Exposes information about the host to the ROM, through the non-standard device page
+    /// described in [`Environment`]. This is disabled by default.
+    pub fn enable_environment(&mut self, environment: Environment) {
+        self.environment = Some(environment);
+    }
+
+    /// Warns when the ROM writes to ports which are slow to handle, like the Screen size, every
+    /// frame. This is disabled by default.
+    pub fn enable_port_diagnostics(&mut self) {
+        self.port_diagnostics = Some(PortDiagnostics::new());
+    }
+
+    /// Queues a byte to be delivered to the ROM through the Console vector, alongside any input
+    /// from stdin.
+    pub fn queue_console_input(&self, byte: u8, input_type: ConsoleType) {
+        self.console.queue_input(byte, input_type);
+    }
+
+    /// Passes command-line arguments to the ROM through the Console vector, a byte at a
+    /// time, before any other input. [`ConsoleType`] tells them apart.
+    pub fn queue_arguments(&mut self, arguments: &[String]) {
+        self.console.queue_arguments(arguments);
+    }
+
+    /// Gets a handle which can queue console input from other threads, even once the device has
+    /// been handed to a [`Core`](crate::Core).
+    pub fn console_input(&self) -> ConsoleInput {
+        self.console.input_handle()
+    }
+
+    /// Changes how many times a second the Screen vector runs, from the usual 60.
+    pub fn set_frame_rate(&mut self, frames_per_second: u32) {
+        self.event_loop = EventLoop::with_frame_interval(Duration::from_secs(1) / frames_per_second.max(1));
+    }
+
+    /// Changes which host keys do what on the Controller.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.controller.keymap = keymap;
+    }
+
+    /// Gets a handle which passes key events to the Controller, for hosts which aren't using the
+    /// window from [`VarvaraDevice::take_display`]. That window already sends its keys.
+    pub fn controller_input(&self) -> ControllerInput {
+        self.controller.input_handle()
+    }
+
+    /// Collects the ROM's console output into a [`CapturingConsole`], instead of printing it to
+    /// stdout and stderr.
+    pub fn capture_console(&mut self, capture: CapturingConsole) {
+        self.console.capture(capture);
+    }
+
+    /// Connects the Console to a [`TcpConsole`] instead of stdin and stdout. Bytes received are
+    /// delivered through the Console vector, and `.Console/write` is sent back.
+    pub fn bridge_console(&mut self, console: TcpConsole) -> io::Result<()> {
+        self.console.bridge(console)
+    }
+
+    /// Stops the Console from reading stdin, for hosts which provide input themselves.
+    pub fn disable_stdin(&mut self) {
+        self.console.disable_stdin();
+    }
+
+    /// The ROM's metadata, if it has provided some through `.System/metadata`.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Takes the [`Display`] which shows the Screen in a window. The host should run it on its main
+    /// thread, with the core on another. If nobody takes it, no window is opened.
+    ///
+    /// This is `None` if the Screen was given a different backend.
+    #[cfg(feature = "window")]
+    pub fn take_display(&mut self) -> Option<Display> {
+        let mut display = self.screen.take_display()?;
+        display.forward_keys(self.controller.input_handle());
+        Some(display)
+    }
+
+    /// The current contents of the screen, independent of the window presenting it.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.screen.framebuffer
+    }
+
+    /// Where the ROM wants to be called each frame, if it's set `.Screen/vector`. This is for hosts
+    /// which run frames themselves, rather than with [`Device::wait_for_event`].
+    pub fn screen_vector(&self) -> Option<u16> {
+        self.screen.vector
+    }
+}
+
+#[cfg(feature = "window")]
+impl Default for VarvaraDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for VarvaraDevice {
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        self.next_event(true).expect("blocking wait produced no event")
+    }
+
+    fn poll_event(&mut self) -> Option<DeviceEvent> {
+        self.next_event(false)
+    }
+
+    fn is_halted(&self) -> bool {
+        self.exit_code.is_some()
+    }
+
+    // Matches when `next_event` would exit because there are no vectors to run
+    fn is_idle(&self) -> bool {
+        self.exit_code.is_none() && self.screen.is_open() && self.replay.is_none()
+            && self.screen.vector.is_none() && !self.has_console_vector()
+    }
+
+    fn take_fault(&mut self) -> Option<UxnError> {
+        self.fault.take()
+    }
+
+    fn fault_vector(&self) -> Option<u16> {
+        (self.system_vector != 0).then_some(self.system_vector)
+    }
+
+    fn reset(&mut self) {
+        self.system_vector = 0;
+        self.expansion = 0;
+        self.working_stack_pointer = 0;
+        self.return_stack_pointer = 0;
+        self.debug = 0;
+        self.exit_code = None;
+        self.metadata_addr = 0;
+        self.metadata = None;
+
+        self.console.vector = None;
+        self.console.read = 0;
+        self.console.input_type = ConsoleType::NoQueue;
+
+        self.controller.reset();
+        self.screen.reset();
+        self.event_loop = EventLoop::with_frame_interval(self.event_loop.frame_interval());
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        let optional_short = |writer: &mut SnapshotWriter, short: Option<u16>| {
+            writer.u8(short.is_some() as u8);
+            writer.u16(short.unwrap_or(0));
+        };
+
+        // System
+        writer.u16(self.system_vector);
+        writer.u16(self.expansion);
+        writer.u8(self.working_stack_pointer);
+        writer.u8(self.return_stack_pointer);
+        writer.u8(self.debug);
+        writer.u16(self.metadata_addr);
+        for colour in self.screen.framebuffer.colours {
+            writer.u32(colour.to_0rgb());
+        }
+
+        // Console
+        optional_short(&mut writer, self.console.vector);
+        writer.u8(self.console.read);
+        writer.u8(self.console.input_type as u8);
+
+        // Screen
+        optional_short(&mut writer, self.screen.vector);
+        let (width, height) = self.screen.get_size();
+        for short in [width, height, self.screen.x, self.screen.y, self.screen.sprite_addr] {
+            writer.u16(short);
+        }
+        writer.u8(self.screen.sprite);
+        writer.u8(self.screen.auto);
+        writer.bytes(self.screen.framebuffer.layer(Layer::Background));
+        writer.bytes(self.screen.framebuffer.layer(Layer::Foreground));
+
+        // Controller
+        optional_short(&mut writer, self.controller.vector);
+        writer.u8(self.controller.button);
+
+        // Environment, which is only read back if the host has enabled it again
+        writer.u8(self.environment.is_some() as u8);
+        let (field, cursor) = self.environment.as_ref().map_or((0, 0), Environment::position);
+        writer.u8(field);
+        writer.u32(cursor as u32);
+
+        writer.finish()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut reader = SnapshotReader::new(snapshot);
+        let optional_short = |reader: &mut SnapshotReader| -> Result<Option<u16>, Box<dyn Error>> {
+            let present = reader.u8()? != 0;
+            let short = reader.u16()?;
+            Ok(present.then_some(short))
+        };
+
+        // Read everything before changing anything, so that a bad snapshot leaves the device as it
+        // was
+        let system_vector = reader.u16()?;
+        let expansion = reader.u16()?;
+        let (working_stack_pointer, return_stack_pointer, debug) = (reader.u8()?, reader.u8()?, reader.u8()?);
+        let metadata_addr = reader.u16()?;
+        let mut colours = [Colour::new(); 4];
+        for colour in &mut colours {
+            *colour = Colour::from_0rgb(reader.u32()?);
+        }
+
+        let console_vector = optional_short(&mut reader)?;
+        let console_read = reader.u8()?;
+        let console_type = ConsoleType::from_byte(reader.u8()?).ok_or("invalid console type")?;
+
+        let screen_vector = optional_short(&mut reader)?;
+        let (width, height) = (reader.u16()?, reader.u16()?);
+        if width == 0 || height == 0 {
+            return Err(format!("invalid screen size {width}x{height}").into());
+        }
+        let (x, y, sprite_addr, sprite, auto) = (reader.u16()?, reader.u16()?, reader.u16()?, reader.u8()?, reader.u8()?);
+        let size = width as usize * height as usize;
+        let (background, foreground) = (reader.bytes(size)?, reader.bytes(size)?);
+
+        let controller_vector = optional_short(&mut reader)?;
+        let button = reader.u8()?;
+
+        let has_environment = reader.u8()? != 0;
+        let (field, cursor) = (reader.u8()?, reader.u32()? as usize);
+        reader.finish()?;
+
+        // System
+        self.system_vector = system_vector;
+        self.expansion = expansion;
+        self.working_stack_pointer = working_stack_pointer;
+        self.return_stack_pointer = return_stack_pointer;
+        self.debug = debug;
+        self.metadata_addr = metadata_addr;
+
+        // Console
+        // The console doesn't start reading stdin if it wasn't already, because the host may
+        // not want it to
+        self.console.vector = console_vector;
+        self.console.read = console_read;
+        self.console.input_type = console_type;
+
+        // Screen
+        self.screen.vector = screen_vector;
+        (self.screen.x, self.screen.y, self.screen.sprite_addr, self.screen.sprite, self.screen.auto) = (x, y, sprite_addr, sprite, auto);
+        if self.screen.get_size() != (width, height) {
+            self.screen.set_size(width, height);
+        }
+        self.screen.framebuffer.colours = colours;
+        self.screen.framebuffer.layer_mut(Layer::Background).copy_from_slice(background);
+        self.screen.framebuffer.layer_mut(Layer::Foreground).copy_from_slice(foreground);
+
+        // Controller
+        self.controller.vector = controller_vector;
+        self.controller.button = button;
+
+        // Environment
+        if let Some(environment) = &mut self.environment && has_environment {
+            environment.set_position(field, cursor);
+        }
+
+        Ok(())
+    }
+
+    fn dei(&mut self, port: u8, context: DeviceContext) -> u8 {
+        self.before_input(port, context);
+        self.read_port(port)
+    }
+
+    fn deo(&mut self, port: u8, value: u8, context: DeviceContext) {
+        self.write_port(port, value);
+        self.after_output(port, context);
+
+        if let Some(diagnostics) = &mut self.port_diagnostics {
+            diagnostics.record_write(port);
+        }
+    }
+}
+
+impl VarvaraDevice {
+    /// Whether the Console could still run its vector.
+    fn has_console_vector(&self) -> bool {
+        self.console.vector.is_some() && !self.console.has_ended()
+    }
+
+    /// Works out which vector to run next. If `block` is false and nothing is ready yet, this
+    /// returns `None` instead of waiting.
+    fn next_event(&mut self, block: bool) -> Option<DeviceEvent> {
+        // A replay whose output diverged fails, however the ROM stopped
+        if let Some(code) = self.exit_code {
+            let diverged = self.finish_replay();
+            return Some(DeviceEvent::Exit(if diverged && code == 0 { 1 } else { code }))
+        }
+
+        if !self.screen.is_open() {
+            let diverged = self.finish_replay();
+            return Some(DeviceEvent::Exit(diverged as u8))
+        }
+
+        // When replaying, inputs come from the session instead
+        if let Some(replay) = &mut self.replay {
+            self.controller.key = 0;
+            loop {
+                return Some(match replay.next_input() {
+                    Some(SessionEvent::Input(byte, input_type)) => {
+                        self.console.set_input(byte, input_type);
+                        self.console_event()
+                    },
+                    Some(SessionEvent::Controller(button, key)) => {
+                        (self.controller.button, self.controller.key) = (button, key);
+                        match self.controller.vector {
+                            Some(vector) => DeviceEvent::Vector(vector),
+                            None => continue,
+                        }
+                    },
+                    Some(_) => self.screen_event(),
+                    // Exit unsuccessfully if the output diverged, so replays can be used as tests
+                    None => {
+                        replay.finish();
+                        DeviceEvent::Exit(if replay.divergence().is_some() { 1 } else { 0 })
+                    },
+                })
+            }
+        }
+
+        let screen = self.screen.vector.is_some();
+        let console = self.has_console_vector();
+        if !screen && !console {
+            return Some(DeviceEvent::Exit(0))
+        }
+
+        // Keys are only checked between the other sources, so they can wait up to a frame. They're
+        // recorded even without a vector, since the ROM may read the button byte some other time.
+        while self.controller.take_input() {
+            self.record(SessionEvent::Controller(self.controller.button, self.controller.key));
+            if let Some(vector) = self.controller.vector {
+                return Some(DeviceEvent::Vector(vector));
+            }
+        }
+
+        // The read and type ports are updated just before the Console vector runs
+        let console = console.then_some(&mut self.console);
+        match self.event_loop.next_source(screen, console, block) {
+            Some(EventSource::Screen) => Some(self.screen_event()),
+            Some(EventSource::Console) => Some(self.console_event()),
+
+            // Blocking only gives up if input ended while waiting
+            None if block => Some(DeviceEvent::Exit(0)),
+            None => None,
+        }
+    }
+
+    fn before_input(&mut self, port: u8, context: DeviceContext) {
+        match port {
+            // .System/wst
+            0x04 => self.working_stack_pointer = context.working_stack.pointer,
+
+            // .System/rst
+            0x05 => self.return_stack_pointer = context.return_stack.pointer,
+
+            _ => {},
+        }
+    }
+
+    fn after_output(&mut self, port: u8, context: DeviceContext) {
+        match port {
+            // .System/expansion, once both bytes of the address are written
+            0x03 => system::run_expansion(context.memory, self.expansion),
+
+            // .System/wst
+            0x04 => context.working_stack.pointer = self.working_stack_pointer,
+
+            // .System/rst
+            0x05 => context.return_stack.pointer = self.return_stack_pointer,
+
+            // .System/metadata, once both bytes of the address are written
+            0x07 => {
+                let metadata = Metadata::parse(context.memory, self.metadata_addr);
+                self.screen.set_title(metadata.name());
+                self.metadata = Some(metadata);
+            },
+
+            // .System/debug
+            0x0e if self.debug != 0 => {
+                eprintln!("{}", system::format_stack("wst", context.working_stack));
+                eprintln!("{}", system::format_stack("rst", context.return_stack));
+            },
+
+            // .Screen, which only needs to read memory
+            0x20..=0x2f => self.screen.after_output(port, context.memory),
+
+            _ => {},
+        }
+    }
+
+    fn console_event(&mut self) -> DeviceEvent {
+        self.record(SessionEvent::Input(self.console.read, self.console.input_type));
+        self.console.vector.map_or(DeviceEvent::Exit(0), DeviceEvent::Vector)
+    }
+
+    fn screen_event(&mut self) -> DeviceEvent {
+        // TODO: currently, this means whatever we draw is one frame behind
+        // This is *probably* fine but does need to be sorted at some point
+        self.screen.update();
+
+        if let Some(diagnostics) = &mut self.port_diagnostics {
+            for port in diagnostics.end_frame() {
+                let name = port_name(port, port).unwrap_or("an unknown port");
+                crate::warning!("ROM writes to {name} every frame, which is slow because it resizes the screen");
+            }
+        }
+
+        self.record(SessionEvent::Frame);
+        self.screen.vector.map_or(DeviceEvent::Exit(0), DeviceEvent::Vector)
+    }
+
+    fn record(&mut self, event: SessionEvent) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(event);
+        }
+        if let Some(replay) = &mut self.replay
+            && matches!(event, SessionEvent::Output(_) | SessionEvent::Error(_)) {
+            replay.record_output(event);
+        }
+    }
+
+    /// Reports how a replay went, if this is one. Returns whether its output diverged.
+    fn finish_replay(&mut self) -> bool {
+        let Some(replay) = &mut self.replay else { return false };
+        replay.finish();
+        replay.divergence().is_some()
+    }
+}
+
+impl VarvaraDevice {
+    fn read_port(&mut self, addr: u8) -> u8 {
+        // TODO: reading mostly unimplemented
+        match addr {
+            // .System/vector
+            0x00 => ((self.system_vector & 0xFF00) >> 8) as u8,
+            0x01 =>  (self.system_vector & 0x00FF) as u8,
+
+            // .System/wst
+            0x04 => self.working_stack_pointer,
+
+            // .System/rst
+            0x05 => self.return_stack_pointer,
+
+            // .Screen/width
+            0x22 => ((self.screen.get_size().0 & 0xFF00) >> 8) as u8,
+            0x23 =>  (self.screen.get_size().0 & 0x00FF) as u8,
+
+            // .Screen/height
+            0x24 => ((self.screen.get_size().1 & 0xFF00) >> 8) as u8,
+            0x25 =>  (self.screen.get_size().1 & 0x00FF) as u8,
+
+            // .Screen/auto
+            0x26 => self.screen.auto,
+
+            // .Screen/x
+            0x28 => ((self.screen.x & 0xFF00) >> 8) as u8,
+            0x29 =>  (self.screen.x & 0x00FF) as u8,
+
+            // .Screen/y
+            0x2a => ((self.screen.y & 0xFF00) >> 8) as u8,
+            0x2b =>  (self.screen.y & 0x00FF) as u8,
+
+            // .Screen/addr
+            0x2c => ((self.screen.sprite_addr & 0xFF00) >> 8) as u8,
+            0x2d =>  (self.screen.sprite_addr & 0x00FF) as u8,
+
+            // .Console/read
+            0x12 => self.console.read,
+
+            // .Controller/vector
+            0x80 => (self.controller.vector.unwrap_or(0) >> 8) as u8,
+            0x81 => self.controller.vector.unwrap_or(0) as u8,
+
+            // .Controller/button
+            0x82 => self.controller.button,
+
+            // .Controller/key
+            0x83 => self.controller.key,
+
+            // .Console/type
+            0x17 => self.console.input_type as u8,
+
+            // Host environment extension
+            0xe0..=0xef if let Some(environment) = &mut self.environment => environment.read_byte(addr & 0x0f),
+
+            _ => 0,
+        }
+    }
+
+    fn write_port(&mut self, addr: u8, byte: u8) {
+        // See: https://wiki.xxiivv.com/site/varvara.html
+        match addr {
+            // .System/vector
+            // This is run by the core when the ROM faults
+            0x00 => set_high_byte(&mut self.system_vector, byte),
+            0x01 => set_low_byte( &mut self.system_vector, byte),
+
+            // .System/expansion
+            // This is actioned in `after_output`, because it needs main memory
+            0x02 => set_high_byte(&mut self.expansion, byte),
+            0x03 => set_low_byte( &mut self.expansion, byte),
+
+            // .System/wst and .System/rst
+            // These are actioned in `after_output`, because they need the stacks
+            0x04 => self.working_stack_pointer = byte,
+            0x05 => self.return_stack_pointer = byte,
+
+            // .System/metadata
+            // This is actioned in `after_output`, because it needs main memory
+            0x06 => set_high_byte(&mut self.metadata_addr, byte),
+            0x07 => set_low_byte( &mut self.metadata_addr, byte),
+
+            // TODO: reduce duplication in colour channel code
+
+            // .System/red
+            0x08 => {
+                let (hi, lo) = split_nibbles(byte);
+                self.screen.framebuffer.colours[0].set_red_from_nibble(hi);
+                self.screen.framebuffer.colours[1].set_red_from_nibble(lo);
+            },
+            0x09 => {
+                let (hi, lo) = split_nibbles(byte);
+                self.screen.framebuffer.colours[2].set_red_from_nibble(hi);
+                self.screen.framebuffer.colours[3].set_red_from_nibble(lo);
+            },
+
+            // .System/green
+            0x0a => {
+                let (hi, lo) = split_nibbles(byte);
+                self.screen.framebuffer.colours[0].set_green_from_nibble(hi);
+                self.screen.framebuffer.colours[1].set_green_from_nibble(lo);
+            },
+            0x0b => {
+                let (hi, lo) = split_nibbles(byte);
+                self.screen.framebuffer.colours[2].set_green_from_nibble(hi);
+                self.screen.framebuffer.colours[3].set_green_from_nibble(lo);
+            },
+
+            // .System/blue
+            0x0c => {
+                let (hi, lo) = split_nibbles(byte);
+                self.screen.framebuffer.colours[0].set_blue_from_nibble(hi);
+                self.screen.framebuffer.colours[1].set_blue_from_nibble(lo);
+            },
+            0x0d => {
+                let (hi, lo) = split_nibbles(byte);
+                self.screen.framebuffer.colours[2].set_blue_from_nibble(hi);
+                self.screen.framebuffer.colours[3].set_blue_from_nibble(lo);
+            },
+
+            // .System/debug
+            // This is actioned in `after_output`, because it needs the stacks
+            0x0e => self.debug = byte,
+
+            // .System/state
+            0x0f => {
+                if byte != 0 {
+                    self.exit_code = Some(byte & 0x7f);
+                }
+            },
+
+            // .Console/vector
+            0x10 => self.console.map_vector(|v| with_high_byte(v, byte)),
+            0x11 => self.console.map_vector(|v| with_low_byte(v, byte)),
+
+            // .Console/read and .Console/type are read-only
+            0x12 | 0x17 => {},
+
+            // .Console/write
+            0x18 => {
+                self.console.write(byte);
+                self.record(SessionEvent::Output(byte));
+            },
+
+            // .Console/error
+            0x19 => {
+                self.console.write_error(byte);
+                self.record(SessionEvent::Error(byte));
+            },
+
+            // .Screen/vector
+            0x20 => {
+                self.screen.vector = Some(with_high_byte(self.screen.vector.unwrap_or(0), byte));
+            },
+            0x21 => {
+                self.screen.vector = Some(with_low_byte(self.screen.vector.unwrap_or(0), byte));
+            },
+
+            // .Screen/width
+            0x22 => self.screen.map_size(|w, h| (with_high_byte(w, byte), h)),
+            0x23 => self.screen.map_size(|w, h| (with_low_byte(w, byte), h)),
+
+            // .Screen/height
+            0x24 => self.screen.map_size(|w, h| (w, with_high_byte(h, byte))),
+            0x25 => self.screen.map_size(|w, h| (w, with_low_byte(h, byte))),
+
+            // .Screen/auto
+            0x26 => self.screen.auto = byte,
+
+            // .Screen/x
+            0x28 => set_high_byte(&mut self.screen.x, byte),
+            0x29 => set_low_byte( &mut self.screen.x, byte),
+
+            // .Screen/y
+            0x2a => set_high_byte(&mut self.screen.y, byte),
+            0x2b => set_low_byte( &mut self.screen.y, byte),
+
+            // .Screen/addr
+            0x2c => set_high_byte(&mut self.screen.sprite_addr, byte),
+            0x2d => set_low_byte( &mut self.screen.sprite_addr, byte),
+
+            // .Screen/pixel
+            0x2e => {
+                let (fill, layer, flip_y, flip_x, _, _, c1, c0) = explode_byte(byte);
+
+                // 2-bit number is a colour index
+                let colour_index = ((c1 as u8) << 1) | (c0 as u8);
+                let layer = if layer { Layer::Foreground } else { Layer::Background };
+
+                if fill {
+                    let x_dir = if flip_x { FillDirection::Negative } else { FillDirection::Positive };
+                    let y_dir = if flip_y { FillDirection::Negative } else { FillDirection::Positive };
+
+                    self.screen.framebuffer.fill_pixels(self.screen.x, self.screen.y, x_dir, y_dir, colour_index, layer);
+                } else {
+                    self.screen.framebuffer.draw_pixel(self.screen.x, self.screen.y, colour_index, layer);
+
+                    // Pixels move along one at a time with .Screen/auto
+                    if self.screen.auto & 0x01 != 0 { self.screen.x = self.screen.x.wrapping_add(1) }
+                    if self.screen.auto & 0x02 != 0 { self.screen.y = self.screen.y.wrapping_add(1) }
+                }
+            },
+
+            // .Screen/sprite, drawn from main memory once written
+            0x2f => self.screen.sprite = byte,
+
+            // .Controller/vector
+            0x80 => self.controller.map_vector(|v| with_high_byte(v, byte)),
+            0x81 => self.controller.map_vector(|v| with_low_byte(v, byte)),
+
+            // .Controller/button and .Controller/key are read-only
+            0x82 | 0x83 => {},
+
+            // Host environment extension
+            0xe0..=0xef if let Some(environment) = &mut self.environment => environment.write_byte(addr & 0x0f, byte),
+
+            _ => self.fault = Some(UxnError::UnsupportedPort(addr)),
+        }
+    }
+}
+
+fn with_high_byte(short: u16, new: u8) -> u16 {
+    (short & 0x00FF) | ((new as u16) << 8)
+}
+
+fn with_low_byte(short: u16, new: u8) -> u16 {
+    (short & 0xFF00) | (new as u16)
+}
+
+fn set_high_byte(short: &mut u16, new: u8) {
+    *short = with_high_byte(*short, new);
+}
+
+fn set_low_byte(short: &mut u16, new: u8) {
+    *short = with_low_byte(*short, new);
+}
+
+// MSB first
+fn explode_byte(byte: u8) -> (bool, bool, bool, bool, bool, bool, bool, bool) {
+    (
+        byte & 0b1000_0000 != 0,
+        byte & 0b0100_0000 != 0,
+        byte & 0b0010_0000 != 0,
+        byte & 0b0001_0000 != 0,
+        byte & 0b0000_1000 != 0,
+        byte & 0b0000_0100 != 0,
+        byte & 0b0000_0010 != 0,
+        byte & 0b0000_0001 != 0,
+    )
+}
+
+fn split_nibbles(byte: u8) -> (u8, u8) {
+    ((byte & 0xF0) >> 4, byte & 0x0F)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::UdpSocket, sync::{Arc, Mutex}, thread, time::Duration};
+
+    use crate::{device::{ConsoleType, Device, Environment, Layer, NetworkDevice, OffscreenBackend, Session, SessionEvent}, Core, RunResult, SnapshotWriter};
+
+    use super::VarvaraDevice;
+
+    fn device() -> VarvaraDevice {
+        let mut device = VarvaraDevice::with_screen_backend(OffscreenBackend::new());
+        device.disable_stdin();
+        device.enable_environment(Environment::new("en_GB".to_string(), "data".to_string(), None));
+        device
+    }
+
+    #[test]
+    fn test_network_only() {
+        // Only the Network has a vector, which exits with the first byte of the datagram
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let [high, low] = peer.local_addr().unwrap().port().to_be_bytes();
+        let mut core = Core::new_with_uxntal(&format!("
+            |d0 @Network &vector $2 &length $2 &host $4 &port $2 &bind $2 &send $2 &receive $2
+            |100
+                ;on-receive .Network/vector DEO2
+                #0000 .Network/bind DEO2
+                #7f00 .Network/host DEO2 #0001 .Network/host INC INC DEO2
+                #{high:02x}{low:02x} .Network/port DEO2
+                #0001 .Network/length DEO2 ;buffer .Network/send DEO2
+                BRK
+            @on-receive
+                #0001 .Network/length DEO2 ;buffer .Network/receive DEO2
+                ;buffer LDA #80 ORA #0f DEO
+                BRK
+            @buffer $1
+        ")).unwrap();
+        core.set_device(device());
+        core.register_device(0xd0, NetworkDevice::new(core.event_sender()));
+        assert_eq!(core.execute_until_break().unwrap(), RunResult::Break);
+
+        // Reply once the core is already waiting
+        let mut buffer = [0; 1];
+        let (_, from) = peer.recv_from(&mut buffer).unwrap();
+        let replier = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            peer.send_to(&[0x05], from).unwrap();
+        });
+        assert_eq!(core.handle_events_until_exit().unwrap(), RunResult::Exit(5));
+        replier.join().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        // Draws a pixel on a small screen, and starts reading the data path. The vector at 0x200
+        // reads the next byte of it.
+        let code = "
+            |100 #0010 #22 DEO2 #0008 #24 DEO2 #41 #2e DEO #01 #e0 DEO #e1 DEI POP BRK
+            |200 #e1 DEI #00 STZ BRK
+        ";
+        let mut core = Core::new_with_uxntal(code).unwrap();
+        core.set_device(device());
+        core.execute_until_break().unwrap();
+        let snapshot = core.snapshot();
+
+        let mut restored = Core::new_with_uxntal(code).unwrap();
+        restored.set_device(device());
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.device.snapshot(), core.device.snapshot());
+        restored.execute_vector(0x0200, None).unwrap();
+        assert_eq!(restored.memory[0x00], b'a');
+
+        // Bad snapshots leave the device as it was, including ones with an empty screen, which
+        // the screen can't be resized to
+        let mut device = device();
+        let before = device.snapshot();
+        assert!(device.restore(&before[..before.len() - 1]).is_err());
+
+        // An empty screen, after the System and Console fields as `snapshot` writes them
+        let mut writer = SnapshotWriter::new();
+        writer.u16(0); // .System/vector
+        writer.u16(0); // .System/expansion
+        writer.u8(0); // .System/wst
+        writer.u8(0); // .System/rst
+        writer.u8(0); // .System/debug
+        writer.u16(0); // .System/metadata
+        for _ in 0..4 {
+            writer.u32(0); // Colours
+        }
+        writer.u8(0); writer.u16(0); // .Console/vector, which isn't set
+        writer.u8(0); // .Console/read
+        writer.u8(ConsoleType::Stdin as u8); // .Console/type
+        writer.u8(0); writer.u16(0); // .Screen/vector, which isn't set
+        writer.u16(0); writer.u16(0); // .Screen/width and /height
+        assert_eq!(device.restore(&writer.finish()).unwrap_err().to_string(), "invalid screen size 0x0");
+        assert_eq!(device.snapshot(), before);
+    }
+
+    #[test]
+    fn test_replay_exit() {
+        // Prints a byte, then exits by itself with the given state
+        let replay = |byte: u8, state: u8| {
+            let mut device = device();
+            device.replay_session(Session {
+                rom_hash: 0,
+                flags: vec![],
+                build: None,
+                events: vec![SessionEvent::Output(b'a')],
+            });
+            let mut core = Core::new_with_uxntal(&format!("#{byte:02x} #18 DEO #{state:02x} #0f DEO BRK")).unwrap();
+            core.set_device(device);
+            core.execute_until_exit().unwrap()
+        };
+
+        assert_eq!(replay(b'a', 0x80), RunResult::Exit(0));
+        assert_eq!(replay(b'b', 0x80), RunResult::Exit(1));
+        assert_eq!(replay(b'b', 0x83), RunResult::Exit(3));
+    }
+
+    #[test]
+    fn test_stack_pointers() {
+        // Like the reference, the port still counts towards the stack it came from, so `#04 DEI`
+        // gives 1 on an empty stack, and the same goes for `.System/rst` with DEIr
+        let mut core = Core::new_with_uxntal("#04 DEI LITr 05 DEIr STHr LITr 04 DEIr STHr BRK").unwrap();
+        core.set_device(device());
+        core.execute_until_break().unwrap();
+        assert_eq!(core.working_stack.bytes(), [0x01, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn test_screen_auto() {
+        // Draws a row of three sprites with one write, each one pixel further into its tile, then
+        // two pixels moving along x
+        let code = "
+            |100
+            #0020 #22 DEO2 #0010 #24 DEO2
+            #26 #26 DEO ;sprites #2c DEO2 #01 #2f DEO
+            #28 DEI2 #00 STZ2 #2a DEI2 #02 STZ2 #2c DEI2 #04 STZ2
+            #01 #26 DEO #0000 #28 DEO2 #000f #2a DEO2 #02 #2e DEO #02 #2e DEO
+            BRK
+            |200 @sprites 80 00 00 00 00 00 00 00 40 00 00 00 00 00 00 00 20 00 00 00 00 00 00 00
+        ";
+        let device = Arc::new(Mutex::new(device()));
+        let mut core = Core::new_with_uxntal(code).unwrap();
+        core.set_device(device.clone());
+        core.execute_until_break().unwrap();
+
+        let device = device.lock().unwrap();
+        let lit = |y| (0..0x20)
+            .filter(|x| device.framebuffer().get_pixel(*x, y, Layer::Background) != Some(0))
+            .collect::<Vec<_>>();
+        assert_eq!(lit(0), [0, 9, 18]);
+        assert_eq!(lit(15), [0, 1]);
+
+        // Afterwards y has moved down a row, and the address past all three sprites
+        assert_eq!(core.memory.bank(0)[0x00..0x06], [0x00, 0x00, 0x00, 0x08, 0x02, 0x18]);
+    }
+}
@@ -0,0 +1,155 @@
+//! Names for Varvara's device ports, as they're written in Uxntal.
+//!
+//! See: https://wiki.xxiivv.com/site/varvara.html
+
+/// Every port's address, name, and width in bytes.
+const PORTS: &[(u8, &str, u8)] = &[
+    (0x00, ".System/vector", 2),
+    (0x02, ".System/expansion", 2),
+    (0x04, ".System/wst", 1),
+    (0x05, ".System/rst", 1),
+    (0x06, ".System/metadata", 2),
+    (0x08, ".System/r", 2),
+    (0x0a, ".System/g", 2),
+    (0x0c, ".System/b", 2),
+    (0x0e, ".System/debug", 1),
+    (0x0f, ".System/state", 1),
+
+    (0x10, ".Console/vector", 2),
+    (0x12, ".Console/read", 1),
+    (0x17, ".Console/type", 1),
+    (0x18, ".Console/write", 1),
+    (0x19, ".Console/error", 1),
+
+    (0x20, ".Screen/vector", 2),
+    (0x22, ".Screen/width", 2),
+    (0x24, ".Screen/height", 2),
+    (0x26, ".Screen/auto", 1),
+    (0x28, ".Screen/x", 2),
+    (0x2a, ".Screen/y", 2),
+    (0x2c, ".Screen/addr", 2),
+    (0x2e, ".Screen/pixel", 1),
+    (0x2f, ".Screen/sprite", 1),
+
+    (0x30, ".Audio0/vector", 2),
+    (0x32, ".Audio0/position", 2),
+    (0x34, ".Audio0/output", 1),
+    (0x38, ".Audio0/adsr", 2),
+    (0x3a, ".Audio0/length", 2),
+    (0x3c, ".Audio0/addr", 2),
+    (0x3e, ".Audio0/volume", 1),
+    (0x3f, ".Audio0/pitch", 1),
+
+    (0x40, ".Audio1/vector", 2),
+    (0x42, ".Audio1/position", 2),
+    (0x44, ".Audio1/output", 1),
+    (0x48, ".Audio1/adsr", 2),
+    (0x4a, ".Audio1/length", 2),
+    (0x4c, ".Audio1/addr", 2),
+    (0x4e, ".Audio1/volume", 1),
+    (0x4f, ".Audio1/pitch", 1),
+
+    (0x50, ".Audio2/vector", 2),
+    (0x52, ".Audio2/position", 2),
+    (0x54, ".Audio2/output", 1),
+    (0x58, ".Audio2/adsr", 2),
+    (0x5a, ".Audio2/length", 2),
+    (0x5c, ".Audio2/addr", 2),
+    (0x5e, ".Audio2/volume", 1),
+    (0x5f, ".Audio2/pitch", 1),
+
+    (0x60, ".Audio3/vector", 2),
+    (0x62, ".Audio3/position", 2),
+    (0x64, ".Audio3/output", 1),
+    (0x68, ".Audio3/adsr", 2),
+    (0x6a, ".Audio3/length", 2),
+    (0x6c, ".Audio3/addr", 2),
+    (0x6e, ".Audio3/volume", 1),
+    (0x6f, ".Audio3/pitch", 1),
+
+    (0x80, ".Controller/vector", 2),
+    (0x82, ".Controller/button", 1),
+    (0x83, ".Controller/key", 1),
+
+    (0x90, ".Mouse/vector", 2),
+    (0x92, ".Mouse/x", 2),
+    (0x94, ".Mouse/y", 2),
+    (0x96, ".Mouse/state", 1),
+    (0x9a, ".Mouse/scrollx", 2),
+    (0x9c, ".Mouse/scrolly", 2),
+
+    (0xa0, ".File0/vector", 2),
+    (0xa2, ".File0/success", 2),
+    (0xa4, ".File0/stat", 2),
+    (0xa6, ".File0/delete", 1),
+    (0xa7, ".File0/append", 1),
+    (0xa8, ".File0/name", 2),
+    (0xaa, ".File0/length", 2),
+    (0xac, ".File0/read", 2),
+    (0xae, ".File0/write", 2),
+
+    (0xb0, ".File1/vector", 2),
+    (0xb2, ".File1/success", 2),
+    (0xb4, ".File1/stat", 2),
+    (0xb6, ".File1/delete", 1),
+    (0xb7, ".File1/append", 1),
+    (0xb8, ".File1/name", 2),
+    (0xba, ".File1/length", 2),
+    (0xbc, ".File1/read", 2),
+    (0xbe, ".File1/write", 2),
+
+    (0xc0, ".DateTime/year", 2),
+    (0xc2, ".DateTime/month", 1),
+    (0xc3, ".DateTime/day", 1),
+    (0xc4, ".DateTime/hour", 1),
+    (0xc5, ".DateTime/minute", 1),
+    (0xc6, ".DateTime/second", 1),
+    (0xc7, ".DateTime/dotw", 1),
+    (0xc8, ".DateTime/doty", 2),
+    (0xca, ".DateTime/isdst", 1),
+
+    // Non-standard, see `Environment`
+    (0xe0, ".Environment/field", 1),
+    (0xe1, ".Environment/read", 1),
+    (0xe2, ".Environment/present", 1),
+    (0xe8, ".Environment/r", 2),
+    (0xea, ".Environment/g", 2),
+    (0xec, ".Environment/b", 2),
+];
+
+/// Gets the name of a port, like `.Screen/pixel` for page 0x20 and offset 0xe.
+///
+/// Both bytes of a short port have the port's name.
+pub fn port_name(page: u8, offset: u8) -> Option<&'static str> {
+    let addr = (page & 0xf0) | (offset & 0x0f);
+    PORTS.iter()
+        .find(|(port, _, width)| (*port..*port + width).contains(&addr))
+        .map(|(_, name, _)| *name)
+}
+
+/// Gets the address of a named port, like 0x2e for `.Screen/pixel`. The leading `.` is optional.
+pub fn port_address(name: &str) -> Option<u8> {
+    let name = name.strip_prefix('.').unwrap_or(name);
+    PORTS.iter()
+        .find(|(_, port, _)| port[1..] == *name)
+        .map(|(addr, ..)| *addr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{port_address, port_name};
+
+    #[test]
+    fn test_port_names() {
+        assert_eq!(port_name(0x20, 0xe), Some(".Screen/pixel"));
+        assert_eq!(port_name(0x2e, 0x2e), Some(".Screen/pixel"));
+        assert_eq!(port_name(0x00, 0x1), Some(".System/vector"));
+        assert_eq!(port_name(0x10, 0x3), None);
+        assert_eq!(port_name(0x70, 0x0), None);
+
+        assert_eq!(port_address(".Screen/pixel"), Some(0x2e));
+        assert_eq!(port_address("Audio2/pitch"), Some(0x5f));
+        assert_eq!(port_address(".Screen/nothing"), None);
+        assert_eq!(port_address("Screen"), None);
+    }
+}
@@ -0,0 +1,458 @@
+use crate::MainMemory;
+
+use super::backend::ScreenBackend;
+#[cfg(feature = "window")]
+use super::display::{Display, DisplayOutput};
+
+pub struct Screen {
+    pub vector: Option<u16>,
+    output: Box<dyn ScreenBackend>,
+    #[cfg(feature = "window")]
+    display: Option<Display>,
+    pub framebuffer: Framebuffer,
+
+    pub x: u16,
+    pub y: u16,
+    pub sprite_addr: u16,
+
+    /// The last byte written to `.Screen/sprite`, drawn once the write has finished.
+    pub sprite: u8,
+
+    /// `.Screen/auto`: the high nibble is how many more sprites to draw after the first, and the
+    /// low bits say whether to move along x, y and the sprite address after drawing.
+    pub auto: u8,
+}
+
+impl Screen {
+    /// Creates a screen shown in a window, once the [`Display`] has been taken and run.
+    #[cfg(feature = "window")]
+    pub fn new() -> Self {
+        let (output, display) = DisplayOutput::new(800, 600, "uxn");
+        let mut screen = Self::with_backend(output);
+        screen.display = Some(display);
+        screen
+    }
+
+    /// Creates a screen which presents its frames to a backend, instead of a window.
+    pub fn with_backend(backend: impl ScreenBackend + 'static) -> Self {
+        Screen {
+            vector: None,
+            output: Box::new(backend),
+            #[cfg(feature = "window")]
+            display: None,
+            framebuffer: Framebuffer::new(800, 600),
+
+            x: 0,
+            y: 0,
+            sprite_addr: 0,
+            sprite: 0,
+            auto: 0,
+        }
+    }
+
+    /// Takes the host's half of the display, which shows this screen in a window. If nobody takes
+    /// it, the screen is never shown.
+    #[cfg(feature = "window")]
+    pub fn take_display(&mut self) -> Option<Display> {
+        self.display.take()
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.output.is_closed()
+    }
+
+    pub fn get_size(&self) -> (u16, u16) {
+        self.framebuffer.get_size()
+    }
+
+    /// Puts the screen back how it was when created, keeping the window.
+    pub fn reset(&mut self) {
+        self.vector = None;
+        self.x = 0;
+        self.y = 0;
+        self.sprite_addr = 0;
+        self.sprite = 0;
+        self.auto = 0;
+
+        self.framebuffer.colours = [Colour::new(); 4];
+        self.set_size(800, 600);
+    }
+
+    pub fn set_size(&mut self, mut width: u16, mut height: u16) {
+        if width == 0 { width = 1 }
+        if height == 0 { height = 1 }
+
+        // Ensure there's no stale framebuffer, and let the display resize straight away
+        self.framebuffer.resize(width, height);
+        self.update();
+    }
+
+    pub fn map_size(&mut self, func: impl FnOnce(u16, u16) -> (u16, u16)) {
+        let (w, h) = self.get_size();
+        let (w, h) = func(w, h);
+        self.set_size(w, h);
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.output.set_title(title);
+    }
+
+    /// Handles the side effects of a write to a Screen port, which may read from main memory.
+    pub fn after_output(&mut self, port: u8, memory: &MainMemory) {
+        // .Screen/sprite
+        if port == 0x2f {
+            let length = if self.sprite & 0x80 != 0 { 16 } else { 8 };
+
+            // Moving along x draws the extra sprites down a column, and moving along y draws them
+            // across a row, in whichever direction the sprites are flipped
+            let step = |enabled: bool, flip: bool| match (enabled, flip) {
+                (false, _) => 0u16,
+                (true, false) => 8,
+                (true, true) => 8u16.wrapping_neg(),
+            };
+            let step_x = step(self.auto & 0x01 != 0, self.sprite & 0x10 != 0);
+            let step_y = step(self.auto & 0x02 != 0, self.sprite & 0x20 != 0);
+            let across = step(self.auto & 0x02 != 0, self.sprite & 0x10 != 0);
+            let down = step(self.auto & 0x01 != 0, self.sprite & 0x20 != 0);
+
+            for i in 0..=(self.auto >> 4) as u16 {
+                let data: Vec<u8> = (0..length)
+                    .map(|i| memory[self.sprite_addr.wrapping_add(i) as usize])
+                    .collect();
+                let x = self.x.wrapping_add(across.wrapping_mul(i));
+                let y = self.y.wrapping_add(down.wrapping_mul(i));
+                self.framebuffer.draw_sprite(x, y, self.sprite, &data);
+
+                if self.auto & 0x04 != 0 {
+                    self.sprite_addr = self.sprite_addr.wrapping_add(length);
+                }
+            }
+
+            self.x = self.x.wrapping_add(step_x);
+            self.y = self.y.wrapping_add(step_y);
+        }
+    }
+
+    pub fn update(&mut self) {
+        let (width, height) = self.get_size();
+        self.output.present(width, height, self.framebuffer.composite());
+    }
+}
+
+/// The window-independent parts of the screen: the palette, and the colour indices painted onto
+/// each layer.
+///
+/// This is kept separate from [`Screen`] so that drawing logic can be used (and tested) without
+/// opening a window.
+pub struct Framebuffer {
+    width: u16,
+    height: u16,
+    pub colours: [Colour; 4],
+
+    // Stores colour indices
+    background: Vec<u8>,
+    foreground: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut framebuffer = Framebuffer {
+            width: 0,
+            height: 0,
+            colours: [Colour::new(); 4],
+
+            background: vec![],
+            foreground: vec![],
+        };
+        framebuffer.resize(width, height);
+        framebuffer
+    }
+
+    pub fn get_size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Changes the size of the framebuffer, clearing both layers.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+
+        let size = (width as usize) * (height as usize);
+
+        // Each frame starts off filled with colour 0
+        self.background = vec![0; size];
+        self.foreground = vec![0; size];
+    }
+
+    /// Gets the colour index at a position on a layer, or `None` if it is off-screen.
+    pub fn get_pixel(&self, x: u16, y: u16, layer: Layer) -> Option<u8> {
+        self.index(x, y).map(|index| self.get_layer(layer)[index])
+    }
+
+    pub fn draw_pixel(&mut self, x: u16, y: u16, colour_index: u8, layer: Layer) {
+        // Ignore off-screen painting
+        if let Some(index) = self.index(x, y) {
+            self.get_layer_mut(layer)[index] = colour_index;
+        }
+    }
+
+    pub fn fill_pixels(&mut self, x_start: u16, y_start: u16, x_dir: FillDirection, y_dir: FillDirection, colour_index: u8, layer: Layer) {
+        // Ignore fill if it starts off-screen
+        if x_start >= self.width || y_start >= self.height {
+            return;
+        }
+
+        let x_range = match x_dir {
+            FillDirection::Positive => x_start..self.width,
+            FillDirection::Negative => 0..x_start,
+        };
+        let y_range = match y_dir {
+            FillDirection::Positive => y_start..self.height,
+            FillDirection::Negative => 0..y_start,
+        };
+
+        // TODO: can do memset or something
+        for x in x_range {
+            for y in y_range.clone() {
+                self.draw_pixel(x, y, colour_index, layer);
+            }
+        }
+    }
+
+    /// Draws an 8x8 sprite, as described by a byte written to `.Screen/sprite`. The data is either 8
+    /// bytes for 1bpp, or 16 bytes for 2bpp (a plane of low bits followed by a plane of high bits).
+    ///
+    /// The low nibble is the blend mode, which selects how the sprite's colours map onto the
+    /// palette, and whether colour 0 is transparent.
+    ///
+    /// See: https://wiki.xxiivv.com/site/varvara.html#screen
+    pub fn draw_sprite(&mut self, x: u16, y: u16, sprite: u8, data: &[u8]) {
+        const BLENDING: [[u8; 16]; 4] = [
+            [0, 0, 0, 0, 1, 0, 1, 1, 2, 2, 0, 2, 3, 3, 3, 0],
+            [0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3],
+            [1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1],
+            [2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2],
+        ];
+        let layer = if sprite & 0x40 != 0 { Layer::Foreground } else { Layer::Background };
+        let flip_y = sprite & 0x20 != 0;
+        let flip_x = sprite & 0x10 != 0;
+        let blend = (sprite & 0x0f) as usize;
+        let opaque = !blend.is_multiple_of(5);
+
+        for row in 0..8 {
+            for column in 0..8 {
+                let bit = |plane: usize| (data.get(plane * 8 + row).copied().unwrap_or(0) >> (7 - column)) & 1;
+                let colour = if sprite & 0x80 != 0 { bit(0) | (bit(1) << 1) } else { bit(0) };
+                if colour == 0 && !opaque {
+                    continue;
+                }
+
+                let dx = if flip_x { 7 - column } else { column };
+                let dy = if flip_y { 7 - row } else { row };
+                self.draw_pixel(
+                    x.wrapping_add(dx as u16), y.wrapping_add(dy as u16),
+                    BLENDING[colour as usize][blend], layer,
+                );
+            }
+        }
+    }
+
+    /// Combines both layers into a single buffer of 0RGB pixels, suitable for `minifb`.
+    pub fn composite(&self) -> Vec<u32> {
+        self.background.iter().zip(&self.foreground)
+            .map(|(bg, fg)| {
+                // colour 0 is transparent on the foreground
+                if *fg == 0 {
+                    self.colours[*bg as usize].to_0rgb()
+                } else {
+                    self.colours[*fg as usize].to_0rgb()
+                }
+            })
+            .collect()
+    }
+
+    fn index(&self, x: u16, y: u16) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        Some(y as usize * self.width as usize + x as usize)
+    }
+
+    /// The colour indices painted onto a layer, row by row.
+    pub fn layer(&self, layer: Layer) -> &[u8] {
+        self.get_layer(layer)
+    }
+
+    pub fn layer_mut(&mut self, layer: Layer) -> &mut [u8] {
+        self.get_layer_mut(layer)
+    }
+
+    fn get_layer(&self, layer: Layer) -> &Vec<u8> {
+        match layer {
+            Layer::Foreground => &self.foreground,
+            Layer::Background => &self.background,
+        }
+    }
+
+    fn get_layer_mut(&mut self, layer: Layer) -> &mut Vec<u8> {
+        match layer {
+            Layer::Foreground => &mut self.foreground,
+            Layer::Background => &mut self.background,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Foreground,
+    Background,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FillDirection {
+    Positive,
+    Negative,
+}
+
+/// A Varvara-compatible colour.
+///
+/// This holds a `minifb`-compatible 0RGB representation with 8-bits per channel, but it is in fact
+/// limited to only showing Varvara's colour space, with 4 bits per channel instead of 8.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Colour(u32);
+
+impl Default for Colour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Colour {
+    pub fn new() -> Self {
+        Self(0)
+    }
+
+    pub fn set_red_from_nibble(&mut self, value: u8) {
+        let scaled = (value << 4) | value;
+        let [z, _, g, b] = self.0.to_be_bytes();
+        self.0 = u32::from_be_bytes([z, scaled, g, b]);
+    }
+
+    pub fn set_green_from_nibble(&mut self, value: u8) {
+        let scaled = (value << 4) | value;
+        let [z, r, _, b] = self.0.to_be_bytes();
+        self.0 = u32::from_be_bytes([z, r, scaled, b]);
+    }
+
+    pub fn set_blue_from_nibble(&mut self, value: u8) {
+        let scaled = (value << 4) | value;
+        let [z, r, g, _] = self.0.to_be_bytes();
+        self.0 = u32::from_be_bytes([z, r, g, scaled]);
+    }
+
+    pub fn from_0rgb(value: u32) -> Self {
+        Self(value & 0x00ffffff)
+    }
+
+    pub fn to_0rgb(self) -> u32 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Colour, FillDirection, Framebuffer, Layer};
+
+    #[test]
+    fn test_colour_channels() {
+        let mut colour = Colour::new();
+        colour.set_red_from_nibble(0xf);
+        assert_eq!(colour.to_0rgb(), 0x00ff0000);
+        colour.set_green_from_nibble(0x8);
+        assert_eq!(colour.to_0rgb(), 0x00ff8800);
+        colour.set_blue_from_nibble(0x1);
+        assert_eq!(colour.to_0rgb(), 0x00ff8811);
+    }
+
+    #[test]
+    fn test_draw_pixel() {
+        let mut fb = Framebuffer::new(4, 3);
+        fb.draw_pixel(1, 2, 3, Layer::Background);
+        assert_eq!(fb.get_pixel(1, 2, Layer::Background), Some(3));
+        assert_eq!(fb.get_pixel(1, 2, Layer::Foreground), Some(0));
+
+        // Off-screen pixels are ignored
+        fb.draw_pixel(4, 0, 3, Layer::Background);
+        fb.draw_pixel(0, 3, 3, Layer::Background);
+        assert_eq!(fb.get_pixel(4, 0, Layer::Background), None);
+        assert_eq!(fb.composite().len(), 12);
+    }
+
+    #[test]
+    fn test_fill_pixels() {
+        // Positive fill includes the start position
+        let mut fb = Framebuffer::new(4, 4);
+        fb.fill_pixels(2, 1, FillDirection::Positive, FillDirection::Positive, 1, Layer::Background);
+        assert_eq!(fb.get_pixel(1, 1, Layer::Background), Some(0));
+        assert_eq!(fb.get_pixel(2, 0, Layer::Background), Some(0));
+        assert_eq!(fb.get_pixel(2, 1, Layer::Background), Some(1));
+        assert_eq!(fb.get_pixel(3, 3, Layer::Background), Some(1));
+
+        // Negative fill stops before the start position
+        let mut fb = Framebuffer::new(4, 4);
+        fb.fill_pixels(2, 1, FillDirection::Negative, FillDirection::Negative, 1, Layer::Background);
+        assert_eq!(fb.get_pixel(0, 0, Layer::Background), Some(1));
+        assert_eq!(fb.get_pixel(1, 0, Layer::Background), Some(1));
+        assert_eq!(fb.get_pixel(2, 0, Layer::Background), Some(0));
+        assert_eq!(fb.get_pixel(0, 1, Layer::Background), Some(0));
+
+        // Fills starting off-screen are ignored
+        let mut fb = Framebuffer::new(4, 4);
+        fb.fill_pixels(4, 0, FillDirection::Negative, FillDirection::Positive, 1, Layer::Background);
+        assert!(fb.composite().iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn test_draw_sprite() {
+        let arrow = [0x80, 0xc0, 0xe0, 0xf0, 0xe0, 0xc0, 0x80, 0x00];
+
+        // Blend 1 maps colour 1 to 1, and leaves colour 0 as 0
+        let mut fb = Framebuffer::new(8, 8);
+        fb.draw_pixel(7, 7, 3, Layer::Background);
+        fb.draw_sprite(0, 0, 0x01, &arrow);
+        assert_eq!(fb.get_pixel(0, 0, Layer::Background), Some(1));
+        assert_eq!(fb.get_pixel(1, 0, Layer::Background), Some(0));
+        assert_eq!(fb.get_pixel(3, 3, Layer::Background), Some(1));
+        assert_eq!(fb.get_pixel(7, 7, Layer::Background), Some(0));
+
+        // Blend 5 is transparent, and flipping starts from the other corner
+        let mut fb = Framebuffer::new(8, 8);
+        fb.draw_pixel(0, 0, 3, Layer::Foreground);
+        fb.draw_sprite(0, 0, 0x75, &arrow);
+        assert_eq!(fb.get_pixel(7, 7, Layer::Foreground), Some(1));
+        assert_eq!(fb.get_pixel(0, 0, Layer::Foreground), Some(3));
+
+        // 2bpp sprites combine both planes
+        let mut fb = Framebuffer::new(8, 8);
+        let mut data = [0; 16];
+        data[0] = 0b1010_0000;
+        data[8] = 0b0110_0000;
+        fb.draw_sprite(0, 0, 0x81, &data);
+        let row: Vec<_> = (0..4).map(|x| fb.get_pixel(x, 0, Layer::Background).unwrap()).collect();
+        assert_eq!(row, vec![1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_composite() {
+        let mut fb = Framebuffer::new(2, 1);
+        fb.colours[1].set_red_from_nibble(0xf);
+        fb.colours[2].set_blue_from_nibble(0xf);
+
+        // Foreground colour 0 is transparent, showing the background through it
+        fb.draw_pixel(0, 0, 1, Layer::Background);
+        fb.draw_pixel(1, 0, 1, Layer::Background);
+        fb.draw_pixel(1, 0, 2, Layer::Foreground);
+        assert_eq!(fb.composite(), vec![0x00ff0000, 0x000000ff]);
+    }
+}
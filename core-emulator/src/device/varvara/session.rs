@@ -0,0 +1,287 @@
+//! Recording of everything which passes through the Console, alongside screen frames and
+//! Controller input, so that a misbehaving session can be attached to a bug report and replayed by
+//! someone else.
+//!
+//! Sessions are stored as UTF-8 text, one event per line, after a header. Numbers are written in
+//! hex, and either `\n` or `\r\n` line endings are accepted, so files can be moved between
+//! platforms freely:
+//!
+//! ```text
+//! uxnsession 1
+//! rom 0123456789abcdef
+//! flags game.rom --record-session out.uxnsession
+//! build {"crates":{"uxn-core-emulator":"0.1.0"},...}
+//! frame
+//! in 01 61
+//! out 61
+//! ctrl 10 00
+//! ```
+
+use std::{collections::VecDeque, error::Error, fmt::Display, io::{LineWriter, Write}};
+
+use super::ConsoleType;
+
+const MAGIC: &str = "uxnsession 1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The Screen vector was invoked for a new frame.
+    Frame,
+
+    /// A byte was delivered to the Console vector.
+    Input(u8, ConsoleType),
+
+    /// A byte was written to `.Console/write`.
+    Output(u8),
+
+    /// A byte was written to `.Console/error`.
+    Error(u8),
+
+    /// The Controller's button and key bytes changed, running its vector if it has one.
+    Controller(u8, u8),
+}
+
+impl Display for SessionEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionEvent::Frame => write!(f, "frame"),
+            SessionEvent::Input(byte, input_type) => write!(f, "in {:02x} {byte:02x}", *input_type as u8),
+            SessionEvent::Output(byte) => write!(f, "out {byte:02x}"),
+            SessionEvent::Error(byte) => write!(f, "err {byte:02x}"),
+            SessionEvent::Controller(button, key) => write!(f, "ctrl {button:02x} {key:02x}"),
+        }
+    }
+}
+
+impl SessionEvent {
+    fn parse(line: &str) -> Option<Self> {
+        let parts: Vec<&str> = line.split(' ').collect();
+        let hex = |i: usize| parts.get(i).and_then(|part| u8::from_str_radix(part, 16).ok());
+
+        match parts[0] {
+            "frame" => Some(SessionEvent::Frame),
+            "in" => Some(SessionEvent::Input(hex(2)?, ConsoleType::from_byte(hex(1)?)?)),
+            "out" => Some(SessionEvent::Output(hex(1)?)),
+            "err" => Some(SessionEvent::Error(hex(1)?)),
+            "ctrl" => Some(SessionEvent::Controller(hex(1)?, hex(2)?)),
+            _ => None,
+        }
+    }
+}
+
+/// A recorded session, loaded back from its text form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub rom_hash: u64,
+    pub flags: Vec<String>,
+
+    /// The [`BuildInfo`](crate::BuildInfo) of the recording emulator, as JSON. Older sessions
+    /// don't have this.
+    pub build: Option<String>,
+
+    pub events: Vec<SessionEvent>,
+}
+
+impl Session {
+    pub fn parse(text: &str) -> Result<Session, Box<dyn Error>> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err("not a uxn session file".into());
+        }
+
+        let rom_hash = lines.next()
+            .and_then(|line| line.strip_prefix("rom "))
+            .ok_or("missing ROM hash")?;
+        let rom_hash = u64::from_str_radix(rom_hash, 16)?;
+
+        let flags = lines.next()
+            .and_then(|line| line.strip_prefix("flags"))
+            .ok_or("missing flags")?
+            .split_whitespace()
+            .map(|flag| flag.to_string())
+            .collect();
+
+        let mut lines = lines.peekable();
+        let build = lines.next_if(|line| line.starts_with("build "))
+            .and_then(|line| line.strip_prefix("build "))
+            .map(|build| build.to_string());
+
+        let events = lines
+            .map(|line| SessionEvent::parse(line).ok_or_else(|| format!("invalid event: {line}")))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Session { rom_hash, flags, build, events })
+    }
+}
+
+/// Writes events to a session file as they happen, so that nothing is lost if the emulator exits
+/// abruptly.
+pub struct SessionRecorder {
+    writer: LineWriter<Box<dyn Write + Send>>,
+}
+
+impl SessionRecorder {
+    pub fn new(writer: impl Write + Send + 'static, rom_hash: u64, flags: &[String]) -> std::io::Result<Self> {
+        let mut writer = LineWriter::new(Box::new(writer) as Box<dyn Write + Send>);
+        writeln!(writer, "{MAGIC}")?;
+        writeln!(writer, "rom {rom_hash:016x}")?;
+        writeln!(writer, "flags {}", flags.join(" "))?;
+        writeln!(writer, "build {}", crate::build_info().to_json())?;
+        Ok(SessionRecorder { writer })
+    }
+
+    pub fn record(&mut self, event: SessionEvent) {
+        // Failing to record shouldn't stop the ROM
+        if let Err(e) = writeln!(self.writer, "{event}") {
+            crate::warning!("Could not record session event: {e}");
+        }
+    }
+}
+
+/// Plays back the inputs of a recorded session, checking that the ROM produces the same console
+/// output as it did when recorded.
+pub struct SessionReplay {
+    events: VecDeque<SessionEvent>,
+    expected: Vec<SessionEvent>,
+    actual: Vec<SessionEvent>,
+    finished: bool,
+}
+
+impl SessionReplay {
+    pub fn new(session: Session) -> Self {
+        SessionReplay {
+            events: session.events.into(),
+            expected: vec![],
+            actual: vec![],
+            finished: false,
+        }
+    }
+
+    /// Gets the next input-like event to act upon - a frame, console input or controller input - or
+    /// `None` if the replay is over. Any output events passed along the way are remembered as expected.
+    pub fn next_input(&mut self) -> Option<SessionEvent> {
+        while let Some(event) = self.events.pop_front() {
+            match event {
+                SessionEvent::Frame | SessionEvent::Input(..) | SessionEvent::Controller(..) => return Some(event),
+                SessionEvent::Output(_) | SessionEvent::Error(_) => self.expected.push(event),
+            }
+        }
+        None
+    }
+
+    pub fn record_output(&mut self, event: SessionEvent) {
+        self.actual.push(event);
+    }
+
+    /// Prints whether the output matched the recording. This only reports once.
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        // Any output after the final input is expected too
+        self.expected.extend(self.events.drain(..).filter(|e| matches!(e, SessionEvent::Output(_) | SessionEvent::Error(_))));
+
+        match self.divergence() {
+            None => eprintln!("Replay finished: console output matched the recording"),
+            Some(index) => eprintln!(
+                "Replay finished: console output diverged at byte {index} (expected {:?}, got {:?})",
+                self.expected.get(index), self.actual.get(index),
+            ),
+        }
+    }
+
+    /// The index of the first output event which differs from the recording, if any.
+    pub fn divergence(&self) -> Option<usize> {
+        if self.expected == self.actual {
+            return None;
+        }
+
+        Some(self.expected.iter().zip(&self.actual)
+            .position(|(e, a)| e != a)
+            .unwrap_or(self.expected.len().min(self.actual.len())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, sync::{Arc, Mutex}};
+
+    use super::{ConsoleType, Session, SessionEvent, SessionRecorder, SessionReplay};
+
+    // A writer which can still be inspected after being handed to a recorder
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let buffer = SharedBuffer::default();
+        let flags = vec!["game.rom".to_string(), "--record-session".to_string(), "out".to_string()];
+        let mut recorder = SessionRecorder::new(buffer.clone(), 0xabcd, &flags).unwrap();
+        let events = [
+            SessionEvent::Frame,
+            SessionEvent::Input(b'a', ConsoleType::Stdin),
+            SessionEvent::Output(b'A'),
+            SessionEvent::Error(b'!'),
+            SessionEvent::Controller(0x11, b'x'),
+        ];
+        for event in events {
+            recorder.record(event);
+        }
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let session = Session::parse(&text).unwrap();
+        assert_eq!(session, Session {
+            rom_hash: 0xabcd,
+            flags,
+            build: Some(crate::build_info().to_json()),
+            events: events.to_vec(),
+        });
+
+        // The build line is optional
+        let session = Session::parse("uxnsession 1\nrom 0001\nflags\nframe").unwrap();
+        assert_eq!(session.build, None);
+        assert_eq!(session.events, vec![SessionEvent::Frame]);
+
+        // Sessions checked out on Windows may have different line endings
+        let session = Session::parse("uxnsession 1\r\nrom 0001\r\nflags\r\nin 01 61\r\n").unwrap();
+        assert_eq!(session.events, vec![SessionEvent::Input(b'a', ConsoleType::Stdin)]);
+
+        assert!(Session::parse("something else").is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let session = Session {
+            rom_hash: 0,
+            flags: vec![],
+            build: None,
+            events: vec![
+                SessionEvent::Input(b'a', ConsoleType::Stdin),
+                SessionEvent::Output(b'A'),
+                SessionEvent::Frame,
+                SessionEvent::Output(b'B'),
+            ],
+        };
+
+        let mut replay = SessionReplay::new(session);
+        assert_eq!(replay.next_input(), Some(SessionEvent::Input(b'a', ConsoleType::Stdin)));
+        replay.record_output(SessionEvent::Output(b'A'));
+        assert_eq!(replay.next_input(), Some(SessionEvent::Frame));
+        replay.record_output(SessionEvent::Output(b'C'));
+        assert_eq!(replay.next_input(), None);
+
+        assert_eq!(replay.divergence(), Some(1));
+    }
+}
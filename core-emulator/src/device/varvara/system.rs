@@ -0,0 +1,178 @@
+//! Parts of the System device which operate on main memory.
+
+use crate::{MainMemory, Stack};
+
+/// Performs the `.System/expansion` command stored in main memory at `addr`.
+///
+/// See: https://wiki.xxiivv.com/site/varvara.html#system
+pub fn run_expansion(memory: &mut MainMemory, addr: u16) {
+    let byte = |offset: u16| memory[addr.overflowing_add(offset).0 as usize];
+    let short = |offset: u16| u16::from_be_bytes([byte(offset), byte(offset + 1)]);
+
+    let command = byte(0);
+    let length = short(1);
+
+    match command {
+        // fill: length* bank* addr* value
+        0x00 => {
+            let (bank, dest, value) = (short(3), short(5), byte(7));
+            let bank = memory.bank_mut(bank);
+            for i in 0..length {
+                bank[dest.overflowing_add(i).0 as usize] = value;
+            }
+        },
+
+        // cpyl, cpyr: length* src-bank* src-addr* dst-bank* dst-addr*
+        0x01 | 0x02 => {
+            let (src_bank, src, dest_bank, dest) = (short(3), short(5), short(7), short(9));
+
+            let copy = |memory: &mut MainMemory, i: u16| {
+                let byte = memory.bank(src_bank)[src.overflowing_add(i).0 as usize];
+                memory.bank_mut(dest_bank)[dest.overflowing_add(i).0 as usize] = byte;
+            };
+
+            // cpyl copies from the start, and cpyr from the end, which matters for overlapping ranges
+            if command == 0x01 {
+                for i in 0..length { copy(memory, i) }
+            } else {
+                for i in (0..length).rev() { copy(memory, i) }
+            }
+        },
+
+        _ => crate::warning!("Unknown expansion command {command:#04x}"),
+    }
+}
+
+/// The metadata structure which `.System/metadata` points to, describing the ROM.
+///
+/// See: https://wiki.xxiivv.com/site/metadata.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub version: u8,
+
+    /// Free text - by convention, the first line is the ROM's name and version.
+    pub text: String,
+
+    /// Extra fields, as pairs of a type byte and an address.
+    pub fields: Vec<(u8, u16)>,
+}
+
+impl Metadata {
+    pub fn parse(memory: &MainMemory, addr: u16) -> Self {
+        let byte = |offset: u16| memory[addr.overflowing_add(offset).0 as usize];
+
+        let version = byte(0);
+
+        let mut text = vec![];
+        let mut offset = 1;
+        while byte(offset) != 0 && offset < u16::MAX {
+            text.push(byte(offset));
+            offset += 1;
+        }
+        let text = String::from_utf8_lossy(&text).into_owned();
+
+        // Skip the null terminator to find the fields
+        let mut offset = offset.saturating_add(1);
+        let count = byte(offset);
+        offset = offset.saturating_add(1);
+        let fields = (0..count)
+            .map(|i| {
+                let field = offset.overflowing_add(i as u16 * 3).0;
+                (byte(field), u16::from_be_bytes([byte(field.wrapping_add(1)), byte(field.wrapping_add(2))]))
+            })
+            .collect();
+
+        Metadata { version, text, fields }
+    }
+
+    /// The ROM's name, which is the first line of the text.
+    pub fn name(&self) -> &str {
+        self.text.lines().next().unwrap_or("")
+    }
+}
+
+/// Formats a stack for `.System/debug`, in the same style as uxnemu: `wst 01 02 03|`.
+pub fn format_stack(name: &str, stack: &Stack) -> String {
+    if stack.is_empty() {
+        format!("{name}|")
+    } else {
+        format!("{name} {stack}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{MainMemory, Stack};
+
+    use super::{format_stack, run_expansion, Metadata};
+
+    #[test]
+    fn test_metadata() {
+        let mut memory = MainMemory::new();
+        let meta = b"\x00Nasu 1.0\nSprite Editor\x00\x01\x0a\x12\x34";
+        memory.bank_mut(0)[0x200..0x200 + meta.len()].copy_from_slice(meta);
+
+        let metadata = Metadata::parse(&memory, 0x200);
+        assert_eq!(metadata, Metadata {
+            version: 0,
+            text: "Nasu 1.0\nSprite Editor".to_string(),
+            fields: vec![(0x0a, 0x1234)],
+        });
+        assert_eq!(metadata.name(), "Nasu 1.0");
+    }
+
+    #[test]
+    fn test_format_stack() {
+        assert_eq!(format_stack("wst", &Stack::new_with_data(&[0x01, 0xab, 0x03])), "wst 01 ab 03|");
+        assert_eq!(format_stack("rst", &Stack::new()), "rst|");
+    }
+
+    fn memory_with_command(command: &[u8]) -> MainMemory {
+        let mut memory = MainMemory::new();
+        memory.bank_mut(0)[0x100..0x100 + command.len()].copy_from_slice(command);
+        memory
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut memory = memory_with_command(&[0x00, 0x00, 0x03, 0x00, 0x00, 0x02, 0x00, 0xab]);
+        run_expansion(&mut memory, 0x100);
+        assert_eq!(memory.bank(0)[0x1ff..0x204], [0x00, 0xab, 0xab, 0xab, 0x00]);
+    }
+
+    #[test]
+    fn test_copy() {
+        let command = [0x01, 0x00, 0x03, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x01];
+        let mut memory = memory_with_command(&command);
+        memory.bank_mut(0)[0x200..0x204].copy_from_slice(&[1, 2, 3, 4]);
+
+        // Copying forwards over an overlapping range smears the first byte
+        run_expansion(&mut memory, 0x100);
+        assert_eq!(memory.bank(0)[0x200..0x205], [1, 1, 1, 1, 0]);
+
+        // Copying backwards doesn't
+        memory[0x100] = 0x02;
+        memory.bank_mut(0)[0x200..0x204].copy_from_slice(&[1, 2, 3, 4]);
+        run_expansion(&mut memory, 0x100);
+        assert_eq!(memory.bank(0)[0x200..0x205], [1, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_banks() {
+        // Fill bank 2, then copy it back into bank 0
+        let mut memory = memory_with_command(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xff, 0xff, 0x12]);
+        run_expansion(&mut memory, 0x100);
+        assert_eq!(memory.bank(2)[0xffff], 0x12);
+        assert_eq!(memory.bank(2)[0x0000], 0x12);
+        assert_eq!(memory.bank(0)[0xffff], 0x00);
+
+        let command = [0x01, 0x00, 0x01, 0x00, 0x02, 0xff, 0xff, 0x00, 0x00, 0x03, 0x00];
+        memory.bank_mut(0)[0x100..0x100 + command.len()].copy_from_slice(&command);
+        run_expansion(&mut memory, 0x100);
+        assert_eq!(memory[0x300], 0x12);
+
+        // Bank numbers wrap around
+        assert_eq!(memory.bank(18)[0xffff], 0x12);
+        assert_eq!(memory.bank(1)[0xffff], 0x00);
+    }
+}
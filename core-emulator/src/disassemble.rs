@@ -0,0 +1,62 @@
+use std::fmt::Write;
+
+use uxn_utils::Symbols;
+
+use crate::{immediate_info, mnemonic};
+
+/// Disassembles a ROM into one line per instruction, with its address, its bytes and its
+/// mnemonic. Immediate instructions are shown with their operands, and labels from `symbols` are
+/// put on their own lines before what they label.
+///
+/// ROMs mix code and data, and there's no telling them apart, so data is shown as whatever
+/// instructions its bytes would be.
+pub fn disassemble(rom: &[u8], symbols: Option<&Symbols>) -> String {
+    let mut output = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = 0x0100u16.wrapping_add(offset as u16);
+        if let Some(label) = symbols.and_then(|symbols| symbols.name(address)) {
+            writeln!(output, "@{label}").unwrap();
+        }
+
+        // LIT2 and the immediate jumps are followed by a short, and LIT by a byte
+        let ins = rom[offset];
+        let operand_length = match immediate_info(ins) {
+            Some(_) if ins & 0xa0 == 0x80 => 1,
+            Some(_) => 2,
+            None => 0,
+        };
+        let end = (offset + 1 + operand_length).min(rom.len());
+        let bytes = &rom[offset..end];
+        let hex = bytes.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ");
+        let operand = bytes[1..].iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+        let text = if operand.is_empty() { mnemonic(ins) } else { format!("{} {operand}", mnemonic(ins)) };
+        writeln!(output, "{address:04x}  {hex:<8}  {text}").unwrap();
+
+        offset = end;
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use uxn_utils::Symbols;
+
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        let mut symbols = Symbols::new();
+        symbols.insert(0x0106, "loop");
+        let rom = [0x80, 0x18, 0xa0, 0x12, 0x34, 0x3f, 0x40, 0xff, 0xfa, 0x00, 0xa0, 0x01];
+        assert_eq!(disassemble(&rom, Some(&symbols)), "\
+0100  80 18     LIT 18
+0102  a0 12 34  LIT2 1234
+0105  3f        SFT2
+@loop
+0106  40 ff fa  JMI fffa
+0109  00        BRK
+010a  a0 01     LIT2 01
+");
+    }
+}
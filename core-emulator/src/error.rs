@@ -0,0 +1,54 @@
+use std::{error::Error, fmt::Display};
+
+use uxn_utils::asm::AssembleError;
+
+use crate::{StackFault, StackMode};
+
+/// Something which stopped a ROM from running, which the host should report rather than crash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UxnError {
+    /// The ROM used a device port which the attached device doesn't implement.
+    UnsupportedPort(u8),
+
+    /// An instruction wrapped a stack pointer around, while the stack was in strict mode.
+    Stack { stack: StackMode, fault: StackFault, program_counter: u16 },
+
+    /// Uxntal source couldn't be assembled.
+    Assembly(AssembleError),
+}
+
+impl UxnError {
+    /// The error code uxn gives a ROM's fault handler for this, or `None` if it isn't a fault which
+    /// the ROM can handle.
+    ///
+    /// See: https://wiki.xxiivv.com/site/varvara.html#system
+    pub fn code(&self) -> Option<u8> {
+        match self {
+            UxnError::Stack { fault: StackFault::Underflow, .. } => Some(1),
+            UxnError::Stack { fault: StackFault::Overflow, .. } => Some(2),
+            UxnError::UnsupportedPort(_) | UxnError::Assembly(_) => None,
+        }
+    }
+}
+
+impl Display for UxnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UxnError::UnsupportedPort(port) => {
+                #[cfg(feature = "varvara")]
+                let name = crate::device::port_name(*port, *port).unwrap_or("unknown");
+                #[cfg(not(feature = "varvara"))]
+                let name = "unknown";
+                write!(f, "unsupported device port {port:#04x} ({name})")
+            },
+            UxnError::Stack { stack, fault, program_counter } => write!(
+                f, "{} stack {} at {program_counter:#06x}",
+                match stack { StackMode::Working => "working", StackMode::Return => "return" },
+                match fault { StackFault::Underflow => "underflow", StackFault::Overflow => "overflow" },
+            ),
+            UxnError::Assembly(error) => write!(f, "assembly failed: {error}"),
+        }
+    }
+}
+
+impl Error for UxnError {}
@@ -0,0 +1,432 @@
+//! A server for GDB's Remote Serial Protocol, so that gdb, lldb and front-ends built on them can
+//! debug ROMs.
+//!
+//! The target description names three registers: `pc`, and the working and return stack pointers
+//! `wsp` and `rsp`. Memory is main memory, and software breakpoints map to
+//! [`Core::add_breakpoint`].
+//!
+//! See: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+use std::{io::{self, Read, Write}, net::{TcpListener, TcpStream, ToSocketAddrs}};
+
+use crate::{device::{Device, DeviceEvent}, Core, RunResult, UxnError};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.uxn.core">
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="0"/>
+    <reg name="wsp" bitsize="8" type="uint8" regnum="1"/>
+    <reg name="rsp" bitsize="8" type="uint8" regnum="2"/>
+  </feature>
+</target>
+"#;
+
+/// The stop reply after a breakpoint or a step, as a SIGTRAP.
+const STOPPED: &str = "S05";
+
+/// The stop reply after the ROM faults, as a SIGSEGV.
+const FAULTED: &str = "S0b";
+
+/// What to do after handling a packet.
+enum Reply {
+    Packet(String),
+
+    /// Send this, then stop serving, since the ROM exited.
+    Finish(String, Result<RunResult, UxnError>),
+
+    /// Acknowledge, then let the ROM carry on by itself.
+    Detach,
+
+    /// Stop serving without replying.
+    Kill,
+}
+
+/// Serves one debugger connection, controlling a core.
+///
+/// Interrupting a running ROM with Ctrl-C isn't supported, so set breakpoints before continuing.
+pub struct GdbStub<S> {
+    stream: S,
+
+    /// Whether the program counter is part-way through a vector. Once a vector reaches its BRK,
+    /// the next one to run is whichever the device asks for.
+    in_vector: bool,
+}
+
+impl GdbStub<TcpStream> {
+    /// Listens on an address, like `127.0.0.1:1234`, and waits for a debugger to connect. See
+    /// [`GdbStub::new`] for `in_vector`.
+    pub fn accept(address: impl ToSocketAddrs, in_vector: bool) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream, in_vector))
+    }
+}
+
+impl<S: Read + Write> GdbStub<S> {
+    /// Serves a connection to a core. `in_vector` should be set if the program counter is part-way
+    /// through a vector, like the reset vector of a core which hasn't started yet, and cleared if
+    /// the core is between vectors, like after restoring a snapshot.
+    pub fn new(stream: S, in_vector: bool) -> Self {
+        Self { stream, in_vector }
+    }
+
+    /// Handles packets until the ROM exits, or the debugger kills it or detaches. After detaching,
+    /// or if the connection is lost, the ROM carries on running without breakpoints.
+    ///
+    /// Returns how the ROM finished, like [`Core::execute_until_exit`]. Killing the ROM counts as
+    /// exiting with code 0.
+    pub fn serve(&mut self, core: &mut Core) -> Result<RunResult, UxnError> {
+        loop {
+            let packet = match self.read_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => return self.detach(core),
+                Err(e) => {
+                    crate::warning!("Lost the debugger's connection: {e}");
+                    return self.detach(core);
+                },
+            };
+
+            let (reply, result) = match self.handle(core, &packet) {
+                Reply::Packet(reply) => (reply, None),
+                Reply::Finish(reply, result) => (reply, Some(result)),
+                Reply::Detach => {
+                    let _ = self.send_packet("OK");
+                    return self.detach(core);
+                },
+                Reply::Kill => return Ok(RunResult::Exit(0)),
+            };
+            if let Err(e) = self.send_packet(&reply) {
+                crate::warning!("Lost the debugger's connection: {e}");
+                return self.detach(core);
+            }
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+
+    fn handle(&mut self, core: &mut Core, packet: &str) -> Reply {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => STOPPED.to_string(),
+            "g" => format!("{}{:02x}{:02x}", hex(&core.program_counter.to_le_bytes()), core.working_stack.pointer, core.return_stack.pointer),
+            "G" => match parse_hex_bytes(args).as_deref() {
+                Some(&[pc_low, pc_high, wsp, rsp]) => {
+                    core.set_program_counter(u16::from_le_bytes([pc_low, pc_high]));
+                    core.working_stack.pointer = wsp;
+                    core.return_stack.pointer = rsp;
+                    "OK".to_string()
+                },
+                _ => "E01".to_string(),
+            },
+            "p" => match u8::from_str_radix(args, 16) {
+                Ok(0) => hex(&core.program_counter.to_le_bytes()),
+                Ok(1) => format!("{:02x}", core.working_stack.pointer),
+                Ok(2) => format!("{:02x}", core.return_stack.pointer),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let Some((register, value)) = args.split_once('=') else { return Reply::Packet("E01".to_string()) };
+                match (u8::from_str_radix(register, 16), parse_hex_bytes(value).as_deref()) {
+                    (Ok(0), Some(&[low, high])) => core.set_program_counter(u16::from_le_bytes([low, high])),
+                    (Ok(1), Some(&[wsp])) => core.working_stack.pointer = wsp,
+                    (Ok(2), Some(&[rsp])) => core.return_stack.pointer = rsp,
+                    _ => return Reply::Packet("E01".to_string()),
+                }
+                "OK".to_string()
+            },
+            "m" => match parse_range(args) {
+                Some((address, length)) => {
+                    let bytes: Vec<u8> = (0..length).map(|i| core.memory[address.wrapping_add(i) as usize]).collect();
+                    hex(&bytes)
+                },
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let Some((range, data)) = args.split_once(':') else { return Reply::Packet("E01".to_string()) };
+                match (parse_range(range), parse_hex_bytes(data)) {
+                    (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+                        core.poke_memory(address, &bytes);
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+
+            // Hardware breakpoints are treated like software ones, since they're all the same here
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let (Some("0" | "1"), Some(address)) = (parts.next(), parts.next()) else {
+                    return Reply::Packet(String::new());
+                };
+                let Ok(address) = u16::from_str_radix(address, 16) else { return Reply::Packet("E01".to_string()) };
+                if command == "Z" {
+                    core.add_breakpoint(address);
+                } else {
+                    core.remove_breakpoint(address);
+                }
+                "OK".to_string()
+            },
+
+            "s" | "c" => {
+                if let Ok(address) = u16::from_str_radix(args, 16) {
+                    core.set_program_counter(address);
+                }
+                return if command == "s" { self.step(core) } else { self.resume(core) };
+            },
+            "k" => return Reply::Kill,
+            "D" => return Reply::Detach,
+
+            // Only one thread, which is already selected
+            "H" => "OK".to_string(),
+            _ => self.query(packet),
+        };
+        Reply::Packet(reply)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = range.split_once(',') else { return "E01".to_string() };
+            let (Ok(offset), Ok(length)) = (usize::from_str_radix(offset, 16), usize::from_str_radix(length, 16)) else {
+                return "E01".to_string();
+            };
+            let rest = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+            return if rest.len() > length { format!("m{}", &rest[..length]) } else { format!("l{rest}") };
+        }
+
+        match packet {
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }.to_string()
+    }
+
+    fn step(&mut self, core: &mut Core) -> Reply {
+        // Between vectors, the next one is whichever the device asks for, so wait for it and step
+        // into its first instruction
+        let outcome = if self.in_vector {
+            core.execute_instructions(1)
+        } else {
+            match core.device.wait_for_event() {
+                DeviceEvent::Vector(vector) => core.execute_vector(vector, Some(1)),
+                DeviceEvent::Exit(code) => return Reply::Finish(format!("W{code:02x}"), Ok(RunResult::Exit(code))),
+            }
+        };
+        self.in_vector = true;
+
+        match outcome.map(|outcome| outcome.result) {
+            Ok(RunResult::Break) => {
+                self.in_vector = false;
+                Reply::Packet(STOPPED.to_string())
+            },
+            Ok(RunResult::Exit(code)) => Reply::Finish(format!("W{code:02x}"), Ok(RunResult::Exit(code))),
+            Ok(RunResult::Stopped(_) | RunResult::BudgetExceeded) => Reply::Packet(STOPPED.to_string()),
+            Err(e) => {
+                crate::warning!("The ROM stopped with an error: {e}");
+                Reply::Packet(FAULTED.to_string())
+            },
+        }
+    }
+
+    fn resume(&mut self, core: &mut Core) -> Reply {
+        let result = self.run(core);
+        match result {
+            Ok(RunResult::Exit(code)) => Reply::Finish(format!("W{code:02x}"), result),
+            Ok(_) => Reply::Packet(STOPPED.to_string()),
+            Err(e) => {
+                crate::warning!("The ROM stopped with an error: {e}");
+                Reply::Packet(FAULTED.to_string())
+            },
+        }
+    }
+
+    /// Runs until a breakpoint or until the ROM exits.
+    fn run(&mut self, core: &mut Core) -> Result<RunResult, UxnError> {
+        let result = if self.in_vector {
+            core.execute_until_exit()
+        } else {
+            core.handle_events_until_exit()
+        };
+
+        // Breakpoints and running out of budget both stop part-way through a vector
+        self.in_vector = !matches!(result, Ok(RunResult::Exit(_)));
+        result
+    }
+
+    fn detach(&mut self, core: &mut Core) -> Result<RunResult, UxnError> {
+        core.clear_breakpoints();
+        self.run(core)
+    }
+
+    /// Reads the next packet, acknowledging it. Returns `None` once the connection closes.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acknowledgements, and interrupts which arrive while nothing is running
+            if self.read_byte()? != Some(b'$') {
+                match self.read_byte_until(b'$')? {
+                    true => {},
+                    false => return Ok(None),
+                }
+            }
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else { return Ok(None) };
+
+            let expected = u8::from_str_radix(&String::from_utf8_lossy(&[high, low]), 16).ok();
+            if expected == Some(checksum(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    /// Skips bytes until `target`. Returns false if the connection closes first.
+    fn read_byte_until(&mut self, target: u8) -> io::Result<bool> {
+        loop {
+            match self.read_byte()? {
+                Some(byte) if byte == target => return Ok(true),
+                Some(_) => {},
+                None => return Ok(false),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        write!(self.stream, "${data}#{:02x}", checksum(data.as_bytes()))?;
+        self.stream.flush()
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Parses the `address,length` of a memory packet.
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+    let (address, length) = range.split_once(',')?;
+    Some((u16::from_str_radix(address, 16).ok()?, u16::from_str_radix(length, 16).ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor, Read, Write};
+
+    use crate::{device::DeviceEvent, Core, RunResult};
+
+    use super::{checksum, GdbStub};
+
+    /// A connection which replays what a debugger sent, and keeps what was sent back.
+    struct FakeConnection {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for FakeConnection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeConnection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Sends packets to a stub, giving how the ROM finished and the replies.
+    fn converse(core: &mut Core, packets: &[&str]) -> (RunResult, Vec<String>) {
+        let input = packets.iter()
+            .map(|packet| format!("+${packet}#{:02x}", checksum(packet.as_bytes())))
+            .collect::<String>();
+        let mut stub = GdbStub::new(FakeConnection { input: Cursor::new(input.into_bytes()), output: vec![] }, true);
+        let result = stub.serve(core).unwrap();
+
+        let output = String::from_utf8(stub.stream.output).unwrap();
+        let replies = output.split('$').skip(1)
+            .map(|reply| {
+                let (data, sum) = reply.split_once('#').unwrap();
+                assert_eq!(u8::from_str_radix(&sum[..2], 16).unwrap(), checksum(data.as_bytes()));
+                data.to_string()
+            })
+            .collect();
+        (result, replies)
+    }
+
+    #[test]
+    fn test_gdb_stub() {
+        // The ADD is at 0x0104
+        let mut core = Core::new_with_uxntal("#01 #02 ADD BRK").unwrap();
+        let (result, replies) = converse(&mut core, &[
+            "qSupported:swbreak+", "?", "Z0,104,1", "c", "g", "m0100,5", "s", "p0", "p1",
+            "M0000,2:abcd", "m0000,2", "z0,104,1", "Pfe=00", "qfThreadInfo", "vMustReplyEmpty", "c",
+        ]);
+        assert_eq!(replies, [
+            "PacketSize=1000;qXfer:features:read+", "S05", "OK", "S05", "04010200", "8001800218", "S05", "0501", "01",
+            "OK", "abcd", "OK", "E01", "m1", "", "W00",
+        ]);
+        assert_eq!(result, RunResult::Exit(0));
+    }
+
+    #[test]
+    fn test_gdb_stub_step_between_vectors() {
+        // Stepping past the BRK waits for the next vector, at 0x0103, and stops after its first
+        // instruction
+        let mut core = Core::new_with_uxntal("#01 BRK #02 BRK").unwrap();
+        core.event_sender().send(DeviceEvent::Vector(0x0103));
+        let (result, replies) = converse(&mut core, &["s", "s", "s", "p0", "c"]);
+        assert_eq!(replies, ["S05", "S05", "S05", "0501", "W00"]);
+        assert_eq!(result, RunResult::Exit(0));
+        assert_eq!(core.working_stack.bytes(), [0x01, 0x02]);
+    }
+
+    #[test]
+    fn test_gdb_stub_detach() {
+        // Detaching lets the ROM run to the end, ignoring the breakpoint
+        let mut core = Core::new_with_uxntal("#01 #02 ADD BRK").unwrap();
+        let (result, replies) = converse(&mut core, &["Z0,104,1", "D"]);
+        assert_eq!(replies, ["OK", "OK"]);
+        assert_eq!(result, RunResult::Exit(0));
+        assert_eq!(core.working_stack.bytes(), [0x03]);
+
+        let (result, replies) = converse(&mut Core::new(), &["qXfer:features:read:target.xml:0,10"]);
+        assert_eq!(replies, ["m<?xml version=\"1"]);
+        assert_eq!(result, RunResult::Exit(0));
+    }
+}
@@ -1,6 +1,3 @@
-#![feature(type_changing_struct_update)]
-#![feature(unbounded_shifts)]
-
 mod common;
 pub use common::*;
 
@@ -10,4 +7,25 @@ pub use stack::*;
 mod core;
 pub use core::*;
 
+mod opcodes;
+pub use opcodes::*;
+
+mod warning;
+pub use warning::*;
+
+mod build_info;
+pub use build_info::*;
+
+mod error;
+pub use error::*;
+
+mod conformance;
+pub use conformance::*;
+
+mod gdb;
+pub use gdb::*;
+
+mod disassemble;
+pub use disassemble::*;
+
 pub mod device;
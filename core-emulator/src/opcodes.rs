@@ -0,0 +1,274 @@
+//! Metadata describing each of uxn's 32 base opcodes, intended as the one authoritative table for
+//! anything which needs to know about the instruction set (interpreter, disassemblers, editors...)
+
+use std::fmt::Display;
+
+/// The kind of value an opcode takes from or leaves on the stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    /// A byte or short, depending on whether the instruction is in short mode.
+    Item,
+
+    /// Always a byte, regardless of short mode.
+    Byte,
+
+    /// Always a short, regardless of short mode.
+    Short,
+}
+
+/// Information about a single base opcode (the low 5 bits of an instruction).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    /// The three-letter mnemonic, without any mode suffixes.
+    pub name: &'static str,
+
+    /// The operands popped from the target stack, deepest first.
+    pub inputs: &'static [OperandKind],
+
+    /// The values pushed onto the target stack, deepest first.
+    pub outputs: &'static [OperandKind],
+
+    /// The stack effect as written in the uxntal reference. Effects on the other stack follow a `|`.
+    pub effect: &'static str,
+
+    /// Whether the instruction reads immediate operands from memory following it.
+    pub immediate: bool,
+}
+
+use OperandKind::*;
+
+const fn op(
+    name: &'static str,
+    inputs: &'static [OperandKind],
+    outputs: &'static [OperandKind],
+    effect: &'static str,
+) -> OpcodeInfo {
+    OpcodeInfo { name, inputs, outputs, effect, immediate: false }
+}
+
+/// Metadata for every base opcode, indexed by the opcode's value.
+///
+/// Opcode 0x00 is described as `BRK`. With mode bits set it instead encodes the immediate
+/// instructions `JCI`, `JMI`, `JSI` and `LIT`, which [`immediate_info`] describes.
+pub const OPCODES: [OpcodeInfo; 32] = [
+    op("BRK", &[], &[], "--"),
+    op("INC", &[Item], &[Item], "a -- a+1"),
+    op("POP", &[Item], &[], "a --"),
+    op("NIP", &[Item, Item], &[Item], "a b -- b"),
+    op("SWP", &[Item, Item], &[Item, Item], "a b -- b a"),
+    op("ROT", &[Item, Item, Item], &[Item, Item, Item], "a b c -- b c a"),
+    op("DUP", &[Item], &[Item, Item], "a -- a a"),
+    op("OVR", &[Item, Item], &[Item, Item, Item], "a b -- a b a"),
+    op("EQU", &[Item, Item], &[Byte], "a b -- bool8"),
+    op("NEQ", &[Item, Item], &[Byte], "a b -- bool8"),
+    op("GTH", &[Item, Item], &[Byte], "a b -- bool8"),
+    op("LTH", &[Item, Item], &[Byte], "a b -- bool8"),
+    op("JMP", &[Item], &[], "addr --"),
+    op("JCN", &[Byte, Item], &[], "cond8 addr --"),
+    op("JSR", &[Item], &[], "addr -- | ret16"),
+    op("STH", &[Item], &[], "a -- | a"),
+    op("LDZ", &[Byte], &[Item], "addr8 -- value"),
+    op("STZ", &[Item, Byte], &[], "value addr8 --"),
+    op("LDR", &[Byte], &[Item], "addr8 -- value"),
+    op("STR", &[Item, Byte], &[], "value addr8 --"),
+    op("LDA", &[Short], &[Item], "addr16 -- value"),
+    op("STA", &[Item, Short], &[], "value addr16 --"),
+    op("DEI", &[Byte], &[Item], "device8 -- value"),
+    op("DEO", &[Item, Byte], &[], "value device8 --"),
+    op("ADD", &[Item, Item], &[Item], "a b -- a+b"),
+    op("SUB", &[Item, Item], &[Item], "a b -- a-b"),
+    op("MUL", &[Item, Item], &[Item], "a b -- a*b"),
+    op("DIV", &[Item, Item], &[Item], "a b -- a/b"),
+    op("AND", &[Item, Item], &[Item], "a b -- a&b"),
+    op("ORA", &[Item, Item], &[Item], "a b -- a|b"),
+    op("EOR", &[Item, Item], &[Item], "a b -- a^b"),
+    op("SFT", &[Item, Byte], &[Item], "a shift8 -- c"),
+];
+
+const JCI: OpcodeInfo = OpcodeInfo { immediate: true, ..op("JCI", &[Byte], &[], "cond8 --") };
+const JMI: OpcodeInfo = OpcodeInfo { immediate: true, ..op("JMI", &[], &[], "--") };
+const JSI: OpcodeInfo = OpcodeInfo { immediate: true, ..op("JSI", &[], &[], "-- | ret16") };
+const LIT: OpcodeInfo = OpcodeInfo { immediate: true, ..op("LIT", &[], &[Item], "-- a") };
+
+/// Gets the metadata for a full instruction byte, taking the special meanings of opcode 0x00 into
+/// account.
+pub fn instruction_info(ins: u8) -> &'static OpcodeInfo {
+    immediate_info(ins).unwrap_or(&OPCODES[(ins & 0x1F) as usize])
+}
+
+/// If the instruction byte is one of the immediate instructions encoded by opcode 0x00 with mode
+/// bits set, gets its metadata.
+pub fn immediate_info(ins: u8) -> Option<&'static OpcodeInfo> {
+    match ins {
+        0x20 => Some(&JCI),
+        0x40 => Some(&JMI),
+        0x60 => Some(&JSI),
+        0x80 | 0xa0 | 0xc0 | 0xe0 => Some(&LIT),
+        _ => None,
+    }
+}
+
+/// Gets the full uxntal mnemonic for an instruction byte, including mode suffixes, such as `ADD2kr`.
+pub fn mnemonic(ins: u8) -> String {
+    Instruction::decode(ins).to_string()
+}
+
+/// An operation, without its modes. The immediate instructions encoded by opcode 0x00 with mode
+/// bits set each have their own variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Brk, Jci, Jmi, Jsi, Lit,
+    Inc, Pop, Nip, Swp, Rot, Dup, Ovr,
+    Equ, Neq, Gth, Lth, Jmp, Jcn, Jsr, Sth,
+    Ldz, Stz, Ldr, Str, Lda, Sta, Dei, Deo,
+    Add, Sub, Mul, Div, And, Ora, Eor, Sft,
+}
+
+/// The opcodes which take modes, indexed by their value in the low 5 bits of an instruction.
+const BASE_OPCODES: [Opcode; 32] = {
+    use Opcode::*;
+    [
+        Brk, Inc, Pop, Nip, Swp, Rot, Dup, Ovr,
+        Equ, Neq, Gth, Lth, Jmp, Jcn, Jsr, Sth,
+        Ldz, Stz, Ldr, Str, Lda, Sta, Dei, Deo,
+        Add, Sub, Mul, Div, And, Ora, Eor, Sft,
+    ]
+};
+
+impl Opcode {
+    /// The instruction byte for this opcode with no modes set. LIT is always in keep mode.
+    pub fn base(self) -> u8 {
+        match self {
+            Opcode::Jci => 0x20,
+            Opcode::Jmi => 0x40,
+            Opcode::Jsi => 0x60,
+            Opcode::Lit => 0x80,
+            _ => BASE_OPCODES.iter().position(|opcode| *opcode == self).unwrap() as u8,
+        }
+    }
+
+    /// Whether this opcode takes the keep, return and short modes. The immediate jumps and BRK
+    /// don't, and LIT takes all but keep.
+    pub fn has_modes(self) -> bool {
+        !matches!(self, Opcode::Brk | Opcode::Jci | Opcode::Jmi | Opcode::Jsi)
+    }
+
+    pub fn info(self) -> &'static OpcodeInfo {
+        instruction_info(self.base())
+    }
+}
+
+/// A decoded instruction byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    pub opcode: Opcode,
+
+    /// Leave operands on the stack, rather than popping them.
+    pub keep: bool,
+
+    /// Operate on the return stack, rather than the working stack.
+    pub ret: bool,
+
+    /// Operate on shorts, rather than bytes.
+    pub short: bool,
+}
+
+impl Instruction {
+    /// An instruction with no modes set.
+    pub fn new(opcode: Opcode) -> Self {
+        Self { opcode, keep: opcode == Opcode::Lit, ret: false, short: false }
+    }
+
+    /// Decodes an instruction byte. Every byte is a valid instruction.
+    pub fn decode(ins: u8) -> Self {
+        //
+        //   .- Don't pop any operands
+        //   |.- Operate on the return stack
+        //   ||.- Operate on shorts instead of bytes
+        //   |||.---. Opcode
+        // 0b11111111
+        //
+        let keep = ins & 0x80 != 0;
+        let ret = ins & 0x40 != 0;
+        let short = ins & 0x20 != 0;
+
+        let opcode = match ins {
+            0x20 => Opcode::Jci,
+            0x40 => Opcode::Jmi,
+            0x60 => Opcode::Jsi,
+            0x80 | 0xa0 | 0xc0 | 0xe0 => Opcode::Lit,
+            _ => BASE_OPCODES[(ins & 0x1f) as usize],
+        };
+
+        if opcode.has_modes() {
+            Self { opcode, keep, ret, short }
+        } else {
+            Self::new(opcode)
+        }
+    }
+
+    /// Encodes the instruction as a byte. Modes are ignored for opcodes which don't take them.
+    pub fn encode(self) -> u8 {
+        if !self.opcode.has_modes() {
+            return self.opcode.base();
+        }
+
+        let mut ins = self.opcode.base();
+        if self.keep { ins |= 0x80 }
+        if self.ret { ins |= 0x40 }
+        if self.short { ins |= 0x20 }
+        ins
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.opcode.info().name)?;
+        if !self.opcode.has_modes() {
+            return Ok(());
+        }
+
+        // LIT is always implicitly in keep mode
+        if self.short { write!(f, "2")? }
+        if self.keep && self.opcode != Opcode::Lit { write!(f, "k")? }
+        if self.ret { write!(f, "r")? }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{instruction_info, mnemonic, Instruction, Opcode, OPCODES};
+
+    #[test]
+    fn test_mnemonic() {
+        assert_eq!(mnemonic(0x00), "BRK");
+        assert_eq!(mnemonic(0x18), "ADD");
+        assert_eq!(mnemonic(0xf8), "ADD2kr");
+        assert_eq!(mnemonic(0x20), "JCI");
+        assert_eq!(mnemonic(0x60), "JSI");
+        assert_eq!(mnemonic(0x80), "LIT");
+        assert_eq!(mnemonic(0xe0), "LIT2r");
+    }
+
+    #[test]
+    fn test_instruction_info() {
+        assert_eq!(OPCODES.iter().filter(|info| info.immediate).count(), 0);
+        assert!(instruction_info(0xa0).immediate);
+        assert_eq!(instruction_info(0x21).name, "INC");
+    }
+
+    #[test]
+    fn test_instruction_round_trip() {
+        for ins in 0..=u8::MAX {
+            let instruction = Instruction::decode(ins);
+            assert_eq!(instruction.encode(), ins, "{instruction:?}");
+            assert_eq!(instruction.opcode.info().name, instruction_info(ins).name);
+        }
+
+        let add = Instruction { opcode: Opcode::Add, keep: true, ret: false, short: true };
+        assert_eq!(Instruction::decode(0xb8), add);
+        assert_eq!(Instruction::new(Opcode::Lit).encode(), 0x80);
+        assert_eq!(Instruction { ret: true, ..Instruction::new(Opcode::Jmi) }.encode(), 0x40);
+    }
+}
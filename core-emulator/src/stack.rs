@@ -1,13 +1,38 @@
 //! Overcomplicated circular stack implementation, with type-safe APIs for working with the stack
 //! as either a byte or short stack, and supporting the "keep" mode.
 
+use std::fmt::Display;
+
 use crate::common::{Item, ItemSize};
 
 /// Models uxn's circular stack.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Stack {
     pub pointer: u8,
     pub data: [u8; 256], // Easier to store and shorts and cast on the way out, imo
+
+    /// Whether to record a [`StackFault`] when the pointer wraps around, rather than wrapping
+    /// silently. Well-behaved ROMs never wrap, so this catches stack bugs early.
+    pub strict: bool,
+
+    // The first fault since the last `take_fault`, in strict mode
+    fault: Option<StackFault>,
+}
+
+/// A way in which the stack pointer wrapped around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackFault {
+    /// More was taken from the stack than it held.
+    Underflow,
+
+    /// More was pushed to the stack than it can hold.
+    Overflow,
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Stack {
@@ -15,13 +40,53 @@ impl Stack {
         Self {
             pointer: 0,
             data: [0; 256],
+            strict: false,
+            fault: None,
         }
     }
 
+    /// Empties the stack, keeping whether it's strict.
+    pub fn clear(&mut self) {
+        *self = Self { strict: self.strict, ..Self::new() };
+    }
+
+    /// Takes the fault recorded since this was last called, if any.
+    pub fn take_fault(&mut self) -> Option<StackFault> {
+        self.fault.take()
+    }
+
+    fn record_fault(&mut self, fault: StackFault) {
+        if self.strict {
+            self.fault.get_or_insert(fault);
+        }
+    }
+
+    /// The bytes currently on the stack, bottom first.
     pub fn bytes(&self) -> &[u8] {
         &self.data[..self.pointer as usize]
     }
 
+    /// How many bytes are on the stack.
+    pub fn depth(&self) -> usize {
+        self.pointer as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pointer == 0
+    }
+
+    /// The bytes on the stack, bottom first.
+    pub fn iter_bytes(&self) -> impl DoubleEndedIterator<Item = u8> + '_ {
+        self.bytes().iter().copied()
+    }
+
+    /// The stack as shorts, bottom first. These are paired from the top, since that's where
+    /// instructions take shorts from, so if the depth is odd then the bottom byte is left out.
+    pub fn iter_shorts(&self) -> impl DoubleEndedIterator<Item = u16> + '_ {
+        let bytes = self.bytes();
+        bytes[bytes.len() % 2..].chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+    }
+
     pub fn new_with_data(data: &[u8]) -> Self {
         let mut stack = Self::new();
 
@@ -33,6 +98,9 @@ impl Stack {
     }
 
     pub fn push_byte(&mut self, byte: u8) {
+        if self.pointer == u8::MAX {
+            self.record_fault(StackFault::Overflow);
+        }
         self.data[self.pointer as usize] = byte;
         self.pointer = self.pointer.overflowing_add(1).0;
     }
@@ -50,11 +118,19 @@ impl Stack {
         }
     }
 
-    pub fn take_operands(&mut self, mode: AccessMode, item_size: ItemSize) -> StackOperandAccessor<()> {
+    pub fn take_operands(&mut self, mode: AccessMode, item_size: ItemSize) -> StackOperandAccessor<'_, ()> {
         StackOperandAccessor::new(self, mode, item_size)
     }
 }
 
+/// Formats the stack like uxnemu's debug output, bottom first, ending at the pointer: `01 02 03|`.
+impl Display for Stack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes: Vec<String> = self.iter_bytes().map(|byte| format!("{byte:02x}")).collect();
+        write!(f, "{}|", bytes.join(" "))
+    }
+}
+
 pub struct StackOperandAccessor<'s, T> {
     stack: &'s mut Stack,
     pointer: u8,
@@ -106,6 +182,12 @@ impl<'s, T> StackOperandAccessor<'s, T> {
     }
 
     pub fn done(self) -> T {
+        // At most a few bytes are taken, so this can't wrap all the way around
+        let taken = self.stack.pointer.wrapping_sub(self.pointer);
+        if taken > self.stack.pointer {
+            self.stack.record_fault(StackFault::Underflow);
+        }
+
         if self.mode == AccessMode::Pop {
             self.stack.pointer = self.pointer;
         }
@@ -116,61 +198,73 @@ impl<'s, T> StackOperandAccessor<'s, T> {
 
 impl<'s> StackOperandAccessor<'s, ()> {
     pub fn byte(self) -> StackOperandAccessor<'s, (u8,)> {
-        let (byte, pointer) = self.this_byte();
-        StackOperandAccessor { pointer, data: (byte,), ..self }
+        self.then_byte()
     }
 
     pub fn short(self) -> StackOperandAccessor<'s, (u16,)> {
-        let (short, pointer) = self.this_short();
-        StackOperandAccessor { pointer, data: (short,), ..self }
+        self.then_short()
     }
 
     pub fn item(self) -> StackOperandAccessor<'s, (Item,)> {
-        let (item, pointer) = self.this_item();
-        StackOperandAccessor { pointer, data: (item,), ..self }
+        self.then_item()
     }
 }
 
-impl<'s, T1> StackOperandAccessor<'s, (T1,)> {
-    pub fn then_byte(self) -> StackOperandAccessor<'s, (T1, u8)> {
+impl<'s, T> StackOperandAccessor<'s, T> {
+    pub fn then_byte(self) -> StackOperandAccessor<'s, T::Output> where T: AppendOperand<u8> {
         let (byte, pointer) = self.this_byte();
-        let (d1,) = self.data;
-        StackOperandAccessor { pointer, data: (d1, byte), ..self }
+        let data = self.data.append(byte);
+        StackOperandAccessor { stack: self.stack, pointer, mode: self.mode, item_size: self.item_size, data }
     }
 
-    pub fn then_short(self) -> StackOperandAccessor<'s, (T1, u16)> {
+    pub fn then_short(self) -> StackOperandAccessor<'s, T::Output> where T: AppendOperand<u16> {
         let (short, pointer) = self.this_short();
-        let (d1,) = self.data;
-        StackOperandAccessor { pointer, data: (d1, short), ..self }
+        let data = self.data.append(short);
+        StackOperandAccessor { stack: self.stack, pointer, mode: self.mode, item_size: self.item_size, data }
     }
 
-    pub fn then_item(self) -> StackOperandAccessor<'s, (T1, Item)> {
+    pub fn then_item(self) -> StackOperandAccessor<'s, T::Output> where T: AppendOperand<Item> {
         let (item, pointer) = self.this_item();
-        let (d1,) = self.data;
-        StackOperandAccessor { pointer, data: (d1, item), ..self }
+        let data = self.data.append(item);
+        StackOperandAccessor { stack: self.stack, pointer, mode: self.mode, item_size: self.item_size, data }
     }
 }
 
-impl<'s, T1, T2> StackOperandAccessor<'s, (T1, T2)> {
-    pub fn then_byte(self) -> StackOperandAccessor<'s, (T1, T2, u8)> {
-        let (byte, pointer) = self.this_byte();
-        let (d1, d2) = self.data;
-        StackOperandAccessor { pointer, data: (d1, d2, byte), ..self }
-    }
+/// A tuple of operands which another can be added to the end of, so that a
+/// [`StackOperandAccessor`] can take any number of operands, up to 12.
+pub trait AppendOperand<T> {
+    type Output;
 
-    pub fn then_short(self) -> StackOperandAccessor<'s, (T1, T2, u16)> {
-        let (short, pointer) = self.this_short();
-        let (d1, d2) = self.data;
-        StackOperandAccessor { pointer, data: (d1, d2, short), ..self }
-    }
+    fn append(self, operand: T) -> Self::Output;
+}
 
-    pub fn then_item(self) -> StackOperandAccessor<'s, (T1, T2, Item)> {
-        let (item, pointer) = self.this_item();
-        let (d1, d2) = self.data;
-        StackOperandAccessor { pointer, data: (d1, d2, item), ..self }
-    }
+macro_rules! impl_append_operand {
+    ($($name:ident),*) => {
+        impl<$($name,)* T> AppendOperand<T> for ($($name,)*) {
+            type Output = ($($name,)* T,);
+
+            #[allow(non_snake_case)]
+            fn append(self, operand: T) -> Self::Output {
+                let ($($name,)*) = self;
+                ($($name,)* operand,)
+            }
+        }
+    };
 }
 
+impl_append_operand!();
+impl_append_operand!(T1);
+impl_append_operand!(T1, T2);
+impl_append_operand!(T1, T2, T3);
+impl_append_operand!(T1, T2, T3, T4);
+impl_append_operand!(T1, T2, T3, T4, T5);
+impl_append_operand!(T1, T2, T3, T4, T5, T6);
+impl_append_operand!(T1, T2, T3, T4, T5, T6, T7);
+impl_append_operand!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_append_operand!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_append_operand!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_append_operand!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AccessMode {
     /// Remove accessed items from the stack.
@@ -183,8 +277,33 @@ pub enum AccessMode {
 
 #[cfg(test)]
 mod test {
+    use proptest::prelude::*;
+
     use crate::{common::Item, stack::ItemSize};
-    use super::{AccessMode, Stack};
+    use super::{AccessMode, Stack, StackFault};
+
+    #[test]
+    fn test_stack_inspection() {
+        let stack = Stack::new_with_data(&[0x01, 0x02, 0x03]);
+        assert_eq!(stack.depth(), 3);
+        assert!(!stack.is_empty() && Stack::new().is_empty());
+        assert_eq!(stack.iter_bytes().rev().collect::<Vec<_>>(), [0x03, 0x02, 0x01]);
+        assert_eq!(stack.iter_shorts().collect::<Vec<_>>(), [0x0203]);
+        assert_eq!(stack.to_string(), "01 02 03|");
+        assert_eq!(Stack::new().to_string(), "|");
+    }
+
+    #[test]
+    fn test_many_operands() {
+        let mut stack = Stack::new_with_data(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let operands = stack
+            .take_operands(AccessMode::Pop, ItemSize::Short)
+            .byte().then_byte().then_short().then_item().then_byte()
+            .done();
+
+        assert_eq!(operands, (8, 7, 0x0506, Item::Short(0x0304), 2));
+        assert_eq!(stack.bytes(), [1]);
+    }
 
     #[test]
     fn test_stack_pop() {
@@ -226,5 +345,157 @@ mod test {
         assert_eq!(short1, Item::Short(0x0304));
         assert_eq!(short2, 0x0102);
     }
-}
 
+    #[test]
+    fn test_strict_stack() {
+        // Wrapping is silent by default
+        let mut stack = Stack::new();
+        stack.take_operands(AccessMode::Pop, ItemSize::Byte).byte().done();
+        assert_eq!((stack.pointer, stack.take_fault()), (0xff, None));
+
+        let mut stack = Stack::new_with_data(&[1]);
+        stack.strict = true;
+        stack.take_operands(AccessMode::Keep, ItemSize::Byte).byte().done();
+        assert_eq!(stack.take_fault(), None);
+        stack.take_operands(AccessMode::Keep, ItemSize::Short).short().done();
+        assert_eq!(stack.take_fault(), Some(StackFault::Underflow));
+        assert_eq!(stack.take_fault(), None);
+
+        // The stack holds at most 255 bytes, since another would bring the pointer back to 0
+        let mut stack = Stack::new_with_data(&[0; 254]);
+        stack.strict = true;
+        stack.push_byte(1);
+        assert_eq!(stack.take_fault(), None);
+        stack.push_short(2);
+        assert_eq!(stack.take_fault(), Some(StackFault::Overflow));
+
+        stack.clear();
+        assert!(stack.strict);
+        assert_eq!(stack.bytes(), []);
+    }
+
+    /// The obvious implementation of a 256-byte circular stack, to check [`Stack`] against.
+    struct ModelStack {
+        data: Vec<u8>,
+        pointer: usize,
+        fault: Option<StackFault>,
+    }
+
+    impl ModelStack {
+        fn push(&mut self, byte: u8) {
+            if self.pointer == 255 {
+                self.fault.get_or_insert(StackFault::Overflow);
+            }
+            self.data[self.pointer] = byte;
+            self.pointer = (self.pointer + 1) % 256;
+        }
+
+        // Reads operands from the top of the stack, popping them if asked
+        fn take(&mut self, sizes: &[ItemSize], mode: AccessMode) -> Vec<Item> {
+            let mut cursor = self.pointer;
+            let items = sizes.iter()
+                .map(|size| match size {
+                    ItemSize::Byte => {
+                        cursor = (cursor + 255) % 256;
+                        Item::Byte(self.data[cursor])
+                    },
+                    ItemSize::Short => {
+                        cursor = (cursor + 254) % 256;
+                        Item::Short(u16::from_be_bytes([self.data[cursor], self.data[(cursor + 1) % 256]]))
+                    },
+                })
+                .collect();
+
+            let taken = sizes.iter().map(|size| if *size == ItemSize::Short { 2 } else { 1 }).sum::<usize>();
+            if taken > self.pointer {
+                self.fault.get_or_insert(StackFault::Underflow);
+            }
+            if mode == AccessMode::Pop {
+                self.pointer = cursor;
+            }
+            items
+        }
+    }
+
+    /// Something to do to a stack, which [`check`] does to both a [`Stack`] and a [`ModelStack`].
+    #[derive(Clone, Debug)]
+    enum Operation {
+        PushByte(u8),
+        PushShort(u16),
+        TakeByte(AccessMode),
+        TakeShort(AccessMode),
+        TakeMixed(AccessMode),
+
+        // Items are always the size the accessor was created with
+        TakeItems(AccessMode, ItemSize),
+    }
+
+    /// Applies operations to a stack and the model, starting with the pointer at `start`, and
+    /// checks they always agree.
+    fn check(start: u8, strict: bool, operations: impl IntoIterator<Item = Operation>) {
+        let mut stack = Stack { pointer: start, strict, ..Stack::new() };
+        let mut model = ModelStack { data: vec![0; 256], pointer: start as usize, fault: None };
+
+        for (i, operation) in operations.into_iter().enumerate() {
+            match operation {
+                Operation::PushByte(byte) => {
+                    stack.push_byte(byte);
+                    model.push(byte);
+                },
+                Operation::PushShort(short) => {
+                    stack.push_short(short);
+                    model.push((short >> 8) as u8);
+                    model.push(short as u8);
+                },
+                Operation::TakeByte(mode) => {
+                    let (byte,) = stack.take_operands(mode, ItemSize::Byte).byte().done();
+                    assert_eq!(vec![Item::Byte(byte)], model.take(&[ItemSize::Byte], mode), "operation {i}");
+                },
+                Operation::TakeShort(mode) => {
+                    let (short,) = stack.take_operands(mode, ItemSize::Short).short().done();
+                    assert_eq!(vec![Item::Short(short)], model.take(&[ItemSize::Short], mode), "operation {i}");
+                },
+                Operation::TakeMixed(mode) => {
+                    let (a, b, c) = stack.take_operands(mode, ItemSize::Byte).byte().then_short().then_byte().done();
+                    let sizes = [ItemSize::Byte, ItemSize::Short, ItemSize::Byte];
+                    assert_eq!(vec![Item::Byte(a), Item::Short(b), Item::Byte(c)], model.take(&sizes, mode), "operation {i}");
+                },
+                Operation::TakeItems(mode, item_size) => {
+                    let (a, b) = stack.take_operands(mode, item_size).item().then_item().done();
+                    assert_eq!(vec![a, b], model.take(&[item_size, item_size], mode), "operation {i}");
+                },
+            }
+
+            assert_eq!(stack.pointer as usize, model.pointer, "operation {i}");
+            assert_eq!(stack.data[..], model.data[..], "operation {i}");
+            let expected_fault = if strict { model.fault.take() } else { None };
+            model.fault = None;
+            assert_eq!(stack.take_fault(), expected_fault, "operation {i}");
+        }
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        let mode = prop_oneof![Just(AccessMode::Pop), Just(AccessMode::Keep)];
+        let size = prop_oneof![Just(ItemSize::Byte), Just(ItemSize::Short)];
+        prop_oneof![
+            any::<u8>().prop_map(Operation::PushByte),
+            any::<u16>().prop_map(Operation::PushShort),
+            mode.clone().prop_map(Operation::TakeByte),
+            mode.clone().prop_map(Operation::TakeShort),
+            mode.clone().prop_map(Operation::TakeMixed),
+            (mode, size).prop_map(|(mode, size)| Operation::TakeItems(mode, size)),
+        ]
+    }
+
+    proptest! {
+        // Starting anywhere, including right by the ends, means short runs still wrap around
+        #[test]
+        fn test_stack_matches_model(
+            start in prop_oneof![any::<u8>(), 0u8..4, 252u8..=255],
+            strict in any::<bool>(),
+            operations in prop::collection::vec(operation(), 0..64),
+        ) {
+            check(start, strict, operations);
+        }
+    }
+}
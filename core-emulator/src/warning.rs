@@ -0,0 +1,27 @@
+//! Warnings from the emulator itself, as opposed to output from the ROM.
+//!
+//! These always go to stderr, so that stdout only ever carries the ROM's console output, and can
+//! be silenced entirely with [`set_quiet`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppresses all warnings, for the whole process.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a warning to stderr, unless warnings have been silenced with [`set_quiet`].
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::is_quiet() {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
@@ -0,0 +1,40 @@
+//! Runs arbitrary bytes as a ROM, checking that the core reports whatever goes wrong as an error
+//! rather than panicking, and that it's left in a consistent state.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uxn_core_emulator::{Core, RunResult, UxnError};
+
+/// How many instructions to run, so that ROMs which loop forever still finish.
+const FUEL: u64 = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the options, and the rest is the ROM
+    let Some((&options, rom)) = data.split_first() else { return };
+    uxn_core_emulator::set_quiet(true);
+
+    let mut core = Core::new_with_rom(rom);
+    core.set_strict_stacks(options & 1 != 0);
+    let result = core.execute_vector(0x0100, Some(FUEL));
+
+    match result {
+        Ok(outcome) => {
+            assert!(outcome.instructions <= FUEL);
+            if outcome.result == RunResult::Break {
+                // The program counter is left just past the BRK
+                assert_eq!(core.memory[core.program_counter.wrapping_sub(1) as usize], 0x00);
+            }
+        },
+        Err(UxnError::Stack { program_counter, .. }) => assert!(options & 1 != 0, "stack fault at {program_counter:#06x} without strict stacks"),
+        Err(_) => {},
+    }
+    assert_eq!(core.working_stack.bytes().len(), core.working_stack.pointer as usize);
+    assert_eq!(core.return_stack.bytes().len(), core.return_stack.pointer as usize);
+
+    // Whatever state it ended up in can be saved and restored exactly
+    let snapshot = core.snapshot();
+    let mut restored = Core::new_with_rom(rom);
+    restored.restore(&snapshot).expect("snapshot didn't restore");
+    assert_eq!(restored.snapshot(), snapshot);
+});
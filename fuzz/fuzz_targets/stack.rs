@@ -0,0 +1,103 @@
+//! Runs arbitrary operations on a stack, checking that it always agrees with the obvious
+//! implementation of a 256-byte circular stack, including around the ends where it wraps.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uxn_core_emulator::{AccessMode, Item, ItemSize, Stack, StackFault};
+
+/// The obvious implementation of a 256-byte circular stack, to check [`Stack`] against.
+struct ModelStack {
+    data: [u8; 256],
+    pointer: usize,
+    fault: Option<StackFault>,
+}
+
+impl ModelStack {
+    fn push(&mut self, byte: u8) {
+        if self.pointer == 255 {
+            self.fault.get_or_insert(StackFault::Overflow);
+        }
+        self.data[self.pointer] = byte;
+        self.pointer = (self.pointer + 1) % 256;
+    }
+
+    // Reads operands from the top of the stack, popping them if asked
+    fn take(&mut self, sizes: &[ItemSize], mode: AccessMode) -> Vec<Item> {
+        let mut cursor = self.pointer;
+        let items = sizes.iter()
+            .map(|size| match size {
+                ItemSize::Byte => {
+                    cursor = (cursor + 255) % 256;
+                    Item::Byte(self.data[cursor])
+                },
+                ItemSize::Short => {
+                    cursor = (cursor + 254) % 256;
+                    Item::Short(u16::from_be_bytes([self.data[cursor], self.data[(cursor + 1) % 256]]))
+                },
+            })
+            .collect();
+
+        let taken = sizes.iter().map(|size| if *size == ItemSize::Short { 2 } else { 1 }).sum::<usize>();
+        if taken > self.pointer {
+            self.fault.get_or_insert(StackFault::Underflow);
+        }
+        if mode == AccessMode::Pop {
+            self.pointer = cursor;
+        }
+        items
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte is where the pointer starts, the second whether the stack is strict, and each
+    // three bytes after that are an operation and its argument
+    let [start, strict, operations @ ..] = data else { return };
+    let strict = strict & 1 != 0;
+    let mut stack = Stack::new();
+    stack.pointer = *start;
+    stack.strict = strict;
+    let mut model = ModelStack { data: [0; 256], pointer: *start as usize, fault: None };
+
+    for (i, operation) in operations.chunks_exact(3).enumerate() {
+        let [kind, hi, lo] = *operation else { unreachable!() };
+        let mode = if kind & 0x10 != 0 { AccessMode::Keep } else { AccessMode::Pop };
+        let size = if kind & 0x20 != 0 { ItemSize::Short } else { ItemSize::Byte };
+
+        match kind % 6 {
+            0 => {
+                stack.push_byte(lo);
+                model.push(lo);
+            },
+            1 => {
+                stack.push_short(u16::from_be_bytes([hi, lo]));
+                model.push(hi);
+                model.push(lo);
+            },
+            2 => {
+                let (byte,) = stack.take_operands(mode, ItemSize::Byte).byte().done();
+                assert_eq!(vec![Item::Byte(byte)], model.take(&[ItemSize::Byte], mode), "operation {i}");
+            },
+            3 => {
+                let (short,) = stack.take_operands(mode, ItemSize::Short).short().done();
+                assert_eq!(vec![Item::Short(short)], model.take(&[ItemSize::Short], mode), "operation {i}");
+            },
+            4 => {
+                let (a, b, c) = stack.take_operands(mode, ItemSize::Byte).byte().then_short().then_byte().done();
+                let sizes = [ItemSize::Byte, ItemSize::Short, ItemSize::Byte];
+                assert_eq!(vec![Item::Byte(a), Item::Short(b), Item::Byte(c)], model.take(&sizes, mode), "operation {i}");
+            },
+            _ => {
+                // Items are always the size the accessor was created with
+                let (a, b) = stack.take_operands(mode, size).item().then_item().done();
+                assert_eq!(vec![a, b], model.take(&[size, size], mode), "operation {i}");
+            },
+        }
+
+        assert_eq!(stack.pointer as usize, model.pointer, "operation {i}");
+        assert_eq!(stack.data, model.data, "operation {i}");
+        let expected_fault = if strict { model.fault.take() } else { None };
+        model.fault = None;
+        assert_eq!(stack.take_fault(), expected_fault, "operation {i}");
+    }
+});
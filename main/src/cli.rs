@@ -0,0 +1,260 @@
+//! The command-line interface.
+
+use clap::{Args, Parser, Subcommand};
+
+/// An emulator for uxn and Varvara, with tools for working on ROMs.
+#[derive(Parser)]
+#[command(disable_version_flag = true)]
+pub struct Cli {
+    /// Describe this build
+    #[arg(long)]
+    pub version: bool,
+
+    /// With --version, describe the build as JSON
+    #[arg(long, requires = "version")]
+    pub json: bool,
+
+    /// Silence the emulator's own warnings, leaving just the ROM's output
+    #[arg(long, global = true)]
+    pub quiet: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a ROM, along with its .sym file if it has one
+    Run(RunOptions),
+
+    /// Run a ROM, after waiting for gdb or lldb to connect on a local port
+    Debug {
+        /// The port to wait for a debugger on
+        #[arg(long, default_value_t = 1234)]
+        port: u16,
+
+        #[command(flatten)]
+        options: RunOptions,
+    },
+
+    /// Run several ROMs side by side in one window, as tabs which F1 to F9 switch between
+    Tabs {
+        /// The ROMs to run, in tab order. A .tal file is assembled first
+        #[arg(required = true)]
+        roms: Vec<String>,
+
+        /// Show the window this many times bigger: 1, 2, 4, 8, 16 or 32
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=32))]
+        scale: u8,
+
+        /// How many times a second to run each ROM's Screen vector
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u32).range(1..))]
+        fps: u32,
+    },
+
+    /// Assemble a file, writing the ROM and its .sym file
+    Asm {
+        source: String,
+
+        /// Where to write the ROM, instead of next to the source
+        rom: Option<String>,
+    },
+
+    /// Disassemble a ROM, with labels from its .sym file if it has one
+    Dis {
+        rom: String,
+    },
+
+    /// Summarise what's in a ROM
+    #[command(alias = "rom-info")]
+    Info {
+        rom: String,
+    },
+
+    /// Point out likely mistakes in a file
+    Lint {
+        source: String,
+    },
+
+    /// List the bytes which differ between two ROMs, by label if the new ROM has a .sym file
+    #[command(alias = "rom-diff")]
+    Diff {
+        old: String,
+        new: String,
+    },
+
+    /// Replay a session recorded with `run --record-session`
+    ReplaySession {
+        session: String,
+
+        /// The ROM to replay it with, instead of the one it was recorded with
+        rom: Option<String>,
+    },
+
+    /// Check ROMs against the list in compat.txt
+    CompatRun {
+        /// Print a ROM's hash instead, for adding it to the list
+        #[arg(long, value_name = "ROM", exclusive = true)]
+        hash: Option<String>,
+
+        /// Where the ROMs are
+        #[arg(required_unless_present = "hash")]
+        directory: Option<String>,
+
+        /// How many frames to run each ROM for
+        frames: Option<u32>,
+    },
+}
+
+#[derive(Args, Clone)]
+pub struct RunOptions {
+    /// The ROM to run, or - to read it from stdin. A .tal file is assembled first
+    #[arg(required_unless_present = "demo")]
+    pub rom_path: Option<String>,
+
+    /// Arguments to pass to the ROM through the Console. Options after the first of these are
+    /// passed on too
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub arguments: Vec<String>,
+
+    /// Run a small built-in program instead of a ROM
+    #[arg(long, conflicts_with = "rom_path")]
+    pub demo: bool,
+
+    /// Show the window this many times bigger: 1, 2, 4, 8, 16 or 32
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=32))]
+    pub scale: u8,
+
+    /// Run without a window
+    #[arg(long)]
+    pub headless: bool,
+
+    /// How many times a second to run the Screen vector
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u32).range(1..))]
+    pub fps: u32,
+
+    /// Print every instruction to stderr as it runs, with both stacks
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Record console input and frames to a file, for replay-session
+    #[arg(long = "record-session", value_name = "SESSION")]
+    pub record_path: Option<String>,
+
+    /// Change which keys do what on the Controller
+    #[arg(long = "keymap", value_name = "FILE")]
+    pub keymap_path: Option<String>,
+
+    /// Read console input a line at a time, with editing and history
+    #[arg(long)]
+    pub line_edit: bool,
+
+    /// Wait for a connection on a local port, and use it for console input and output
+    #[arg(long = "tcp-console", value_name = "PORT")]
+    pub tcp_console_port: Option<u16>,
+
+    /// Warn about ROMs which write to slow ports every frame
+    #[arg(long)]
+    pub port_diagnostics: bool,
+
+    /// Print how much work the core did once the ROM exits
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Print main memory as a hex dump once the ROM exits
+    #[arg(long)]
+    pub dump_on_exit: bool,
+
+    /// Stop the ROM if either stack underflows or overflows
+    #[arg(long)]
+    pub strict_stack: bool,
+
+    /// Stop the ROM if a vector runs more than this many instructions
+    #[arg(long, value_name = "INSTRUCTIONS")]
+    pub vector_budget: Option<u64>,
+
+    /// Save the ROM's state if the window is closed, and pick up from there next time
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Whenever the ROM's file changes, soft reboot into the new ROM, keeping the window open
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Add the non-standard UDP device on page 0xd0
+    #[arg(long)]
+    pub network: bool,
+
+    /// Add the non-standard MIDI device on page 0xf0, connected to MIDI ports whose names contain
+    /// this, or the first ones if it's ""
+    #[arg(long = "midi", value_name = "PORT")]
+    pub midi_port: Option<String>,
+
+    /// Set by the debug command
+    #[arg(skip)]
+    pub gdb_port: Option<u16>,
+}
+
+/// The same as clap's defaults, for running without a command line.
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            rom_path: None,
+            arguments: vec![],
+            demo: false,
+            scale: 1,
+            headless: false,
+            fps: 60,
+            debug: false,
+            record_path: None,
+            keymap_path: None,
+            line_edit: false,
+            tcp_console_port: None,
+            port_diagnostics: false,
+            stats: false,
+            dump_on_exit: false,
+            strict_stack: false,
+            vector_budget: None,
+            resume: false,
+            watch: false,
+            network: false,
+            midi_port: None,
+            gdb_port: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::{CommandFactory, Parser};
+
+    use super::{Cli, Command, RunOptions};
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["uxn", "run", "game.rom", "--scale", "2", "--quiet"]);
+        assert!(cli.quiet);
+        let Some(Command::Run(options)) = cli.command else { panic!("not run") };
+        assert_eq!(options.rom_path.as_deref(), Some("game.rom"));
+        assert_eq!((options.scale, options.fps), (2, 60));
+        let Some(Command::Run(options)) = Cli::parse_from(["uxn", "run", "--demo"]).command else { panic!("not run") };
+        let default = RunOptions::default();
+        assert_eq!((default.scale, default.fps), (options.scale, options.fps));
+        assert!(!options.watch);
+
+        assert!(Cli::try_parse_from(["uxn", "run"]).is_err());
+        assert!(Cli::try_parse_from(["uxn", "run", "--demo", "game.rom"]).is_err());
+        assert!(Cli::try_parse_from(["uxn", "run", "--demo", "--fps", "0"]).is_err());
+        assert!(matches!(Cli::parse_from(["uxn", "rom-info", "game.rom"]).command, Some(Command::Info { .. })));
+        let Some(Command::Tabs { roms, scale, fps }) = Cli::parse_from(["uxn", "tabs", "left.rom", "right.tal", "--scale", "2"]).command else { panic!("not tabs") };
+        assert_eq!((roms.as_slice(), scale, fps), (["left.rom".to_string(), "right.tal".to_string()].as_slice(), 2, 60));
+        assert!(Cli::try_parse_from(["uxn", "tabs"]).is_err());
+
+        let cli = Cli::parse_from(["uxn", "run", "--stats", "convert.rom", "in.txt", "--scale", "2"]);
+        let Some(Command::Run(options)) = cli.command else { panic!("not run") };
+        assert!(options.stats);
+        assert_eq!(options.arguments, ["in.txt", "--scale", "2"]);
+    }
+}
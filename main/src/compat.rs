@@ -0,0 +1,230 @@
+//! Runs community ROMs headlessly to track which ones work, for `compat-run`.
+
+use std::{error::Error, fmt::Display, fs, panic, path::Path, sync::mpsc::{self, RecvTimeoutError}, thread, time::Duration};
+
+use uxn_core_emulator::{device::{DeviceEvent, OffscreenBackend, VarvaraDevice}, Core, RunResult, UxnError};
+use uxn_utils::{asm::{assemble_file, AssembleOptions}, Rom, RomFormat};
+
+/// The ROMs to check, with the status they're expected to have.
+const FIXTURES: &str = include_str!("../compat.txt");
+
+/// How many frames to run each ROM for, if not told otherwise.
+const DEFAULT_FRAMES: u32 = 120;
+
+/// The exit code used to stop a ROM once it's run for long enough.
+const TIMEOUT_EXIT_CODE: u8 = 0xff;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Status {
+    /// Ran for all of its frames, or exited successfully.
+    Ok,
+    /// Exited with a non-zero exit code.
+    Exit(u8),
+    /// Used a device port which isn't implemented yet.
+    UnsupportedPort(String),
+    /// Panicked for some other reason.
+    Crash(String),
+    /// Got stuck in a vector and stopped responding.
+    Hang,
+    /// The ROM file couldn't be found.
+    Missing,
+    /// The ROM file isn't the one the fixture describes.
+    HashMismatch,
+}
+
+impl Status {
+    /// The short name used in the fixture list.
+    fn kind(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Exit(_) => "exit",
+            Status::UnsupportedPort(_) => "unsupported-port",
+            Status::Crash(_) => "crash",
+            Status::Hang => "hang",
+            Status::Missing => "missing",
+            Status::HashMismatch => "hash-mismatch",
+        }
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Exit(code) => write!(f, "exit {code:#04x}"),
+            Status::UnsupportedPort(message) | Status::Crash(message) => write!(f, "{}: {message}", self.kind()),
+            _ => write!(f, "{}", self.kind()),
+        }
+    }
+}
+
+struct Fixture<'a> {
+    name: &'a str,
+    hash: u64,
+    expected: &'a str,
+}
+
+/// Parses the fixture list. Each line is a ROM file name, its hash from [`rom_hash`] in hex, and
+/// the expected status. Blank lines and lines starting with `#` are ignored.
+fn parse_fixtures(text: &str) -> Result<Vec<Fixture<'_>>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let [name, hash, expected] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(format!("expected a name, hash and status: {line}"));
+            };
+            let hash = u64::from_str_radix(hash, 16).map_err(|e| format!("bad hash in {line}: {e}"))?;
+            Ok(Fixture { name, hash, expected })
+        })
+        .collect()
+}
+
+/// Runs every ROM in the list from a directory, for `frames` frames or a default number, printing
+/// a compatibility matrix. Returns whether every ROM had its expected status.
+pub fn compat_run(directory: &str, frames: Option<u32>) -> bool {
+    let fixtures = parse_fixtures(FIXTURES).expect("could not parse compat.txt");
+    check_fixtures(&fixtures, directory, frames.unwrap_or(DEFAULT_FRAMES))
+}
+
+/// Runs each fixture's ROM from a directory, as in [`compat_run`]. An empty list fails, since it
+/// can't show that anything works.
+fn check_fixtures(fixtures: &[Fixture], directory: &str, frames: u32) -> bool {
+    if fixtures.is_empty() {
+        eprintln!("compat.txt doesn't list any ROMs yet, so there's nothing to check");
+        return false;
+    }
+
+    // Crashes are expected here, and reported in the matrix instead
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut all_expected = true;
+    println!("{:<24} {:<18} status", "rom", "expected");
+    for fixture in fixtures {
+        let status = check(&Path::new(directory).join(fixture.name), fixture.hash, frames);
+        let matches = status.kind() == fixture.expected;
+        all_expected &= matches;
+
+        println!(
+            "{:<24} {:<18} {status}{}",
+            fixture.name, fixture.expected, if matches { "" } else { "  (changed)" },
+        );
+    }
+
+    panic::set_hook(hook);
+    all_expected
+}
+
+/// Prints a ROM's hash, for adding it to the list.
+pub fn print_hash(path: &str) {
+    println!("{:016x}", load_fixture(Path::new(path)).expect("could not load ROM").hash);
+}
+
+/// Loads a ROM in the list. Uxntal sources, like the ones shipped in `main/compat`, are assembled
+/// first, and anything else is loaded like any other ROM file.
+fn load_fixture(path: &Path) -> Result<Rom, Box<dyn Error>> {
+    let contents = match path.extension() {
+        Some(extension) if extension == "tal" => assemble_file(path, &AssembleOptions::default())?.rom,
+        _ => fs::read(path)?,
+    };
+    Rom::from_file_contents(&contents, RomFormat::from_path(path))
+}
+
+fn check(path: &Path, hash: u64, frames: u32) -> Status {
+    if !path.exists() {
+        return Status::Missing;
+    }
+    let rom = match load_fixture(path) {
+        Ok(rom) => rom,
+        Err(e) => return Status::Crash(e.to_string()),
+    };
+    if rom.hash != hash {
+        return Status::HashMismatch;
+    }
+    run_headless(rom.bytes, frames)
+}
+
+/// Runs a ROM without a window for roughly the given number of frames.
+fn run_headless(rom: Vec<u8>, frames: u32) -> Status {
+    let duration = Duration::from_secs(frames.into()) / 60;
+
+    let (result_sender, result) = mpsc::channel();
+    let (events_sender, events) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        let mut device = VarvaraDevice::with_screen_backend(OffscreenBackend::new());
+        device.disable_stdin();
+
+        let mut core = Core::new_with_rom(&rom);
+        core.set_device(device);
+        let _ = events_sender.send(core.event_sender());
+        let _ = result_sender.send(core.execute_until_exit());
+    });
+
+    // Once the ROM has had long enough, ask it to stop. If it doesn't, it's stuck in a vector.
+    let Ok(events) = events.recv() else { return crash_status(worker) };
+    let (finished, stopped) = match result.recv_timeout(duration) {
+        Err(RecvTimeoutError::Timeout) => {
+            events.send(DeviceEvent::Exit(TIMEOUT_EXIT_CODE));
+            (result.recv_timeout(Duration::from_secs(5)), true)
+        },
+        finished => (finished, false),
+    };
+
+    match finished {
+        // Only exiting because it was asked to stop counts as running for all of its frames
+        Ok(Ok(RunResult::Exit(TIMEOUT_EXIT_CODE))) if stopped => Status::Ok,
+        Ok(Ok(RunResult::Exit(0))) => Status::Ok,
+        Ok(Ok(RunResult::Exit(code))) => Status::Exit(code),
+        Ok(Ok(_)) => unreachable!("no breakpoints are set"),
+        Ok(Err(e @ UxnError::UnsupportedPort(_))) => Status::UnsupportedPort(e.to_string()),
+        Ok(Err(e)) => Status::Crash(e.to_string()),
+        Err(RecvTimeoutError::Disconnected) => crash_status(worker),
+        Err(RecvTimeoutError::Timeout) => Status::Hang,
+    }
+}
+
+fn crash_status(worker: thread::JoinHandle<()>) -> Status {
+    let Err(payload) = worker.join() else { return Status::Crash("no result".to_string()) };
+    let message = payload.downcast_ref::<String>().cloned()
+        .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+        .unwrap_or_else(|| "unknown panic".to_string());
+    Status::Crash(message)
+}
+
+#[cfg(test)]
+mod test {
+    use uxn_utils::assemble_uxntal;
+
+    use super::{check_fixtures, compat_run, parse_fixtures, run_headless, Status, FIXTURES};
+
+    #[test]
+    fn test_fixtures_parse() {
+        assert!(parse_fixtures(FIXTURES).is_ok());
+
+        let fixtures = parse_fixtures("# comment\n\nclock.rom 00ff ok\n").unwrap();
+        assert_eq!(fixtures.len(), 1);
+        assert_eq!((fixtures[0].name, fixtures[0].hash, fixtures[0].expected), ("clock.rom", 0xff, "ok"));
+
+        assert!(parse_fixtures("clock.rom ok").is_err());
+    }
+
+    #[test]
+    fn test_no_fixtures() {
+        assert!(!check_fixtures(&[], ".", 1));
+    }
+
+    #[test]
+    fn test_shipped_fixtures() {
+        assert!(compat_run(concat!(env!("CARGO_MANIFEST_DIR"), "/compat"), Some(5)));
+    }
+
+    #[test]
+    fn test_run_headless() {
+        assert_eq!(run_headless(assemble_uxntal("BRK").unwrap(), 1), Status::Ok);
+        assert_eq!(run_headless(assemble_uxntal("#2a #0f DEO BRK").unwrap(), 1), Status::Exit(0x2a));
+
+        // .Audio0 isn't implemented yet
+        let status = run_headless(assemble_uxntal("#01 #3f DEO BRK").unwrap(), 1);
+        assert_eq!(status.kind(), "unsupported-port");
+    }
+}
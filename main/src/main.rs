@@ -1,67 +1,664 @@
-use std::{env::args, fs::File, io::Read};
+use std::{env::args, fs::{self, File}, io, panic, path::Path, process::exit, sync::mpsc::{self, Receiver}, thread, time::Duration};
 
-use uxn_core_emulator::{device::VarvaraDevice, Core};
+use clap::{CommandFactory, Parser};
+use rustyline::DefaultEditor;
+use uxn_core_emulator::{build_info, device::{ConsoleInput, ConsoleType, Display, EventSender, Keymap, MidiDevice, NetworkDevice, OffscreenBackend, Session, SessionRecorder, TcpConsole, VarvaraDevice}, disassemble, set_quiet, warning, BuildInfo, Core, GdbStub, RunResult, Stats, Symbols, UxnError};
+use uxn_utils::{asm::{assemble_file, lint::lint_file, AssembleOptions}, assemble_uxntal, diff_roms, rom_hash, write_rom_file};
+
+mod compat;
+
+mod cli;
+use cli::*;
 
 fn main() {
-    // Current interface:
-    //   - If this has an argument, assume it's a ROM, and load it
-    //   - Otherwise, run some hardcoded text
-    //
-    // Keeping the latter means I can try Varvara stuff quickly.
-    // TODO: tidy this up at some point
+    let cli = Cli::parse();
+    set_quiet(cli.quiet);
+    if cli.version {
+        print_version(cli.json);
+        return;
+    }
+
+    // Make sure crash reports say what they crashed
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        eprintln!("Build: {}", full_build_info().to_json());
+    }));
+
+    let Some(command) = cli.command else {
+        let _ = Cli::command().print_help();
+        exit(2);
+    };
+    match command {
+        Command::Run(options) => exit(run_rom(options)),
+        Command::Debug { port, mut options } => {
+            options.gdb_port = Some(port);
+            exit(run_rom(options));
+        },
+        Command::Tabs { roms, scale, fps } => exit(run_tabs(&roms, scale, fps)),
+        Command::Asm { source, rom } => exit(if assemble(&source, rom) { 0 } else { 1 }),
+        Command::Dis { rom } => disassemble_rom(&rom),
+        Command::Info { rom } => rom_info(&rom),
+        Command::Lint { source } => exit(if lint(&source) { 0 } else { 1 }),
+        Command::Diff { old, new } => exit(if rom_diff(&old, &new) { 0 } else { 1 }),
+        Command::ReplaySession { session, rom } => replay_session(&session, rom),
+        Command::CompatRun { hash: Some(rom), .. } => compat::print_hash(&rom),
+        Command::CompatRun { directory, frames, .. } => {
+            exit(if compat::compat_run(&directory.unwrap(), frames) { 0 } else { 1 });
+        },
+    }
+}
+
+/// Sets up the devices as the options say, and runs the ROM. Returns the exit code.
+fn run_rom(options: RunOptions) -> i32 {
+    let (rom, symbols) = load_rom(options.rom_path.as_deref());
+
+    let mut device = if options.headless {
+        VarvaraDevice::with_screen_backend(OffscreenBackend::new())
+    } else {
+        VarvaraDevice::new()
+    };
+    device.set_frame_rate(options.fps);
+    device.queue_arguments(&options.arguments);
+    if options.port_diagnostics {
+        device.enable_port_diagnostics();
+    }
+    if let Some(keymap_path) = &options.keymap_path {
+        match fs::read_to_string(keymap_path).map_err(|e| e.into()).and_then(|text| Keymap::parse(&text)) {
+            Ok(keymap) => device.set_keymap(keymap),
+            Err(e) => warning!("Ignoring keymap {keymap_path}: {e}"),
+        }
+    }
+    if options.line_edit {
+        device.disable_stdin();
+        spawn_line_editor(device.console_input());
+    }
+    if let Some(port) = options.tcp_console_port {
+        eprintln!("Waiting for a console connection on port {port}");
+        let bridged = TcpConsole::accept(("127.0.0.1", port)).and_then(|console| device.bridge_console(console));
+        if let Err(e) = bridged {
+            warning!("Could not listen for a console connection: {e}");
+        }
+    }
+    if let Some(record_path) = &options.record_path {
+        let file = File::create(record_path).expect("could not create session file");
+        let args: Vec<String> = args().skip(1).collect();
+        let recorder = SessionRecorder::new(file, rom_hash(&rom), &args)
+            .expect("could not write session file");
+        device.record_session(recorder);
+    }
+
+    // The state lives next to the ROM, so there's nowhere to keep it for the demo program or a ROM
+    // from stdin
+    let rom_file = options.rom_path.as_deref().filter(|path| *path != "-");
+    let state_path = match (options.resume, rom_file) {
+        (true, Some(rom_path)) => Some(format!("{rom_path}.state")),
+        (true, None) => {
+            warning!("--resume needs a ROM file, so the state won't be saved");
+            None
+        },
+        (false, _) => None,
+    };
+
+    let reloads = match (options.watch, rom_file) {
+        (true, Some(rom_path)) => Some(watch_rom(rom_path.to_string())),
+        (true, None) => {
+            warning!("--watch needs a ROM file, so there's nothing to watch");
+            None
+        },
+        (false, _) => None,
+    };
+
+    run(rom, device, state_path, symbols, reloads, &options)
+}
+
+/// Runs several ROMs at once, each with its own core on its own thread, as tabs in one window.
+/// Returns the first non-zero exit code, or 0 if every ROM exited successfully.
+fn run_tabs(rom_paths: &[String], scale: u8, fps: u32) -> i32 {
+    let (exit_sender, exit) = mpsc::channel();
+    let mut displays = vec![];
+    for path in rom_paths {
+        let (rom, symbols) = load_rom(Some(path));
+        let mut device = VarvaraDevice::new();
+        device.set_frame_rate(fps);
+
+        // The ROMs can't all read stdin, so none of them do
+        device.disable_stdin();
+        if let Some(mut display) = device.take_display() {
+            display.set_scale(scale);
+            displays.push(display);
+        }
+
+        let (path, exit_sender) = (path.clone(), exit_sender.clone());
+        thread::spawn(move || {
+            let mut core = Core::new_with_rom(&rom);
+            core.set_device(device);
+            if let Some(symbols) = symbols {
+                core.set_symbols(symbols);
+            }
+
+            let code = match core.execute_until_exit() {
+                Ok(RunResult::Exit(code)) => code as i32,
+                Ok(result) => unreachable!("{result:?} needs a budget or breakpoints, which tabs don't set"),
+                Err(e) => {
+                    eprintln!("In {path}:");
+                    report_error(&core, &e);
+                    1
+                },
+            };
+            let _ = exit_sender.send(code);
+        });
+    }
+    drop(exit_sender);
+
+    // As in `run`, cores which don't notice the window closing soon are given up on
+    let closed = Display::run_tabs(displays);
+    let codes: Vec<i32> = if closed {
+        (0..rom_paths.len()).map_while(|_| exit.recv_timeout(Duration::from_secs(1)).ok()).collect()
+    } else {
+        exit.iter().collect()
+    };
+    codes.into_iter().find(|code| *code != 0).unwrap_or(0)
+}
+
+fn replay_session(session_path: &str, rom_path: Option<String>) {
+    let text = fs::read_to_string(session_path).expect("could not read session file");
+    let session = Session::parse(&text).expect("could not parse session file");
+
+    // Use the ROM the session was recorded with, unless told otherwise
+    let rom_path = rom_path.or_else(|| recorded_rom_path(&session.flags));
+    if rom_path.is_none() && !session.flags.iter().any(|flag| flag == "--demo") {
+        eprintln!("Could not tell which ROM {session_path} was recorded with, so pass it after the session");
+        exit(1);
+    }
+    let (rom, _) = load_rom(rom_path.as_deref());
+    if rom_hash(&rom) != session.rom_hash {
+        warning!("ROM differs from the one this session was recorded with");
+    }
 
-    let mut core;
-    if args().len() > 1 {
-        let rom_path = args().nth(1).unwrap();
-        let mut rom_data = vec![];
+    let mut device = VarvaraDevice::new();
+    device.replay_session(session);
+    exit(run(rom, device, None, None, None, &RunOptions::default()));
+}
+
+/// Finds which ROM a session was recorded with, from the arguments it was recorded with.
+///
+/// Sessions recorded before there were commands just have flags, with the ROM as the only argument
+/// which isn't a flag or a flag's value.
+fn recorded_rom_path(flags: &[String]) -> Option<String> {
+    if let Ok(cli) = Cli::try_parse_from(["uxn".to_string()].iter().chain(flags)) {
+        return match cli.command? {
+            Command::Run(options) | Command::Debug { options, .. } => options.rom_path,
+            _ => None,
+        };
+    }
+
+    const FLAGS_WITH_VALUES: [&str; 6] = ["--record-session", "--keymap", "--vector-budget", "--midi", "--tcp-console", "--gdb"];
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        if FLAGS_WITH_VALUES.contains(&flag.as_str()) {
+            flags.next();
+        } else if !flag.starts_with("--") {
+            return Some(flag.clone());
+        }
+    }
+    None
+}
+
+/// Assembles a file to a ROM, next to it unless told otherwise, with a .sym file alongside the ROM
+/// like uxnasm's. Returns whether it worked.
+fn assemble(source_path: &str, rom_path: Option<String>) -> bool {
+    let rom_path = rom_path
+        .unwrap_or_else(|| Path::new(source_path).with_extension("rom").to_string_lossy().into_owned());
+
+    let assembly = match assemble_file(Path::new(source_path), &AssembleOptions::default()) {
+        Ok(assembly) => assembly,
+        Err(e) => {
+            eprintln!("{e}");
+            return false;
+        },
+    };
+    let written = write_rom_file(&rom_path, &assembly.rom)
+        .and_then(|()| Ok(fs::write(format!("{rom_path}.sym"), assembly.symbols.to_bytes())?));
+    if let Err(e) = written {
+        eprintln!("Could not write ROM: {e}");
+        return false;
+    }
+
+    println!("Assembled {rom_path} in {} bytes, with {} labels", assembly.rom.len(), assembly.symbols.labels().count());
+    true
+}
+
+/// Prints everything the linter finds in a file. Returns whether it found nothing.
+fn lint(source_path: &str) -> bool {
+    match lint_file(Path::new(source_path), &AssembleOptions::default()) {
+        Ok(lints) => {
+            for lint in &lints {
+                println!("{lint}");
+            }
+            lints.is_empty()
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            false
+        },
+    }
+}
 
-        File::open(rom_path).unwrap().read_to_end(&mut rom_data).unwrap();
+fn rom_info(rom_path: &str) {
+    let rom = match uxn_utils::load_rom(rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Could not load ROM: {e}");
+            exit(1);
+        },
+    };
+    let info = rom.info();
 
-        core = Core::new_with_rom(&rom_data);
+    println!("size:         {} bytes", info.size);
+    println!("reset vector: {:04x}", info.reset_vector);
+    match info.metadata {
+        Some(metadata) => println!("metadata:     {metadata:04x}"),
+        None => println!("metadata:     none"),
+    }
+    let zero_page = info.zero_page.iter().map(|address| format!("{address:02x}")).collect::<Vec<_>>();
+    println!("zero page:    {} ({} bytes)", if zero_page.is_empty() { "none".to_string() } else { zero_page.join(" ") }, zero_page.len());
+    println!("pages:");
+    for range in &info.pages {
+        println!("  {:05x}-{:05x}", range.start, range.end - 1);
+    }
+}
+
+/// Prints a ROM's instructions, with labels from its .sym file if it has one.
+fn disassemble_rom(rom_path: &str) {
+    let (rom, symbols) = load_rom(Some(rom_path));
+    print!("{}", disassemble(&rom, symbols.as_ref()));
+}
+
+/// Prints the differences between two ROMs. Returns whether they're the same.
+fn rom_diff(old_path: &str, new_path: &str) -> bool {
+    let (old, _) = load_rom(Some(old_path));
+    let (new, symbols) = load_rom(Some(new_path));
+
+    let changes = diff_roms(&old, &new);
+    for change in &changes {
+        match symbols.as_ref().and_then(|symbols| change.locate(symbols)) {
+            Some(label) => println!("{change}  {label}"),
+            None => println!("{change}"),
+        }
+    }
+    if changes.is_empty() {
+        println!("ROMs are identical");
+    }
+    changes.is_empty()
+}
+
+/// Runs the core on a worker thread, while the window runs on this one so that it stays responsive
+/// during long vectors. Returns the exit code.
+///
+/// If a state path is given, the core is restored from it if it exists, and saved to it if the
+/// window is closed. If the ROM exits by itself, the saved state is deleted.
+///
+/// If `reloads` is given, the core soft reboots into each ROM which arrives from it.
+fn run(
+    rom: Vec<u8>,
+    mut device: VarvaraDevice,
+    state_path: Option<String>,
+    symbols: Option<Symbols>,
+    reloads: Option<Receiver<Program>>,
+    options: &RunOptions,
+) -> i32 {
+    let display = device.take_display().map(|mut display| {
+        display.set_scale(options.scale);
+        display
+    });
+    let hash = rom_hash(&rom);
+    let (strict_stack, vector_budget, show_stats, dump_on_exit, gdb_port, network, trace) = (
+        options.strict_stack, options.vector_budget, options.stats, options.dump_on_exit, options.gdb_port,
+        options.network, options.debug,
+    );
+    let midi_port = options.midi_port.clone();
+
+    let (exit_sender, exit) = mpsc::channel();
+    let core_state_path = state_path.clone();
+    thread::spawn(move || {
+        let mut core = Core::new_with_rom(&rom);
+        core.set_device(device);
+        if network {
+            core.register_device(0xd0, NetworkDevice::new(core.event_sender()));
+        }
+        if let Some(port) = midi_port {
+            core.register_device(0xf0, midi_device(core.event_sender(), &port));
+        }
+        core.set_strict_stacks(strict_stack);
+        if let Some(symbols) = symbols {
+            core.set_symbols(symbols);
+        }
+        core.vector_budget = vector_budget;
+        if trace {
+            core.trace_to_writer(io::stderr());
+        }
+
+        let resumed = core_state_path.as_ref().is_some_and(|path| load_state(&mut core, path, hash));
+        let result = if let Some(port) = gdb_port {
+            eprintln!("Waiting for a debugger on port {port}");
+            match GdbStub::accept(("127.0.0.1", port), !resumed) {
+                Ok(mut stub) => stub.serve(&mut core),
+                Err(e) => {
+                    warning!("Could not listen for a debugger: {e}");
+                    if resumed { core.handle_events_until_exit() } else { core.execute_until_exit() }
+                },
+            }
+        } else if let Some(reloads) = reloads {
+            execute_watching(&mut core, &reloads, resumed)
+        } else if resumed {
+            core.handle_events_until_exit()
+        } else {
+            core.execute_until_exit()
+        };
+
+        let code = match result {
+            Ok(RunResult::Exit(code)) => code,
+            Ok(RunResult::BudgetExceeded) => {
+                warning!("Stopping, since a vector ran for more than {} instructions", vector_budget.unwrap());
+                1
+            },
+            Ok(RunResult::Break | RunResult::Stopped(_)) => unreachable!("breakpoints are only set through the debugger, which handles them"),
+            Err(e) => {
+                report_error(&core, &e);
+                1
+            },
+        };
+        if show_stats {
+            print_stats(core.stats());
+        }
+        if dump_on_exit {
+            eprint!("{}", core.dump_memory(..));
+        }
+        let snapshot = core_state_path.is_some().then(|| core.snapshot());
+        let _ = exit_sender.send((code as i32, snapshot));
+    });
+
+    if display.is_some_and(|display| display.run()) {
+        // The core will notice the window closing once its current vector finishes. If it doesn't
+        // finish soon, give up on it rather than appearing to hang.
+        let Ok((code, snapshot)) = exit.recv_timeout(Duration::from_secs(1)) else { return 0 };
+        if let (Some(path), Some(snapshot)) = (state_path, snapshot) {
+            save_state(&path, hash, &snapshot);
+        }
+        code
     } else {
-        core = Core::new_with_uxntal(r#"
-            |00 @System &vector $2 &expansion $2 &wst $1 &rst $1 &metadata $2 &r $2 &g $2 &b $2 &debug $1 &state $1
-            |10 @Console [ &vector $2 &read $1 &pad $5 &write $1 &error $1 ]
-            |20 @Screen [ &vector $2 &width $2 &height $2 &auto $2 &x $2 &y $2 &addr $2 &pixel $1 &sprite $1 ]
-        
-            |0100 
-
-            @on-reset ( -> )
-                ;on-screen .Screen/vector DEO2
-                #0320 .Screen/width  DEO2 ( 800px )
-                #0258 .Screen/height DEO2 ( 600px )
-
-                #af00 .System/r DEO2
-                #0f00 .System/b DEO2
-                #0f00 .System/g DEO2
-
-                ;hello_world_str
-                &print_loop
-                    LDAk                    ( Load pointed character )
-                    .Console/write DEO      ( Print it )
-                    INC                     ( Increment pointer )
-                    LDAk ,&print_loop JCN   ( If it's non-zero, iterate again )
-                POP                         ( Drop pointer once we're done )
-            BRK
-
-            @on-screen ( -> )
-                ;counter LDA INC
-                DUP #20 NEQ ,&skip_forward JCN [ #0f00 .System/r DEO2          ] &skip_forward
-                DUP #40 NEQ ,&skip_back    JCN [ #af00 .System/r DEO2  POP #00 ] &skip_back
-                ;counter STA
-
-                ( Also paint a white pixel )
-                #0100 .Screen/x DEO2
-                #0100 .Screen/y DEO2
-                #01 .Screen/pixel DEO
-            BRK
-
-            @counter 00
-
-            @hello_world_str "Hello 2c 20 "World 21 0a $1
-        "#);
-    }
-
-    core.set_device(VarvaraDevice::new());
-    core.execute_until_exit();
+        // If the core panicked, the panic has already been reported
+        let Ok((code, _)) = exit.recv() else { return 101 };
+        if let Some(path) = state_path {
+            let _ = fs::remove_file(path);
+        }
+        code
+    }
+}
+
+/// Runs the ROM like [`Core::execute_until_exit`], but soft reboots into each ROM which arrives from
+/// `reloads` between vectors. If the ROM stops with an error, this waits for the next one instead.
+fn execute_watching(core: &mut Core, reloads: &Receiver<Program>, resumed: bool) -> Result<RunResult, UxnError> {
+    let mut result = if resumed { Ok(RunResult::Break) } else { core.execute_until_break() };
+    loop {
+        let program = match result {
+            Ok(RunResult::Break) => reloads.try_iter().last(),
+            Err(e) => {
+                report_error(core, &e);
+                eprintln!("Waiting for the ROM to change");
+                let Ok(program) = reloads.recv() else { return Err(e) };
+                Some(program)
+            },
+            stopped => return stopped,
+        };
+
+        result = match program {
+            Some((rom, symbols)) => {
+                eprintln!("Rebooting into the changed ROM");
+                core.reboot_with_rom(&rom);
+                if let Some(symbols) = symbols {
+                    core.set_symbols(symbols);
+                }
+                core.execute_until_break()
+            },
+            None => core.execute_pending().map(|result| result.unwrap_or_else(|| {
+                thread::sleep(Duration::from_millis(1));
+                RunResult::Break
+            })),
+        };
+    }
+}
+
+/// How often --watch checks whether the ROM has changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Checks a ROM's file on another thread, and sends the ROM whenever the file changes. Changes which
+/// don't load, such as a .tal file which doesn't assemble, are warned about and skipped.
+fn watch_rom(path: String) -> Receiver<Program> {
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut last_modified = modified(&path);
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let now = modified(&path);
+            if now == last_modified {
+                continue;
+            }
+            last_modified = now;
+
+            match read_rom(&path) {
+                Ok(program) => if sender.send(program).is_err() {
+                    return;
+                },
+                Err(e) => warning!("Not reloading {path}: {e}"),
+            }
+        }
+    });
+    receiver
+}
+
+fn report_error(core: &Core, e: &UxnError) {
+    eprintln!("The ROM stopped with an error: {e}");
+    eprintln!("Last instructions before the error, oldest first:");
+    eprint!("{}", core.dump_history());
+}
+
+fn print_stats(stats: Stats) {
+    eprintln!(
+        "{} instructions, {} vectors, {} DEI, {} DEO",
+        stats.instructions, stats.vectors, stats.device_inputs, stats.device_outputs,
+    );
+    if let Some(mips) = stats.mips() {
+        eprintln!("{mips:.1} MIPS over {:.2?} of execution", stats.busy_time);
+    }
+}
+
+/// Restores a state saved by [`save_state`], if there is one for this ROM. Returns whether it did.
+fn load_state(core: &mut Core, path: &str, hash: u64) -> bool {
+    let Ok(state) = fs::read(path) else { return false };
+
+    // The state starts with the hash of the ROM it was saved from
+    let Some((saved_hash, snapshot)) = state.split_first_chunk::<8>() else {
+        warning!("Ignoring malformed state file {path}");
+        return false;
+    };
+    if u64::from_be_bytes(*saved_hash) != hash {
+        warning!("Ignoring state file {path}, which was saved from a different ROM");
+        return false;
+    }
+
+    match core.restore(snapshot) {
+        Ok(()) => true,
+        Err(e) => {
+            warning!("Could not restore state file {path}: {e}");
+            false
+        },
+    }
+}
+
+/// Saves a state file: the ROM's hash as a big-endian u64, followed by a [`Core::snapshot`].
+fn save_state(path: &str, hash: u64, snapshot: &[u8]) {
+    let mut state = hash.to_be_bytes().to_vec();
+    state.extend_from_slice(snapshot);
+    if let Err(e) = fs::write(path, state) {
+        warning!("Could not save state file {path}: {e}");
+    }
+}
+
+/// The core's build info, plus this crate.
+fn full_build_info() -> BuildInfo {
+    let mut info = build_info();
+    info.crates.insert(0, (env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+    info
+}
+
+fn print_version(json: bool) {
+    let info = full_build_info();
+    if json {
+        println!("{}", info.to_json());
+        return;
+    }
+
+    for (name, version) in &info.crates {
+        println!("{name} {version}");
+    }
+    println!("backends: {}", info.backends.join(", "));
+    let pages: Vec<String> = info.device_pages.iter()
+        .map(|(page, name)| format!("{name} ({page:#04x})"))
+        .collect();
+    println!("devices: {}", pages.join(", "));
+}
+
+/// Creates the MIDI device, connected to the system's MIDI ports whose names contain `port`.
+#[cfg(feature = "midi")]
+fn midi_device(events: EventSender, port: &str) -> MidiDevice {
+    let mut device = MidiDevice::new(events);
+    if let Err(e) = device.connect(Some(port)) {
+        warning!("Could not connect to MIDI: {e}");
+    }
+    device
+}
+
+#[cfg(not(feature = "midi"))]
+fn midi_device(events: EventSender, _port: &str) -> MidiDevice {
+    warning!("This build can't use MIDI ports, since it doesn't have the midi feature");
+    MidiDevice::new(events)
+}
+
+/// Reads lines from the terminal with editing and history, feeding each completed line to the ROM
+/// as console input.
+fn spawn_line_editor(input: ConsoleInput) {
+    thread::spawn(move || {
+        let mut editor = DefaultEditor::new().expect("could not start line editor");
+        while let Ok(line) = editor.readline("") {
+            let _ = editor.add_history_entry(&line);
+
+            for byte in line.bytes().chain(*b"\n") {
+                if !input.send(byte, ConsoleType::Stdin) {
+                    return;
+                }
+            }
+        }
+        input.send(0, ConsoleType::End);
+    });
+}
+
+/// Loads the symbols uxnasm wrote alongside a ROM, as either `game.rom.sym` or `game.sym`.
+fn load_symbols(rom_path: &str) -> Option<Symbols> {
+    let paths = [format!("{rom_path}.sym"), Path::new(rom_path).with_extension("sym").to_string_lossy().into_owned()];
+    let (path, contents) = paths.into_iter().find_map(|path| Some((path.clone(), fs::read(&path).ok()?)))?;
+    match Symbols::parse(&contents) {
+        Ok(symbols) => Some(symbols),
+        Err(e) => {
+            warning!("Ignoring symbol file {path}: {e}");
+            None
+        },
+    }
+}
+
+/// A ROM, along with its labels if it has any.
+type Program = (Vec<u8>, Option<Symbols>);
+
+/// Loads a ROM like [`read_rom`], or the demo program if there's no path, exiting if it can't.
+fn load_rom(path: Option<&str>) -> Program {
+    let Some(path) = path else { return (assemble_uxntal(DEMO_PROGRAM).unwrap(), None) };
+    match read_rom(path) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Could not load ROM: {e}");
+            exit(1);
+        },
+    }
+}
+
+/// Reads a ROM and its .sym file, or assembles it if it's a .tal file. There's no .sym file for a
+/// ROM from stdin.
+fn read_rom(path: &str) -> Result<Program, Box<dyn std::error::Error>> {
+    if path.ends_with(".tal") {
+        let assembly = assemble_file(Path::new(path), &AssembleOptions::default())?;
+        Ok((assembly.rom, Some(assembly.symbols)))
+    } else {
+        let symbols = if path == "-" { None } else { load_symbols(path) };
+        Ok((uxn_utils::load_rom(path)?.bytes, symbols))
+    }
+}
+
+const DEMO_PROGRAM: &str = r#"
+    |00 @System &vector $2 &expansion $2 &wst $1 &rst $1 &metadata $2 &r $2 &g $2 &b $2 &debug $1 &state $1
+    |10 @Console [ &vector $2 &read $1 &pad $5 &write $1 &error $1 ]
+    |20 @Screen [ &vector $2 &width $2 &height $2 &auto $2 &x $2 &y $2 &addr $2 &pixel $1 &sprite $1 ]
+
+    |0100 
+
+    @on-reset ( -> )
+        ;on-screen .Screen/vector DEO2
+        #0320 .Screen/width  DEO2 ( 800px )
+        #0258 .Screen/height DEO2 ( 600px )
+
+        #af00 .System/r DEO2
+        #0f00 .System/b DEO2
+        #0f00 .System/g DEO2
+
+        ;hello_world_str
+        &print_loop
+            LDAk                    ( Load pointed character )
+            .Console/write DEO      ( Print it )
+            INC                     ( Increment pointer )
+            LDAk ,&print_loop JCN   ( If it's non-zero, iterate again )
+        POP                         ( Drop pointer once we're done )
+    BRK
+
+    @on-screen ( -> )
+        ;counter LDA INC
+        DUP #20 NEQ ,&skip_forward JCN [ #0f00 .System/r DEO2          ] &skip_forward
+        DUP #40 NEQ ,&skip_back    JCN [ #af00 .System/r DEO2  POP #00 ] &skip_back
+        ;counter STA
+
+        ( Also paint a white pixel )
+        #0100 .Screen/x DEO2
+        #0100 .Screen/y DEO2
+        #01 .Screen/pixel DEO
+    BRK
+
+    @counter 00
+
+    @hello_world_str "Hello 2c 20 "World 21 0a $1
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::recorded_rom_path;
+
+    #[test]
+    fn test_recorded_rom_path() {
+        let flags = |flags: &[&str]| flags.iter().map(|flag| flag.to_string()).collect::<Vec<_>>();
+        assert_eq!(recorded_rom_path(&flags(&["run", "--stats", "game.rom", "in.txt"])).as_deref(), Some("game.rom"));
+        assert_eq!(recorded_rom_path(&flags(&["run", "--demo"])), None);
+
+        // From before there were commands
+        assert_eq!(recorded_rom_path(&flags(&["game.rom", "--record-session", "out"])).as_deref(), Some("game.rom"));
+        assert_eq!(recorded_rom_path(&flags(&["--gdb", "1234", "--record-session", "out", "game.rom"])).as_deref(), Some("game.rom"));
+    }
 }
@@ -0,0 +1,13 @@
+use std::{env, path::PathBuf};
+
+// Regenerates `include/uxn.h` from the functions in `src/lib.rs`
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    cbindgen::generate_with_config(&crate_dir, config)
+        .expect("could not generate uxn.h")
+        .write_to_file(crate_dir.join("include/uxn.h"));
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
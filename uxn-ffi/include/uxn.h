@@ -0,0 +1,118 @@
+#ifndef UXN_H
+#define UXN_H
+
+/* Generated by cbindgen from uxn-ffi/src/lib.rs when the crate is built. Don't edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * What happened when running a core.
+ */
+typedef enum UxnStatus {
+  /**
+   * Instructions ran, and there are more to run.
+   */
+  UXN_STATUS_RUNNING,
+  /**
+   * A BRK was reached.
+   */
+  UXN_STATUS_BREAK,
+  /**
+   * A breakpoint was reached.
+   */
+  UXN_STATUS_BREAKPOINT,
+  /**
+   * The ROM faulted. [`uxn_last_error`] describes why.
+   */
+  UXN_STATUS_ERROR,
+} UxnStatus;
+
+/**
+ * An emulator core, as seen from C.
+ */
+typedef struct UxnCore UxnCore;
+
+/**
+ * Called for each DEO, with the `user_data` it was registered with, the port and the byte. This
+ * can be null.
+ */
+typedef void (*UxnDeoCallback)(void *user_data, uint8_t port, uint8_t value);
+
+/**
+ * Creates a core with empty memory. Free it with [`uxn_destroy`].
+ */
+struct UxnCore *uxn_create(void);
+
+/**
+ * Frees a core from [`uxn_create`]. Does nothing if `core` is null.
+ *
+ * # Safety
+ * `core` must be null or from [`uxn_create`], and mustn't be used afterwards.
+ */
+void uxn_destroy(struct UxnCore *core);
+
+/**
+ * Loads a ROM at the reset vector, and moves the program counter there.
+ *
+ * # Safety
+ * `core` must be from [`uxn_create`], and `rom` must point to `length` readable bytes.
+ */
+void uxn_load_rom(struct UxnCore *core, const uint8_t *rom, size_t length);
+
+/**
+ * Runs the instruction at the program counter.
+ *
+ * # Safety
+ * `core` must be from [`uxn_create`].
+ */
+enum UxnStatus uxn_step(struct UxnCore *core);
+
+/**
+ * Runs a vector until it reaches a BRK, for at most `fuel` instructions, or without a limit if
+ * `fuel` is 0. Returns [`UxnStatus::Running`] if it ran out of fuel.
+ *
+ * # Safety
+ * `core` must be from [`uxn_create`].
+ */
+enum UxnStatus uxn_run_vector(struct UxnCore *core, uint16_t vector, uint64_t fuel);
+
+/**
+ * Copies `length` bytes of memory, starting at `address`, into `out`. Addresses wrap around.
+ *
+ * # Safety
+ * `core` must be from [`uxn_create`], and `out` must point to `length` writable bytes.
+ */
+void uxn_read_memory(const struct UxnCore *core, uint16_t address, uint8_t *out, size_t length);
+
+/**
+ * Copies `length` bytes from `data` into memory, starting at `address`. Addresses wrap around.
+ *
+ * # Safety
+ * `core` must be from [`uxn_create`], and `data` must point to `length` readable bytes.
+ */
+void uxn_write_memory(struct UxnCore *core, uint16_t address, const uint8_t *data, size_t length);
+
+/**
+ * Calls `callback` with `user_data` for every DEO, replacing any earlier callback. A null
+ * `callback` removes it.
+ *
+ * # Safety
+ * `core` must be from [`uxn_create`], and `user_data` must stay valid for as long as the callback
+ * is registered.
+ */
+void uxn_register_deo_callback(struct UxnCore *core, UxnDeoCallback callback, void *user_data);
+
+/**
+ * Describes the last [`UxnStatus::Error`], or gives null if there hasn't been one. The string
+ * belongs to the core, and lasts until the next error or until the core is destroyed.
+ *
+ * # Safety
+ * `core` must be from [`uxn_create`].
+ */
+const char *uxn_last_error(const struct UxnCore *core);
+
+#endif  /* UXN_H */
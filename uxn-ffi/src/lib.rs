@@ -0,0 +1,237 @@
+//! C bindings for the emulator core, so that it can be embedded in C, C++ and anything else which
+//! can call C functions.
+//!
+//! Building this crate regenerates `include/uxn.h`. A core is created with [`uxn_create`], must
+//! be freed with [`uxn_destroy`], and can't be used from more than one thread at a time.
+
+use std::{ffi::{c_char, c_void, CString}, ptr, slice, sync::{Arc, Mutex}};
+
+use uxn_core_emulator::{device::{Device, DeviceContext, DeviceEvent}, Core, RunResult, UxnError};
+
+/// Called for each DEO, with the `user_data` it was registered with, the port and the byte. This
+/// can be null.
+pub type UxnDeoCallback = Option<extern "C" fn(user_data: *mut c_void, port: u8, value: u8)>;
+
+/// What happened when running a core.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UxnStatus {
+    /// Instructions ran, and there are more to run.
+    Running,
+
+    /// A BRK was reached.
+    Break,
+
+    /// A breakpoint was reached.
+    Breakpoint,
+
+    /// The ROM faulted. [`uxn_last_error`] describes why.
+    Error,
+}
+
+/// The device behind every port. DEIs read back the last byte written to the port, like a
+/// device which isn't there, and DEOs are passed to the callback.
+struct CallbackDevice {
+    ports: [u8; 256],
+    deo: UxnDeoCallback,
+    user_data: *mut c_void,
+}
+
+// The C side promises not to use a core from more than one thread at a time, which covers the
+// user data too
+unsafe impl Send for CallbackDevice {}
+
+impl Device for CallbackDevice {
+    fn dei(&mut self, port: u8, _context: DeviceContext) -> u8 {
+        self.ports[port as usize]
+    }
+
+    fn deo(&mut self, port: u8, value: u8, _context: DeviceContext) {
+        self.ports[port as usize] = value;
+        if let Some(callback) = self.deo {
+            callback(self.user_data, port, value);
+        }
+    }
+
+    // Vectors are run by the host, so there are never any events
+    fn wait_for_event(&mut self) -> DeviceEvent {
+        DeviceEvent::Exit(0)
+    }
+}
+
+/// An emulator core, as seen from C.
+pub struct UxnCore {
+    core: Core,
+    device: Arc<Mutex<CallbackDevice>>,
+    last_error: Option<CString>,
+}
+
+impl UxnCore {
+    fn status(&mut self, result: Result<RunResult, UxnError>) -> UxnStatus {
+        match result {
+            Ok(RunResult::Break | RunResult::Exit(_)) => UxnStatus::Break,
+            Ok(RunResult::Stopped(_)) => UxnStatus::Breakpoint,
+            Ok(RunResult::BudgetExceeded) => UxnStatus::Running,
+            Err(e) => {
+                self.last_error = CString::new(e.to_string()).ok();
+                UxnStatus::Error
+            },
+        }
+    }
+}
+
+/// Creates a core with empty memory. Free it with [`uxn_destroy`].
+#[unsafe(no_mangle)]
+pub extern "C" fn uxn_create() -> *mut UxnCore {
+    let device = Arc::new(Mutex::new(CallbackDevice { ports: [0; 256], deo: None, user_data: ptr::null_mut() }));
+    let mut core = Core::new();
+    core.set_device(device.clone());
+    Box::into_raw(Box::new(UxnCore { core, device, last_error: None }))
+}
+
+/// Frees a core from [`uxn_create`]. Does nothing if `core` is null.
+///
+/// # Safety
+/// `core` must be null or from [`uxn_create`], and mustn't be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_destroy(core: *mut UxnCore) {
+    if !core.is_null() {
+        drop(unsafe { Box::from_raw(core) });
+    }
+}
+
+/// Loads a ROM at the reset vector, and moves the program counter there.
+///
+/// # Safety
+/// `core` must be from [`uxn_create`], and `rom` must point to `length` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_load_rom(core: *mut UxnCore, rom: *const u8, length: usize) {
+    let core = unsafe { &mut *core };
+    core.core.load_rom(unsafe { slice::from_raw_parts(rom, length) });
+    core.core.set_program_counter(0x0100);
+}
+
+/// Runs the instruction at the program counter.
+///
+/// # Safety
+/// `core` must be from [`uxn_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_step(core: *mut UxnCore) -> UxnStatus {
+    let core = unsafe { &mut *core };
+    let result = core.core.execute_instructions(1).map(|outcome| outcome.result);
+    core.status(result)
+}
+
+/// Runs a vector until it reaches a BRK, for at most `fuel` instructions, or without a limit if
+/// `fuel` is 0. Returns [`UxnStatus::Running`] if it ran out of fuel.
+///
+/// # Safety
+/// `core` must be from [`uxn_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_run_vector(core: *mut UxnCore, vector: u16, fuel: u64) -> UxnStatus {
+    let core = unsafe { &mut *core };
+    let result = core.core.execute_vector(vector, (fuel != 0).then_some(fuel)).map(|outcome| outcome.result);
+    core.status(result)
+}
+
+/// Copies `length` bytes of memory, starting at `address`, into `out`. Addresses wrap around.
+///
+/// # Safety
+/// `core` must be from [`uxn_create`], and `out` must point to `length` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_read_memory(core: *const UxnCore, address: u16, out: *mut u8, length: usize) {
+    let core = unsafe { &*core };
+    let out = unsafe { slice::from_raw_parts_mut(out, length) };
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = core.core.memory[address.wrapping_add(i as u16) as usize];
+    }
+}
+
+/// Copies `length` bytes from `data` into memory, starting at `address`. Addresses wrap around.
+///
+/// # Safety
+/// `core` must be from [`uxn_create`], and `data` must point to `length` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_write_memory(core: *mut UxnCore, address: u16, data: *const u8, length: usize) {
+    let core = unsafe { &mut *core };
+    let data = unsafe { slice::from_raw_parts(data, length) };
+    core.core.poke_memory(address, data);
+}
+
+/// Calls `callback` with `user_data` for every DEO, replacing any earlier callback. A null
+/// `callback` removes it.
+///
+/// # Safety
+/// `core` must be from [`uxn_create`], and `user_data` must stay valid for as long as the callback
+/// is registered.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_register_deo_callback(core: *mut UxnCore, callback: UxnDeoCallback, user_data: *mut c_void) {
+    let core = unsafe { &mut *core };
+    let mut device = core.device.lock().unwrap();
+    device.deo = callback;
+    device.user_data = user_data;
+}
+
+/// Describes the last [`UxnStatus::Error`], or gives null if there hasn't been one. The string
+/// belongs to the core, and lasts until the next error or until the core is destroyed.
+///
+/// # Safety
+/// `core` must be from [`uxn_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uxn_last_error(core: *const UxnCore) -> *const c_char {
+    let core = unsafe { &*core };
+    core.last_error.as_ref().map_or(ptr::null(), |error| error.as_ptr())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::{c_void, CStr}, ptr};
+
+    use uxn_utils::assemble_uxntal;
+
+    use super::*;
+
+    extern "C" fn record_deo(user_data: *mut c_void, port: u8, value: u8) {
+        let writes = unsafe { &mut *(user_data as *mut Vec<(u8, u8)>) };
+        writes.push((port, value));
+    }
+
+    #[test]
+    fn test_ffi() {
+        let rom = assemble_uxntal("#2a #18 DEO #1234 #00 STZ2 BRK").unwrap();
+        let mut writes: Vec<(u8, u8)> = vec![];
+        unsafe {
+            let core = uxn_create();
+            uxn_load_rom(core, rom.as_ptr(), rom.len());
+            uxn_register_deo_callback(core, Some(record_deo), &mut writes as *mut _ as *mut c_void);
+
+            assert_eq!(uxn_step(core), UxnStatus::Running);
+            assert_eq!(uxn_run_vector(core, 0x0100, 0), UxnStatus::Break);
+
+            let mut memory = [0; 2];
+            uxn_read_memory(core, 0x0000, memory.as_mut_ptr(), memory.len());
+            assert_eq!(memory, [0x12, 0x34]);
+            uxn_write_memory(core, 0xffff, [0xab, 0xcd].as_ptr(), 2);
+            uxn_read_memory(core, 0xffff, memory.as_mut_ptr(), memory.len());
+            assert_eq!(memory, [0xab, 0xcd]);
+            assert!(uxn_last_error(core).is_null());
+
+            uxn_destroy(core);
+        }
+        assert_eq!(writes, [(0x18, 0x2a)]);
+    }
+
+    #[test]
+    fn test_ffi_error() {
+        let rom = assemble_uxntal("POP BRK").unwrap();
+        unsafe {
+            let core = uxn_create();
+            uxn_load_rom(core, rom.as_ptr(), rom.len());
+            (*core).core.set_strict_stacks(true);
+            assert_eq!(uxn_run_vector(core, 0x0100, 0), UxnStatus::Error);
+            assert_eq!(CStr::from_ptr(uxn_last_error(core)).to_str().unwrap(), "working stack underflow at 0x0100");
+            uxn_destroy(core);
+            uxn_destroy(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::{env, path::PathBuf};
+
+use proc_macro::{Literal, TokenStream, TokenTree};
+use uxn_utils::asm::{assemble_file, AssembleOptions};
+
+/// Assembles a uxntal file at compile time, giving the ROM as a `&'static [u8]`.
+///
+/// ```ignore
+/// const ROM: &[u8] = uxn_macros::include_uxntal!("roms/clock.tal");
+/// ```
+///
+/// The path is relative to the crate's `Cargo.toml`, like `env!("CARGO_MANIFEST_DIR")`. Assembly
+/// errors are reported as compile errors.
+///
+/// The crate is rebuilt when the file changes, but not when a file it `~include`s does.
+#[proc_macro]
+pub fn include_uxntal(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(output) => output,
+        Err(e) => format!("compile_error!({e:?})").parse().unwrap(),
+    }
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let tokens: Vec<_> = input.into_iter().collect();
+    let [TokenTree::Literal(literal)] = &tokens[..] else {
+        return Err("include_uxntal! takes a single string literal".to_string());
+    };
+    let relative_path = parse_string_literal(&literal.to_string())
+        .ok_or("include_uxntal! takes a single string literal")?;
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").map_err(|e| format!("could not find the crate's directory: {e}"))?;
+    let path = PathBuf::from(manifest_dir).join(&relative_path);
+    let assembly = assemble_file(&path, &AssembleOptions::default()).map_err(|e| e.to_string())?;
+
+    // Including the file as well makes Cargo rebuild when it changes
+    let path = path.to_str().ok_or("include_uxntal! needs a UTF-8 path")?;
+    let rom = Literal::byte_string(&assembly.rom);
+    let path = Literal::string(path);
+    Ok(format!("{{ const _: &[u8] = include_bytes!({path}); {rom} as &'static [u8] }}").parse().unwrap())
+}
+
+/// Gets the contents of a plain or raw string literal, as written in source.
+fn parse_string_literal(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let raw = &raw[hashes..raw.len() - hashes];
+        return Some(raw.strip_prefix('"')?.strip_suffix('"')?.to_string());
+    }
+
+    let contents = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut result = String::new();
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        result.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('\\' | '"' | '\'') => c,
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(result)
+}
@@ -0,0 +1,9 @@
+use uxn_macros::include_uxntal;
+
+const ROM: &[u8] = include_uxntal!("tests/counter.tal");
+
+#[test]
+fn test_include_uxntal() {
+    assert_eq!(ROM, [0x80, 0x01, 0x80, 0x02, 0x18]);
+    assert_eq!(include_uxntal!(r"tests/counter.tal"), ROM);
+}
@@ -0,0 +1,169 @@
+//! Python bindings, for assembling and running uxntal from scripts and tests.
+//!
+//! Build and install the `uxn` module with `maturin develop`, from this directory:
+//!
+//! ```python
+//! import uxn
+//! core = uxn.Core.from_uxntal('|100 LIT "h #18 DEO #01 #02 ADD BRK')
+//! core.run()             # "break"
+//! core.working_stack     # [3]
+//! core.output            # "h"
+//! ```
+
+use pyo3::{exceptions::{PyRuntimeError, PyValueError}, prelude::*, types::PyList};
+use uxn_core_emulator::{device::{CapturingConsole, OffscreenBackend, VarvaraDevice}, Core as EmulatorCore, RunResult, UxnError};
+
+fn runtime_error(error: UxnError) -> PyErr {
+    PyRuntimeError::new_err(error.to_string())
+}
+
+/// Assembles uxntal source into ROM bytes, raising `ValueError` if it doesn't assemble.
+#[pyfunction]
+fn assemble(source: &str) -> PyResult<Vec<u8>> {
+    uxn_utils::assemble_uxntal(source).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// A core running a ROM, with Varvara attached headlessly and its console output captured.
+#[pyclass(unsendable)]
+struct Core {
+    core: EmulatorCore,
+    console: CapturingConsole,
+}
+
+#[pymethods]
+impl Core {
+    #[new]
+    fn new(rom: &[u8]) -> Self {
+        let console = CapturingConsole::new();
+        let mut device = VarvaraDevice::with_screen_backend(OffscreenBackend::new());
+        device.disable_stdin();
+        device.capture_console(console.clone());
+
+        let mut core = EmulatorCore::new_with_rom(rom);
+        core.set_device(device);
+        Self { core, console }
+    }
+
+    /// Assembles uxntal source, and creates a core running it.
+    #[staticmethod]
+    fn from_uxntal(source: &str) -> PyResult<Self> {
+        Ok(Self::new(&assemble(source)?))
+    }
+
+    /// Runs from the program counter until a BRK, for at most `fuel` instructions. Returns why it
+    /// stopped: `"break"`, `"exit"`, `"breakpoint"` or `"out of fuel"`.
+    #[pyo3(signature = (fuel=None))]
+    fn run(&mut self, fuel: Option<u64>) -> PyResult<&'static str> {
+        let outcome = self.core.execute_instructions(fuel.unwrap_or(u64::MAX)).map_err(runtime_error)?;
+        Ok(describe(outcome.result))
+    }
+
+    /// Like [`Core::run`], but starts from a vector.
+    #[pyo3(signature = (vector, fuel=None))]
+    fn run_vector(&mut self, vector: u16, fuel: Option<u64>) -> PyResult<&'static str> {
+        let outcome = self.core.execute_vector(vector, fuel).map_err(runtime_error)?;
+        Ok(describe(outcome.result))
+    }
+
+    #[getter]
+    fn program_counter(&self) -> u16 {
+        self.core.program_counter
+    }
+
+    #[setter]
+    fn set_program_counter(&mut self, address: u16) {
+        self.core.set_program_counter(address);
+    }
+
+    /// The working stack as a list, bottom first.
+    #[getter]
+    fn working_stack<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        PyList::new(py, self.core.working_stack.bytes())
+    }
+
+    /// The return stack as a list, bottom first.
+    #[getter]
+    fn return_stack<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        PyList::new(py, self.core.return_stack.bytes())
+    }
+
+    /// Reads `length` bytes of memory from an address, wrapping around.
+    fn read_memory(&self, address: u16, length: usize) -> Vec<u8> {
+        (0..length).map(|i| self.core.memory[address.wrapping_add(i as u16) as usize]).collect()
+    }
+
+    /// Writes bytes into memory from an address, wrapping around.
+    fn write_memory(&mut self, address: u16, data: &[u8]) {
+        self.core.poke_memory(address, data);
+    }
+
+    /// Everything written to `.Console/write` so far.
+    #[getter]
+    fn output(&self) -> String {
+        self.console.output_string()
+    }
+
+    /// Everything written to `.Console/error` so far.
+    #[getter]
+    fn error(&self) -> String {
+        self.console.error_string()
+    }
+
+    /// Forgets the console output so far.
+    fn clear_output(&self) {
+        self.console.clear();
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.core)
+    }
+}
+
+fn describe(result: RunResult) -> &'static str {
+    match result {
+        RunResult::Break => "break",
+        RunResult::Exit(_) => "exit",
+        RunResult::Stopped(_) => "breakpoint",
+        RunResult::BudgetExceeded => "out of fuel",
+    }
+}
+
+#[pymodule]
+fn uxn(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(assemble, module)?)?;
+    module.add_class::<Core>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pyo3::{ffi::c_str, prelude::*, types::PyDict};
+
+    #[test]
+    fn test_python_module() {
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "uxn").unwrap();
+            super::uxn(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("uxn", module).unwrap();
+
+            py.run(c_str!(r##"
+core = uxn.Core.from_uxntal('|100 LIT "h #18 DEO #01 #02 ADD #1234 #00 STZ2 BRK')
+assert core.run() == "break"
+assert core.working_stack == [3]
+assert core.return_stack == []
+assert core.output == "h"
+assert core.read_memory(0, 2) == b"\x12\x34"
+core.write_memory(0xffff, b"\xab\xcd")
+assert core.read_memory(0xffff, 2) == b"\xab\xcd"
+assert uxn.Core(uxn.assemble("#01 BRK")).run(fuel=0) == "out of fuel"
+
+try:
+    uxn.assemble(";missing")
+    assert False
+except ValueError:
+    pass
+"##), Some(&globals), None).unwrap();
+        });
+    }
+}
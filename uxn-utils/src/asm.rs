@@ -0,0 +1,620 @@
+//! A native uxntal assembler, so that assembling doesn't need `uxnasm`.
+//!
+//! This supports the syntax which most ROMs use: labels and sublabels, absolute and relative pads,
+//! literals, raw bytes and strings, the addressing runes, macros and includes. It aims to produce the
+//! same ROM as uxnasm for any source which both accept.
+//!
+//! See: https://wiki.xxiivv.com/site/uxntal_syntax.html
+
+use std::{collections::HashMap, error::Error, fmt::Display, fs, path::{Path, PathBuf}, rc::Rc};
+
+use crate::{Symbols, ROM_ORIGIN};
+
+pub mod lint;
+
+/// The base opcodes' mnemonics, indexed by value. Opcode 0x00 is written `LIT` here, since `BRK`
+/// is the only form of it without modes.
+const MNEMONICS: [&str; 32] = [
+    "LIT", "INC", "POP", "NIP", "SWP", "ROT", "DUP", "OVR",
+    "EQU", "NEQ", "GTH", "LTH", "JMP", "JCN", "JSR", "STH",
+    "LDZ", "STZ", "LDR", "STR", "LDA", "STA", "DEI", "DEO",
+    "ADD", "SUB", "MUL", "DIV", "AND", "ORA", "EOR", "SFT",
+];
+
+/// How many macros can be expanded inside each other, so that a macro which uses itself is caught.
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// The result of [`assemble_with`] or [`assemble_file`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assembly {
+    /// The ROM, which should be loaded at 0x0100.
+    pub rom: Vec<u8>,
+
+    /// Every label, which can be saved as a `.sym` file with [`Symbols::to_bytes`].
+    pub symbols: Symbols,
+}
+
+/// Options for [`assemble_with`] and [`assemble_file`].
+#[derive(Clone, Debug, Default)]
+pub struct AssembleOptions {
+    /// Directories to search for files named by `~include`, after the directory of the file doing
+    /// the including.
+    pub include_paths: Vec<PathBuf>,
+}
+
+/// Why source couldn't be assembled, and where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub message: String,
+
+    /// The file the problem is in, or `None` if it's in the source given directly.
+    pub file: Option<PathBuf>,
+
+    /// The line the problem is on, from 1, if known.
+    pub line: Option<usize>,
+
+    /// The column the problem starts at, from 1, if known.
+    pub column: Option<usize>,
+
+    /// The whole line of source which the problem is on, if known.
+    pub source_snippet: Option<String>,
+}
+
+impl AssembleError {
+    /// An error which isn't about any particular part of the source.
+    pub fn new(message: impl Display) -> Self {
+        Self { message: message.to_string(), file: None, line: None, column: None, source_snippet: None }
+    }
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Like `game.tal:3:14: message`, leaving out whatever isn't known
+        let location = [
+            self.file.as_ref().map(|file| file.display().to_string()),
+            self.line.map(|line| line.to_string()),
+            self.column.map(|column| column.to_string()),
+        ];
+        for part in location.into_iter().flatten() {
+            write!(f, "{part}:")?;
+        }
+        if self.file.is_some() || self.line.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)?;
+
+        // Point out the problem underneath the line it's on
+        if let Some(snippet) = &self.source_snippet {
+            write!(f, "\n    {snippet}")?;
+            if let Some(column) = self.column {
+                let indent: String = snippet.chars().take(column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                write!(f, "\n    {indent}^")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for AssembleError {}
+
+/// A word of source, with where it was found for error messages.
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    file: Option<Rc<Path>>,
+    line: usize,
+    column: usize,
+    line_text: Rc<str>,
+}
+
+impl Token {
+    fn error(&self, message: impl Display) -> AssembleError {
+        AssembleError {
+            message: message.to_string(),
+            file: self.file.as_deref().map(Path::to_path_buf),
+            line: Some(self.line),
+            column: Some(self.column),
+            source_snippet: Some(self.line_text.trim_end().to_string()),
+        }
+    }
+}
+
+/// Splits source into words, dropping comments and brackets, which are only for readability.
+fn tokenize(source: &str, file: Option<Rc<Path>>) -> Result<Vec<Token>, AssembleError> {
+    let mut tokens = vec![];
+    let mut comment_depth = 0;
+    let mut comment_start = None;
+    for (line_index, line) in source.lines().enumerate() {
+        let line_text: Rc<str> = line.into();
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            let word_length = rest[start..].find(char::is_whitespace).unwrap_or(rest.len() - start);
+            let text = &rest[start..start + word_length];
+            let column = line.len() - rest.len() + start + 1;
+            rest = &rest[start + word_length..];
+            let token = Token {
+                text: text.to_string(),
+                file: file.clone(),
+                line: line_index + 1,
+                column,
+                line_text: line_text.clone(),
+            };
+
+            if text.starts_with('(') {
+                comment_depth += 1;
+                comment_start.get_or_insert_with(|| token.clone());
+            }
+            if comment_depth > 0 {
+                if text.ends_with(')') {
+                    comment_depth -= 1;
+                }
+                if comment_depth == 0 {
+                    comment_start = None;
+                }
+                continue;
+            }
+            if text == "[" || text == "]" {
+                continue;
+            }
+
+            tokens.push(token);
+        }
+    }
+
+    match comment_start {
+        Some(start) => Err(start.error("comment is never closed")),
+        None => Ok(tokens),
+    }
+}
+
+/// Expands macros and includes, leaving just the tokens which produce bytes.
+struct Preprocessor<'a> {
+    options: &'a AssembleOptions,
+    macros: HashMap<String, Vec<Token>>,
+
+    // The files currently being included, to catch files which include themselves
+    including: Vec<PathBuf>,
+
+    output: Vec<Token>,
+}
+
+impl<'a> Preprocessor<'a> {
+    fn new(options: &'a AssembleOptions) -> Self {
+        Self { options, macros: HashMap::new(), including: vec![], output: vec![] }
+    }
+
+    fn process(&mut self, tokens: Vec<Token>, depth: usize) -> Result<(), AssembleError> {
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            if let Some(name) = token.text.strip_prefix('%') {
+                let body = Self::macro_body(&token, &mut tokens)?;
+                if self.macros.insert(name.to_string(), body).is_some() {
+                    return Err(token.error(format!("macro {name} is defined twice")));
+                }
+            } else if let Some(name) = token.text.strip_prefix('~') {
+                self.include(&token, name)?;
+            } else if let Some(body) = self.macros.get(&token.text) {
+                if depth >= MAX_MACRO_DEPTH {
+                    return Err(token.error(format!("macro {} expands forever", token.text)));
+                }
+                self.process(body.clone(), depth + 1)?;
+            } else {
+                self.output.push(token);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the body of a macro, between braces, which may contain braces of its own.
+    fn macro_body(definition: &Token, tokens: &mut impl Iterator<Item = Token>) -> Result<Vec<Token>, AssembleError> {
+        if tokens.next().is_none_or(|token| token.text != "{") {
+            return Err(definition.error("macro has no body in braces"));
+        }
+
+        let mut body = vec![];
+        let mut depth = 0;
+        loop {
+            let token = tokens.next().ok_or_else(|| definition.error("macro is never closed"))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {},
+            }
+            body.push(token);
+        }
+    }
+
+    fn include(&mut self, token: &Token, name: &str) -> Result<(), AssembleError> {
+        let path = self.find_include(token, name).ok_or_else(|| token.error(format!("could not find {name} to include")))?;
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        if self.including.contains(&path) {
+            return Err(token.error(format!("{name} includes itself")));
+        }
+
+        let source = fs::read_to_string(&path).map_err(|e| token.error(format!("could not read {name}: {e}")))?;
+        let tokens = tokenize(&source, Some(path.as_path().into()))?;
+        self.including.push(path);
+        self.process(tokens, 0)?;
+        self.including.pop();
+        Ok(())
+    }
+
+    /// Looks for an included file next to the file including it, then in each include path.
+    fn find_include(&self, token: &Token, name: &str) -> Option<PathBuf> {
+        let beside = match token.file.as_deref().and_then(Path::parent) {
+            Some(directory) => directory.join(name),
+            None => PathBuf::from(name),
+        };
+        std::iter::once(beside)
+            .chain(self.options.include_paths.iter().map(|directory| directory.join(name)))
+            .find(|path| path.is_file())
+    }
+}
+
+/// A label used before its address might be known, to be filled in at the end.
+struct Reference {
+    name: String,
+    rune: char,
+    address: usize,
+    token: Token,
+}
+
+struct Assembler {
+    memory: Vec<u8>,
+    pointer: usize,
+
+    // Just past the last non-zero byte, since trailing zeroes aren't written to the ROM
+    length: usize,
+
+    scope: String,
+    labels: Symbols,
+    references: Vec<Reference>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self {
+            memory: vec![0; 0x10000],
+            pointer: ROM_ORIGIN as usize,
+            length: ROM_ORIGIN as usize,
+            scope: String::new(),
+            labels: Symbols::new(),
+            references: vec![],
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), String> {
+        if self.pointer < ROM_ORIGIN as usize {
+            return Err(format!("can't write to the zero page, at {:#04x}", self.pointer));
+        }
+        if self.pointer >= self.memory.len() {
+            return Err("ROM doesn't fit in memory".to_string());
+        }
+
+        self.memory[self.pointer] = byte;
+        self.pointer += 1;
+        if byte != 0 {
+            self.length = self.length.max(self.pointer);
+        }
+        Ok(())
+    }
+
+    fn write_short(&mut self, short: u16) -> Result<(), String> {
+        let [hi, lo] = short.to_be_bytes();
+        self.write_byte(hi)?;
+        self.write_byte(lo)
+    }
+
+    /// Writes a placeholder, to be replaced with a label's address once every label is known.
+    fn write_reference(&mut self, rune: char, name: &str, token: &Token) -> Result<(), String> {
+        self.references.push(Reference {
+            name: self.full_name(name),
+            rune,
+            address: self.pointer,
+            token: token.clone(),
+        });
+        match rune {
+            '.' | ',' | '-' | '_' => self.write_byte(0xff),
+            _ => self.write_short(0xffff),
+        }
+    }
+
+    /// Expands sublabels, like `&loop`, to include the label they're under.
+    fn full_name(&self, name: &str) -> String {
+        match name.strip_prefix('&') {
+            Some(sublabel) => format!("{}/{sublabel}", self.scope),
+            None => name.to_string(),
+        }
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), String> {
+        if name.is_empty() || name.ends_with('/') {
+            return Err("label has no name".to_string());
+        }
+        if self.labels.address(&name).is_some() {
+            return Err(format!("label {name} is defined twice"));
+        }
+        self.labels.insert(self.pointer as u16, &name);
+        Ok(())
+    }
+
+    fn label_address(&self, name: &str) -> Result<u16, String> {
+        let name = self.full_name(name);
+        self.labels.address(&name).ok_or_else(|| format!("unknown label {name}"))
+    }
+
+    fn token(&mut self, token: &Token) -> Result<(), String> {
+        let text = token.text.as_str();
+        let mut chars = text.chars();
+        let rune = chars.next().unwrap();
+        let rest = chars.as_str();
+
+        match rune {
+            // Padding
+            '|' => self.pointer = match parse_hex(rest) {
+                Some(address) => address as usize,
+                None => self.label_address(rest)? as usize,
+            },
+            '$' => self.pointer += parse_hex(rest).ok_or_else(|| format!("invalid padding {text}"))? as usize,
+
+            // Labels
+            '@' => {
+                self.scope = rest.to_string();
+                self.define_label(rest.to_string())?;
+            },
+            '&' => self.define_label(self.full_name(text))?,
+
+            // Literals
+            '#' => match (parse_hex(rest), rest.len()) {
+                (Some(byte), 2) => {
+                    self.write_byte(0x80)?;
+                    self.write_byte(byte as u8)?;
+                },
+                (Some(short), 4) => {
+                    self.write_byte(0xa0)?;
+                    self.write_short(short)?;
+                },
+                _ => return Err(format!("invalid literal {text}")),
+            },
+            '.' | ',' => {
+                self.write_byte(0x80)?;
+                self.write_reference(rune, rest, token)?;
+            },
+            ';' => {
+                self.write_byte(0xa0)?;
+                self.write_reference(rune, rest, token)?;
+            },
+
+            // Raw addresses
+            '-' | '_' | '=' => self.write_reference(rune, rest, token)?,
+
+            // Immediate jumps
+            '!' | '?' => {
+                self.write_byte(if rune == '!' { 0x40 } else { 0x20 })?;
+                self.write_reference(rune, rest, token)?;
+            },
+
+            // Raw text
+            '"' => {
+                for byte in rest.bytes() {
+                    self.write_byte(byte)?;
+                }
+            },
+            '\'' => {
+                let [byte] = rest.as_bytes() else { return Err(format!("invalid character {text}")) };
+                self.write_byte(*byte)?;
+            },
+
+            _ => {
+                if let Some(opcode) = parse_opcode(text) {
+                    self.write_byte(opcode)?;
+                } else if let (Some(value), 2 | 4) = (parse_hex(text), text.len()) {
+                    if text.len() == 2 {
+                        self.write_byte(value as u8)?;
+                    } else {
+                        self.write_short(value)?;
+                    }
+                } else {
+                    // Anything else calls a label
+                    self.write_byte(0x60)?;
+                    self.write_reference(' ', text, token)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn resolve(&mut self, reference: &Reference) -> Result<(), String> {
+        let address = self.labels.address(&reference.name).ok_or_else(|| format!("unknown label {}", reference.name))?;
+
+        // Relative addresses are from just after the instruction which uses them
+        let relative = address as isize - reference.address as isize - 2;
+        match reference.rune {
+            '.' if address > 0xff => return Err(format!("label {} isn't in the zero page", reference.name)),
+            '.' | '-' => self.memory[reference.address] = address as u8,
+            ',' | '_' => {
+                let relative = i8::try_from(relative)
+                    .map_err(|_| format!("label {} is too far away for a relative address", reference.name))?;
+                self.memory[reference.address] = relative as u8;
+            },
+            ';' | '=' => self.patch_short(reference.address, address),
+            _ => self.patch_short(reference.address, relative as u16),
+        }
+        Ok(())
+    }
+
+    fn patch_short(&mut self, address: usize, short: u16) {
+        self.memory[address..address + 2].copy_from_slice(&short.to_be_bytes());
+    }
+}
+
+/// Parses a word as an instruction, like `ADD2kr`, returning its byte.
+fn parse_opcode(word: &str) -> Option<u8> {
+    if word == "BRK" {
+        return Some(0x00);
+    }
+
+    let (name, modes) = word.split_at_checked(3)?;
+    let mut opcode = MNEMONICS.iter().position(|mnemonic| *mnemonic == name)? as u8;
+    if name == "LIT" {
+        opcode |= 0x80;
+    }
+    for mode in modes.chars() {
+        let bit = match mode {
+            '2' => 0x20,
+            'r' => 0x40,
+            'k' => 0x80,
+            _ => return None,
+        };
+        opcode |= bit;
+    }
+    Some(opcode)
+}
+
+/// Parses lowercase hex, as used for all numbers in uxntal.
+fn parse_hex(text: &str) -> Option<u16> {
+    let valid = !text.is_empty() && text.len() <= 4 && text.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'));
+    valid.then(|| u16::from_str_radix(text, 16).unwrap())
+}
+
+/// Assembles uxntal source into a ROM, which should be loaded at 0x0100. Included files are looked
+/// for relative to the working directory.
+///
+/// Returns an error describing the first problem found, with where it is.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    Ok(assemble_with(source, &AssembleOptions::default())?.rom)
+}
+
+/// Like [`assemble`], but with options such as where to look for included files, and giving the
+/// labels too.
+pub fn assemble_with(source: &str, options: &AssembleOptions) -> Result<Assembly, AssembleError> {
+    assemble_tokens(tokenize(source, None)?, options)
+}
+
+/// Assembles a uxntal file. Files it includes are looked for relative to it first.
+pub fn assemble_file(path: &Path, options: &AssembleOptions) -> Result<Assembly, AssembleError> {
+    let source = fs::read_to_string(path).map_err(|e| AssembleError::new(format!("could not read {}: {e}", path.display())))?;
+    assemble_tokens(tokenize(&source, Some(path.into()))?, options)
+}
+
+fn assemble_tokens(tokens: Vec<Token>, options: &AssembleOptions) -> Result<Assembly, AssembleError> {
+    let mut preprocessor = Preprocessor::new(options);
+    preprocessor.process(tokens, 0)?;
+
+    let mut assembler = Assembler::new();
+    for token in &preprocessor.output {
+        assembler.token(token).map_err(|e| token.error(format!("{e} ({})", token.text)))?;
+    }
+
+    let references = std::mem::take(&mut assembler.references);
+    for reference in &references {
+        assembler.resolve(reference).map_err(|e| reference.token.error(e))?;
+    }
+
+    Ok(Assembly {
+        rom: assembler.memory[ROM_ORIGIN as usize..assembler.length].to_vec(),
+        symbols: assembler.labels,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::{assemble, assemble_file, assemble_with, AssembleError, AssembleOptions, parse_opcode};
+
+    #[test]
+    fn test_opcodes() {
+        assert_eq!(parse_opcode("BRK"), Some(0x00));
+        assert_eq!(parse_opcode("ADD"), Some(0x18));
+        assert_eq!(parse_opcode("ADD2kr"), Some(0xf8));
+        assert_eq!(parse_opcode("LIT2"), Some(0xa0));
+        assert_eq!(parse_opcode("ADDx"), None);
+        assert_eq!(parse_opcode("BR"), None);
+    }
+
+    #[test]
+    fn test_assemble() {
+        assert_eq!(assemble("|100 01 02 03").unwrap(), [1, 2, 3]);
+        assert_eq!(assemble("#12 #3456 ADD2k ( comment ( nested ) ) [ BRK ]").unwrap(), [0x80, 0x12, 0xa0, 0x34, 0x56, 0xb8]);
+        assert_eq!(assemble("\"hi 'c 00 $2 ff").unwrap(), [b'h', b'i', b'c', 0, 0, 0, 0xff]);
+
+        // Trailing zeroes aren't written
+        assert_eq!(assemble("#01 BRK 00 $10").unwrap(), [0x80, 0x01]);
+
+        // Padding backwards doesn't lose what was written further on
+        let rom = assemble("|200 01 |100 02").unwrap();
+        assert_eq!((rom.len(), rom[0], rom[0x100]), (0x101, 0x02, 0x01));
+    }
+
+    #[test]
+    fn test_labels() {
+        let rom = assemble("|00 @Console &pad $8 &write $1 |100 .Console/write ;data =data -data ,&next &next BRK @data").unwrap();
+        assert_eq!(rom, [0x80, 0x08, 0xa0, 0x01, 0x0b, 0x01, 0x0b, 0x0b, 0x80, 0xff]);
+
+        let symbols = assemble_with("|10 @Console &vector $2 |100 @on-reset &loop !&loop", &AssembleOptions::default()).unwrap().symbols;
+        assert_eq!(symbols.labels().collect::<Vec<_>>(), [
+            (0x0010, "Console"), (0x0010, "Console/vector"), (0x0100, "on-reset"), (0x0100, "on-reset/loop"),
+        ]);
+        assert_eq!(symbols.locate(0x0101).as_deref(), Some("on-reset+1"));
+
+        // Immediate jumps and calls are relative to just after their address
+        assert_eq!(assemble("@loop !loop ?loop loop").unwrap(), [0x40, 0xff, 0xfd, 0x20, 0xff, 0xfa, 0x60, 0xff, 0xf7]);
+
+        assert!(assemble("#01 ;missing JMP2").unwrap_err().to_string().contains("unknown label missing"));
+        assert!(assemble("@a @a").is_err());
+        assert!(assemble("|00 01").is_err());
+        assert!(assemble("( never closed").is_err());
+        assert!(assemble(",far $100 @far 01").is_err());
+        assert!(assemble(".far @far").unwrap_err().to_string().contains("isn't in the zero page"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = assemble("#01 #02\n  ADD ;missing JMP2").unwrap_err();
+        assert_eq!(error, AssembleError {
+            message: "unknown label missing".to_string(),
+            file: None,
+            line: Some(2),
+            column: Some(7),
+            source_snippet: Some("  ADD ;missing JMP2".to_string()),
+        });
+        assert_eq!(error.to_string(), "2:7: unknown label missing\n      ADD ;missing JMP2\n          ^");
+
+        let error = assemble("( open").unwrap_err();
+        assert_eq!((error.line, error.column), (Some(1), Some(1)));
+        assert_eq!(AssembleError::new("oops").to_string(), "oops");
+    }
+
+    #[test]
+    fn test_macros() {
+        assert_eq!(assemble("%twice { DUP ADD } %quad { twice twice } #01 quad").unwrap(), [0x80, 0x01, 0x06, 0x18, 0x06, 0x18]);
+
+        assert!(assemble("%forever { forever } forever").unwrap_err().to_string().contains("expands forever"));
+        assert!(assemble("%open { DUP").is_err());
+        assert!(assemble("%bare DUP").is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let project = tempdir().unwrap();
+        let library = tempdir().unwrap();
+        fs::create_dir(project.path().join("src")).unwrap();
+        fs::write(project.path().join("main.tal"), "~src/util.tal ~lib.tal #01 double BRK").unwrap();
+        fs::write(project.path().join("src/util.tal"), "%double { DUP ADD }").unwrap();
+        fs::write(library.path().join("lib.tal"), "( nothing but a comment )").unwrap();
+        fs::write(project.path().join("loop.tal"), "~loop.tal").unwrap();
+
+        // The library is only found through the include paths
+        let main = project.path().join("main.tal");
+        assert!(assemble_file(&main, &AssembleOptions::default()).unwrap_err().to_string().contains("could not find lib.tal"));
+
+        let options = AssembleOptions { include_paths: vec![library.path().to_path_buf()] };
+        assert_eq!(assemble_file(&main, &options).unwrap().rom, [0x80, 0x01, 0x06, 0x18]);
+        assert!(assemble_file(&project.path().join("loop.tal"), &options).unwrap_err().to_string().contains("includes itself"));
+    }
+}
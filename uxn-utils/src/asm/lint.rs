@@ -0,0 +1,270 @@
+//! Checks uxntal source for things which assemble fine, but are probably mistakes.
+
+use std::{collections::HashSet, fmt::Display, fs, path::{Path, PathBuf}};
+
+use crate::ROM_ORIGIN;
+
+use super::{parse_opcode, tokenize, AssembleError, AssembleOptions, Assembler, Preprocessor, Token};
+
+/// What kind of problem a [`Lint`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintKind {
+    /// A label which nothing refers to.
+    UnusedLabel,
+
+    /// Code straight after an unconditional jump or `BRK`, without a label to reach it by.
+    Unreachable,
+
+    /// A `DEO` to a literal port which isn't one of Varvara's device fields.
+    UndefinedDeviceField,
+
+    /// Zero-page variables which share addresses.
+    ZeroPageOverlap,
+}
+
+/// A likely mistake in uxntal source, and where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lint {
+    pub kind: LintKind,
+    pub message: String,
+
+    /// The file the problem is in, or `None` if it's in the source given directly.
+    pub file: Option<PathBuf>,
+
+    /// The line and column the problem starts at, from 1.
+    pub line: usize,
+    pub column: usize,
+
+    /// The whole line of source which the problem is on.
+    pub source_snippet: String,
+}
+
+impl Token {
+    fn lint(&self, kind: LintKind, message: impl Display) -> Lint {
+        let AssembleError { message, file, source_snippet, .. } = self.error(message);
+        Lint { kind, message, file, line: self.line, column: self.column, source_snippet: source_snippet.unwrap() }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Shown just like an error, so that editors can jump to it
+        let error = AssembleError {
+            message: self.message.clone(),
+            file: self.file.clone(),
+            line: Some(self.line),
+            column: Some(self.column),
+            source_snippet: Some(self.source_snippet.clone()),
+        };
+        write!(f, "{error}")
+    }
+}
+
+/// Lints uxntal source. Included files are looked for relative to the working directory.
+///
+/// Returns an error if the source doesn't assemble.
+pub fn lint(source: &str) -> Result<Vec<Lint>, AssembleError> {
+    lint_tokens(tokenize(source, None)?, &AssembleOptions::default())
+}
+
+/// Lints a uxntal file, along with anything it includes.
+pub fn lint_file(path: &Path, options: &AssembleOptions) -> Result<Vec<Lint>, AssembleError> {
+    let source = fs::read_to_string(path).map_err(|e| AssembleError::new(format!("could not read {}: {e}", path.display())))?;
+    lint_tokens(tokenize(&source, Some(path.into()))?, options)
+}
+
+fn lint_tokens(tokens: Vec<Token>, options: &AssembleOptions) -> Result<Vec<Lint>, AssembleError> {
+    let mut preprocessor = Preprocessor::new(options);
+    preprocessor.process(tokens, 0)?;
+    let tokens = preprocessor.output;
+
+    // Assemble as usual, keeping where each token went
+    let mut assembler = Assembler::new();
+    let mut addresses = Vec::with_capacity(tokens.len() + 1);
+    for token in &tokens {
+        addresses.push(assembler.pointer);
+        assembler.token(token).map_err(|e| token.error(format!("{e} ({})", token.text)))?;
+    }
+    addresses.push(assembler.pointer);
+
+    let references = std::mem::take(&mut assembler.references);
+    for reference in &references {
+        assembler.resolve(reference).map_err(|e| reference.token.error(e))?;
+    }
+
+    let mut used: HashSet<&str> = HashSet::new();
+    for reference in &references {
+        // Using a sublabel counts as using the label it's under
+        used.insert(&reference.name);
+        if let Some((scope, _)) = reference.name.split_once('/') {
+            used.insert(scope);
+        }
+    }
+    for token in &tokens {
+        if let Some(name) = token.text.strip_prefix('|') {
+            used.insert(name);
+        }
+    }
+
+    let mut lints = vec![];
+    let labels = labels(&tokens, &addresses);
+    for label in &labels {
+        let is_entry_point = label.start == ROM_ORIGIN as usize;
+        if !is_entry_point && !label.is_device() && !used.contains(label.name.as_str()) {
+            lints.push((label.index, tokens[label.index].lint(LintKind::UnusedLabel, format!("label {} is never used", label.name))));
+        }
+    }
+    lints.extend(unreachable(&tokens));
+    lints.extend(undefined_device_fields(&tokens, &addresses, &assembler.memory));
+    lints.extend(zero_page_overlaps(&tokens, &labels));
+
+    lints.sort_by_key(|(index, _)| *index);
+    Ok(lints.into_iter().map(|(_, lint)| lint).collect())
+}
+
+/// A label definition, and the addresses up to the next label.
+struct Label {
+    name: String,
+    index: usize,
+    start: usize,
+    end: usize,
+}
+
+impl Label {
+    /// Whether this is part of a device's ports, which are named with a capital letter.
+    fn is_device(&self) -> bool {
+        self.name.starts_with(|c: char| c.is_ascii_uppercase())
+    }
+}
+
+fn labels(tokens: &[Token], addresses: &[usize]) -> Vec<Label> {
+    let mut labels = vec![];
+    let mut scope = "";
+    for (index, token) in tokens.iter().enumerate() {
+        let name = match token.text.split_at(1) {
+            ("@", name) => {
+                scope = name;
+                name.to_string()
+            },
+            ("&", name) => format!("{scope}/{name}"),
+            _ => continue,
+        };
+
+        // Each label covers everything up to the next label or absolute pad
+        let end = tokens[index + 1..].iter()
+            .position(|token| token.text.starts_with(['@', '&', '|']))
+            .map_or(addresses[tokens.len()], |offset| addresses[index + 1 + offset]);
+        labels.push(Label { name, index, start: addresses[index], end });
+    }
+    labels
+}
+
+fn unreachable(tokens: &[Token]) -> Vec<(usize, Lint)> {
+    let mut lints = vec![];
+    let mut jump: Option<&Token> = None;
+    for (index, token) in tokens.iter().enumerate() {
+        if token.text.starts_with(['@', '&', '|']) {
+            jump = None;
+            continue;
+        }
+        if token.text.starts_with('$') {
+            continue;
+        }
+
+        if let Some(jump) = jump.take() {
+            lints.push((index, token.lint(LintKind::Unreachable, format!("unreachable code after {}", jump.text))));
+        }
+        let is_jump = token.text.starts_with('!')
+            || parse_opcode(&token.text).is_some_and(|opcode| opcode == 0x00 || opcode & 0x1f == 0x0c);
+        if is_jump {
+            jump = Some(token);
+        }
+    }
+    lints
+}
+
+fn undefined_device_fields(tokens: &[Token], addresses: &[usize], memory: &[u8]) -> Vec<(usize, Lint)> {
+    let mut lints = vec![];
+    for (index, token) in tokens.iter().enumerate().skip(1) {
+        // Only ports given as a literal just before, from the working stack, can be checked
+        let Some(opcode) = parse_opcode(&token.text) else { continue };
+        let is_literal = tokens[index - 1].text.starts_with(['#', '.']) && addresses[index] - addresses[index - 1] == 2;
+        if opcode & 0x5f != 0x17 || !is_literal {
+            continue;
+        }
+
+        let port = memory[addresses[index] - 1];
+        let ports = if opcode & 0x20 != 0 { vec![port, port.wrapping_add(1)] } else { vec![port] };
+        if let Some(port) = ports.into_iter().find(|port| !is_varvara_field(*port)) {
+            let message = format!("port {port:#04x} isn't a Varvara device field");
+            lints.push((index, token.lint(LintKind::UndefinedDeviceField, message)));
+        }
+    }
+    lints
+}
+
+/// Whether a port belongs to a field of one of Varvara's devices.
+///
+/// See: https://wiki.xxiivv.com/site/varvara.html
+fn is_varvara_field(port: u8) -> bool {
+    match port {
+        // System, Screen and File have no gaps
+        0x00..=0x0f | 0x20..=0x2f | 0xa0..=0xbf => port != 0x27,
+        0x10..=0x1f => matches!(port, 0x10..=0x12 | 0x17..=0x19),
+        0x30..=0x6f => !matches!(port & 0x0f, 0x05..=0x07),
+        0x80..=0x8f => matches!(port, 0x80..=0x83 | 0x85..=0x87),
+        0x90..=0x9f => matches!(port, 0x90..=0x96 | 0x9a..=0x9d),
+        0xc0..=0xca => true,
+        _ => false,
+    }
+}
+
+fn zero_page_overlaps(tokens: &[Token], labels: &[Label]) -> Vec<(usize, Lint)> {
+    let variables: Vec<_> = labels.iter()
+        .filter(|label| label.start < ROM_ORIGIN as usize && label.start < label.end && !label.is_device())
+        .collect();
+
+    let mut lints = vec![];
+    for (i, label) in variables.iter().enumerate() {
+        if let Some(earlier) = variables[..i].iter().find(|earlier| label.start < earlier.end && earlier.start < label.end) {
+            let message = format!("zero-page label {} overlaps {}", label.name, earlier.name);
+            lints.push((label.index, tokens[label.index].lint(LintKind::ZeroPageOverlap, message)));
+        }
+    }
+    lints
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lint, LintKind};
+
+    fn kinds(source: &str) -> Vec<(LintKind, String)> {
+        lint(source).unwrap().into_iter().map(|lint| (lint.kind, lint.message)).collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let clean = "|10 @Console &vector $2 &read $1 &pad $4 &type $1 &write $1 |000 @count $1 |100 @on-reset .count LDZ print BRK @print .Console/write DEO JMP2r";
+        assert_eq!(kinds(clean), []);
+
+        assert_eq!(kinds("|100 ;used/a JMP2 @unused BRK @used &a BRK"), [
+            (LintKind::UnusedLabel, "label unused is never used".to_string()),
+        ]);
+        assert_eq!(kinds("|100 #01 !end #02 BRK @end BRK"), [
+            (LintKind::Unreachable, "unreachable code after !end".to_string()),
+        ]);
+        assert_eq!(kinds("|100 #01 #18 DEO #0102 #27 DEO2 #0102 #2e DEO2"), [
+            (LintKind::UndefinedDeviceField, "port 0x27 isn't a Varvara device field".to_string()),
+        ]);
+        assert_eq!(kinds("|00 @a $2 |01 @b $1 |100 .a LDZ .b LDZ BRK"), [
+            (LintKind::ZeroPageOverlap, "zero-page label b overlaps a".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_lint_location() {
+        let lints = lint("|100 BRK\n  #01").unwrap();
+        assert_eq!((lints[0].line, lints[0].column), (2, 3));
+        assert_eq!(lints[0].to_string(), "2:3: unreachable code after BRK\n      #01\n      ^");
+    }
+}
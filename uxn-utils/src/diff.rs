@@ -0,0 +1,77 @@
+use std::{fmt::Display, ops::Range};
+
+use crate::{Symbols, ROM_ORIGIN};
+
+/// A run of bytes which differ between two ROMs, from [`diff_roms`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomChange {
+    /// The addresses which differ. These can go past 0xffff for ROMs which spill into other banks.
+    pub addresses: Range<usize>,
+
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl RomChange {
+    /// Describes where the change starts relative to the nearest label, if there is one.
+    pub fn locate(&self, symbols: &Symbols) -> Option<String> {
+        u16::try_from(self.addresses.start).ok().and_then(|address| symbols.locate(address))
+    }
+}
+
+impl Display for RomChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = |bytes: &[u8]| bytes.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ");
+        write!(
+            f, "{:04x}-{:04x}: {} -> {}",
+            self.addresses.start, self.addresses.end - 1, bytes(&self.old), bytes(&self.new),
+        )
+    }
+}
+
+/// Finds the runs of bytes which differ between two ROMs, in address order.
+///
+/// Memory past the end of a ROM is zero when it's loaded, so where one ROM is longer than the
+/// other, the shorter one is treated as if it had zeroes on the end.
+pub fn diff_roms(old: &[u8], new: &[u8]) -> Vec<RomChange> {
+    let byte = |rom: &[u8], i: usize| rom.get(i).copied().unwrap_or(0);
+
+    let mut changes: Vec<RomChange> = vec![];
+    for i in 0..old.len().max(new.len()) {
+        let (old_byte, new_byte) = (byte(old, i), byte(new, i));
+        if old_byte == new_byte {
+            continue;
+        }
+
+        let address = ROM_ORIGIN as usize + i;
+        match changes.last_mut() {
+            Some(change) if change.addresses.end == address => {
+                change.addresses.end += 1;
+                change.old.push(old_byte);
+                change.new.push(new_byte);
+            },
+            _ => changes.push(RomChange { addresses: address..address + 1, old: vec![old_byte], new: vec![new_byte] }),
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Symbols;
+
+    use super::diff_roms;
+
+    #[test]
+    fn test_diff_roms() {
+        assert!(diff_roms(&[1, 2, 3], &[1, 2, 3, 0]).is_empty());
+
+        let changes = diff_roms(&[1, 2, 3, 4, 5], &[1, 9, 9, 4, 5, 6]);
+        let lines: Vec<_> = changes.iter().map(|change| change.to_string()).collect();
+        assert_eq!(lines, ["0101-0102: 02 03 -> 09 09", "0105-0105: 00 -> 06"]);
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0x0100, "on-reset");
+        assert_eq!(changes[0].locate(&symbols).as_deref(), Some("on-reset+1"));
+    }
+}
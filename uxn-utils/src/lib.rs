@@ -1,41 +1,104 @@
-#![feature(exit_status_error)]
-
-use std::{error::Error, io::{Read, Write}, process::Command};
+use std::{io::{Read, Write}, path::Path, process::Command};
 
 use tempfile::NamedTempFile;
 
-/// Assembles uxntal code using the `uxnasm` command-line tool, which must be on your PATH.
-/// 
+mod rom;
+pub use rom::*;
+
+mod symbols;
+pub use symbols::*;
+
+mod diff;
+pub use diff::*;
+
+pub mod asm;
+use asm::AssembleError;
+
+/// Assembles uxntal code with the native assembler in [`asm`].
+///
 /// Returns the sequence of bytes of the ROM.
 /// This should be loaded at 0x0100 in an uxn interpreter.
-/// 
-/// Returns an error if `uxnasm` is not on your PATH, or if assembly fails.
-pub fn assemble_uxntal(code: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+///
+/// Returns an error if assembly fails.
+pub fn assemble_uxntal(code: &str) -> Result<Vec<u8>, AssembleError> {
+    asm::assemble(code)
+}
+
+/// Assembles uxntal code using the `uxnasm` command-line tool, which must be on your PATH. This is
+/// for source which uses syntax the native assembler doesn't support yet.
+///
+/// Returns an error if `uxnasm` is not on your PATH, or if assembly fails. In that case, the
+/// location is worked out from what `uxnasm` printed, where possible.
+pub fn assemble_uxntal_with_uxnasm(code: &str) -> Result<Vec<u8>, AssembleError> {
+    let io_error = |e: std::io::Error| AssembleError::new(format!("could not run uxnasm: {e}"));
+
     // Write code to a file
-    let mut code_file = NamedTempFile::new()?;
-    write!(code_file, "{}", code)?;
+    let mut code_file = NamedTempFile::new().map_err(io_error)?;
+    write!(code_file, "{}", code).map_err(io_error)?;
 
     // Execute `uxnasm` to write to a new ROM file
-    let mut rom_file = NamedTempFile::new()?;
-    Command::new("uxnasm")
+    let mut rom_file = NamedTempFile::new().map_err(io_error)?;
+    let output = Command::new("uxnasm")
         .arg(code_file.path())
         .arg(rom_file.path())
-        .status()?
-        .exit_ok()?;
+        .output()
+        .map_err(io_error)?;
+    if !output.status.success() {
+        return Err(parse_uxnasm_error(&String::from_utf8_lossy(&output.stderr), code_file.path(), code));
+    }
 
     // Read ROM out of file
     let mut bytes = vec![];
-    rom_file.read_to_end(&mut bytes)?;
+    rom_file.read_to_end(&mut bytes).map_err(io_error)?;
     Ok(bytes)
 }
 
+/// Makes sense of an error printed by `uxnasm`, which look like
+/// `Label unknown: missing in @on-reset, /tmp/code.tal:3.` There's no column.
+fn parse_uxnasm_error(stderr: &str, code_path: &Path, code: &str) -> AssembleError {
+    let message = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("uxnasm failed").trim();
+
+    let location = format!(", {}:", code_path.display());
+    let Some((message, rest)) = message.rsplit_once(&location) else { return AssembleError::new(message) };
+    let line = rest.trim_end_matches('.').parse().ok();
+    AssembleError {
+        line,
+        source_snippet: line.and_then(|line: usize| code.lines().nth(line.checked_sub(1)?)).map(str::to_string),
+        ..AssembleError::new(message)
+    }
+}
+
+/// Computes a stable 64-bit hash of a ROM (FNV-1a), for identifying ROMs in logs and recordings.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 #[cfg(test)]
 mod test {
-    use crate::assemble_uxntal;
+    use std::path::Path;
+
+    use crate::{assemble_uxntal, parse_uxnasm_error, rom_hash};
 
     #[test]
     fn test_asm() {
         let rom = assemble_uxntal("|100 01 02 03").unwrap();
         assert_eq!(rom, vec![1, 2, 3])
     }
+
+    #[test]
+    fn test_parse_uxnasm_error() {
+        let error = parse_uxnasm_error("Label unknown: missing in @on-reset, /tmp/a.tal:2.\n", Path::new("/tmp/a.tal"), "BRK\n;missing");
+        assert_eq!(error.message, "Label unknown: missing in @on-reset");
+        assert_eq!((error.line, error.source_snippet.as_deref()), (Some(2), Some(";missing")));
+
+        assert_eq!(parse_uxnasm_error("Something else\n", Path::new("/tmp/a.tal"), "").message, "Something else");
+    }
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(rom_hash(&[]), 0xcbf29ce484222325);
+        assert_eq!(rom_hash(b"a"), 0xaf63dc4c8601ec8c);
+    }
 }
@@ -0,0 +1,259 @@
+use std::{collections::BTreeSet, error::Error, fs, io::{self, Read}, ops::Range, path::Path};
+
+use crate::rom_hash;
+
+/// Where ROMs are loaded in memory.
+pub const ROM_ORIGIN: u16 = 0x0100;
+
+/// The largest ROM which fits in memory, spilling over from the main bank into the other 15.
+pub const MAX_ROM_SIZE: usize = 16 * 0x10000 - ROM_ORIGIN as usize;
+
+/// How big a page is in [`RomInfo::pages`].
+pub const PAGE_SIZE: usize = 0x100;
+
+/// How a ROM was stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomFormat {
+    /// Raw bytes, as written by an assembler.
+    Binary,
+
+    /// Bytes written as pairs of hex digits, optionally separated by whitespace, as is common when
+    /// pasting small ROMs into chat or bug reports.
+    HexText,
+}
+
+impl RomFormat {
+    /// Works out a ROM file's format from its extension: `.hex` files are hex text, and anything
+    /// else is binary.
+    pub fn from_path(path: impl AsRef<Path>) -> RomFormat {
+        match path.as_ref().extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("hex") => RomFormat::HexText,
+            _ => RomFormat::Binary,
+        }
+    }
+}
+
+/// A ROM, ready to be loaded into a core.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rom {
+    pub bytes: Vec<u8>,
+
+    /// The address the ROM should be loaded at.
+    pub origin: u16,
+
+    /// The ROM's hash from [`rom_hash`].
+    pub hash: u64,
+
+    pub format: RomFormat,
+}
+
+impl Rom {
+    /// Interprets the contents of a ROM file in the given format.
+    ///
+    /// Returns an error if the ROM is too large, or isn't valid hex text when it should be.
+    pub fn from_file_contents(contents: &[u8], format: RomFormat) -> Result<Rom, Box<dyn Error>> {
+        let bytes = match format {
+            RomFormat::Binary => contents.to_vec(),
+            RomFormat::HexText => parse_hex_text(contents).ok_or("ROM isn't valid hex text")?,
+        };
+
+        if bytes.len() > MAX_ROM_SIZE {
+            return Err(format!("ROM is {} bytes, but at most {MAX_ROM_SIZE} fit in memory", bytes.len()).into());
+        }
+
+        Ok(Rom { hash: rom_hash(&bytes), bytes, origin: ROM_ORIGIN, format })
+    }
+
+    /// Reads a whole ROM file from a reader, like stdin, and interprets it as in
+    /// [`Rom::from_file_contents`].
+    pub fn from_reader(mut reader: impl Read, format: RomFormat) -> Result<Rom, Box<dyn Error>> {
+        let mut contents = vec![];
+        reader.read_to_end(&mut contents)?;
+        Rom::from_file_contents(&contents, format)
+    }
+
+    /// Works out a summary of what's in the ROM, for sanity-checking builds.
+    pub fn info(&self) -> RomInfo {
+        let origin = self.origin as usize;
+
+        // Follow a jump at the very start, which is how most ROMs skip over their data
+        let reset_vector = match self.bytes[..] {
+            [0x40, hi, lo, ..] => self.origin.wrapping_add(3).wrapping_add(u16::from_be_bytes([hi, lo])),
+            _ => self.origin,
+        };
+
+        // By convention, ROMs announce their metadata with `;meta .System/metadata DEO2` straight away
+        let start = (reset_vector.wrapping_sub(self.origin) as usize).min(self.bytes.len());
+        let metadata = self.bytes[start..].windows(6)
+            .take(16)
+            .find(|window| matches!(window, [0xa0, _, _, 0x80, 0x06, 0x37]))
+            .map(|window| u16::from_be_bytes([window[1], window[2]]));
+
+        let mut pages: Vec<Range<usize>> = vec![];
+        for (i, page) in self.bytes.chunks(PAGE_SIZE).enumerate() {
+            if page.iter().all(|byte| *byte == 0) {
+                continue;
+            }
+            let start = origin + i * PAGE_SIZE;
+            let end = start + page.len();
+            match pages.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => pages.push(start..end),
+            }
+        }
+
+        RomInfo { size: self.bytes.len(), reset_vector, metadata, zero_page: self.zero_page_usage(), pages }
+    }
+
+    /// Finds zero-page addresses used by LDZ and STZ with a literal address, stepping through the
+    /// ROM as if it were all code.
+    fn zero_page_usage(&self) -> BTreeSet<u8> {
+        let mut used = BTreeSet::new();
+        let mut i = 0;
+        while i < self.bytes.len() {
+            match self.bytes[i..] {
+                // LIT, then LDZ or STZ in any mode
+                [0x80, address, ins, ..] if matches!(ins & 0x1f, 0x10 | 0x11) => {
+                    used.insert(address);
+                    if ins & 0x20 != 0 {
+                        used.insert(address.wrapping_add(1));
+                    }
+                    i += 2;
+                },
+
+                // Skip over immediate operands, so they aren't mistaken for instructions
+                [0x80 | 0xc0, ..] => i += 2,
+                [0x20 | 0x40 | 0x60 | 0xa0 | 0xe0, ..] => i += 3,
+                _ => i += 1,
+            }
+        }
+        used
+    }
+}
+
+/// A summary of what's in a ROM, from [`Rom::info`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    /// The size of the ROM in bytes.
+    pub size: usize,
+
+    /// Where execution really starts, following a jump at the start of the ROM if there is one.
+    pub reset_vector: u16,
+
+    /// Where the ROM's metadata is, if it announces it when it starts.
+    pub metadata: Option<u16>,
+
+    /// Zero-page addresses which the ROM loads from or stores to with a literal address.
+    pub zero_page: BTreeSet<u8>,
+
+    /// Ranges of addresses with anything in them, to the nearest [`PAGE_SIZE`]. These can go past
+    /// 0xffff for ROMs which spill into other banks.
+    pub pages: Vec<Range<usize>>,
+}
+
+/// Loads a ROM from a file path, or from stdin if the path is `-`, so that ROMs can be piped in.
+/// The format comes from the path as in [`RomFormat::from_path`], and stdin is always binary.
+///
+/// URLs and archives aren't handled here yet. They can be downloaded or unpacked by another
+/// program, and piped in through stdin.
+///
+/// Returns an error if it can't be read, or [`Rom::from_file_contents`] rejects it.
+pub fn load_rom(source: &str) -> Result<Rom, Box<dyn Error>> {
+    if source == "-" {
+        return Rom::from_reader(io::stdin().lock(), RomFormat::Binary).map_err(|e| format!("could not read stdin: {e}").into());
+    }
+
+    let contents = fs::read(Path::new(source)).map_err(|e| format!("could not read {source}: {e}"))?;
+    Rom::from_file_contents(&contents, RomFormat::from_path(source))
+}
+
+/// Writes a ROM to a file, as raw bytes which can be loaded with [`load_rom`] or any other
+/// emulator.
+///
+/// Returns an error if the ROM is too large to be loaded, or it can't be written.
+pub fn write_rom_file(path: impl AsRef<Path>, rom: &[u8]) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(format!("ROM is {} bytes, but at most {MAX_ROM_SIZE} can be loaded", rom.len()).into());
+    }
+    fs::write(path, rom).map_err(|e| format!("could not write {}: {e}", path.display()))?;
+    Ok(())
+}
+
+/// Parses a ROM written as hex text. Returns `None` if it isn't whole bytes of hex digits.
+fn parse_hex_text(contents: &[u8]) -> Option<Vec<u8>> {
+    let text = str::from_utf8(contents).ok()?;
+    let digits: Vec<u8> = text.split_whitespace()
+        .flat_map(|word| word.bytes())
+        .collect();
+
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+
+    digits.chunks(2)
+        .map(|pair| u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_rom, write_rom_file, Rom, RomFormat, MAX_ROM_SIZE};
+    use crate::assemble_uxntal;
+
+    #[test]
+    fn test_rom_formats() {
+        let rom = Rom::from_file_contents(&[0x80, 0x01, 0x00], RomFormat::Binary).unwrap();
+        assert_eq!((rom.bytes, rom.format, rom.origin), (vec![0x80, 0x01, 0x00], RomFormat::Binary, 0x0100));
+
+        let rom = Rom::from_file_contents(b"80 01\n00\n", RomFormat::HexText).unwrap();
+        assert_eq!((rom.bytes, rom.format), (vec![0x80, 0x01, 0x00], RomFormat::HexText));
+        assert!(Rom::from_file_contents(b"abc", RomFormat::HexText).is_err());
+
+        // Binary ROMs are taken literally, whatever they look like
+        for contents in [&b"abcd"[..], b"PK\x03\x04", b"\x1f\x8b", b""] {
+            let rom = Rom::from_file_contents(contents, RomFormat::Binary).unwrap();
+            assert_eq!(rom.bytes, contents);
+        }
+
+        assert!(Rom::from_file_contents(&vec![0; MAX_ROM_SIZE + 1], RomFormat::Binary).is_err());
+
+        let rom = Rom::from_reader(&b"80 01 00"[..], RomFormat::HexText).unwrap();
+        assert_eq!((rom.bytes, rom.format), (vec![0x80, 0x01, 0x00], RomFormat::HexText));
+
+        assert_eq!(RomFormat::from_path("small.hex"), RomFormat::HexText);
+        assert_eq!(RomFormat::from_path("dir.hex/game.rom"), RomFormat::Binary);
+    }
+
+    #[test]
+    fn test_write_rom_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        // Even ROMs which look like hex text load back as they were written
+        for rom in [&[0x80, 0x2a][..], b"abcd", b""] {
+            write_rom_file(path, rom).unwrap();
+            assert_eq!(load_rom(path).unwrap().bytes, rom);
+        }
+
+        assert!(write_rom_file(path, &vec![0; MAX_ROM_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_rom_info() {
+        let source = "|00 @count $2 |100 !on-reset @meta 00 \"Demo @on-reset ;meta #06 DEO2 .count LDZ2 #01 .count STZ $200 ff";
+        let rom = Rom::from_file_contents(&assemble_uxntal(source).unwrap(), RomFormat::Binary).unwrap();
+        let info = rom.info();
+
+        assert_eq!(info.size, 0x217);
+        assert_eq!(info.reset_vector, 0x0108);
+        assert_eq!(info.metadata, Some(0x0103));
+        assert_eq!(info.zero_page.into_iter().collect::<Vec<_>>(), [0x00, 0x01]);
+        assert_eq!(info.pages, [0x0100..0x0200, 0x0300..0x0317]);
+
+        // Metadata is found after the jump, however long it is
+        let source = "|100 !on-reset @meta 00 \"A-much-longer-description @on-reset ;meta #06 DEO2 BRK";
+        let rom = Rom::from_file_contents(&assemble_uxntal(source).unwrap(), RomFormat::Binary).unwrap();
+        assert_eq!(rom.info().metadata, Some(0x0103));
+    }
+}
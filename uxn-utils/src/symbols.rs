@@ -0,0 +1,112 @@
+use std::{collections::HashMap, error::Error};
+
+/// The labels from a ROM's source, so that addresses can be shown and given by name.
+///
+/// These come from the `.sym` file which uxnasm writes alongside a ROM, or from [`asm`](crate::asm). Each label is its address
+/// as a big-endian short, followed by its name and a null byte. Sublabels are named in full, like
+/// `on-screen/loop`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    // Sorted by address
+    labels: Vec<(u16, String)>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the contents of a `.sym` file.
+    pub fn parse(bytes: &[u8]) -> Result<Symbols, Box<dyn Error>> {
+        let mut symbols = Symbols::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let [hi, lo, tail @ ..] = rest else { return Err("symbol file ends part-way through an address".into()) };
+            let end = tail.iter().position(|byte| *byte == 0).ok_or("symbol file ends part-way through a name")?;
+            let name = str::from_utf8(&tail[..end]).map_err(|_| "symbol file has a name which isn't UTF-8")?;
+
+            symbols.insert(u16::from_be_bytes([*hi, *lo]), name);
+            rest = &tail[end + 1..];
+        }
+        Ok(symbols)
+    }
+
+    /// Adds a label. If there's already one with this name, it's moved.
+    pub fn insert(&mut self, address: u16, name: &str) {
+        if self.addresses.insert(name.to_string(), address).is_some() {
+            self.labels.retain(|(_, n)| n != name);
+        }
+        let index = self.labels.partition_point(|(a, _)| *a <= address);
+        self.labels.insert(index, (address, name.to_string()));
+    }
+
+    /// The address of a label, like `on-screen/loop`.
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    /// The first label at exactly this address.
+    pub fn name(&self, address: u16) -> Option<&str> {
+        let index = self.labels.partition_point(|(a, _)| *a < address);
+        self.labels.get(index)
+            .filter(|(a, _)| *a == address)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Describes an address relative to the nearest label at or before it, like `on-screen/loop+3`.
+    /// Returns `None` if there are no labels that early.
+    pub fn locate(&self, address: u16) -> Option<String> {
+        let index = self.labels.partition_point(|(a, _)| *a <= address).checked_sub(1)?;
+
+        // Of several labels at the same address, prefer the first
+        let (label_address, _) = self.labels[index];
+        let first = self.labels.partition_point(|(a, _)| *a < label_address);
+        let name = &self.labels[first].1;
+        Some(match address - label_address {
+            0 => name.clone(),
+            offset => format!("{name}+{offset}"),
+        })
+    }
+
+    /// Every label, in address order.
+    pub fn labels(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.labels.iter().map(|(address, name)| (*address, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Writes the labels in the `.sym` format which [`Symbols::parse`] reads.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (address, name) in &self.labels {
+            bytes.extend(address.to_be_bytes());
+            bytes.extend(name.as_bytes());
+            bytes.push(0);
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Symbols;
+
+    #[test]
+    fn test_symbols() {
+        let symbols = Symbols::parse(b"\x01\x00on-reset\0\x01\x08on-reset/loop\0\x00\x10Console\0").unwrap();
+        assert_eq!(symbols.address("on-reset/loop"), Some(0x0108));
+        assert_eq!(symbols.name(0x0100), Some("on-reset"));
+        assert_eq!(symbols.name(0x0101), None);
+        assert_eq!(symbols.locate(0x0100).as_deref(), Some("on-reset"));
+        assert_eq!(symbols.locate(0x010b).as_deref(), Some("on-reset/loop+3"));
+        assert_eq!(symbols.locate(0x000f), None);
+        assert_eq!(symbols.labels().next(), Some((0x0010, "Console")));
+        assert_eq!(Symbols::parse(&symbols.to_bytes()).unwrap(), symbols);
+
+        assert!(Symbols::parse(b"\x01\x00on-reset").is_err());
+        assert!(Symbols::parse(b"\x01").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Runs Varvara ROMs in the browser, drawing the Screen on a canvas and appending the Console's
+//! output to an element.
+//!
+//! Build with `wasm-pack build uxn-web --target web`, then drive it from JavaScript:
+//!
+//! ```js
+//! const uxn = new Uxn(rom, document.querySelector("canvas"), document.querySelector("pre"));
+//! const frame = () => { uxn.frame(); if (!uxn.is_halted()) requestAnimationFrame(frame); };
+//! requestAnimationFrame(frame);
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use uxn_core_emulator::{device::{CapturingConsole, Device, OffscreenBackend, VarvaraDevice}, Core};
+use wasm_bindgen::{prelude::*, Clamped};
+use web_sys::{CanvasRenderingContext2d, Element, HtmlCanvasElement, ImageData};
+
+/// How many instructions each vector can run, so that a broken ROM can't hang the page.
+const FUEL: u64 = 10_000_000;
+
+/// A ROM running on a canvas.
+#[wasm_bindgen]
+pub struct Uxn {
+    core: Core,
+    device: Arc<Mutex<VarvaraDevice>>,
+    console: CapturingConsole,
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+    console_element: Element,
+}
+
+#[wasm_bindgen]
+impl Uxn {
+    /// Loads a ROM and runs its reset vector.
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], canvas: HtmlCanvasElement, console_element: Element) -> Result<Uxn, JsValue> {
+        let context = canvas.get_context("2d")?
+            .ok_or("canvas has no 2d context")?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+
+        // The canvas is drawn from the framebuffer directly, so the backend's frames aren't needed
+        let console = CapturingConsole::new();
+        let mut device = VarvaraDevice::with_screen_backend(OffscreenBackend::new());
+        device.disable_stdin();
+        device.capture_console(console.clone());
+        let device = Arc::new(Mutex::new(device));
+
+        let mut core = Core::new_with_rom(rom);
+        core.set_device(device.clone());
+
+        let mut uxn = Uxn { core, device, console, canvas, context, console_element };
+        uxn.run_vector(0x0100)?;
+        uxn.draw()?;
+        Ok(uxn)
+    }
+
+    /// Runs the Screen vector, then redraws the canvas and adds any new console output. Call this
+    /// once per animation frame.
+    pub fn frame(&mut self) -> Result<(), JsValue> {
+        if self.is_halted() {
+            return Ok(());
+        }
+
+        let vector = self.device.lock().unwrap().screen_vector();
+        if let Some(vector) = vector {
+            self.run_vector(vector)?;
+        }
+        self.draw()
+    }
+
+    /// Whether the ROM has exited through `.System/state`.
+    pub fn is_halted(&self) -> bool {
+        self.device.is_halted()
+    }
+
+    fn run_vector(&mut self, vector: u16) -> Result<(), JsValue> {
+        self.core.execute_vector(vector, Some(FUEL)).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(())
+    }
+
+    fn draw(&mut self) -> Result<(), JsValue> {
+        let (width, height, pixels) = {
+            let device = self.device.lock().unwrap();
+            let (width, height) = device.framebuffer().get_size();
+            (width, height, device.framebuffer().composite())
+        };
+
+        if (self.canvas.width(), self.canvas.height()) != (width.into(), height.into()) {
+            self.canvas.set_width(width.into());
+            self.canvas.set_height(height.into());
+        }
+        let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&to_rgba(&pixels)), width.into(), height.into())?;
+        self.context.put_image_data(&image, 0.0, 0.0)?;
+
+        // There's only one element, so errors are shown alongside the normal output
+        let mut output = self.console.output();
+        output.extend(self.console.error());
+        if !output.is_empty() {
+            self.console_element.append_with_str_1(&String::from_utf8_lossy(&output))?;
+            self.console.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Converts 0RGB pixels into the opaque RGBA bytes which canvases use.
+fn to_rgba(pixels: &[u32]) -> Vec<u8> {
+    pixels.iter()
+        .flat_map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b, 0xff]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::to_rgba;
+
+    #[test]
+    fn test_to_rgba() {
+        assert_eq!(to_rgba(&[0x00123456, 0x00ff0000]), [0x12, 0x34, 0x56, 0xff, 0xff, 0x00, 0x00, 0xff]);
+    }
+
+    // These run under Node, as the README describes, with just enough of a fake DOM for a canvas
+    // and a console element
+    #[cfg(target_arch = "wasm32")]
+    mod wasm {
+        use wasm_bindgen::{prelude::*, JsCast};
+        use wasm_bindgen_test::wasm_bindgen_test;
+
+        use super::super::Uxn;
+
+        #[wasm_bindgen(inline_js = "
+            export function fake_canvas() {
+                globalThis.CanvasRenderingContext2D ??= class { putImageData(image) { this.image = image; } };
+                globalThis.ImageData ??= class { constructor(data, width, height) { Object.assign(this, { data, width, height }); } };
+                const context = new CanvasRenderingContext2D();
+                return { width: 0, height: 0, getContext: () => context };
+            }
+            export function fake_element() { return { text: '', append(text) { this.text += text; } }; }
+            export function element_text(element) { return element.text; }
+        ")]
+        extern "C" {
+            fn fake_canvas() -> JsValue;
+            fn fake_element() -> JsValue;
+            fn element_text(element: &JsValue) -> String;
+        }
+
+        #[wasm_bindgen_test]
+        fn test_uxn() {
+            // Writes "hi" to the console
+            let rom = [0x80, b'h', 0x80, 0x18, 0x17, 0x80, b'i', 0x80, 0x18, 0x17, 0x00];
+            let element = fake_element();
+            let mut uxn = Uxn::new(&rom, fake_canvas().unchecked_into(), element.clone().unchecked_into()).unwrap();
+            uxn.frame().unwrap();
+            assert!(!uxn.is_halted());
+            assert_eq!(element_text(&element), "hi");
+        }
+    }
+}